@@ -0,0 +1,52 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+
+    if output.status.success() {
+        String::from_utf8(output.stdout)
+            .ok()
+            .map(|output| output.trim().to_string())
+    } else {
+        None
+    }
+}
+
+// 実行中のバイナリがどのコミットからどのようにビルドされたかを、/meta/info とメトリクスで報告するために埋め込む
+fn main() {
+    // .git の無い環境 (Dockerイメージのビルドなど) では環境変数で渡せるようにする
+    let git_commit = std::env::var("GIT_COMMIT_HASH")
+        .ok()
+        .or_else(|| command_output("git", &["rev-parse", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version =
+        command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+
+    let build_timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+
+    let mut features = std::env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect::<Vec<_>>();
+    features.sort();
+
+    println!("cargo:rustc-env=SEICHI_API_GIT_COMMIT={git_commit}");
+    println!("cargo:rustc-env=SEICHI_API_RUSTC_VERSION={rustc_version}");
+    println!("cargo:rustc-env=SEICHI_API_BUILD_TIMESTAMP={build_timestamp}");
+    println!("cargo:rustc-env=SEICHI_API_FEATURES={}", features.join(","));
+
+    // コミットが変わったときに埋め込む値を更新する。ビルド時刻もこのときに更新される
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT_HASH");
+    for path in ["HEAD", "refs"] {
+        if let Some(path) = command_output("git", &["rev-parse", "--git-path", path]) {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+}
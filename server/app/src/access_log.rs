@@ -0,0 +1,286 @@
+use crate::metrics::Routes;
+use crate::request_id::REQUEST_ID_HEADER;
+use bytes::Bytes;
+use http::{HeaderMap, Request, Response};
+use http_body::Body;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tonic::transport::server::TcpConnectInfo;
+use tower::{Layer, Service};
+
+/// リクエストの送信元のIPアドレス。[`AccessLogLayer`] がリクエストのextensionに設定する
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// `X-Forwarded-For` を付け加える信頼できるプロキシが `trusted_proxy_depth` 段あるとして、リクエストの送信元を決める。
+///
+/// 接続元のアドレスから順に、`X-Forwarded-For` を右から辿って `trusted_proxy_depth` 個目のアドレスを使う。
+/// 辿れるアドレスが足りない場合は、最も遠いものを使う。
+fn client_ip(
+    headers: &HeaderMap,
+    remote_address: Option<IpAddr>,
+    trusted_proxy_depth: usize,
+) -> Option<IpAddr> {
+    let forwarded = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+
+    let mut hops = remote_address.into_iter().chain(
+        forwarded
+            .into_iter()
+            .rev()
+            .map_while(|address| address.parse().ok()),
+    );
+
+    let mut client = hops.next()?;
+    for hop in hops.take(trusted_proxy_depth) {
+        client = hop;
+    }
+    Some(client)
+}
+
+/// 完了したリクエストごとに一つ、`access_log` をターゲットとするINFOのイベントを出すレイヤー。
+///
+/// 応答の本文を送り終えるか、途中で接続が切れた時点で出す。
+/// ログの出力が無効な場合も、送信元を [`ClientIp`] としてリクエストに設定するためにレイヤーは挟んでおく。
+#[derive(Clone)]
+pub struct AccessLogLayer {
+    enabled: bool,
+    routes: Routes,
+    excluded_paths: Arc<[String]>,
+    trusted_proxy_depth: usize,
+}
+
+impl AccessLogLayer {
+    pub fn new(
+        enabled: bool,
+        routes: Routes,
+        excluded_paths: Vec<String>,
+        trusted_proxy_depth: usize,
+    ) -> Self {
+        Self {
+            enabled,
+            routes,
+            excluded_paths: excluded_paths.into(),
+            trusted_proxy_depth,
+        }
+    }
+
+    fn is_logged(&self, path: &str) -> bool {
+        self.enabled && !self.excluded_paths.iter().any(|excluded| excluded == path)
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLog<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessLog {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AccessLog<S> {
+    inner: S,
+    layer: AccessLogLayer,
+}
+
+/// 一つのリクエストについて、応答の本文を送り終えるまでに集めた情報
+struct Entry {
+    method: String,
+    route: String,
+    status: u16,
+    grpc_status: Option<String>,
+    client_ip: Option<IpAddr>,
+    request_id: Option<String>,
+    started_at: Instant,
+    response_bytes: usize,
+}
+
+impl Entry {
+    fn emit(&self) {
+        tracing::info!(
+            target: "access_log",
+            method = %self.method,
+            route = %self.route,
+            status = self.status,
+            grpc_status = self.grpc_status.as_deref(),
+            duration_ms = u64::try_from(self.started_at.elapsed().as_millis()).unwrap_or(u64::MAX),
+            response_bytes = self.response_bytes,
+            client_ip = self.client_ip.map(tracing::field::display),
+            request_id = self.request_id.as_deref(),
+            "request completed"
+        );
+    }
+}
+
+fn grpc_status(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("grpc-status")
+        .and_then(|value| value.to_str().ok())
+        .map(ToString::to_string)
+}
+
+/// 送った本文の大きさを数え、破棄されるときにアクセスログを出す応答の本文
+pub struct AccessLogBody<B> {
+    inner: B,
+    entry: Option<Entry>,
+}
+
+impl<B> Drop for AccessLogBody<B> {
+    fn drop(&mut self) {
+        if let Some(entry) = &self.entry {
+            entry.emit();
+        }
+    }
+}
+
+impl<B> Body for AccessLogBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let polled = Pin::new(&mut self.inner).poll_data(cx);
+
+        if let (Poll::Ready(Some(Ok(data))), Some(entry)) = (&polled, &mut self.entry) {
+            entry.response_bytes += data.len();
+        }
+        polled
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let polled = Pin::new(&mut self.inner).poll_trailers(cx);
+
+        // 正常な応答の grpc-status はトレーラーで送られる
+        if let (Poll::Ready(Ok(Some(trailers))), Some(entry)) = (&polled, &mut self.entry) {
+            if let Some(status) = grpc_status(trailers) {
+                entry.grpc_status = Some(status);
+            }
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<S, B, ResBody> Service<Request<B>> for AccessLog<S>
+where
+    S: Service<Request<B>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    ResBody: Body<Data = Bytes> + Unpin,
+{
+    type Response = Response<AccessLogBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        // tonic はTCPで受け付けたリクエストに接続元の情報を付ける
+        let remote_address = request
+            .extensions()
+            .get::<TcpConnectInfo>()
+            .and_then(TcpConnectInfo::remote_addr)
+            .map(|address| address.ip());
+        let client_ip = client_ip(
+            request.headers(),
+            remote_address,
+            self.layer.trusted_proxy_depth,
+        );
+        if let Some(client_ip) = client_ip {
+            request.extensions_mut().insert(ClientIp(client_ip));
+        }
+
+        let path = request.uri().path();
+        let entry = self.layer.is_logged(path).then(|| Entry {
+            method: request.method().to_string(),
+            route: self.layer.routes.label(path),
+            status: 0,
+            grpc_status: None,
+            client_ip,
+            request_id: request
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(ToString::to_string),
+            started_at: Instant::now(),
+            response_bytes: 0,
+        });
+
+        let response = self.inner.call(request);
+
+        Box::pin(async move {
+            let response = response.await?;
+            let entry = entry.map(|entry| Entry {
+                status: response.status().as_u16(),
+                // エラーの grpc-status はヘッダーで送られる
+                grpc_status: grpc_status(response.headers()),
+                ..entry
+            });
+
+            Ok(response.map(|inner| AccessLogBody { inner, entry }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn forwarded_for(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", value.parse().unwrap());
+        headers
+    }
+
+    fn ip(address: &str) -> Option<IpAddr> {
+        Some(address.parse().unwrap())
+    }
+
+    #[test]
+    fn forwarded_for_is_only_trusted_up_to_the_proxy_depth() {
+        let headers = forwarded_for("203.0.113.1, 198.51.100.2, 10.0.0.3");
+        let proxy = ip("10.0.0.4");
+
+        assert_eq!(client_ip(&headers, proxy, 0), proxy);
+        assert_eq!(client_ip(&headers, proxy, 1), ip("10.0.0.3"));
+        assert_eq!(client_ip(&headers, proxy, 3), ip("203.0.113.1"));
+        // 辿れるアドレスより多くのプロキシを信頼する設定でも、最も遠いアドレスで止まる
+        assert_eq!(client_ip(&headers, proxy, 10), ip("203.0.113.1"));
+    }
+
+    #[test]
+    fn unparsable_forwarded_for_entry_stops_the_walk() {
+        let headers = forwarded_for("203.0.113.1, unknown, 10.0.0.3");
+
+        assert_eq!(client_ip(&headers, ip("10.0.0.4"), 3), ip("10.0.0.3"));
+    }
+}
@@ -0,0 +1,80 @@
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use serde::Serialize;
+use std::time::Instant;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_COMMIT: &str = env!("SEICHI_API_GIT_COMMIT");
+pub const RUSTC_VERSION: &str = env!("SEICHI_API_RUSTC_VERSION");
+const BUILD_TIMESTAMP: &str = env!("SEICHI_API_BUILD_TIMESTAMP");
+const FEATURES: &str = env!("SEICHI_API_FEATURES");
+
+/// ビルドされた時刻 (RFC 3339)
+pub fn build_timestamp() -> String {
+    BUILD_TIMESTAMP
+        .parse()
+        .ok()
+        .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single())
+        .map_or_else(
+            || "unknown".to_string(),
+            |timestamp| timestamp.to_rfc3339_opts(SecondsFormat::Secs, true),
+        )
+}
+
+/// 有効にしてビルドされたcargoのfeature
+pub fn features() -> Vec<&'static str> {
+    FEATURES
+        .split(',')
+        .filter(|feature| !feature.is_empty())
+        .collect()
+}
+
+/// 実行中のプロセスについての情報。秘匿情報や接続先は含めない
+pub struct ProcessInfo {
+    started_at: DateTime<Utc>,
+    started: Instant,
+    profile: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Info {
+    version: &'static str,
+    git_commit: &'static str,
+    build_timestamp: String,
+    rustc_version: &'static str,
+    features: Vec<&'static str>,
+    started_at: String,
+    uptime_seconds: u64,
+    /// 設定ファイル中の環境の名前。選ばれていなければ `null`
+    profile: Option<String>,
+}
+
+impl ProcessInfo {
+    pub fn new(profile: Option<String>) -> Self {
+        Self {
+            started_at: Utc::now(),
+            started: Instant::now(),
+            profile,
+        }
+    }
+
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    pub const fn started_at(&self) -> DateTime<Utc> {
+        self.started_at
+    }
+
+    pub fn info(&self) -> Info {
+        Info {
+            version: VERSION,
+            git_commit: GIT_COMMIT,
+            build_timestamp: build_timestamp(),
+            rustc_version: RUSTC_VERSION,
+            features: features(),
+            started_at: self.started_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            uptime_seconds: self.started.elapsed().as_secs(),
+            profile: self.profile.clone(),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+
+/// 整地鯖のゲームDBのデータをgRPCで提供するAPIサーバー
+#[derive(Parser, Debug)]
+pub struct Cli {
+    /// 設定ファイルのパス。指定しなければ環境変数 SEICHI_API_CONFIG から決める
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// 設定ファイル中の `[profile.<名前>]` のうち使う環境の名前。指定しなければ環境変数 SEICHI_API_PROFILE から決める
+    #[arg(long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// ログのフィルタ (例: `info,sqlx=warn`)。指定しなければ環境変数 RUST_LOG から決める
+    #[arg(long, global = true, value_name = "FILTER")]
+    pub log_level: Option<String>,
+
+    /// 省略した場合は `serve` として動作する
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// gRPCサーバーを起動する
+    Serve,
+    /// 設定を読み込んで検証し、結果をJSONで標準出力に書き出す。設定が有効なら終了コード0、そうでなければ1で終了する
+    CheckConfig,
+    /// 設定を読み込んで検証した上で、待ち受けるアドレス、ゲームDBへの接続、各リソースのクエリなどを確かめ、
+    /// 結果をJSONで標準出力に書き出す。全て確かめられれば終了コード0、そうでなければ1で終了する
+    SelfTest {
+        /// 一つの確認を待つ秒数。これを過ぎた確認は失敗とする
+        #[arg(long, default_value_t = 5)]
+        timeout_seconds: u64,
+    },
+    /// 指定したリソースをゲームDBから一度だけ取得し、JSONで標準出力に書き出す
+    Fetch { resource: Resource },
+    /// 選んだリソースをゲームDBから一度ずつ取得し、リソースごとに一つのファイルへ書き出す。
+    /// 書き出したファイルと行数をJSONで標準出力に書き出し、全て書き出せれば終了コード0、そうでなければ1で終了する
+    Export {
+        /// 書き出すリソースをカンマで区切ったもの (例: `break_counts,vote_counts`)。`all` なら有効な全てのリソース
+        #[arg(long, value_delimiter = ',', required = true, value_parser = parse_resource_selection)]
+        resources: Vec<ResourceSelection>,
+        /// 書き出す形式
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// 書き出すディレクトリ。無ければ作る
+        #[arg(long, value_name = "PATH")]
+        output_directory: PathBuf,
+        /// `parquet` で書き出すとき、一つの行グループに入れる行数の上限
+        #[arg(long, default_value = "1048576", value_name = "ROWS")]
+        parquet_row_group_size: NonZeroUsize,
+    },
+    /// `export` が書き出した二つのファイル (`ndjson` か `csv`) をUUIDで突き合わせ、増えたプレイヤー、
+    /// いなくなったプレイヤー、値が変わったプレイヤーを標準出力に書き出す。
+    /// 読めなかった行があれば、その行番号を標準エラー出力に書き出して終了コード1で終了する
+    Diff {
+        /// 古い方のファイル
+        old: PathBuf,
+        /// 新しい方のファイル
+        new: PathBuf,
+        /// 値の差の絶対値がこれ未満のプレイヤーは、変わったものとして書き出さない。退出時刻の差は秒で数える
+        #[arg(long, default_value_t = 1)]
+        min_delta: u64,
+        /// 書き出す形式
+        #[arg(long, value_enum, default_value_t = DiffFormat::Table)]
+        format: DiffFormat,
+    },
+    /// バージョンを表示する
+    Version,
+}
+
+/// APIが提供するリソース
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[value(rename_all = "snake_case")]
+pub enum Resource {
+    LastQuits,
+    BreakCounts,
+    BuildCounts,
+    PlayTicks,
+    VoteCounts,
+}
+
+impl Resource {
+    pub const ALL: [Self; 5] = [
+        Self::LastQuits,
+        Self::BreakCounts,
+        Self::BuildCounts,
+        Self::PlayTicks,
+        Self::VoteCounts,
+    ];
+
+    /// 設定やメトリクスで使うリソースの名前
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::LastQuits => "last_quits",
+            Self::BreakCounts => "break_counts",
+            Self::BuildCounts => "build_counts",
+            Self::PlayTicks => "play_ticks",
+            Self::VoteCounts => "vote_counts",
+        }
+    }
+}
+
+/// `export` で書き出すリソースの指定
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceSelection {
+    /// 有効な全てのリソース
+    All,
+    Only(Resource),
+}
+
+fn parse_resource_selection(value: &str) -> Result<ResourceSelection, String> {
+    if value == "all" {
+        Ok(ResourceSelection::All)
+    } else {
+        Resource::from_str(value, false).map(ResourceSelection::Only)
+    }
+}
+
+/// `export` で書き出すファイルの形式
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[value(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// レコードの配列
+    Json,
+    /// 一行に一つのレコード
+    Ndjson,
+    /// 見出しの行に続けて、一行に一つのレコード
+    Csv,
+    /// UUIDと名前を文字列、回数を符号なし64ビット整数、退出時刻を秒の精度のUTCのタイムスタンプの列とするApache Parquet
+    Parquet,
+}
+
+/// `diff` で違いを書き出す形式
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[value(rename_all = "snake_case")]
+pub enum DiffFormat {
+    /// 列を揃えた表
+    Table,
+    /// 違いと読めなかった行をまとめた一つのオブジェクト
+    Json,
+    /// 見出しの行に続けて、一行に一つの違い
+    Csv,
+}
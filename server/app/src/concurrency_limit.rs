@@ -0,0 +1,174 @@
+use http::{header, Request, Response, StatusCode};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::Semaphore;
+use tonic::body::BoxBody;
+use tower::{Layer, Service};
+
+/// 同時に処理するリクエストの数を制限し、上限に達している間に来たリクエストはすぐに `503 Service Unavailable` で断るレイヤー。
+///
+/// gRPCサービス `limited_service` へのリクエストのみを数えるため、
+/// ヘルスチェックなど他のサービスへのリクエストは負荷が高い間も処理される。
+#[derive(Clone)]
+pub struct ConcurrencyLimitLayer {
+    semaphore: Arc<Semaphore>,
+    max_concurrent_requests: usize,
+    limited_service: &'static str,
+    retry_after_seconds: u64,
+}
+
+impl ConcurrencyLimitLayer {
+    pub fn new(
+        max_concurrent_requests: usize,
+        limited_service: &'static str,
+        retry_after_seconds: u64,
+    ) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent_requests)),
+            max_concurrent_requests,
+            limited_service,
+            retry_after_seconds,
+        }
+    }
+
+    /// gRPCのリクエストのパスは `/<サービス名>/<メソッド名>` の形をしている
+    fn is_limited(&self, path: &str) -> bool {
+        path.strip_prefix('/')
+            .and_then(|path| path.strip_prefix(self.limited_service))
+            .map_or(false, |method| method.starts_with('/'))
+    }
+
+    /// 現在処理中のリクエストの数
+    pub fn in_flight_requests(&self) -> usize {
+        self.max_concurrent_requests - self.semaphore.available_permits()
+    }
+}
+
+impl<S> Layer<S> for ConcurrencyLimitLayer {
+    type Service = ConcurrencyLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ConcurrencyLimit {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ConcurrencyLimit<S> {
+    inner: S,
+    layer: ConcurrencyLimitLayer,
+}
+
+fn overloaded_response(retry_after_seconds: u64) -> Response<BoxBody> {
+    // gRPCクライアントはHTTPステータス503を UNAVAILABLE として扱うが、念のため grpc-status も付けておく
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(header::CONTENT_TYPE, "application/grpc")
+        .header(header::RETRY_AFTER, retry_after_seconds)
+        .header("grpc-status", (tonic::Code::Unavailable as i32).to_string())
+        .header("grpc-message", "too many concurrent requests")
+        .body(tonic::body::empty_body())
+        .expect("Building a response from valid parts")
+}
+
+impl<S, B> Service<Request<B>> for ConcurrencyLimit<S>
+where
+    S: Service<Request<B>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        if !self.layer.is_limited(request.uri().path()) {
+            return Box::pin(self.inner.call(request));
+        }
+
+        match self.layer.semaphore.clone().try_acquire_owned() {
+            Ok(permit) => {
+                let response = self.inner.call(request);
+                Box::pin(async move {
+                    let response = response.await;
+                    drop(permit);
+                    response
+                })
+            }
+            Err(_) => {
+                tracing::warn!(
+                    max_concurrent_requests = self.layer.max_concurrent_requests,
+                    "shedding a request because too many requests are in flight"
+                );
+                let response = overloaded_response(self.layer.retry_after_seconds);
+                Box::pin(async move { Ok(response) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::Infallible;
+    use tokio::sync::oneshot;
+    use tower::ServiceExt;
+
+    fn request(path: &str) -> Request<()> {
+        Request::builder().uri(path).body(()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_limit_are_shed_until_a_slot_frees_up() {
+        let (release, released) = oneshot::channel::<()>();
+        let released = Arc::new(tokio::sync::Mutex::new(Some(released)));
+
+        let layer = ConcurrencyLimitLayer::new(1, "limited", 3);
+        let service = layer.layer(tower::service_fn(move |_: Request<()>| {
+            let released = released.clone();
+            async move {
+                // 最初のリクエストだけが release されるまで処理を終えない
+                let first = released.lock().await.take();
+                if let Some(released) = first {
+                    released.await.unwrap();
+                }
+                Ok::<_, Infallible>(Response::new(tonic::body::empty_body()))
+            }
+        }));
+
+        let first = tokio::spawn(service.clone().oneshot(request("/limited/A")));
+        while layer.in_flight_requests() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let shed = service
+            .clone()
+            .oneshot(request("/limited/B"))
+            .await
+            .unwrap();
+        assert_eq!(shed.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(shed.headers()[header::RETRY_AFTER], "3");
+
+        let unlimited = service
+            .clone()
+            .oneshot(request("/limited.health/Check"))
+            .await
+            .unwrap();
+        assert_eq!(unlimited.status(), StatusCode::OK);
+
+        release.send(()).unwrap();
+        assert_eq!(first.await.unwrap().unwrap().status(), StatusCode::OK);
+        assert_eq!(layer.in_flight_requests(), 0);
+
+        let after = service.oneshot(request("/limited/C")).await.unwrap();
+        assert_eq!(after.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,572 @@
+use crate::cli::DiffFormat;
+use chrono::DateTime;
+use domain::models::PlayerUuid;
+use infra_repository_impl::csv_data_source::{csv_records, split_csv};
+use serde::Serialize;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+/// 一つのプレイヤーの、比べる値。回数はそのまま、退出時刻はUNIX時間の秒とする
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Record {
+    uuid: PlayerUuid,
+    name: String,
+    value: i128,
+}
+
+/// 読めなかった行
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct MalformedLine {
+    pub file: String,
+    /// 1から数えた行番号。複数の行にわたるCSVのレコードでは、その最初の行とする
+    pub line: usize,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    /// 新しい方のファイルにだけ含まれる
+    Added,
+    /// 古い方のファイルにだけ含まれる
+    Removed,
+    /// 両方に含まれ、値の差が閾値以上
+    Changed,
+}
+
+impl ChangeKind {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Changed => "changed",
+        }
+    }
+}
+
+/// 一人のプレイヤーについての違い。名前は新しい方のファイルにあればそちらのものとする
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub change: ChangeKind,
+    pub uuid: PlayerUuid,
+    pub last_known_name: String,
+    pub old: Option<i128>,
+    pub new: Option<i128>,
+    pub delta: Option<i128>,
+}
+
+/// 二つのファイルの違い
+#[derive(Serialize, Debug)]
+pub struct Diff {
+    /// 比べた値の列の名前
+    pub metric: String,
+    pub changes: Vec<Change>,
+    /// 古い方のファイル、新しい方のファイルの順に、それぞれ行番号の順とする
+    pub malformed_lines: Vec<MalformedLine>,
+}
+
+/// `export` が書き出したファイルの形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputFormat {
+    Ndjson,
+    Csv,
+}
+
+impl InputFormat {
+    fn of(path: &Path) -> anyhow::Result<Self> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ndjson") => Ok(Self::Ndjson),
+            Some("csv") => Ok(Self::Csv),
+            _ => anyhow::bail!(
+                "{} is neither an .ndjson nor a .csv file written by export",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// 回数の数値か、RFC 3339の退出時刻を比べる値にする
+fn parse_value(raw: &str) -> Result<i128, String> {
+    if let Ok(count) = raw.parse::<u64>() {
+        return Ok(i128::from(count));
+    }
+    DateTime::parse_from_rfc3339(raw)
+        .map(|date_time| i128::from(date_time.timestamp()))
+        .map_err(|_| format!("{raw:?} is neither a count nor an RFC 3339 date-time"))
+}
+
+fn parse_uuid(raw: &str) -> Result<PlayerUuid, String> {
+    PlayerUuid::try_from(raw).map_err(|error| error.to_string())
+}
+
+/// NDJSONの一行を読み、値の列の名前とレコードを返す
+fn parse_ndjson_line(line: &str) -> Result<(String, Record), String> {
+    let json: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(line).map_err(|error| error.to_string())?;
+    let player = json
+        .get("player")
+        .and_then(serde_json::Value::as_object)
+        .ok_or("no player object")?;
+    let text = |field: &str| {
+        player
+            .get(field)
+            .and_then(serde_json::Value::as_str)
+            .ok_or_else(|| format!("no player.{field} string"))
+    };
+    let (metric, value) = json
+        .iter()
+        .find(|(key, _)| key.as_str() != "player")
+        .ok_or("no value field")?;
+    let value = match value {
+        serde_json::Value::Number(number) => parse_value(&number.to_string())?,
+        serde_json::Value::String(date_time) => parse_value(date_time)?,
+        _ => return Err(format!("{metric} is neither a number nor a string")),
+    };
+
+    Ok((
+        metric.clone(),
+        Record {
+            uuid: parse_uuid(text("uuid")?)?,
+            name: text("last_known_name")?.to_string(),
+            value,
+        },
+    ))
+}
+
+/// CSVの見出しの行を読み、値の列の名前を返す
+fn parse_csv_header(line: &str) -> Result<String, String> {
+    match split_csv(line)?.as_slice() {
+        [uuid, name, metric] if uuid == "uuid" && name == "last_known_name" => Ok(metric.clone()),
+        _ => Err(format!(
+            "{line:?} is not a uuid,last_known_name,<metric> header"
+        )),
+    }
+}
+
+fn parse_csv_line(line: &str) -> Result<Record, String> {
+    match split_csv(line)?.as_slice() {
+        [uuid, name, value] => Ok(Record {
+            uuid: parse_uuid(uuid)?,
+            name: name.clone(),
+            value: parse_value(value)?,
+        }),
+        fields => Err(format!("{} fields instead of 3", fields.len())),
+    }
+}
+
+/// `path` のレコードを一行ずつ読んで `each` に渡し、値の列の名前を返す。
+///
+/// 読めなかった行、値の列が他と異なる行、同じファイルの前の行と同じUUIDの行は `malformed` に加え、残りの行を読み続ける。
+/// 同じUUIDの行は、最初の行のみを比べる。
+fn read(
+    path: &Path,
+    malformed: &mut Vec<MalformedLine>,
+    mut each: impl FnMut(Record),
+) -> anyhow::Result<Option<String>> {
+    let format = InputFormat::of(path)?;
+    let file = std::fs::File::open(path)
+        .map_err(|error| anyhow::anyhow!("failed to open {}: {error}", path.display()))?;
+    let mut metric = None;
+    let mut first_lines = HashMap::new();
+
+    let reader = BufReader::new(file);
+    // CSVでは、引用符で囲まれた値の中の改行で区切らない
+    let lines: Box<dyn Iterator<Item = std::io::Result<(usize, String)>>> = match format {
+        InputFormat::Csv => Box::new(csv_records(reader)),
+        InputFormat::Ndjson => Box::new(
+            reader
+                .lines()
+                .enumerate()
+                .map(|(index, line)| line.map(|line| (index + 1, line))),
+        ),
+    };
+
+    for line in lines {
+        let (line_number, line) = line?;
+
+        let parsed = match format {
+            InputFormat::Csv if line_number == 1 => match parse_csv_header(&line) {
+                Ok(header) => {
+                    metric = Some(header);
+                    continue;
+                }
+                Err(error) => anyhow::bail!("{}:{line_number}: {error}", path.display()),
+            },
+            InputFormat::Csv => parse_csv_line(&line),
+            InputFormat::Ndjson => parse_ndjson_line(&line).and_then(|(name, record)| {
+                let expected = metric.get_or_insert_with(|| name.clone());
+                if *expected == name {
+                    Ok(record)
+                } else {
+                    Err(format!("{name} instead of {expected}"))
+                }
+            }),
+        };
+        let parsed = parsed.and_then(|record| match first_lines.entry(record.uuid) {
+            Entry::Vacant(entry) => {
+                entry.insert(line_number);
+                Ok(record)
+            }
+            Entry::Occupied(entry) => Err(format!(
+                "{} is already on line {}",
+                record.uuid,
+                entry.get()
+            )),
+        });
+
+        match parsed {
+            Ok(record) => each(record),
+            Err(error) => malformed.push(MalformedLine {
+                file: path.display().to_string(),
+                line: line_number,
+                error,
+            }),
+        }
+    }
+
+    Ok(metric)
+}
+
+/// 二つのファイルをUUIDで突き合わせる。
+///
+/// 小さい方のファイルだけを読み込んでおき、大きい方のファイルは一行ずつ読みながら比べるため、
+/// 必要なメモリは、小さい方のファイルのレコードの数と、同じUUIDの行を見つけるために覚えておく大きい方のファイルのUUIDの数に比例する。値の差の絶対値が `min_delta` 未満の変化は含めない。
+pub fn run(old: &Path, new: &Path, min_delta: u64) -> anyhow::Result<Diff> {
+    let mut malformed_lines = Vec::new();
+    let old_is_smaller = std::fs::metadata(old)?.len() <= std::fs::metadata(new)?.len();
+    let (smaller, larger) = if old_is_smaller {
+        (old, new)
+    } else {
+        (new, old)
+    };
+
+    let mut remaining = HashMap::new();
+    let smaller_metric = read(smaller, &mut malformed_lines, |record| {
+        remaining.insert(record.uuid, record);
+    })?;
+    let smaller_malformed_lines = malformed_lines.len();
+
+    let mut changes = Vec::new();
+    let larger_metric = read(larger, &mut malformed_lines, |record| {
+        let (before, after) = match remaining.remove(&record.uuid) {
+            Some(other) if old_is_smaller => (Some(other), Some(record)),
+            Some(other) => (Some(record), Some(other)),
+            None if old_is_smaller => (None, Some(record)),
+            None => (Some(record), None),
+        };
+        changes.extend(change(before, after, min_delta));
+    })?;
+    for record in remaining.into_values() {
+        let (before, after) = if old_is_smaller {
+            (Some(record), None)
+        } else {
+            (None, Some(record))
+        };
+        changes.extend(change(before, after, min_delta));
+    }
+
+    let metric = match (smaller_metric, larger_metric) {
+        (Some(smaller_metric), Some(larger_metric)) if smaller_metric != larger_metric => {
+            anyhow::bail!(
+                "{} has {smaller_metric} but {} has {larger_metric}",
+                smaller.display(),
+                larger.display()
+            )
+        }
+        (Some(metric), _) | (None, Some(metric)) => metric,
+        (None, None) => String::new(),
+    };
+    changes.sort_by_key(|change| (change.change, change.uuid));
+    // 読んだ順によらず、古い方のファイルの行を先にする
+    if !old_is_smaller {
+        malformed_lines.rotate_left(smaller_malformed_lines);
+    }
+
+    Ok(Diff {
+        metric,
+        changes,
+        malformed_lines,
+    })
+}
+
+fn change(old: Option<Record>, new: Option<Record>, min_delta: u64) -> Option<Change> {
+    let old_value = old.as_ref().map(|record| record.value);
+    let new_value = new.as_ref().map(|record| record.value);
+    let (change, record) = match (old, new) {
+        (Some(old), Some(new)) => {
+            // 値が変わっていないプレイヤーは、閾値によらず含めない
+            if (new.value - old.value).unsigned_abs() < u128::from(min_delta.max(1)) {
+                return None;
+            }
+            (ChangeKind::Changed, new)
+        }
+        (None, Some(new)) => (ChangeKind::Added, new),
+        (Some(old), None) => (ChangeKind::Removed, old),
+        (None, None) => return None,
+    };
+
+    Some(Change {
+        change,
+        uuid: record.uuid,
+        last_known_name: record.name,
+        old: old_value,
+        new: new_value,
+        delta: old_value.zip(new_value).map(|(old, new)| new - old),
+    })
+}
+
+const COLUMNS: [&str; 6] = ["change", "uuid", "last_known_name", "old", "new", "delta"];
+
+fn cells(change: &Change) -> [String; 6] {
+    let number = |value: Option<i128>| value.map(|value| value.to_string()).unwrap_or_default();
+    [
+        change.change.name().to_string(),
+        change.uuid.to_string(),
+        change.last_known_name.clone(),
+        number(change.old),
+        number(change.new),
+        number(change.delta),
+    ]
+}
+
+/// `diff` を `format` で書き出す
+pub fn write(diff: &Diff, format: DiffFormat, writer: &mut impl Write) -> anyhow::Result<()> {
+    match format {
+        DiffFormat::Json => {
+            serde_json::to_writer(&mut *writer, diff)?;
+            writer.write_all(b"\n")?;
+        }
+        DiffFormat::Csv => {
+            writeln!(writer, "{}", COLUMNS.join(","))?;
+            for change in &diff.changes {
+                let row = cells(change);
+                for (index, cell) in row.iter().enumerate() {
+                    if index != 0 {
+                        writer.write_all(b",")?;
+                    }
+                    crate::export::write_csv_field(writer, cell)?;
+                }
+                writer.write_all(b"\n")?;
+            }
+        }
+        DiffFormat::Table => {
+            let rows = diff.changes.iter().map(cells).collect::<Vec<_>>();
+            let mut widths = COLUMNS.map(str::len);
+            for row in &rows {
+                for (width, cell) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(cell.chars().count());
+                }
+            }
+
+            let mut write_row = |row: &[&str]| -> std::io::Result<()> {
+                let line = row
+                    .iter()
+                    .zip(widths)
+                    .map(|(cell, width)| format!("{cell:width$}"))
+                    .collect::<Vec<_>>()
+                    .join("  ");
+                writeln!(writer, "{}", line.trim_end())
+            };
+            write_row(&COLUMNS)?;
+            for row in &rows {
+                write_row(&row.iter().map(String::as_str).collect::<Vec<_>>())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ALICE: &str = "b66cc3f6-a045-42ad-b4b8-320f20caf140";
+    const BOB: &str = "0b6ad70e-c79c-4e4f-9b7c-60a3f2e0bd4a";
+    const CAROL: &str = "4c7a3bd5-6a5e-44b4-a3ab-453f1f0f1a6c";
+    const DAVE: &str = "e2b61d0f-53e5-4b3a-9f1c-2b8e0f5a7d11";
+
+    fn write_temporary(name: &str, content: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "seichi-game-api-diff-test-{}-{name}",
+            std::process::id()
+        ));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn ndjson(records: &[(&str, &str, u64)]) -> String {
+        records
+            .iter()
+            .map(|(uuid, name, break_count)| {
+                format!(
+                    "{{\"player\":{{\"uuid\":\"{uuid}\",\"last_known_name\":\"{name}\"}},\"break_count\":{break_count}}}\n"
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quoted_csv_fields_are_split_back() {
+        let mut line = Vec::new();
+        crate::export::write_csv_field(&mut line, "a,\"b\"").unwrap();
+        let line = format!("{ALICE},{},12", String::from_utf8(line).unwrap());
+
+        assert_eq!(
+            parse_csv_line(&line),
+            Ok(Record {
+                uuid: parse_uuid(ALICE).unwrap(),
+                name: "a,\"b\"".to_string(),
+                value: 12,
+            })
+        );
+        assert!(parse_csv_line(&format!("{ALICE},\"unterminated,12")).is_err());
+        assert_eq!(parse_value("2023-04-01T12:34:56Z"), Ok(1_680_352_496));
+    }
+
+    #[test]
+    fn players_are_matched_by_uuid_and_malformed_lines_are_reported() {
+        let old = write_temporary(
+            "old.ndjson",
+            &ndjson(&[(ALICE, "alice", 100), (BOB, "bob", 50), (CAROL, "carol", 7)]),
+        );
+        let new = write_temporary(
+            "new.ndjson",
+            &format!(
+                "{}not json\n{}",
+                ndjson(&[(ALICE, "alice2", 180), (CAROL, "carol", 9)]),
+                ndjson(&[(DAVE, "dave", 1)])
+            ),
+        );
+
+        let diff = run(&old, &new, 5).unwrap();
+
+        assert_eq!(diff.metric, "break_count");
+        assert_eq!(
+            diff.changes
+                .iter()
+                .map(|change| (
+                    change.change,
+                    change.last_known_name.as_str(),
+                    change.old,
+                    change.new,
+                    change.delta
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (ChangeKind::Added, "dave", None, Some(1), None),
+                (ChangeKind::Removed, "bob", Some(50), None, None),
+                // carol は差が閾値未満なので含めない
+                (
+                    ChangeKind::Changed,
+                    "alice2",
+                    Some(100),
+                    Some(180),
+                    Some(80)
+                ),
+            ]
+        );
+        assert_eq!(
+            diff.malformed_lines
+                .iter()
+                .map(|line| (line.file.clone(), line.line))
+                .collect::<Vec<_>>(),
+            vec![(new.display().to_string(), 3)]
+        );
+
+        // 小さい方のファイルを新しい方としても、結果は変わらない
+        let reversed = run(&new, &old, 5).unwrap();
+        assert_eq!(
+            reversed
+                .changes
+                .iter()
+                .map(|change| (change.change, change.delta))
+                .collect::<Vec<_>>(),
+            vec![
+                (ChangeKind::Added, None),
+                (ChangeKind::Removed, None),
+                (ChangeKind::Changed, Some(-80)),
+            ]
+        );
+
+        let mut table = Vec::new();
+        write(&diff, DiffFormat::Table, &mut table).unwrap();
+        assert_eq!(
+            String::from_utf8(table).unwrap(),
+            format!(
+                "change   uuid                                  last_known_name  old  new  delta\n\
+                 added    {DAVE}  dave                  1\n\
+                 removed  {BOB}  bob              50\n\
+                 changed  {ALICE}  alice2           100  180  80\n"
+            )
+        );
+
+        std::fs::remove_file(old).unwrap();
+        std::fs::remove_file(new).unwrap();
+    }
+
+    #[test]
+    fn duplicate_uuids_within_a_file_are_reported() {
+        let old = write_temporary(
+            "duplicate-old.ndjson",
+            &ndjson(&[(ALICE, "alice", 100), (BOB, "bob", 50), (ALICE, "alice", 1)]),
+        );
+        let new = write_temporary(
+            "duplicate-new.csv",
+            &format!(
+                "uuid,last_known_name,break_count\n{ALICE},alice,180\n{BOB},bob,50\n{BOB},bob,90\n"
+            ),
+        );
+
+        let diff = run(&old, &new, 1).unwrap();
+
+        // 同じUUIDの行は最初の行のみを比べる
+        assert_eq!(
+            diff.changes
+                .iter()
+                .map(|change| (change.change, change.uuid.to_string(), change.delta))
+                .collect::<Vec<_>>(),
+            vec![(ChangeKind::Changed, ALICE.to_string(), Some(80))]
+        );
+        assert_eq!(
+            diff.malformed_lines
+                .iter()
+                .map(|line| (line.file.clone(), line.line, line.error.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    old.display().to_string(),
+                    3,
+                    format!("{ALICE} is already on line 1")
+                ),
+                (
+                    new.display().to_string(),
+                    4,
+                    format!("{BOB} is already on line 3")
+                ),
+            ]
+        );
+
+        std::fs::remove_file(old).unwrap();
+        std::fs::remove_file(new).unwrap();
+    }
+
+    #[test]
+    fn files_of_different_metrics_are_not_compared() {
+        let old = write_temporary(
+            "metric-old.csv",
+            &format!("uuid,last_known_name,break_count\n{ALICE},alice,1\n"),
+        );
+        let new = write_temporary(
+            "metric-new.csv",
+            &format!("uuid,last_known_name,vote_count\n{ALICE},alice,1\n"),
+        );
+
+        assert!(run(&old, &new, 1).is_err());
+
+        std::fs::remove_file(old).unwrap();
+        std::fs::remove_file(new).unwrap();
+    }
+}
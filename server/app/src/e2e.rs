@@ -0,0 +1,822 @@
+//! 実際のサーバーと同じレイヤーを組み立てたgRPCサーバーをプロセス内で起動し、クライアントから呼び出すテスト。
+
+use super::*;
+use domain::app_models::DataSourceError;
+use infra_grpc::buf_generated::gigantic_minecraft::seichi_game_data::v1::{
+    BreakCountsResponse, BuildCountsResponse, LastQuitsResponse, PlayTicksResponse,
+    VoteCountsResponse,
+};
+use proptest::prelude::*;
+use test_fixtures::fault_injection::FaultInjectingDataSource;
+use test_fixtures::{playerdata_rows, FixedDataSource, PlayerdataRow};
+use tonic::codec::ProstCodec;
+use tonic::transport::Channel;
+use tonic::Code;
+
+fn logging_config() -> LoggingConfig {
+    LoggingConfig {
+        filter: None,
+        format: config::LogFormat::default(),
+        file_directory: None,
+        file_rotation: config::LogFileRotation::default(),
+        slow_fetch_threshold_millis: 2000,
+        slow_request_threshold_millis: 3000,
+        slow_acquire_threshold_millis: 500,
+        data_quality_warn_ratio: 0.01,
+        raw_player_names: false,
+        access_log: false,
+        access_log_excluded_paths: Vec::new(),
+    }
+}
+
+/// 名前や値の異なる二人のプレイヤー。一人は一度も退出しておらず、投票もしていない
+fn players() -> Vec<PlayerdataRow> {
+    vec![
+        PlayerdataRow {
+            name: "Notch".to_string(),
+            uuid: "069a79f4-44e9-4726-a5be-fca90e38aaf5".to_string(),
+            lastquit: Some(
+                chrono::DateTime::parse_from_rfc3339("2023-04-01T12:34:56Z")
+                    .unwrap()
+                    .into(),
+            ),
+            totalbreaknum: 100,
+            playtick: 72_000,
+            build_count: 2.0,
+            vote_number: Some(10),
+        },
+        PlayerdataRow {
+            name: "jeb_".to_string(),
+            uuid: "853c80ef-3c37-49fd-aa49-938b674adae6".to_string(),
+            lastquit: None,
+            totalbreaknum: 5,
+            playtick: 20,
+            build_count: 10.0,
+            vote_number: None,
+        },
+    ]
+}
+
+fn served<T: Clone + Send + Sync + 'static>(
+    resource: &'static str,
+    metrics: &Metrics,
+    records: Result<Vec<T>, DataSourceError>,
+) -> Option<Box<dyn VecDataSource<T> + Send + Sync>> {
+    served_from(resource, metrics, FixedDataSource(records))
+}
+
+fn served_from<T: Clone + Send + Sync + 'static>(
+    resource: &'static str,
+    metrics: &Metrics,
+    data_source: impl VecDataSource<T> + Send + Sync + 'static,
+) -> Option<Box<dyn VecDataSource<T> + Send + Sync>> {
+    // 応答を確かめるテストでは、何度失敗させても問い合わせを止めない
+    let breaker = CircuitBreaker::new(
+        "default",
+        0,
+        Duration::from_secs(30),
+        metrics.circuit_breaker.clone(),
+    );
+
+    Some(serving_data_source(
+        resource,
+        metrics,
+        Duration::from_secs(60),
+        (data_source, Some(breaker)),
+    ))
+}
+
+/// 全てのリソースを `rows` から提供するサービス
+fn service(metrics: &Metrics, rows: &[PlayerdataRow]) -> ReadServiceImpl {
+    ReadServiceImpl {
+        last_quit_data_source: served(
+            "last_quits",
+            metrics,
+            Ok(rows.iter().filter_map(PlayerdataRow::last_quit).collect()),
+        ),
+        break_counts_data_source: served(
+            "break_counts",
+            metrics,
+            Ok(rows.iter().map(PlayerdataRow::break_count).collect()),
+        ),
+        build_counts_data_source: served(
+            "build_counts",
+            metrics,
+            Ok(rows.iter().filter_map(PlayerdataRow::build_count).collect()),
+        ),
+        play_ticks_data_source: served(
+            "play_ticks",
+            metrics,
+            Ok(rows.iter().map(PlayerdataRow::play_ticks).collect()),
+        ),
+        vote_counts_data_source: served(
+            "vote_counts",
+            metrics,
+            Ok(rows.iter().filter_map(PlayerdataRow::vote_count).collect()),
+        ),
+    }
+}
+
+/// 取得の状況を `metrics` から読み、一度の失敗で劣化しているとみなすサービス全体の状態
+fn health_from(metrics: &Metrics) -> Arc<ServiceHealth> {
+    Arc::new(ServiceHealth::new(
+        metrics.freshness.clone(),
+        Vec::new(),
+        HealthThresholds {
+            degraded_after_failures: 1,
+            unavailable_ratio: 1.0,
+        },
+        metrics.service_health.clone(),
+    ))
+}
+
+/// 実際のサーバーと同じレイヤーを組み立て、空いているポートで起動するサーバー
+struct Harness {
+    service: ReadServiceImpl,
+    health: Option<Arc<ServiceHealth>>,
+    grpc_web: bool,
+    shutdown: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+/// 起動したサーバー
+struct Running {
+    address: SocketAddr,
+    /// サーバーが止まると完了する
+    server: tokio::task::JoinHandle<()>,
+}
+
+impl Harness {
+    fn new(service: ReadServiceImpl) -> Self {
+        Self {
+            service,
+            health: None,
+            grpc_web: false,
+            shutdown: Box::pin(std::future::pending()),
+        }
+    }
+
+    /// サービス全体の状態を `health` から求める。指定しなければ、取得を記録しないメトリクスから求めるため常に通常どおりとなる
+    fn health(self, health: Arc<ServiceHealth>) -> Self {
+        Self {
+            health: Some(health),
+            ..self
+        }
+    }
+
+    /// gRPC-Webのリクエストも受け付ける
+    fn grpc_web(self) -> Self {
+        Self {
+            grpc_web: true,
+            ..self
+        }
+    }
+
+    /// `shutdown` が完了したら接続を閉じ始める。指定しなければ止めない
+    fn shutdown_on(self, shutdown: impl Future<Output = ()> + Send + 'static) -> Self {
+        Self {
+            shutdown: Box::pin(shutdown),
+            ..self
+        }
+    }
+
+    async fn start(self) -> Running {
+        let metrics = Arc::new(Metrics::new(&ProcessInfo::new(None)).unwrap());
+        let health = self.health.unwrap_or_else(|| health_from(&metrics));
+        let (address, incoming) = bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+        let concurrency_limit = ConcurrencyLimitLayer::new(
+            Semaphore::MAX_PERMITS,
+            <ReadServiceServer<ReadServiceImpl> as NamedService>::NAME,
+            1,
+        );
+        let server = tokio::spawn(async move {
+            serve_grpc(
+                self.service,
+                metrics,
+                health,
+                concurrency_limit,
+                &logging_config(),
+                0,
+                self.grpc_web,
+                incoming,
+                self.shutdown,
+            )
+            .await
+            .unwrap();
+        });
+
+        Running { address, server }
+    }
+}
+
+impl Running {
+    async fn channel(&self) -> Channel {
+        Channel::from_shared(format!("http://{}", self.address))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap()
+    }
+
+    async fn client(&self) -> tonic::client::Grpc<Channel> {
+        tonic::client::Grpc::new(self.channel().await)
+    }
+}
+
+async fn call<Response: prost::Message + Default + 'static>(
+    client: &mut tonic::client::Grpc<Channel>,
+    method: &str,
+    request: tonic::Request<pbjson_types::Empty>,
+) -> Result<tonic::Response<Response>, tonic::Status> {
+    let path = format!(
+        "/{}/{method}",
+        <ReadServiceServer<ReadServiceImpl> as NamedService>::NAME
+    );
+
+    client.ready().await.unwrap();
+    client
+        .unary(
+            request,
+            path.try_into().unwrap(),
+            ProstCodec::<pbjson_types::Empty, Response>::default(),
+        )
+        .await
+}
+
+async fn call_empty<Response: prost::Message + Default + 'static>(
+    client: &mut tonic::client::Grpc<Channel>,
+    method: &str,
+) -> Result<Response, tonic::Status> {
+    call(client, method, tonic::Request::new(pbjson_types::Empty {}))
+        .await
+        .map(tonic::Response::into_inner)
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+proptest! {
+    // 一つのケースごとにサーバーを起動するため、ケースの数を抑える
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    fn every_method_serves_its_records(seed in any::<u64>(), count in 0..20_usize) {
+        let rows = playerdata_rows(seed, count);
+        runtime().block_on(async {
+            let metrics = Metrics::new(&ProcessInfo::new(None)).unwrap();
+            let mut client = Harness::new(service(&metrics, &rows)).start().await.client().await;
+
+            let last_quits: LastQuitsResponse = call_empty(&mut client, "LastQuits").await.unwrap();
+            prop_assert_eq!(
+                last_quits
+                    .results
+                    .iter()
+                    .map(|result| result.rfc_3339_date_time.clone())
+                    .collect::<Vec<_>>(),
+                rows.iter()
+                    .filter_map(PlayerdataRow::last_quit)
+                    .map(|record| domain::models::to_rfc_3339(&record.last_quit))
+                    .collect::<Vec<_>>()
+            );
+
+            let break_counts: BreakCountsResponse =
+                call_empty(&mut client, "BreakCounts").await.unwrap();
+            prop_assert_eq!(
+                break_counts
+                    .results
+                    .iter()
+                    .map(|result| {
+                        let player = result.player.as_ref().unwrap();
+                        (
+                            player.uuid.clone(),
+                            player.last_known_name.clone(),
+                            result.break_count,
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+                rows.iter()
+                    .map(|row| {
+                        let record = row.break_count();
+                        (
+                            record.player.uuid.to_string(),
+                            record.player.last_known_name.to_string(),
+                            record.break_count,
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            );
+
+            let build_counts: BuildCountsResponse =
+                call_empty(&mut client, "BuildCounts").await.unwrap();
+            prop_assert_eq!(build_counts.results.len(), rows.len());
+            let play_ticks: PlayTicksResponse = call_empty(&mut client, "PlayTicks").await.unwrap();
+            prop_assert_eq!(
+                play_ticks
+                    .results
+                    .iter()
+                    .map(|result| result.play_ticks)
+                    .collect::<Vec<_>>(),
+                rows.iter()
+                    .map(|row| row.play_ticks().play_ticks)
+                    .collect::<Vec<_>>()
+            );
+            let vote_counts: VoteCountsResponse = call_empty(&mut client, "VoteCounts").await.unwrap();
+            prop_assert_eq!(
+                vote_counts.results.len(),
+                rows.iter().filter(|row| row.vote_number.is_some()).count()
+            );
+
+            Ok(())
+        })?;
+    }
+}
+
+#[tokio::test]
+async fn the_typed_client_reads_the_served_records_as_models() {
+    let metrics = Metrics::new(&ProcessInfo::new(None)).unwrap();
+    let rows = players();
+    let mut service = service(&metrics, &rows);
+    service.last_quit_data_source = None;
+    service.play_ticks_data_source = served(
+        "play_ticks",
+        &metrics,
+        Err(DataSourceError::Connection(
+            "connection refused".to_string(),
+        )),
+    );
+    let channel = Harness::new(service)
+        .health(health_from(&metrics))
+        .start()
+        .await
+        .channel()
+        .await;
+    let mut client = client::SeichiGameApiClient::new(channel);
+
+    let break_counts = client.break_counts().await.unwrap();
+    assert_eq!(
+        break_counts
+            .iter()
+            .map(|record| (
+                record.player.uuid,
+                record.player.last_known_name.to_string(),
+                record.break_count
+            ))
+            .collect::<Vec<_>>(),
+        rows.iter()
+            .map(|row| {
+                let record = row.break_count();
+                (
+                    record.player.uuid,
+                    record.player.last_known_name.to_string(),
+                    record.break_count,
+                )
+            })
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        client.vote_counts().await.unwrap().len(),
+        rows.iter().filter(|row| row.vote_number.is_some()).count()
+    );
+
+    assert!(matches!(
+        client.last_quits().await,
+        Err(client::ClientError::Disabled(_))
+    ));
+    let error = client.play_ticks().await.unwrap_err();
+    assert!(error.is_retryable(), "{error}");
+}
+
+#[tokio::test]
+async fn grpc_web_requests_are_served_over_http_1_when_enabled() {
+    let metrics = Metrics::new(&ProcessInfo::new(None)).unwrap();
+    let rows = players();
+    let address = Harness::new(service(&metrics, &rows))
+        .grpc_web()
+        .start()
+        .await
+        .address;
+
+    // 空のメッセージ一つだけのフレーム
+    let request = http::Request::post(format!(
+        "http://{address}/{}/BreakCounts",
+        <ReadServiceServer<ReadServiceImpl> as NamedService>::NAME
+    ))
+    .header(http::header::CONTENT_TYPE, "application/grpc-web+proto")
+    .header("x-grpc-web", "1")
+    .body(hyper::Body::from(vec![0_u8; 5]))
+    .unwrap();
+    let response = hyper::Client::new().request(request).await.unwrap();
+
+    assert_eq!(response.version(), http::Version::HTTP_11);
+    assert_eq!(
+        response.headers()[http::header::CONTENT_TYPE],
+        "application/grpc-web+proto"
+    );
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body[0], 0, "the first frame is a message");
+    let length = usize::try_from(u32::from_be_bytes(body[1..5].try_into().unwrap())).unwrap();
+    let message = <BreakCountsResponse as prost::Message>::decode(&body[5..5 + length]).unwrap();
+    assert_eq!(message.results.len(), rows.len());
+
+    let trailers = &body[5 + length..];
+    assert_eq!(trailers[0], 0x80, "the last frame holds the trailers");
+    assert!(String::from_utf8_lossy(&trailers[5..]).contains("grpc-status:0"));
+}
+
+/// wasm32向けにビルドしたクライアントの `client/tests/wasm_smoke.rs` を、Node.js上でこのサーバーに対して動かす
+#[tokio::test]
+#[ignore = "requires Node.js and wasm-pack"]
+async fn the_wasm_client_reads_the_served_records_over_grpc_web() {
+    let metrics = Metrics::new(&ProcessInfo::new(None)).unwrap();
+    let rows = players();
+    let mut service = service(&metrics, &rows);
+    service.last_quit_data_source = None;
+    let address = Harness::new(service).grpc_web().start().await.address;
+    let expected_break_counts = rows
+        .iter()
+        .map(|row| {
+            let record = row.break_count();
+            format!("{}:{}", record.player.uuid, record.break_count)
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    // サーバーは同じランタイムで動き続けるため、テストの完了はブロッキングのスレッドで待つ
+    let status = tokio::task::spawn_blocking(move || {
+        std::process::Command::new("wasm-pack")
+            .args(["test", "--node"])
+            .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/../client"))
+            .args(["--no-default-features", "--features", "wasm"])
+            .env("SEICHI_API_WASM_SMOKE_URL", format!("http://{address}"))
+            .env("SEICHI_API_WASM_SMOKE_BREAK_COUNTS", expected_break_counts)
+            .status()
+    })
+    .await
+    .unwrap()
+    .expect("wasm-pack is installed");
+
+    assert!(status.success(), "wasm-pack test exited with {status}");
+}
+
+#[tokio::test]
+async fn data_source_failures_are_mapped_to_status_codes() {
+    let metrics = Metrics::new(&ProcessInfo::new(None)).unwrap();
+    let mut client = Harness::new(ReadServiceImpl {
+        last_quit_data_source: None,
+        break_counts_data_source: served(
+            "break_counts",
+            &metrics,
+            Err(DataSourceError::Connection(
+                "connection refused".to_string(),
+            )),
+        ),
+        build_counts_data_source: served(
+            "build_counts",
+            &metrics,
+            Err(DataSourceError::Other("syntax error".to_string())),
+        ),
+        play_ticks_data_source: served(
+            "play_ticks",
+            &metrics,
+            Err(DataSourceError::Timeout("lock wait timeout".to_string())),
+        ),
+        vote_counts_data_source: None,
+    })
+    .start()
+    .await
+    .client()
+    .await;
+
+    let code = |result: Result<(), tonic::Status>| result.unwrap_err().code();
+    assert_eq!(
+        code(
+            call_empty::<LastQuitsResponse>(&mut client, "LastQuits")
+                .await
+                .map(drop)
+        ),
+        Code::Unimplemented
+    );
+    assert_eq!(
+        code(
+            call_empty::<BreakCountsResponse>(&mut client, "BreakCounts")
+                .await
+                .map(drop)
+        ),
+        Code::Unavailable
+    );
+    assert_eq!(
+        code(
+            call_empty::<BuildCountsResponse>(&mut client, "BuildCounts")
+                .await
+                .map(drop)
+        ),
+        Code::Unknown
+    );
+    assert_eq!(
+        code(
+            call_empty::<PlayTicksResponse>(&mut client, "PlayTicks")
+                .await
+                .map(drop)
+        ),
+        Code::Unavailable
+    );
+    // 内部のエラーの詳細はクライアントに返さない
+    let status = call_empty::<BuildCountsResponse>(&mut client, "BuildCounts")
+        .await
+        .unwrap_err();
+    assert!(!status.message().contains("syntax error"));
+}
+
+#[tokio::test]
+async fn request_ids_are_echoed_in_the_response_metadata() {
+    let metrics = Metrics::new(&ProcessInfo::new(None)).unwrap();
+    let mut client = Harness::new(service(&metrics, &players()))
+        .start()
+        .await
+        .client()
+        .await;
+
+    let mut request = tonic::Request::new(pbjson_types::Empty {});
+    request
+        .metadata_mut()
+        .insert(request_id::REQUEST_ID_HEADER, "e2e-test.1".parse().unwrap());
+    let response = call::<VoteCountsResponse>(&mut client, "VoteCounts", request)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response
+            .metadata()
+            .get(request_id::REQUEST_ID_HEADER)
+            .unwrap(),
+        "e2e-test.1"
+    );
+
+    let generated = call::<VoteCountsResponse>(
+        &mut client,
+        "VoteCounts",
+        tonic::Request::new(pbjson_types::Empty {}),
+    )
+    .await
+    .unwrap();
+    assert!(generated
+        .metadata()
+        .get(request_id::REQUEST_ID_HEADER)
+        .is_some());
+}
+
+#[tokio::test]
+async fn responses_are_marked_while_the_service_is_degraded() {
+    let metrics = Metrics::new(&ProcessInfo::new(None)).unwrap();
+    let rows = players();
+    let vote_counts = FaultInjectingDataSource::new(FixedDataSource::ok(
+        rows.iter().filter_map(PlayerdataRow::vote_count).collect(),
+    ));
+    let faults = vote_counts.faults();
+    let mut client = Harness::new(ReadServiceImpl {
+        vote_counts_data_source: served_from("vote_counts", &metrics, vote_counts),
+        ..service(&metrics, &rows)
+    })
+    .health(health_from(&metrics))
+    .start()
+    .await
+    .client()
+    .await;
+    let degraded = |response: &tonic::Response<VoteCountsResponse>| {
+        response
+            .metadata()
+            .get(health::SERVICE_DEGRADED_HEADER)
+            .map(|value| value.to_str().unwrap().to_string())
+    };
+    let fresh = call(
+        &mut client,
+        "VoteCounts",
+        tonic::Request::new(pbjson_types::Empty {}),
+    )
+    .await
+    .unwrap();
+    assert_eq!(degraded(&fresh), None);
+
+    faults.fail_next(
+        1,
+        DataSourceError::Connection("connection refused".to_string()),
+    );
+    let stale = call(
+        &mut client,
+        "VoteCounts",
+        tonic::Request::new(pbjson_types::Empty {}),
+    )
+    .await
+    .unwrap();
+    assert_eq!(stale.get_ref(), fresh.get_ref());
+    assert_eq!(degraded(&stale).as_deref(), Some("degraded"));
+
+    let recovered = call(
+        &mut client,
+        "VoteCounts",
+        tonic::Request::new(pbjson_types::Empty {}),
+    )
+    .await
+    .unwrap();
+    assert_eq!(degraded(&recovered), None);
+}
+
+#[tokio::test]
+async fn responses_match_their_snapshots() {
+    let metrics = Metrics::new(&ProcessInfo::new(None)).unwrap();
+    let mut client = Harness::new(service(&metrics, &players()))
+        .start()
+        .await
+        .client()
+        .await;
+
+    insta::assert_debug_snapshot!(
+        "grpc_last_quits",
+        call_empty::<LastQuitsResponse>(&mut client, "LastQuits")
+            .await
+            .unwrap()
+    );
+    insta::assert_debug_snapshot!(
+        "grpc_break_counts",
+        call_empty::<BreakCountsResponse>(&mut client, "BreakCounts")
+            .await
+            .unwrap()
+    );
+    insta::assert_debug_snapshot!(
+        "grpc_build_counts",
+        call_empty::<BuildCountsResponse>(&mut client, "BuildCounts")
+            .await
+            .unwrap()
+    );
+    insta::assert_debug_snapshot!(
+        "grpc_play_ticks",
+        call_empty::<PlayTicksResponse>(&mut client, "PlayTicks")
+            .await
+            .unwrap()
+    );
+    insta::assert_debug_snapshot!(
+        "grpc_vote_counts",
+        call_empty::<VoteCountsResponse>(&mut client, "VoteCounts")
+            .await
+            .unwrap()
+    );
+}
+
+/// エラーの応答のうち、クライアントから見える部分
+#[derive(Serialize)]
+struct StatusSnapshot {
+    method: &'static str,
+    code: String,
+    message: String,
+    request_id: Option<String>,
+}
+
+async fn status_snapshot<Response: prost::Message + Default + 'static>(
+    client: &mut tonic::client::Grpc<Channel>,
+    method: &'static str,
+) -> StatusSnapshot {
+    let status = call_empty::<Response>(client, method).await.unwrap_err();
+
+    StatusSnapshot {
+        method,
+        code: format!("{:?}", status.code()),
+        message: status.message().to_string(),
+        request_id: status
+            .metadata()
+            .get(request_id::REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+    }
+}
+
+#[tokio::test]
+async fn error_statuses_match_their_snapshot() {
+    let metrics = Metrics::new(&ProcessInfo::new(None)).unwrap();
+    let mut client = Harness::new(ReadServiceImpl {
+        last_quit_data_source: None,
+        break_counts_data_source: served(
+            "break_counts",
+            &metrics,
+            Err(DataSourceError::Connection(
+                "connection refused".to_string(),
+            )),
+        ),
+        build_counts_data_source: served(
+            "build_counts",
+            &metrics,
+            Err(DataSourceError::Other("syntax error".to_string())),
+        ),
+        play_ticks_data_source: served(
+            "play_ticks",
+            &metrics,
+            Err(DataSourceError::Timeout("lock wait timeout".to_string())),
+        ),
+        vote_counts_data_source: served(
+            "vote_counts",
+            &metrics,
+            Err(DataSourceError::Decode {
+                column: "vote_number".to_string(),
+                detail: "mismatched types".to_string(),
+            }),
+        ),
+    })
+    .start()
+    .await
+    .client()
+    .await;
+
+    let statuses = vec![
+        status_snapshot::<LastQuitsResponse>(&mut client, "LastQuits").await,
+        status_snapshot::<BreakCountsResponse>(&mut client, "BreakCounts").await,
+        status_snapshot::<BuildCountsResponse>(&mut client, "BuildCounts").await,
+        status_snapshot::<PlayTicksResponse>(&mut client, "PlayTicks").await,
+        status_snapshot::<VoteCountsResponse>(&mut client, "VoteCounts").await,
+    ];
+
+    // リクエストIDは呼び出しごとに生成されるため、スナップショットでは伏せる
+    insta::assert_json_snapshot!("grpc_error_statuses", statuses, {
+        "[].request_id" => "[request_id]",
+    });
+}
+
+#[tokio::test]
+async fn handler_panics_are_answered_with_internal_and_the_server_keeps_serving() {
+    let metrics = Metrics::new(&ProcessInfo::new(None)).unwrap();
+    let rows = players();
+    let panicking = FaultInjectingDataSource::new(FixedDataSource::ok(
+        rows.iter().map(PlayerdataRow::break_count).collect(),
+    ));
+    panicking.faults().panic_next(1);
+    let mut client = Harness::new(ReadServiceImpl {
+        last_quit_data_source: None,
+        break_counts_data_source: served_from("break_counts", &metrics, panicking),
+        build_counts_data_source: None,
+        play_ticks_data_source: served(
+            "play_ticks",
+            &metrics,
+            Ok(rows.iter().map(PlayerdataRow::play_ticks).collect()),
+        ),
+        vote_counts_data_source: None,
+    })
+    .start()
+    .await
+    .client()
+    .await;
+
+    let status = call_empty::<BreakCountsResponse>(&mut client, "BreakCounts")
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Internal);
+    assert_eq!(status.message(), "the request handler panicked");
+
+    // 同じ接続で、他のリソースもパニックしたリソースも引き続き提供する
+    let play_ticks: PlayTicksResponse = call_empty(&mut client, "PlayTicks").await.unwrap();
+    assert_eq!(play_ticks.results.len(), rows.len());
+    let break_counts: BreakCountsResponse = call_empty(&mut client, "BreakCounts").await.unwrap();
+    assert_eq!(break_counts.results.len(), rows.len());
+}
+
+#[tokio::test]
+async fn in_flight_requests_complete_across_a_shutdown() {
+    let metrics = Metrics::new(&ProcessInfo::new(None)).unwrap();
+    let rows = players();
+    let slow = FaultInjectingDataSource::new(FixedDataSource::ok(
+        rows.iter().map(PlayerdataRow::break_count).collect(),
+    ));
+    let faults = slow.faults();
+    faults.delay(Duration::from_millis(300));
+    let service = ReadServiceImpl {
+        last_quit_data_source: None,
+        break_counts_data_source: served_from("break_counts", &metrics, slow),
+        build_counts_data_source: None,
+        play_ticks_data_source: None,
+        vote_counts_data_source: None,
+    };
+    let (signal, signal_received) = oneshot::channel();
+    let running = Harness::new(service)
+        .shutdown_on(async move {
+            signal_received.await.unwrap();
+        })
+        .start()
+        .await;
+    let mut client = running.client().await;
+
+    let request =
+        tokio::spawn(
+            async move { call_empty::<BreakCountsResponse>(&mut client, "BreakCounts").await },
+        );
+    while faults.calls() == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    signal.send(()).unwrap();
+
+    let response = request.await.unwrap().unwrap();
+    assert_eq!(response.results.len(), rows.len());
+    tokio::time::timeout(Duration::from_secs(5), running.server)
+        .await
+        .expect("the server stops once the in-flight request completes")
+        .unwrap();
+}
@@ -0,0 +1,219 @@
+use crate::build_info;
+use config::ErrorReportingConfig;
+use sentry::protocol::{Event, Level};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Record};
+use tracing::{Id, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// 報告に含めてよいspanやイベントのフィールド。
+///
+/// 接続先やプレイヤーの情報が報告に紛れ込まないよう、ここに挙げたもの以外は全て捨てる。
+const REPORTED_FIELDS: &[&str] = &["request_id", "path", "resource", "connection_profile"];
+
+#[derive(Default, Clone)]
+struct ReportedFields {
+    tags: BTreeMap<&'static str, String>,
+    message: Option<String>,
+    error: Option<String>,
+}
+
+impl Visit for ReportedFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.record(field, format!("{value:?}"));
+    }
+}
+
+impl ReportedFields {
+    fn record(&mut self, field: &Field, value: String) {
+        match field.name() {
+            "message" => self.message = Some(value),
+            "error" => self.error = Some(value),
+            name => {
+                if let Some(name) = REPORTED_FIELDS.iter().find(|reported| **reported == name) {
+                    self.tags.insert(name, value);
+                }
+            }
+        }
+    }
+}
+
+/// ERROR のイベントを、それを囲むspanのフィールドと共にSentryへ報告するレイヤー。
+///
+/// 同じリソースについてのエラーは `dedup_window` に一度だけ報告し、
+/// ゲームDBが落ちている間にリクエストごとの報告が溢れないようにする。
+pub struct ErrorReportingLayer {
+    dedup_window: Duration,
+    last_reported: Mutex<HashMap<String, Instant>>,
+}
+
+impl ErrorReportingLayer {
+    fn should_report(&self, dedup_key: &str) -> bool {
+        let mut last_reported = self
+            .last_reported
+            .lock()
+            .expect("Error reporting state is never poisoned");
+        let now = Instant::now();
+
+        match last_reported.get(dedup_key) {
+            Some(reported_at) if now.duration_since(*reported_at) < self.dedup_window => false,
+            _ => {
+                last_reported.insert(dedup_key.to_string(), now);
+                true
+            }
+        }
+    }
+}
+
+impl<S> Layer<S> for ErrorReportingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = ReportedFields::default();
+        attributes.record(&mut fields);
+
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<ReportedFields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+        if *event.metadata().level() != tracing::Level::ERROR {
+            return;
+        }
+
+        // 内側のspanやイベント自身のフィールドを優先する
+        let mut fields = ReportedFields::default();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(span_fields) = span.extensions().get::<ReportedFields>() {
+                    fields.tags.extend(span_fields.tags.clone());
+                }
+            }
+        }
+        event.record(&mut fields);
+
+        let target = event.metadata().target();
+        let dedup_key = match fields.tags.get("resource") {
+            Some(resource) => format!("{target}:{resource}"),
+            None => target.to_string(),
+        };
+        if !self.should_report(&dedup_key) {
+            return;
+        }
+
+        let message = match (&fields.message, &fields.error) {
+            (Some(message), Some(error)) => format!("{message}: {error}"),
+            (Some(message), None) => message.clone(),
+            (None, Some(error)) => error.clone(),
+            (None, None) => target.to_string(),
+        };
+
+        sentry::capture_event(Event {
+            level: Level::Error,
+            logger: Some(target.to_string()),
+            message: Some(message),
+            fingerprint: Cow::Owned(vec![Cow::Owned(dedup_key)]),
+            tags: fields
+                .tags
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+            ..Default::default()
+        });
+    }
+}
+
+/// DSNが設定されていれば、Sentryのクライアントとパニックを報告するフックを設定する。
+///
+/// 返り値のガードが破棄されるときに送信中の報告を送り切るため、プロセスの終了時まで保持しておくこと。
+/// ガードと共に返すレイヤーは、ログの設定に加えること。DSNが無ければ何もせず `None` を返す。
+pub fn initialize(
+    config: &ErrorReportingConfig,
+) -> anyhow::Result<Option<(sentry::ClientInitGuard, ErrorReportingLayer)>> {
+    match &config.dsn {
+        None => Ok(None),
+        Some(dsn) => {
+            // DSNには鍵が含まれるため、解釈できなかった場合もその値はエラーに含めない
+            let dsn = dsn
+                .parse()
+                .map_err(|_| anyhow::anyhow!("ERROR_REPORTING_DSN is not a valid Sentry DSN"))?;
+
+            let guard = sentry::init(sentry::ClientOptions {
+                dsn: Some(dsn),
+                release: Some(format!("{}@{}", build_info::VERSION, build_info::GIT_COMMIT).into()),
+                environment: config.environment.clone().map(Into::into),
+                send_default_pii: false,
+                ..Default::default()
+            });
+
+            let layer = ErrorReportingLayer {
+                dedup_window: Duration::from_secs(config.dedup_window_seconds),
+                last_reported: Mutex::new(HashMap::new()),
+            };
+
+            Ok(Some((guard, layer)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn errors_are_reported_once_per_resource_with_allowed_fields_only() {
+        let layer = ErrorReportingLayer {
+            dedup_window: Duration::from_secs(600),
+            last_reported: Mutex::new(HashMap::new()),
+        };
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        let events = sentry::test::with_captured_events(|| {
+            tracing::subscriber::with_default(subscriber, || {
+                let request = tracing::info_span!(
+                    "request",
+                    path = "/service/BreakCounts",
+                    request_id = "abc",
+                    player = "Notch",
+                );
+                let _entered = request.enter();
+
+                for resource in ["break_counts", "break_counts", "vote_counts"] {
+                    tracing::error!(resource, error = "timed out", "fetch failed");
+                }
+                tracing::warn!(resource = "play_ticks", "not an error");
+            });
+        });
+
+        assert_eq!(events.len(), 2);
+        let event = &events[0];
+        assert_eq!(event.message.as_deref(), Some("fetch failed: timed out"));
+        assert_eq!(event.tags["resource"], "break_counts");
+        assert_eq!(event.tags["request_id"], "abc");
+        assert_eq!(event.tags["path"], "/service/BreakCounts");
+        assert!(!event.tags.contains_key("player"));
+        assert_eq!(events[1].tags["resource"], "vote_counts");
+    }
+}
@@ -0,0 +1,727 @@
+use crate::cli::{ExportFormat, Resource, ResourceSelection};
+use arrow_array::{ArrayRef, RecordBatch, StringArray, TimestampSecondArray, UInt64Array};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use chrono::{DateTime, Utc};
+use domain::app_models::VecDataSource;
+use domain::models::{
+    Player, PlayerBreakCount, PlayerBuildCount, PlayerLastQuit, PlayerPlayTicks, PlayerVoteCount,
+    Rfc3339Seconds,
+};
+use infra_grpc::read_service::ReadServiceImpl;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use serde::Serialize;
+use std::io::{BufWriter, Write};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::Arc;
+
+/// 一つのリソースを書き出した結果
+#[derive(Serialize, Debug)]
+pub struct ExportedFile {
+    pub resource: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rows: Option<usize>,
+    /// 書き出せなかった理由
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 選んだ全てのリソースを書き出した結果
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub ok: bool,
+    pub files: Vec<ExportedFile>,
+}
+
+/// 書き出す形式と、形式ごとの設定
+#[derive(Debug, Clone, Copy)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// Parquetの一つの行グループに入れる行数の上限
+    pub parquet_row_group_size: NonZeroUsize,
+}
+
+/// CSVやParquetで、プレイヤーのUUIDと名前の列に続けて書き出す値
+trait ExportedRecord: Sized {
+    /// 値の列の名前。JSONでのフィールド名と揃える
+    const VALUE_COLUMN: &'static str;
+
+    /// Parquetでの値の列の型
+    fn value_type() -> DataType;
+
+    fn player(&self) -> &Player;
+
+    fn write_value(&self, writer: &mut dyn Write) -> std::io::Result<()>;
+
+    /// `records` の値を並べたParquetの列
+    fn value_array(records: &[Self]) -> ArrayRef;
+}
+
+impl ExportedRecord for PlayerLastQuit {
+    const VALUE_COLUMN: &'static str = "rfc_3339_date_time";
+
+    fn value_type() -> DataType {
+        DataType::Timestamp(TimeUnit::Second, Some("UTC".into()))
+    }
+
+    fn player(&self) -> &Player {
+        &self.player
+    }
+
+    fn write_value(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        write!(writer, "{}", Rfc3339Seconds(&self.last_quit))
+    }
+
+    fn value_array(records: &[Self]) -> ArrayRef {
+        Arc::new(
+            TimestampSecondArray::from_iter_values(
+                records.iter().map(|record| record.last_quit.timestamp()),
+            )
+            .with_timezone("UTC".to_string()),
+        )
+    }
+}
+
+impl ExportedRecord for PlayerBreakCount {
+    const VALUE_COLUMN: &'static str = "break_count";
+
+    fn value_type() -> DataType {
+        DataType::UInt64
+    }
+
+    fn player(&self) -> &Player {
+        &self.player
+    }
+
+    fn write_value(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        write!(writer, "{}", self.break_count)
+    }
+
+    fn value_array(records: &[Self]) -> ArrayRef {
+        Arc::new(UInt64Array::from_iter_values(
+            records.iter().map(|record| record.break_count),
+        ))
+    }
+}
+
+impl ExportedRecord for PlayerBuildCount {
+    const VALUE_COLUMN: &'static str = "build_count";
+
+    fn value_type() -> DataType {
+        DataType::UInt64
+    }
+
+    fn player(&self) -> &Player {
+        &self.player
+    }
+
+    fn write_value(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        write!(writer, "{}", self.build_count)
+    }
+
+    fn value_array(records: &[Self]) -> ArrayRef {
+        Arc::new(UInt64Array::from_iter_values(
+            records.iter().map(|record| record.build_count),
+        ))
+    }
+}
+
+impl ExportedRecord for PlayerPlayTicks {
+    const VALUE_COLUMN: &'static str = "play_ticks";
+
+    fn value_type() -> DataType {
+        DataType::UInt64
+    }
+
+    fn player(&self) -> &Player {
+        &self.player
+    }
+
+    fn write_value(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        write!(writer, "{}", self.play_ticks)
+    }
+
+    fn value_array(records: &[Self]) -> ArrayRef {
+        Arc::new(UInt64Array::from_iter_values(
+            records.iter().map(|record| record.play_ticks),
+        ))
+    }
+}
+
+impl ExportedRecord for PlayerVoteCount {
+    const VALUE_COLUMN: &'static str = "vote_count";
+
+    fn value_type() -> DataType {
+        DataType::UInt64
+    }
+
+    fn player(&self) -> &Player {
+        &self.player
+    }
+
+    fn write_value(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        write!(writer, "{}", self.vote_count)
+    }
+
+    fn value_array(records: &[Self]) -> ArrayRef {
+        Arc::new(UInt64Array::from_iter_values(
+            records.iter().map(|record| record.vote_count),
+        ))
+    }
+}
+
+/// 区切りや引用符、改行を含む値だけを引用符で囲む (RFC 4180)
+pub fn write_csv_field(writer: &mut impl Write, value: &str) -> std::io::Result<()> {
+    if value.contains([',', '"', '\n', '\r']) {
+        write!(writer, "\"{}\"", value.replace('"', "\"\""))
+    } else {
+        writer.write_all(value.as_bytes())
+    }
+}
+
+/// `records` を `options` の形式で一件ずつ `writer` に書き出す。書き出した全体を一度に持つことはない
+fn write_records<T: Serialize + ExportedRecord>(
+    records: &[T],
+    options: ExportOptions,
+    writer: &mut impl Write,
+) -> anyhow::Result<()> {
+    match options.format {
+        ExportFormat::Json => {
+            serde_json::to_writer(&mut *writer, records)?;
+            writer.write_all(b"\n")?;
+        }
+        ExportFormat::Ndjson => {
+            for record in records {
+                serde_json::to_writer(&mut *writer, record)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        ExportFormat::Csv => {
+            writeln!(writer, "uuid,last_known_name,{}", T::VALUE_COLUMN)?;
+            for record in records {
+                let player = record.player();
+                write!(writer, "{},", player.uuid)?;
+                write_csv_field(writer, player.last_known_name.as_str())?;
+                writer.write_all(b",")?;
+                record.write_value(writer)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        ExportFormat::Parquet => {
+            write_parquet(records, options.parquet_row_group_size.get(), writer)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// `records` を、UUIDと名前を文字列、値をその型の列とするParquetのファイルとして書き出す。
+///
+/// 一度に列に変換するのは一つの行グループの分だけとする。
+fn write_parquet<T: ExportedRecord>(
+    records: &[T],
+    row_group_size: usize,
+    writer: &mut impl Write,
+) -> anyhow::Result<()> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("uuid", DataType::Utf8, false),
+        Field::new("last_known_name", DataType::Utf8, false),
+        Field::new(T::VALUE_COLUMN, T::value_type(), false),
+    ]));
+    let properties = WriterProperties::builder()
+        .set_max_row_group_size(row_group_size)
+        .build();
+    let mut writer = ArrowWriter::try_new(writer, schema.clone(), Some(properties))?;
+
+    for chunk in records.chunks(row_group_size) {
+        let uuids: ArrayRef = Arc::new(StringArray::from_iter_values(
+            chunk.iter().map(|record| record.player().uuid.to_string()),
+        ));
+        let names: ArrayRef = Arc::new(StringArray::from_iter_values(
+            chunk
+                .iter()
+                .map(|record| record.player().last_known_name.as_str()),
+        ));
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![uuids, names, T::value_array(chunk)])?;
+        writer.write(&batch)?;
+    }
+    writer.close()?;
+
+    Ok(())
+}
+
+const fn extension(format: ExportFormat) -> &'static str {
+    match format {
+        ExportFormat::Json => "json",
+        ExportFormat::Ndjson => "ndjson",
+        ExportFormat::Csv => "csv",
+        ExportFormat::Parquet => "parquet",
+    }
+}
+
+/// `resource` を取得した時刻を名前に含むファイルへ書き出し、そのパスと行数を返す。
+///
+/// 途中で失敗したファイルを完全なものと取り違えないよう、別の名前で書き終えてから名前を変える。
+async fn export_resource<T: Serialize + ExportedRecord>(
+    data_source: Option<&(dyn VecDataSource<T> + Send + Sync)>,
+    resource: Resource,
+    options: ExportOptions,
+    directory: &Path,
+) -> anyhow::Result<(String, usize)> {
+    let data_source = data_source.ok_or_else(|| {
+        anyhow::anyhow!(
+            "{} is disabled by RESOURCE_{}_ENABLED",
+            resource.name(),
+            resource.name().to_uppercase()
+        )
+    })?;
+    let fetched_at: DateTime<Utc> = Utc::now();
+    let records = data_source.fetch().await?;
+
+    let file_name = format!(
+        "{}-{}.{}",
+        resource.name(),
+        fetched_at.format("%Y%m%dT%H%M%SZ"),
+        extension(options.format)
+    );
+    let path = directory.join(&file_name);
+    let partial_path = directory.join(format!("{file_name}.partial"));
+    let write = || -> anyhow::Result<()> {
+        let mut writer = BufWriter::new(std::fs::File::create(&partial_path)?);
+        write_records(&records, options, &mut writer)?;
+        writer.into_inner()?.sync_all()?;
+        std::fs::rename(&partial_path, &path)?;
+        Ok(())
+    };
+    if let Err(error) = write() {
+        let _ = std::fs::remove_file(&partial_path);
+        return Err(error);
+    }
+
+    Ok((path.display().to_string(), records.len()))
+}
+
+fn is_enabled(service: &ReadServiceImpl, resource: Resource) -> bool {
+    match resource {
+        Resource::LastQuits => service.last_quit_data_source.is_some(),
+        Resource::BreakCounts => service.break_counts_data_source.is_some(),
+        Resource::BuildCounts => service.build_counts_data_source.is_some(),
+        Resource::PlayTicks => service.play_ticks_data_source.is_some(),
+        Resource::VoteCounts => service.vote_counts_data_source.is_some(),
+    }
+}
+
+/// 指定されたリソースを、重複を除いて指定された順に並べる。`all` は有効なリソースのみとする
+fn selected(service: &ReadServiceImpl, selection: &[ResourceSelection]) -> Vec<Resource> {
+    let mut resources = Vec::new();
+    for selection in selection {
+        let chosen = match selection {
+            ResourceSelection::All => Resource::ALL
+                .into_iter()
+                .filter(|resource| is_enabled(service, *resource))
+                .collect(),
+            ResourceSelection::Only(resource) => vec![*resource],
+        };
+        for resource in chosen {
+            if !resources.contains(&resource) {
+                resources.push(resource);
+            }
+        }
+    }
+
+    resources
+}
+
+/// 選んだリソースを `service` から一度ずつ取得し、`directory` にリソースごとに一つのファイルとして書き出す。
+///
+/// 一つのリソースで失敗しても、残りのリソースは書き出す。
+pub async fn run(
+    service: &ReadServiceImpl,
+    selection: &[ResourceSelection],
+    options: ExportOptions,
+    directory: &Path,
+) -> Report {
+    if let Err(error) = std::fs::create_dir_all(directory) {
+        return Report {
+            ok: false,
+            files: vec![ExportedFile {
+                resource: "all",
+                path: None,
+                rows: None,
+                error: Some(format!("failed to create {}: {error}", directory.display())),
+            }],
+        };
+    }
+
+    let mut files = Vec::new();
+    for resource in selected(service, selection) {
+        let result = match resource {
+            Resource::LastQuits => {
+                export_resource(
+                    service.last_quit_data_source.as_deref(),
+                    resource,
+                    options,
+                    directory,
+                )
+                .await
+            }
+            Resource::BreakCounts => {
+                export_resource(
+                    service.break_counts_data_source.as_deref(),
+                    resource,
+                    options,
+                    directory,
+                )
+                .await
+            }
+            Resource::BuildCounts => {
+                export_resource(
+                    service.build_counts_data_source.as_deref(),
+                    resource,
+                    options,
+                    directory,
+                )
+                .await
+            }
+            Resource::PlayTicks => {
+                export_resource(
+                    service.play_ticks_data_source.as_deref(),
+                    resource,
+                    options,
+                    directory,
+                )
+                .await
+            }
+            Resource::VoteCounts => {
+                export_resource(
+                    service.vote_counts_data_source.as_deref(),
+                    resource,
+                    options,
+                    directory,
+                )
+                .await
+            }
+        };
+
+        files.push(match result {
+            Ok((path, rows)) => ExportedFile {
+                resource: resource.name(),
+                path: Some(path),
+                rows: Some(rows),
+                error: None,
+            },
+            Err(error) => ExportedFile {
+                resource: resource.name(),
+                path: None,
+                rows: None,
+                error: Some(format!("{error:#}")),
+            },
+        });
+    }
+
+    Report {
+        ok: files.iter().all(|file| file.error.is_none()),
+        files,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow_array::Array;
+    use chrono::TimeZone;
+    use domain::app_models::DataSourceError;
+    use domain::models::{NameValidation, PlayerName, PlayerUuid};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use test_fixtures::{FixedDataSource, PlayerdataRow};
+
+    /// 名前と値の異なる10人のプレイヤー。3人に1人は一度も退出しておらず、投票もしていない
+    fn players() -> Vec<PlayerdataRow> {
+        (0..10_i64)
+            .map(|index| {
+                let active = index % 3 != 0;
+                PlayerdataRow {
+                    name: format!("player_{index}"),
+                    uuid: format!("{:032x}", index + 1),
+                    lastquit: active
+                        .then(|| Utc.timestamp_opt(1_680_352_496 + index * 3600, 0).unwrap()),
+                    totalbreaknum: index * 1000,
+                    playtick: index * 72_000,
+                    build_count: index as f64 * 1.5,
+                    vote_number: active.then(|| i32::try_from(index).unwrap()),
+                }
+            })
+            .collect()
+    }
+
+    fn break_count(name: &str, break_count: u64) -> PlayerBreakCount {
+        PlayerBreakCount {
+            player: Player {
+                uuid: PlayerUuid::try_from("b66cc3f6-a045-42ad-b4b8-320f20caf140").unwrap(),
+                last_known_name: PlayerName::new(name, NameValidation::Lenient).unwrap(),
+            },
+            break_count,
+        }
+    }
+
+    fn options(format: ExportFormat) -> ExportOptions {
+        ExportOptions {
+            format,
+            parquet_row_group_size: NonZeroUsize::new(4).unwrap(),
+        }
+    }
+
+    fn written(records: &[PlayerBreakCount], format: ExportFormat) -> String {
+        let mut buffer = Vec::new();
+        write_records(records, options(format), &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn records_are_written_in_each_format() {
+        let records = [break_count("unchama", 12), break_count("a,\"b\"", 0)];
+
+        assert_eq!(
+            written(&records, ExportFormat::Csv),
+            "uuid,last_known_name,break_count\n\
+             b66cc3f6-a045-42ad-b4b8-320f20caf140,unchama,12\n\
+             b66cc3f6-a045-42ad-b4b8-320f20caf140,\"a,\"\"b\"\"\",0\n"
+        );
+
+        let ndjson = written(&records, ExportFormat::Ndjson);
+        let lines = ndjson.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(lines[0]).unwrap(),
+            serde_json::json!({
+                "player": {
+                    "uuid": "b66cc3f6-a045-42ad-b4b8-320f20caf140",
+                    "last_known_name": "unchama",
+                },
+                "break_count": 12,
+            })
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&written(&records, ExportFormat::Json)).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn csv_files_are_served_back_by_the_csv_data_source() {
+        use infra_repository_impl::csv_data_source::{CsvDataSource, CsvRecord};
+
+        assert_eq!(
+            [
+                <PlayerLastQuit as ExportedRecord>::VALUE_COLUMN,
+                <PlayerBreakCount as ExportedRecord>::VALUE_COLUMN,
+                <PlayerBuildCount as ExportedRecord>::VALUE_COLUMN,
+                <PlayerPlayTicks as ExportedRecord>::VALUE_COLUMN,
+                <PlayerVoteCount as ExportedRecord>::VALUE_COLUMN,
+            ],
+            [
+                <PlayerLastQuit as CsvRecord>::VALUE_COLUMN,
+                <PlayerBreakCount as CsvRecord>::VALUE_COLUMN,
+                <PlayerBuildCount as CsvRecord>::VALUE_COLUMN,
+                <PlayerPlayTicks as CsvRecord>::VALUE_COLUMN,
+                <PlayerVoteCount as CsvRecord>::VALUE_COLUMN,
+            ]
+        );
+
+        let mut break_counts = players()
+            .iter()
+            .map(PlayerdataRow::break_count)
+            .collect::<Vec<_>>();
+        // 引用符で囲んで書き出す、改行や区切りを含む名前も読み戻せる
+        break_counts.push(PlayerBreakCount {
+            player: Player {
+                uuid: PlayerUuid::try_from("069a79f444e94726a5befca90e38aaf5").unwrap(),
+                last_known_name: PlayerName::new(
+                    "line\nbreak, \"quoted\"\r\n",
+                    NameValidation::Lenient,
+                )
+                .unwrap(),
+            },
+            break_count: 12,
+        });
+        let directory = std::env::temp_dir().join(format!(
+            "seichi-game-api-export-csv-test-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("break_counts.csv"),
+            written(&break_counts, ExportFormat::Csv),
+        )
+        .unwrap();
+
+        let read = VecDataSource::<PlayerBreakCount>::fetch(&CsvDataSource::new(&directory, true))
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let key = |record: &PlayerBreakCount| {
+            (
+                record.player.uuid,
+                record.player.last_known_name.to_string(),
+                record.break_count,
+            )
+        };
+        assert_eq!(
+            read.iter().map(key).collect::<Vec<_>>(),
+            break_counts.iter().map(key).collect::<Vec<_>>()
+        );
+    }
+
+    /// Parquetに書き出したものを読み戻し、全ての行グループのバッチと行グループの数を返す
+    fn parquet_round_trip<T: Serialize + ExportedRecord>(
+        records: &[T],
+    ) -> (Vec<RecordBatch>, usize) {
+        let mut buffer = Vec::new();
+        write_records(records, options(ExportFormat::Parquet), &mut buffer).unwrap();
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(buffer)).unwrap();
+        let row_groups = builder.metadata().num_row_groups();
+        let batches = builder
+            .build()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        (batches, row_groups)
+    }
+
+    fn column<'a, A: 'static>(batch: &'a RecordBatch, name: &str) -> &'a A {
+        batch
+            .column(batch.schema().index_of(name).unwrap())
+            .as_any()
+            .downcast_ref::<A>()
+            .unwrap()
+    }
+
+    #[test]
+    fn parquet_files_read_back_into_the_source_records() {
+        let rows = players();
+        let break_counts = rows
+            .iter()
+            .map(PlayerdataRow::break_count)
+            .collect::<Vec<_>>();
+
+        let (batches, row_groups) = parquet_round_trip(&break_counts);
+        assert_eq!(row_groups, 3);
+        let mut read = Vec::new();
+        for batch in &batches {
+            assert_eq!(
+                batch
+                    .schema()
+                    .field_with_name("break_count")
+                    .unwrap()
+                    .data_type(),
+                &DataType::UInt64
+            );
+            let uuids = column::<StringArray>(batch, "uuid");
+            let names = column::<StringArray>(batch, "last_known_name");
+            let counts = column::<UInt64Array>(batch, "break_count");
+            for index in 0..batch.num_rows() {
+                read.push((
+                    uuids.value(index).to_string(),
+                    names.value(index).to_string(),
+                    counts.value(index),
+                ));
+            }
+        }
+        assert_eq!(
+            read,
+            break_counts
+                .iter()
+                .map(|record| (
+                    record.player.uuid.to_string(),
+                    record.player.last_known_name.to_string(),
+                    record.break_count
+                ))
+                .collect::<Vec<_>>()
+        );
+
+        let last_quits = rows
+            .iter()
+            .filter_map(PlayerdataRow::last_quit)
+            .collect::<Vec<_>>();
+        let (batches, _) = parquet_round_trip(&last_quits);
+        assert_eq!(
+            batches[0]
+                .schema()
+                .field_with_name("rfc_3339_date_time")
+                .unwrap()
+                .data_type(),
+            &DataType::Timestamp(TimeUnit::Second, Some("UTC".into()))
+        );
+        assert_eq!(
+            batches
+                .iter()
+                .flat_map(|batch| {
+                    column::<TimestampSecondArray>(batch, "rfc_3339_date_time")
+                        .values()
+                        .to_vec()
+                })
+                .collect::<Vec<_>>(),
+            last_quits
+                .iter()
+                .map(|record| record.last_quit.timestamp())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn each_enabled_resource_is_written_to_its_own_file_and_failures_are_reported() {
+        let rows = players();
+        let service = ReadServiceImpl {
+            last_quit_data_source: None,
+            break_counts_data_source: Some(Box::new(FixedDataSource::ok(
+                rows.iter().map(PlayerdataRow::break_count).collect(),
+            ))),
+            build_counts_data_source: None,
+            play_ticks_data_source: None,
+            vote_counts_data_source: Some(Box::new(FixedDataSource::<PlayerVoteCount>(Err(
+                DataSourceError::Connection("connection refused".to_string()),
+            )))),
+        };
+        let directory = std::env::temp_dir().join(format!(
+            "seichi-game-api-export-test-{}",
+            std::process::id()
+        ));
+
+        let report = run(
+            &service,
+            &[
+                ResourceSelection::Only(Resource::BreakCounts),
+                ResourceSelection::All,
+            ],
+            options(ExportFormat::Ndjson),
+            &directory,
+        )
+        .await;
+
+        assert!(!report.ok);
+        assert_eq!(
+            report
+                .files
+                .iter()
+                .map(|file| (file.resource, file.rows, file.error.is_some()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("break_counts", Some(10), false),
+                ("vote_counts", None, true)
+            ]
+        );
+        let path = report.files[0].path.as_ref().unwrap();
+        assert!(path.ends_with(".ndjson"), "{path}");
+        assert_eq!(std::fs::read_to_string(path).unwrap().lines().count(), 10);
+        // 書き出せなかったリソースのファイルは残さない
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,384 @@
+use chrono::{DateTime, Utc};
+use domain::app_models::{ActivePlayersDataSource, AggregateDataSource, DataSourceError};
+use domain::models::{PlayerBreakCount, PlayerPlayTicks, PlayerVoteCount};
+use prometheus::{Gauge, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// ゲーム全体の集計値を求められるデータソース
+pub trait StatisticsSource:
+    AggregateDataSource<PlayerBreakCount>
+    + AggregateDataSource<PlayerPlayTicks>
+    + AggregateDataSource<PlayerVoteCount>
+    + ActivePlayersDataSource
+    + Send
+    + Sync
+{
+}
+
+impl<T> StatisticsSource for T where
+    T: AggregateDataSource<PlayerBreakCount>
+        + AggregateDataSource<PlayerPlayTicks>
+        + AggregateDataSource<PlayerVoteCount>
+        + ActivePlayersDataSource
+        + Send
+        + Sync
+{
+}
+
+/// `/metrics` で公開できるゲーム全体の集計値
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameStatistic {
+    TotalBreakCount,
+    TotalPlayTicks,
+    TotalVoteCount,
+    PlayersTotal,
+    ActivePlayers,
+}
+
+impl GameStatistic {
+    /// 設定での名前 (`OpsConfig::GAME_STATISTICS` のいずれか) から求める
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "total_break_count" => Some(Self::TotalBreakCount),
+            "total_play_ticks" => Some(Self::TotalPlayTicks),
+            "total_vote_count" => Some(Self::TotalVoteCount),
+            "players_total" => Some(Self::PlayersTotal),
+            "active_players" => Some(Self::ActivePlayers),
+            _ => None,
+        }
+    }
+}
+
+/// 公開する集計値のゲージ。公開しないものは作らず、レジストリにも登録しない
+struct Gauges {
+    total_break_count: Option<Gauge>,
+    total_play_ticks: Option<Gauge>,
+    total_vote_count: Option<Gauge>,
+    players_total: Option<IntGauge>,
+    active_players: Option<IntGaugeVec>,
+}
+
+/// ゲーム全体の集計値をゲージとして公開する。
+///
+/// 集計は `/metrics` が読まれたときに、前回の集計から `interval` 以上経っていれば行う。
+/// 集計に失敗した場合はゲージを前回の値のまま残す。
+/// プレイヤーごとの値はラベルの数が際限なく増えるため公開しない。
+pub struct GameStatistics {
+    source: Arc<dyn StatisticsSource>,
+    gauges: Gauges,
+    active_windows_days: Vec<u64>,
+    interval: Duration,
+    /// 集計した結果ごとの回数。ラベルは `succeeded` か `failed` のみとする
+    refreshes: IntCounterVec,
+    /// 直近に集計した時刻。集計している間は、同時に読まれた `/metrics` を待たせて一度だけ集計する
+    refreshed_at: Mutex<Option<Instant>>,
+}
+
+impl GameStatistics {
+    /// `statistics` のゲージを作って `registry` に登録する。`statistics` が空なら何も登録せず `None` を返す
+    pub fn register(
+        source: Arc<dyn StatisticsSource>,
+        statistics: &[GameStatistic],
+        active_windows_days: Vec<u64>,
+        interval: Duration,
+        registry: &Registry,
+    ) -> prometheus::Result<Option<Self>> {
+        if statistics.is_empty() {
+            return Ok(None);
+        }
+
+        let enabled = |statistic| statistics.contains(&statistic);
+        let gauge = |statistic, name: &str, help: &str| -> prometheus::Result<Option<Gauge>> {
+            if !enabled(statistic) {
+                return Ok(None);
+            }
+            let gauge = Gauge::new(name, help)?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(Some(gauge))
+        };
+
+        let total_break_count = gauge(
+            GameStatistic::TotalBreakCount,
+            "seichi_total_break_count",
+            "Sum of the break counts of all players",
+        )?;
+        let total_play_ticks = gauge(
+            GameStatistic::TotalPlayTicks,
+            "seichi_total_play_ticks",
+            "Sum of the play ticks of all players",
+        )?;
+        let total_vote_count = gauge(
+            GameStatistic::TotalVoteCount,
+            "seichi_total_vote_count",
+            "Sum of the vote counts of all players",
+        )?;
+        let players_total = if enabled(GameStatistic::PlayersTotal) {
+            let gauge = IntGauge::new("seichi_players_total", "Number of players")?;
+            registry.register(Box::new(gauge.clone()))?;
+            Some(gauge)
+        } else {
+            None
+        };
+        let active_players = if enabled(GameStatistic::ActivePlayers) {
+            let gauge = IntGaugeVec::new(
+                Opts::new(
+                    "seichi_active_players",
+                    "Number of players who last quit within the window",
+                ),
+                &["window"],
+            )?;
+            registry.register(Box::new(gauge.clone()))?;
+            Some(gauge)
+        } else {
+            None
+        };
+
+        let refreshes = IntCounterVec::new(
+            Opts::new(
+                "seichi_game_api_game_statistics_refreshes_total",
+                "Number of refreshes of the game statistics by outcome",
+            ),
+            &["outcome"],
+        )?;
+        registry.register(Box::new(refreshes.clone()))?;
+
+        Ok(Some(Self {
+            source,
+            gauges: Gauges {
+                total_break_count,
+                total_play_ticks,
+                total_vote_count,
+                players_total,
+                active_players,
+            },
+            active_windows_days,
+            interval,
+            refreshes,
+            refreshed_at: Mutex::new(None),
+        }))
+    }
+
+    /// 前回の集計から `interval` 以上経っていれば集計し直す
+    pub async fn refresh_if_due(&self) {
+        let mut refreshed_at = self.refreshed_at.lock().await;
+        if refreshed_at.map_or(false, |at| at.elapsed() < self.interval) {
+            return;
+        }
+
+        match self.refresh(Utc::now()).await {
+            Ok(()) => self.refreshes.with_label_values(&["succeeded"]).inc(),
+            Err(error) => {
+                tracing::warn!("failed to refresh the game statistics: {error}");
+                self.refreshes.with_label_values(&["failed"]).inc();
+            }
+        }
+        // 失敗しても、次に読まれるたびにゲームDBへ問い合わせ直すことはしない
+        *refreshed_at = Some(Instant::now());
+    }
+
+    async fn refresh(&self, now: DateTime<Utc>) -> Result<(), DataSourceError> {
+        let source = &*self.source;
+        let gauges = &self.gauges;
+
+        if gauges.total_break_count.is_some() || gauges.players_total.is_some() {
+            let summary = AggregateDataSource::<PlayerBreakCount>::summarize(source).await?;
+            if let Some(gauge) = &gauges.total_break_count {
+                #[allow(clippy::cast_precision_loss)]
+                let sum = summary.sum as f64;
+                gauge.set(sum);
+            }
+            if let Some(gauge) = &gauges.players_total {
+                gauge.set(i64::try_from(summary.count).unwrap_or(i64::MAX));
+            }
+        }
+        if let Some(gauge) = &gauges.total_play_ticks {
+            let summary = AggregateDataSource::<PlayerPlayTicks>::summarize(source).await?;
+            #[allow(clippy::cast_precision_loss)]
+            let sum = summary.sum as f64;
+            gauge.set(sum);
+        }
+        if let Some(gauge) = &gauges.total_vote_count {
+            let summary = AggregateDataSource::<PlayerVoteCount>::summarize(source).await?;
+            #[allow(clippy::cast_precision_loss)]
+            let sum = summary.sum as f64;
+            gauge.set(sum);
+        }
+        if let Some(gauge) = &gauges.active_players {
+            for &days in &self.active_windows_days {
+                let since = i64::try_from(days)
+                    .ok()
+                    .and_then(chrono::Duration::try_days)
+                    .and_then(|window| now.checked_sub_signed(window))
+                    .unwrap_or(DateTime::<Utc>::MIN_UTC);
+                let players = source.count_active_since(since).await?;
+                gauge
+                    .with_label_values(&[&format!("{days}d")])
+                    .set(i64::try_from(players).unwrap_or(i64::MAX));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_trait::async_trait;
+    use domain::summary::CounterSummary;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+    #[derive(Default)]
+    struct FakeSource {
+        queries: AtomicU64,
+        failing: AtomicBool,
+    }
+
+    impl FakeSource {
+        fn summary(&self, sum: u128) -> Result<CounterSummary, DataSourceError> {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(DataSourceError::Connection(
+                    "connection refused".to_string(),
+                ));
+            }
+            Ok(CounterSummary {
+                count: 3,
+                sum,
+                max: 0,
+            })
+        }
+    }
+
+    #[async_trait]
+    impl AggregateDataSource<PlayerBreakCount> for FakeSource {
+        async fn summarize(&self) -> Result<CounterSummary, DataSourceError> {
+            self.summary(1_000_000)
+        }
+    }
+
+    #[async_trait]
+    impl AggregateDataSource<PlayerPlayTicks> for FakeSource {
+        async fn summarize(&self) -> Result<CounterSummary, DataSourceError> {
+            self.summary(72_000)
+        }
+    }
+
+    #[async_trait]
+    impl AggregateDataSource<PlayerVoteCount> for FakeSource {
+        async fn summarize(&self) -> Result<CounterSummary, DataSourceError> {
+            self.summary(42)
+        }
+    }
+
+    #[async_trait]
+    impl ActivePlayersDataSource for FakeSource {
+        async fn count_active_since(&self, since: DateTime<Utc>) -> Result<u64, DataSourceError> {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            // 期間の日数をそのまま人数として返す
+            Ok(u64::try_from((Utc::now() - since).num_days()).unwrap())
+        }
+    }
+
+    /// 公開されている集計値の名前、`window` ラベルの値、ゲージの値を、名前とラベルの順に並べたもの
+    fn gathered(registry: &Registry) -> Vec<(String, Option<String>, f64)> {
+        let mut gathered = registry
+            .gather()
+            .iter()
+            .filter(|family| !family.get_name().starts_with("seichi_game_api_"))
+            .flat_map(|family| {
+                family.get_metric().iter().map(|metric| {
+                    (
+                        family.get_name().to_string(),
+                        metric
+                            .get_label()
+                            .first()
+                            .map(|label| label.get_value().to_string()),
+                        metric.get_gauge().get_value(),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        gathered.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        gathered
+    }
+
+    #[tokio::test]
+    async fn only_the_configured_statistics_are_exported() {
+        let registry = Registry::new();
+        let statistics = GameStatistics::register(
+            Arc::new(FakeSource::default()),
+            &[
+                GameStatistic::TotalBreakCount,
+                GameStatistic::PlayersTotal,
+                GameStatistic::ActivePlayers,
+            ],
+            vec![1, 7],
+            Duration::from_secs(300),
+            &registry,
+        )
+        .unwrap()
+        .unwrap();
+
+        statistics.refresh_if_due().await;
+
+        assert_eq!(
+            gathered(&registry),
+            vec![
+                (
+                    "seichi_active_players".to_string(),
+                    Some("1d".to_string()),
+                    1.0
+                ),
+                (
+                    "seichi_active_players".to_string(),
+                    Some("7d".to_string()),
+                    7.0
+                ),
+                ("seichi_players_total".to_string(), None, 3.0),
+                ("seichi_total_break_count".to_string(), None, 1_000_000.0),
+            ]
+        );
+        assert!(GameStatistics::register(
+            Arc::new(FakeSource::default()),
+            &[],
+            vec![1],
+            Duration::from_secs(300),
+            &Registry::new(),
+        )
+        .unwrap()
+        .is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn statistics_are_refreshed_after_the_interval_and_kept_on_failure() {
+        let source = Arc::new(FakeSource::default());
+        let registry = Registry::new();
+        let statistics = GameStatistics::register(
+            source.clone(),
+            &[GameStatistic::TotalVoteCount],
+            Vec::new(),
+            Duration::from_secs(300),
+            &registry,
+        )
+        .unwrap()
+        .unwrap();
+
+        statistics.refresh_if_due().await;
+        statistics.refresh_if_due().await;
+        assert_eq!(source.queries.load(Ordering::SeqCst), 1);
+
+        source.failing.store(true, Ordering::SeqCst);
+        tokio::time::advance(Duration::from_secs(300)).await;
+        statistics.refresh_if_due().await;
+        assert_eq!(source.queries.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            gathered(&registry),
+            vec![("seichi_total_vote_count".to_string(), None, 42.0)]
+        );
+        assert_eq!(statistics.refreshes.with_label_values(&["failed"]).get(), 1);
+    }
+}
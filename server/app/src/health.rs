@@ -0,0 +1,311 @@
+use http::{HeaderValue, Request, Response};
+use infra_repository_impl::circuit_breaker_data_source::CircuitBreaker;
+use infra_repository_impl::last_known_good_data_source::{Freshness, ResourceStatus};
+use prometheus::IntGauge;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+
+/// サービスが通常どおりでないとき、データを返すエンドポイントの応答に付けるヘッダー。値は `degraded` か `unavailable`
+pub const SERVICE_DEGRADED_HEADER: &str = "x-service-degraded";
+
+/// サービス全体の状態
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthState {
+    /// 全てのリソースを最新の取得の結果で提供している
+    Ok,
+    /// 直近の取得の結果で代わりに応答しているか、ゲームDBへの問い合わせを止めているが、応答はできている
+    Degraded,
+    /// 多くのリソースで、取得に失敗し代わりに返せるレコードも無い
+    Unavailable,
+}
+
+impl HealthState {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Degraded => "degraded",
+            Self::Unavailable => "unavailable",
+        }
+    }
+
+    const fn gauge(self) -> i64 {
+        match self {
+            Self::Ok => 0,
+            Self::Degraded => 1,
+            Self::Unavailable => 2,
+        }
+    }
+}
+
+/// どの状況をどの状態とみなすかの閾値
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthThresholds {
+    /// リソースの取得がこの回数続けて失敗したら、そのリソースは失敗しているとみなす
+    pub degraded_after_failures: u64,
+    /// 提供しているリソースのうち、失敗していて代わりに返せるレコードも無いものの割合がこれ以上なら利用できないとみなす
+    pub unavailable_ratio: f64,
+}
+
+/// サービス全体の状態と、そう判断した理由
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    pub state: HealthState,
+    /// 失敗していて、直近に成功した取得の結果で応答しているリソース
+    pub stale_resources: Vec<&'static str>,
+    /// 失敗していて、代わりに返せるレコードも無いリソース
+    pub unavailable_resources: Vec<&'static str>,
+    /// 開いているサーキットブレーカーの接続プロファイル
+    pub open_circuits: Vec<String>,
+}
+
+/// リソースごとの直近の取得の状況と開いているサーキットブレーカーから、サービス全体の状態を決める
+pub fn evaluate(
+    thresholds: HealthThresholds,
+    resources: &BTreeMap<&'static str, ResourceStatus>,
+    open_circuits: Vec<String>,
+) -> HealthReport {
+    let failing = resources
+        .iter()
+        .filter(|(_, status)| status.consecutive_failures >= thresholds.degraded_after_failures);
+    let (stale_resources, unavailable_resources): (Vec<_>, Vec<_>) =
+        failing.partition(|(_, status)| status.has_records);
+    let stale_resources = stale_resources
+        .into_iter()
+        .map(|(resource, _)| *resource)
+        .collect::<Vec<_>>();
+    let unavailable_resources = unavailable_resources
+        .into_iter()
+        .map(|(resource, _)| *resource)
+        .collect::<Vec<_>>();
+
+    #[allow(clippy::cast_precision_loss)]
+    let unavailable = !unavailable_resources.is_empty()
+        && unavailable_resources.len() as f64 / resources.len() as f64
+            >= thresholds.unavailable_ratio;
+    let state = if unavailable {
+        HealthState::Unavailable
+    } else if stale_resources.is_empty()
+        && unavailable_resources.is_empty()
+        && open_circuits.is_empty()
+    {
+        HealthState::Ok
+    } else {
+        HealthState::Degraded
+    };
+
+    HealthReport {
+        state,
+        stale_resources,
+        unavailable_resources,
+        open_circuits,
+    }
+}
+
+/// 現在のサービス全体の状態を求め、メトリクスに記録する
+pub struct ServiceHealth {
+    freshness: Freshness,
+    breakers: Vec<CircuitBreaker>,
+    thresholds: HealthThresholds,
+    /// 0なら通常どおり、1なら劣化している、2なら利用できない
+    gauge: IntGauge,
+}
+
+impl ServiceHealth {
+    pub fn new(
+        freshness: Freshness,
+        breakers: Vec<CircuitBreaker>,
+        thresholds: HealthThresholds,
+        gauge: IntGauge,
+    ) -> Self {
+        Self {
+            freshness,
+            breakers,
+            thresholds,
+            gauge,
+        }
+    }
+
+    pub fn report(&self) -> HealthReport {
+        let open_circuits = self
+            .breakers
+            .iter()
+            .filter(|breaker| breaker.is_open())
+            .map(|breaker| breaker.profile().to_string())
+            .collect();
+        let report = evaluate(self.thresholds, &self.freshness.latest(), open_circuits);
+        self.gauge.set(report.state.gauge());
+
+        report
+    }
+}
+
+/// サービスが通常どおりでなければ、応答に `x-service-degraded` を付けるレイヤー。
+///
+/// 状態は応答を返す時点で求め、その応答のための取得の結果も反映する。
+#[derive(Clone)]
+pub struct ServiceDegradedLayer {
+    health: Arc<ServiceHealth>,
+}
+
+impl ServiceDegradedLayer {
+    pub fn new(health: Arc<ServiceHealth>) -> Self {
+        Self { health }
+    }
+}
+
+impl<S> Layer<S> for ServiceDegradedLayer {
+    type Service = ServiceDegraded<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ServiceDegraded {
+            inner,
+            health: self.health.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ServiceDegraded<S> {
+    inner: S,
+    health: Arc<ServiceHealth>,
+}
+
+impl<S, B, ResBody> Service<Request<B>> for ServiceDegraded<S>
+where
+    S: Service<Request<B>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let health = self.health.clone();
+        let response = self.inner.call(request);
+
+        Box::pin(async move {
+            let mut response = response.await?;
+            let state = health.report().state;
+            if state != HealthState::Ok {
+                response.headers_mut().insert(
+                    SERVICE_DEGRADED_HEADER,
+                    HeaderValue::from_static(state.name()),
+                );
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const THRESHOLDS: HealthThresholds = HealthThresholds {
+        degraded_after_failures: 2,
+        unavailable_ratio: 0.5,
+    };
+
+    fn status(consecutive_failures: u64, has_records: bool) -> ResourceStatus {
+        ResourceStatus {
+            consecutive_failures,
+            has_records,
+        }
+    }
+
+    fn state(resources: &[(&'static str, ResourceStatus)], open_circuits: &[&str]) -> HealthState {
+        evaluate(
+            THRESHOLDS,
+            &resources.iter().copied().collect(),
+            open_circuits.iter().map(ToString::to_string).collect(),
+        )
+        .state
+    }
+
+    #[test]
+    fn fresh_resources_are_ok() {
+        assert_eq!(state(&[], &[]), HealthState::Ok);
+        assert_eq!(
+            state(
+                &[
+                    ("break_counts", status(0, true)),
+                    ("vote_counts", status(0, false))
+                ],
+                &[]
+            ),
+            HealthState::Ok
+        );
+    }
+
+    #[test]
+    fn failures_below_the_threshold_are_tolerated() {
+        assert_eq!(
+            state(
+                &[
+                    ("break_counts", status(1, true)),
+                    ("vote_counts", status(1, false))
+                ],
+                &[]
+            ),
+            HealthState::Ok
+        );
+    }
+
+    #[test]
+    fn stale_resources_and_open_circuits_degrade() {
+        assert_eq!(
+            state(
+                &[
+                    ("break_counts", status(2, true)),
+                    ("vote_counts", status(0, true))
+                ],
+                &[]
+            ),
+            HealthState::Degraded
+        );
+        assert_eq!(
+            state(&[("break_counts", status(0, true))], &["ranking"]),
+            HealthState::Degraded
+        );
+    }
+
+    #[test]
+    fn resources_without_records_make_the_service_unavailable_past_the_ratio() {
+        let failing_without_records = [
+            ("break_counts", status(2, false)),
+            ("build_counts", status(0, true)),
+            ("play_ticks", status(0, true)),
+            ("vote_counts", status(0, true)),
+        ];
+        let report = evaluate(
+            THRESHOLDS,
+            &failing_without_records.iter().copied().collect(),
+            Vec::new(),
+        );
+        assert_eq!(report.state, HealthState::Degraded);
+        assert_eq!(report.unavailable_resources, vec!["break_counts"]);
+
+        assert_eq!(
+            state(
+                &[
+                    ("break_counts", status(2, false)),
+                    ("build_counts", status(3, false)),
+                    ("play_ticks", status(5, true)),
+                    ("vote_counts", status(0, true)),
+                ],
+                &["default"]
+            ),
+            HealthState::Unavailable
+        );
+    }
+}
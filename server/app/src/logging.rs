@@ -0,0 +1,210 @@
+use crate::error_reporting;
+use config::{AppConfig, LogFileRotation, LogFormat, TracingConfig};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{self, Sampler, Tracer};
+use opentelemetry_sdk::Resource;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter};
+
+const LOG_FILE_NAME_PREFIX: &str = "seichi-game-api.log";
+
+// spanが閉じるときにもログを出し、リクエストやゲームDBからの取得にかかった時間が分かるようにする
+fn fmt_layer<S>() -> fmt::Layer<S> {
+    fmt::layer().with_span_events(FmtSpan::CLOSE)
+}
+
+fn trace_config(config: &TracingConfig) -> trace::Config {
+    // 呼び出し元が送ると決めたトレースは途中で欠けないよう、親のspanの判断に従う
+    trace::config()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sampling_ratio,
+        ))))
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            config.service_name.clone(),
+        )]))
+}
+
+/// OTLPのエンドポイントが設定されていれば、spanをそこへ送るtracerをグローバルに登録して返す
+fn install_otlp_tracer(config: &TracingConfig) -> anyhow::Result<Option<Tracer>> {
+    match &config.otlp_endpoint {
+        None => Ok(None),
+        Some(endpoint) => {
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(trace_config(config))
+                .install_batch(opentelemetry_sdk::runtime::Tokio)
+                .map_err(|error| anyhow::anyhow!("failed to set up OTLP exporter: {error}"))?;
+
+            Ok(Some(tracer))
+        }
+    }
+}
+
+/// 書き出し途中のログとトレース、送信中のエラーの報告を、破棄されるときに送り切るためのガード
+pub struct LoggingGuard {
+    _file_writer: Option<WorkerGuard>,
+    _error_reporting: Option<sentry::ClientInitGuard>,
+    tracer_installed: bool,
+}
+
+impl Drop for LoggingGuard {
+    fn drop(&mut self) {
+        if self.tracer_installed {
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
+
+/// ログの出力と、設定されていればトレースの送信とエラーの報告を設定する。
+///
+/// フィルタは `filter_override` (コマンドラインの `--log-level`)、設定の `filter`、環境変数 `RUST_LOG` の順に優先し、
+/// いずれも無ければ `info` とする。トレースとして送るspanや報告するエラーにも同じフィルタがかかる。
+/// 返り値の [`LoggingGuard`] が破棄されるまでに書き出されたログやトレースが反映されるため、
+/// プロセスの終了時まで保持しておくこと。
+pub fn initialize(
+    app_config: &AppConfig,
+    filter_override: Option<&str>,
+) -> anyhow::Result<LoggingGuard> {
+    let config = &app_config.logging_config;
+
+    // see https://github.com/tokio-rs/axum/blob/79a0a54bc9f0f585c974b5e6793541baff980662/examples/tracing-aka-logging/src/main.rs
+    let filter = filter_override
+        .map(ToString::to_string)
+        .or_else(|| config.filter.clone())
+        .or_else(|| std::env::var("RUST_LOG").ok())
+        .unwrap_or_else(|| "info".into());
+    let filter = EnvFilter::try_new(&filter)
+        .map_err(|error| anyhow::anyhow!("invalid log filter {filter:?}: {error}"))?;
+
+    let (file_writer, guard) = match &config.file_directory {
+        Some(directory) => {
+            let rotation = match config.file_rotation {
+                LogFileRotation::Hourly => Rotation::HOURLY,
+                LogFileRotation::Daily => Rotation::DAILY,
+                LogFileRotation::Never => Rotation::NEVER,
+            };
+            let appender = RollingFileAppender::new(rotation, directory, LOG_FILE_NAME_PREFIX);
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+
+            (Some(writer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    let tracer = install_otlp_tracer(&app_config.tracing_config)?;
+    let tracer_installed = tracer.is_some();
+
+    let (error_reporting_guard, error_reporting_layer) =
+        match error_reporting::initialize(&app_config.error_reporting_config)? {
+            Some((guard, layer)) => (Some(guard), Some(layer)),
+            None => (None, None),
+        };
+
+    // 送り先が設定されていない場合はレイヤー自体を挟まない
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
+        .with(error_reporting_layer);
+
+    // 標準出力は fetch や check-config の出力に使うため、ログは標準エラー出力に書き出す
+    match config.format {
+        LogFormat::Text => registry
+            .with(fmt_layer().with_writer(std::io::stderr))
+            .with(file_writer.map(|writer| fmt_layer().with_ansi(false).with_writer(writer)))
+            .init(),
+        LogFormat::Json => registry
+            .with(fmt_layer().json().with_writer(std::io::stderr))
+            .with(file_writer.map(|writer| fmt_layer().json().with_writer(writer)))
+            .init(),
+    }
+
+    if !app_config.ignored_config_file_keys.is_empty() {
+        tracing::warn!(
+            "ignoring unknown keys in config file: {}",
+            app_config.ignored_config_file_keys.join(", ")
+        );
+    }
+
+    Ok(LoggingGuard {
+        _file_writer: guard,
+        _error_reporting: error_reporting_guard,
+        tracer_installed,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+    use opentelemetry_sdk::trace::TracerProvider;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Clone, Default)]
+    struct InMemoryExporter(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for InMemoryExporter {
+        fn export(
+            &mut self,
+            batch: Vec<SpanData>,
+        ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(std::future::ready(Ok(())))
+        }
+    }
+
+    fn exported_span_names(sampling_ratio: f64) -> Vec<String> {
+        let config = TracingConfig {
+            otlp_endpoint: None,
+            service_name: "test".to_string(),
+            sampling_ratio,
+        };
+        let exporter = InMemoryExporter::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .with_config(trace_config(&config))
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let request = http::Request::builder()
+                .uri("/service/Method")
+                .body(())
+                .unwrap();
+            crate::request_span::make_span(&request).in_scope(|| {
+                tracing::info_span!("source_fetch", resource = "break_counts").in_scope(|| {});
+            });
+        });
+        provider.force_flush();
+
+        let spans = exporter.0.lock().unwrap();
+        spans.iter().map(|span| span.name.to_string()).collect()
+    }
+
+    #[test]
+    fn spans_are_exported_with_their_names() {
+        let mut names = exported_span_names(1.0);
+        names.sort();
+
+        assert_eq!(names, vec!["request", "source_fetch"]);
+    }
+
+    #[test]
+    fn nothing_is_exported_with_zero_sampling_ratio() {
+        assert!(exported_span_names(0.0).is_empty());
+    }
+}
@@ -2,60 +2,872 @@
 #![warn(clippy::nursery, clippy::pedantic)]
 #![allow(clippy::cargo_common_metadata)]
 
-use config::{AppConfig, FromEnv, SourceDatabaseConfig};
-use infra_grpc::buf_generated::gigantic_minecraft::seichi_game_data::v1::read_service_server::{
-    ReadService, ReadServiceServer,
+mod access_log;
+mod build_info;
+mod cli;
+mod concurrency_limit;
+mod diff;
+#[cfg(test)]
+mod e2e;
+mod error_reporting;
+mod export;
+mod game_statistics;
+mod health;
+mod logging;
+mod metrics;
+mod ops;
+mod panic_isolation;
+mod request_id;
+mod request_span;
+mod self_test;
+
+use crate::access_log::AccessLogLayer;
+use crate::build_info::ProcessInfo;
+use crate::cli::{Cli, Command, Resource};
+use crate::concurrency_limit::ConcurrencyLimitLayer;
+use crate::game_statistics::{GameStatistic, GameStatistics, StatisticsSource};
+use crate::health::{HealthThresholds, ServiceDegradedLayer, ServiceHealth};
+use crate::metrics::{ConnectionPoolStatsSource, Metrics, RequestMetricsLayer, Routes};
+use crate::ops::{DatabasePing, OpsState};
+use crate::request_id::RequestIdLayer;
+use clap::Parser;
+use config::{
+    AppConfig, FromFileAndEnv, LoggingConfig, ResourceConfig, SourceDatabaseConfig,
+    TimestampPrecision,
+};
+use domain::app_models::VecDataSource;
+use domain::models::{
+    PlayerBreakCount, PlayerBuildCount, PlayerLastQuit, PlayerPlayTicks, PlayerVoteCount,
 };
+use infra_grpc::buf_generated::gigantic_minecraft::seichi_game_data::v1::read_service_server::ReadServiceServer;
 use infra_grpc::read_service::ReadServiceImpl;
+use infra_repository_impl::circuit_breaker_data_source::{
+    CircuitBreaker, CircuitBreakingDataSource,
+};
+use infra_repository_impl::csv_data_source::CsvDataSource;
+use infra_repository_impl::last_known_good_data_source::LastKnownGoodDataSource;
+use infra_repository_impl::metered_data_source::MeteredDataSource;
+use infra_repository_impl::mysql_data_source::PasswordSource;
+use infra_repository_impl::single_flight_data_source::SingleFlightDataSource;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{oneshot, Semaphore};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::server::NamedService;
 use tonic::transport::Server;
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::util::SubscriberInitExt;
 
-async fn initialize_database_read_service(
-    config: &SourceDatabaseConfig,
-) -> anyhow::Result<impl ReadService> {
-    use infra_repository_impl::mysql_data_source;
+// 同時に来たリクエストが同じ全件取得クエリを何度も発行しないよう、各データソースは一回の問い合わせを共有させる。
+// ゲームDBへ実際に問い合わせた回数と時間を記録するため、計測はまとめる前に行い、
+// サーキットブレーカーが問い合わせずに失敗させたものは計測しない。
+// 取得に失敗した場合は、サーキットブレーカーが止めたものも含めて、そのリソースの直近の取得の結果で応答する
+// サーキットブレーカーを渡さなければ、失敗が続いても問い合わせを止めない
+fn serving_data_source<T: Clone + Send + Sync + 'static>(
+    resource: &'static str,
+    metrics: &Metrics,
+    slow_fetch_threshold: Duration,
+    (data_source, breaker): (
+        impl VecDataSource<T> + Send + Sync + 'static,
+        Option<CircuitBreaker>,
+    ),
+) -> Box<dyn VecDataSource<T> + Send + Sync> {
+    let metered = MeteredDataSource::new(
+        data_source,
+        resource,
+        metrics.fetch.clone(),
+        slow_fetch_threshold,
+    );
+    let freshness = metrics.freshness.clone();
 
-    let data_source = Box::new(mysql_data_source::from_config(config).await?);
+    match breaker {
+        Some(breaker) => Box::new(SingleFlightDataSource::new(LastKnownGoodDataSource::new(
+            CircuitBreakingDataSource::new(metered, breaker),
+            resource,
+            freshness,
+        ))),
+        None => Box::new(SingleFlightDataSource::new(LastKnownGoodDataSource::new(
+            metered, resource, freshness,
+        ))),
+    }
+}
+
+// 有効なリソースごとに `data_source_for` が割り当てたデータソースとサーキットブレーカーを使い、
+// ゲームDBから読む場合もCSVファイルから読む場合も、同じ `serving_data_source` の組み合わせで提供する
+// 退出時刻のデータソースは、`with_last_quit_precision` で設定の精度に合わせてから使う
+fn build_read_service<D>(
+    config: &AppConfig,
+    metrics: &Metrics,
+    data_source_for: impl Fn(&ResourceConfig) -> anyhow::Result<Option<(D, Option<CircuitBreaker>)>>,
+    with_last_quit_precision: impl Fn(&D, TimestampPrecision) -> D,
+) -> anyhow::Result<ReadServiceImpl>
+where
+    D: VecDataSource<PlayerLastQuit>
+        + VecDataSource<PlayerBreakCount>
+        + VecDataSource<PlayerBuildCount>
+        + VecDataSource<PlayerPlayTicks>
+        + VecDataSource<PlayerVoteCount>
+        + Send
+        + Sync
+        + 'static,
+{
+    let resources = &config.resources_config;
+    let last_quit_precision = resources.last_quits.timestamp_precision.unwrap_or_default();
+    let slow_fetch_threshold = config.logging_config.slow_fetch_threshold();
 
     Ok(ReadServiceImpl {
-        last_quit_data_source: data_source.clone(),
-        break_counts_data_source: data_source.clone(),
-        build_counts_data_source: data_source.clone(),
-        play_ticks_data_source: data_source.clone(),
-        vote_counts_data_source: data_source,
+        last_quit_data_source: data_source_for(&resources.last_quits)?.map(
+            |(data_source, breaker)| {
+                serving_data_source(
+                    "last_quits",
+                    metrics,
+                    slow_fetch_threshold,
+                    (
+                        with_last_quit_precision(&data_source, last_quit_precision),
+                        breaker,
+                    ),
+                )
+            },
+        ),
+        break_counts_data_source: data_source_for(&resources.break_counts)?.map(|data_source| {
+            serving_data_source("break_counts", metrics, slow_fetch_threshold, data_source)
+        }),
+        build_counts_data_source: data_source_for(&resources.build_counts)?.map(|data_source| {
+            serving_data_source("build_counts", metrics, slow_fetch_threshold, data_source)
+        }),
+        play_ticks_data_source: data_source_for(&resources.play_ticks)?.map(|data_source| {
+            serving_data_source("play_ticks", metrics, slow_fetch_threshold, data_source)
+        }),
+        vote_counts_data_source: data_source_for(&resources.vote_counts)?.map(|data_source| {
+            serving_data_source("vote_counts", metrics, slow_fetch_threshold, data_source)
+        }),
     })
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // initialize tracing
-    // see https://github.com/tokio-rs/axum/blob/79a0a54bc9f0f585c974b5e6793541baff980662/examples/tracing-aka-logging/src/main.rs
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into()),
-        ))
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+/// 起動時と同じ設定ファイルと環境変数から、設定を読み直す
+type ReloadConfig = Arc<dyn Fn() -> anyhow::Result<AppConfig> + Send + Sync>;
+
+/// 設定を読み直して、接続プロファイル `profile` (既定のものは `None`) のパスワードを返す
+fn password_source(reload_config: ReloadConfig, profile: Option<&str>) -> PasswordSource {
+    let profile = profile.map(ToString::to_string);
+
+    Arc::new(move || {
+        let mut config = reload_config()?;
+        match &profile {
+            None => config
+                .source_database_config
+                .map(|profile| profile.password)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("the default connection profile is no longer defined")
+                }),
+            Some(name) => config
+                .source_database_profiles
+                .remove(name)
+                .map(|profile| profile.password)
+                .ok_or_else(|| anyhow::anyhow!("connection profile {name:?} is no longer defined")),
+        }
+    })
+}
+
+struct DatabaseReadService {
+    service: ReadServiceImpl,
+    /// 接続プロファイルの名前 (既定のものは `default`) と、そのコネクションプールの状態
+    connection_pools: Vec<(String, ConnectionPoolStatsSource)>,
+    /// 接続プロファイルの名前と、そのゲームDBへのping
+    database_pings: Vec<(String, DatabasePing)>,
+    /// 全ての接続プロファイルのコネクションプールを閉じる
+    close_connection_pools: Pin<Box<dyn Future<Output = ()> + Send>>,
+    /// 全ての接続プロファイルのサーキットブレーカー
+    circuit_breakers: Vec<CircuitBreaker>,
+    /// ゲーム全体の集計値を求める、既定の接続プロファイルのデータソース
+    statistics_source: Arc<dyn StatisticsSource>,
+}
+
+// serve と fetch は同じこの関数でデータソースを構築し、fetch の出力がサーバーの応答と同じものになるようにする
+// 接続プロファイルごとにコネクションプールとサーキットブレーカーを作り、各リソースには設定で割り当てられたプロファイルのものを使わせる
+// 無効化されたリソースはデータソースを作らず、ゲームDBへ一切問い合わせないようにする
+// `reload_config` を渡すと、認証に失敗したときにパスワードを読み直す
+// CSVファイルから読み出す設定なら、ゲームDBには接続せずにそのファイルを読むデータソースを使う
+async fn initialize_database_read_service(
+    config: &AppConfig,
+    metrics: &Metrics,
+    reload_config: Option<ReloadConfig>,
+) -> anyhow::Result<DatabaseReadService> {
+    use infra_repository_impl::mysql_data_source::{self, CombinedDataSource, Instrumentation};
+
+    if let Some(directory) = &config.csv_source_config.directory {
+        return initialize_csv_read_service(config, directory, metrics).await;
+    }
+    let default_profile = config
+        .source_database_config
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("the default connection profile is not configured"))?;
+
+    let instrumentation = Instrumentation {
+        acquire_metrics: metrics.connection_acquire.clone(),
+        slow_acquire_threshold: config.logging_config.slow_acquire_threshold(),
+        data_quality: metrics.data_quality.clone(),
+        data_quality_warn_ratio: config.logging_config.data_quality_warn_ratio,
+        raw_player_names: config.logging_config.raw_player_names,
+    };
+    let circuit_breaker = |name: &str, profile: &SourceDatabaseConfig| {
+        CircuitBreaker::new(
+            name,
+            profile.circuit_breaker_failures,
+            profile.circuit_breaker_cooldown(),
+            metrics.circuit_breaker.clone(),
+        )
+    };
+    let password_source_for = |profile: Option<&str>| {
+        reload_config
+            .clone()
+            .map(|reload_config| password_source(reload_config, profile))
+    };
+    let default_data_source = mysql_data_source::from_config(
+        default_profile,
+        "default",
+        &instrumentation,
+        password_source_for(None),
+    )
+    .await?;
+    let default_breaker = circuit_breaker("default", default_profile);
+
+    let mut profile_data_sources = BTreeMap::new();
+    let mut profile_breakers = BTreeMap::new();
+    for (name, profile) in &config.source_database_profiles {
+        profile_data_sources.insert(
+            name.as_str(),
+            mysql_data_source::from_config(
+                profile,
+                name,
+                &instrumentation,
+                password_source_for(Some(name)),
+            )
+            .await?,
+        );
+        profile_breakers.insert(name.as_str(), circuit_breaker(name, profile));
+    }
+
+    let data_source_for = |resource: &ResourceConfig| -> anyhow::Result<Option<_>> {
+        if !resource.enabled {
+            return Ok(None);
+        }
+
+        match &resource.connection_profile {
+            None => Ok(Some((
+                default_data_source.clone(),
+                Some(default_breaker.clone()),
+            ))),
+            Some(name) => profile_data_sources
+                .get(name.as_str())
+                .cloned()
+                .zip(profile_breakers.get(name.as_str()).cloned().map(Some))
+                .map(Some)
+                .ok_or_else(|| anyhow::anyhow!("undefined connection profile {name:?}")),
+        }
+    };
+    let service = build_read_service(
+        config,
+        metrics,
+        data_source_for,
+        CombinedDataSource::with_last_quit_precision,
+    )?;
+
+    let statistics_source: Arc<dyn StatisticsSource> = Arc::new(default_data_source.clone());
+
+    let circuit_breakers = std::iter::once(default_breaker)
+        .chain(profile_breakers.into_values())
+        .collect();
+
+    let data_sources = std::iter::once(("default", default_data_source))
+        .chain(profile_data_sources)
+        .collect::<Vec<_>>();
+
+    let connection_pools = data_sources
+        .iter()
+        .map(|(name, data_source)| {
+            let data_source = data_source.clone();
+            let stats: ConnectionPoolStatsSource =
+                Box::new(move || data_source.connection_pool_stats());
+            (name.to_string(), stats)
+        })
+        .collect();
+
+    let pools_to_close = data_sources
+        .iter()
+        .map(|(_, data_source)| data_source.clone())
+        .collect::<Vec<_>>();
+    let close_connection_pools = Box::pin(async move {
+        for data_source in pools_to_close {
+            data_source.close().await;
+        }
+    });
+
+    let database_pings = data_sources
+        .into_iter()
+        .map(|(name, data_source)| {
+            let ping: DatabasePing = Box::new(move || {
+                let data_source = data_source.clone();
+                Box::pin(async move { data_source.ping().await })
+            });
+            (name.to_string(), ping)
+        })
+        .collect();
+
+    Ok(DatabaseReadService {
+        service,
+        connection_pools,
+        database_pings,
+        close_connection_pools,
+        circuit_breakers,
+        statistics_source,
+    })
+}
+
+// 起動時に有効なリソースのファイルを全て読み込んで検証し、一つでも読み込めなければ起動しない。
+// 読み込めないファイルは再試行しても読めるようにはならないため、サーキットブレーカーは使わない
+async fn initialize_csv_read_service(
+    config: &AppConfig,
+    directory: &Path,
+    metrics: &Metrics,
+) -> anyhow::Result<DatabaseReadService> {
+    let data_source = CsvDataSource::new(directory, config.csv_source_config.strict);
+    for (resource, resource_config) in config.resources_config.iter() {
+        if resource_config.enabled {
+            data_source.load(resource).await?;
+        }
+    }
+
+    let service = build_read_service(
+        config,
+        metrics,
+        |resource| Ok(resource.enabled.then(|| (data_source.clone(), None))),
+        CsvDataSource::with_last_quit_precision,
+    )?;
+
+    Ok(DatabaseReadService {
+        service,
+        connection_pools: Vec::new(),
+        database_pings: Vec::new(),
+        close_connection_pools: Box::pin(async {}),
+        circuit_breakers: Vec::new(),
+        statistics_source: Arc::new(data_source),
+    })
+}
+
+/// 監査のため、どのリソースをどの精度で提供するかを起動時にログに残す
+fn log_data_policy(config: &AppConfig) {
+    for (resource, resource_config) in config.resources_config.iter() {
+        if !resource_config.enabled {
+            tracing::info!("data policy: {resource} is disabled and never queried");
+        } else if let Some(directory) = &config.csv_source_config.directory {
+            tracing::info!(
+                "data policy: {resource} is served from {} (timestamp precision: {:?})",
+                directory.join(format!("{resource}.csv")).display(),
+                resource_config.timestamp_precision.unwrap_or_default(),
+            );
+        } else {
+            tracing::info!(
+                "data policy: {resource} is served (timestamp precision: {:?}, connection profile: {})",
+                resource_config.timestamp_precision.unwrap_or_default(),
+                resource_config
+                    .connection_profile
+                    .as_deref()
+                    .unwrap_or("default"),
+            );
+        }
+    }
+}
+
+fn read_config(
+    config_file: Option<&Path>,
+    profile: Option<&str>,
+) -> Result<AppConfig, Box<dyn std::error::Error>> {
+    let config = AppConfig::from_file_and_env(config_file, profile)?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// 設定を読み込んで検証し、その結果をJSONで標準出力に書き出す。
+///
+/// 設定が有効であれば `true` を返す。
+fn check_config(config_file: Option<&Path>, profile: Option<&str>) -> bool {
+    let (valid, report) = match AppConfig::from_file_and_env(config_file, profile) {
+        Ok(config) => match config.validate() {
+            Ok(()) => (true, serde_json::json!({ "valid": true, "violations": [] })),
+            Err(errors) => (
+                false,
+                serde_json::json!({ "valid": false, "violations": errors.0 }),
+            ),
+        },
+        Err(error) => (
+            false,
+            serde_json::json!({ "valid": false, "error": error.to_string() }),
+        ),
+    };
+
+    println!("{report}");
+
+    valid
+}
+
+async fn print_as_json<T: Serialize>(
+    data_source: Option<&(dyn VecDataSource<T> + Send + Sync)>,
+    resource: &str,
+) -> anyhow::Result<()> {
+    let data_source = data_source.ok_or_else(|| {
+        anyhow::anyhow!(
+            "{resource} is disabled by RESOURCE_{}_ENABLED",
+            resource.to_uppercase()
+        )
+    })?;
+    let records = data_source.fetch().await?;
+
+    serde_json::to_writer(std::io::stdout(), &records)?;
+    println!();
+
+    Ok(())
+}
 
-    println!("Reading config...");
-    let config = AppConfig::from_env()?;
+async fn fetch(config: &AppConfig, resource: Resource) -> anyhow::Result<()> {
+    // fetch は一度きりなのでメトリクスは記録するだけで公開せず、パスワードも読み直さない
+    let metrics = Metrics::new(&ProcessInfo::new(None))?;
+    let service = initialize_database_read_service(config, &metrics, None)
+        .await?
+        .service;
 
-    let service = initialize_database_read_service(&config.source_database_config)
+    match resource {
+        Resource::LastQuits => {
+            print_as_json(service.last_quit_data_source.as_deref(), "last_quits").await
+        }
+        Resource::BreakCounts => {
+            print_as_json(service.break_counts_data_source.as_deref(), "break_counts").await
+        }
+        Resource::BuildCounts => {
+            print_as_json(service.build_counts_data_source.as_deref(), "build_counts").await
+        }
+        Resource::PlayTicks => {
+            print_as_json(service.play_ticks_data_source.as_deref(), "play_ticks").await
+        }
+        Resource::VoteCounts => {
+            print_as_json(service.vote_counts_data_source.as_deref(), "vote_counts").await
+        }
+    }
+}
+
+/// 指定したアドレスで待ち受けを始める。
+///
+/// ポートに0を指定すると空いているポートが割り当てられるので、実際に待ち受けているアドレスも返す。
+async fn bind(address: SocketAddr) -> std::io::Result<(SocketAddr, TcpListenerStream)> {
+    let listener = TcpListener::bind(address).await?;
+    let local_address = listener.local_addr()?;
+
+    Ok((local_address, TcpListenerStream::new(listener)))
+}
+
+/// 処理中のリクエストを待ちきれずに打ち切って終了したときの終了コード
+const DRAIN_TIMEOUT_EXIT_CODE: i32 = 3;
+
+/// サーバーが止まったときに、処理中のリクエストがどうなったか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shutdown {
+    /// 全てのリクエストの処理が終わってから接続を閉じた
+    Drained,
+    /// 待ちきれずに、処理中のリクエストを打ち切って接続を閉じた
+    TimedOut,
+}
+
+/// `server` が止まるまで動かす。`drain_started` が届いてから `drain_timeout` のうちに止まらなければ、`server` を破棄して打ち切る
+async fn drain_within<E>(
+    server: impl Future<Output = Result<(), E>>,
+    drain_started: oneshot::Receiver<()>,
+    drain_timeout: Duration,
+) -> Result<Shutdown, E> {
+    tokio::pin!(server);
+    let deadline = async {
+        match drain_started.await {
+            Ok(()) => tokio::time::sleep(drain_timeout).await,
+            // 終了の指示を待たずにサーバーが止まった場合は、その結果を待つ
+            Err(_) => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        result = &mut server => result.map(|()| Shutdown::Drained),
+        () = deadline => Ok(Shutdown::TimedOut),
+    }
+}
+
+// 終了の指示を受けたら、`/readyz` を失敗させて待った後に新しい接続を受け付けなくし、
+// 処理中のリクエストが終わるのを待ってからコネクションプールを閉じる。
+// トレースやエラーの報告は、呼び出し側がログのガードを破棄するときに送り切られる
+async fn serve(
+    config: &AppConfig,
+    reload_config: ReloadConfig,
+    process: ProcessInfo,
+) -> Result<Shutdown, Box<dyn std::error::Error>> {
+    let metrics = Arc::new(Metrics::new(&process).expect("Registering metrics"));
+    let DatabaseReadService {
+        service,
+        connection_pools,
+        database_pings,
+        close_connection_pools,
+        circuit_breakers,
+        statistics_source,
+    } = initialize_database_read_service(config, &metrics, Some(reload_config))
         .await
         .expect("Initializing read service");
+    log_data_policy(config);
+
+    let game_statistics = GameStatistics::register(
+        statistics_source,
+        &config
+            .ops_config
+            .game_statistics
+            .iter()
+            .filter_map(|name| GameStatistic::from_name(name))
+            .collect::<Vec<_>>(),
+        config
+            .ops_config
+            .game_statistics_active_windows_days
+            .clone(),
+        config.ops_config.game_statistics_interval(),
+        metrics.registry(),
+    )
+    .expect("Registering game statistics")
+    .map(Arc::new);
 
-    let serve_address = format!("{}:{}", config.http_config.host, config.http_config.port.0)
-        .parse()
-        .expect("Parsing serve address from config");
+    let health = Arc::new(ServiceHealth::new(
+        metrics.freshness.clone(),
+        circuit_breakers,
+        HealthThresholds {
+            degraded_after_failures: config.ops_config.health_degraded_after_failures,
+            unavailable_ratio: config.ops_config.health_unavailable_ratio,
+        },
+        metrics.service_health.clone(),
+    ));
+
+    let listen_address = config
+        .http_config
+        .socket_address()
+        .expect("Parsing listen address from config");
+
+    let (local_address, incoming) = bind(listen_address).await?;
+
+    println!("Server is listening on {local_address}");
+
+    // 上限を指定しない場合も、処理中のリクエスト数を数えるためにレイヤーは挟んでおく
+    let concurrency_limit = ConcurrencyLimitLayer::new(
+        config
+            .http_config
+            .max_concurrent_requests
+            .unwrap_or(Semaphore::MAX_PERMITS),
+        <ReadServiceServer<ReadServiceImpl> as NamedService>::NAME,
+        config.http_config.retry_after_seconds,
+    );
+
+    let shutting_down = Arc::new(AtomicBool::new(false));
+    // 運用のためのサーバーが無ければ readiness を見る者もいないため、待たずに終了する
+    let mut shutdown_delay = Duration::ZERO;
+
+    if let Some(ops_address) = config.ops_config.socket_address() {
+        let ops_address = ops_address.expect("Parsing ops listen address from config");
+        let state = OpsState {
+            process,
+            metrics: metrics.clone(),
+            concurrency_limit: concurrency_limit.clone(),
+            connection_pools,
+            shutting_down: shutting_down.clone(),
+            database_pings: if config.ops_config.readiness_checks_database {
+                database_pings
+            } else {
+                Vec::new()
+            },
+            database_ping_timeout: Duration::from_millis(
+                config.ops_config.readiness_database_timeout_millis,
+            ),
+            health: health.clone(),
+            game_statistics,
+        };
+        shutdown_delay = Duration::from_secs(config.ops_config.shutdown_delay_seconds);
+        let (local_ops_address, ops_server) = ops::bind(ops_address, state)?;
+
+        println!("Ops server is listening on {local_ops_address}");
+
+        tokio::spawn(async move {
+            if let Err(error) = ops_server.await {
+                tracing::error!(%error, "ops server stopped");
+            }
+        });
+    }
+
+    let drain_timeout = config.http_config.drain_timeout();
+    let (drain_started, drain_started_receiver) = oneshot::channel();
+    let server = serve_grpc(
+        service,
+        metrics,
+        health,
+        concurrency_limit,
+        &config.logging_config,
+        config.http_config.trusted_proxy_depth,
+        config.http_config.grpc_web,
+        incoming,
+        async move {
+            shutdown_signal().await;
+            shutting_down.store(true, Ordering::SeqCst);
+            tracing::info!(
+                delay_seconds = shutdown_delay.as_secs(),
+                "shutting down; reporting not ready before closing connections"
+            );
+            tokio::time::sleep(shutdown_delay).await;
+            tracing::info!(
+                timeout_seconds = drain_timeout.as_secs(),
+                "stopped accepting connections; waiting for in-flight requests"
+            );
+            let _ = drain_started.send(());
+        },
+    );
+
+    let shutdown = drain_within(server, drain_started_receiver, drain_timeout).await?;
+    match shutdown {
+        Shutdown::Drained => {
+            tracing::info!("all in-flight requests completed");
+            close_connection_pools.await;
+            tracing::info!("closed the connection pools");
+        }
+        // 打ち切ったリクエストがまだ接続を使っていることがあるため、プールが閉じるのは待たない
+        Shutdown::TimedOut => tracing::error!(
+            timeout_seconds = drain_timeout.as_secs(),
+            "in-flight requests did not complete in time; exiting without waiting for them"
+        ),
+    }
+
+    Ok(shutdown)
+}
 
-    println!("Server will be listening on {serve_address}");
+/// 全てのレイヤーを挟んだgRPCサーバーを `incoming` で待ち受け、`shutdown` が完了するまで動かす。
+///
+/// 結合テストでも同じ組み立てを使い、ルーティングやエラーの変換をデータソースだけ差し替えて確かめる。
+#[allow(clippy::too_many_arguments)]
+async fn serve_grpc(
+    service: ReadServiceImpl,
+    metrics: Arc<Metrics>,
+    health: Arc<ServiceHealth>,
+    concurrency_limit: ConcurrencyLimitLayer,
+    logging_config: &LoggingConfig,
+    trusted_proxy_depth: usize,
+    grpc_web: bool,
+    incoming: TcpListenerStream,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), tonic::transport::Error> {
+    let routes = Routes::new(
+        [
+            "LastQuits",
+            "BreakCounts",
+            "BuildCounts",
+            "PlayTicks",
+            "VoteCounts",
+        ]
+        .iter()
+        .map(|method| {
+            format!(
+                "/{}/{method}",
+                <ReadServiceServer<ReadServiceImpl> as NamedService>::NAME
+            )
+        })
+        .collect(),
+    );
+    let request_panics = metrics.request_panics.clone();
 
     Server::builder()
-        .layer(tower_http::trace::TraceLayer::new_for_http())
+        // gRPC-WebはHTTP/1.1でも送られる
+        .accept_http1(grpc_web)
+        .layer(RequestIdLayer)
+        .layer(
+            tower_http::trace::TraceLayer::new_for_http()
+                .make_span_with(request_span::make_span)
+                .on_response(request_span::on_response),
+        )
+        .layer(AccessLogLayer::new(
+            logging_config.access_log,
+            routes.clone(),
+            logging_config.access_log_excluded_paths.clone(),
+            trusted_proxy_depth,
+        ))
+        .layer(RequestMetricsLayer::new(
+            metrics,
+            routes,
+            logging_config.slow_request_threshold(),
+        ))
+        .layer(ServiceDegradedLayer::new(health))
+        .layer(concurrency_limit)
+        // 内側で捕まえ、外側のレイヤーには通常の応答として記録させる
+        .layer(panic_isolation::catch_panic_layer(request_panics))
+        // 外側のレイヤーには、gRPC-Webのリクエストもそのままのパスで記録させる
+        .layer(tower::util::option_layer(
+            grpc_web.then(tonic_web::GrpcWebLayer::new),
+        ))
         .add_service(ReadServiceServer::new(service))
-        .serve(serve_address)
-        .await?;
+        .serve_with_incoming_shutdown(incoming, shutdown)
+        .await
+}
 
-    Ok(())
+/// Ctrl-C か、Unixでは SIGTERM を受け取るまで待つ
+async fn shutdown_signal() {
+    let interrupt = async {
+        tokio::signal::ctrl_c().await.expect("Listening for Ctrl-C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Listening for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = interrupt => {},
+        () = terminate => {},
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let config_file = cli.config.as_deref();
+    let profile = cli.profile.as_deref();
+    let log_level = cli.log_level.as_deref();
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            println!("Reading config...");
+            let config = read_config(config_file, profile)?;
+            let profile = config::resolve_profile(profile);
+            println!(
+                "active profile: {}",
+                profile.as_deref().unwrap_or("default")
+            );
+            let log_guard = logging::initialize(&config, log_level)?;
+            panic_isolation::install_hook();
+
+            let reload_config: ReloadConfig = {
+                let config_file = config_file.map(Path::to_path_buf);
+                let profile = profile.clone();
+                Arc::new(move || {
+                    Ok(AppConfig::from_file_and_env(
+                        config_file.as_deref(),
+                        profile.as_deref(),
+                    )?)
+                })
+            };
+            if serve(&config, reload_config, ProcessInfo::new(profile)).await? == Shutdown::TimedOut
+            {
+                // 終了する前にガードを破棄し、ログとトレースを送り切る
+                drop(log_guard);
+                std::process::exit(DRAIN_TIMEOUT_EXIT_CODE);
+            }
+            Ok(())
+        }
+        Command::CheckConfig => std::process::exit(if check_config(config_file, profile) {
+            0
+        } else {
+            1
+        }),
+        Command::SelfTest { timeout_seconds } => {
+            let report = match read_config(config_file, profile) {
+                Ok(config) => self_test::run(&config, Duration::from_secs(timeout_seconds)).await,
+                Err(error) => self_test::invalid_config(&error.to_string()),
+            };
+            println!("{}", serde_json::to_string(&report)?);
+
+            std::process::exit(if report.ok { 0 } else { 1 })
+        }
+        Command::Fetch { resource } => {
+            let config = read_config(config_file, profile)?;
+            let _log_guard = logging::initialize(&config, log_level)?;
+
+            Ok(fetch(&config, resource).await?)
+        }
+        Command::Export {
+            resources,
+            format,
+            output_directory,
+            parquet_row_group_size,
+        } => {
+            let config = read_config(config_file, profile)?;
+            let _log_guard = logging::initialize(&config, log_level)?;
+            // export も一度きりなので、メトリクスは記録するだけで公開しない
+            let metrics = Metrics::new(&ProcessInfo::new(None))?;
+            let service = initialize_database_read_service(&config, &metrics, None)
+                .await?
+                .service;
+
+            let options = export::ExportOptions {
+                format,
+                parquet_row_group_size,
+            };
+            let report = export::run(&service, &resources, options, &output_directory).await;
+            println!("{}", serde_json::to_string(&report)?);
+
+            std::process::exit(if report.ok { 0 } else { 1 })
+        }
+        Command::Diff {
+            old,
+            new,
+            min_delta,
+            format,
+        } => {
+            let diff = diff::run(&old, &new, min_delta)?;
+            for line in &diff.malformed_lines {
+                eprintln!("{}:{}: {}", line.file, line.line, line.error);
+            }
+            diff::write(&diff, format, &mut std::io::stdout().lock())?;
+
+            if !diff.malformed_lines.is_empty() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Version => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn draining_is_cut_off_after_the_timeout() {
+        let (drain_started, drain_started_receiver) = oneshot::channel();
+        let server = async move {
+            drain_started.send(()).unwrap();
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok::<_, ()>(())
+        };
+
+        let started_at = tokio::time::Instant::now();
+        assert_eq!(
+            drain_within(server, drain_started_receiver, Duration::from_secs(10)).await,
+            Ok(Shutdown::TimedOut)
+        );
+        assert_eq!(started_at.elapsed(), Duration::from_secs(10));
+
+        let (_, never_started) = oneshot::channel::<()>();
+        assert_eq!(
+            drain_within(async { Ok::<_, ()>(()) }, never_started, Duration::ZERO).await,
+            Ok(Shutdown::Drained)
+        );
+    }
 }
@@ -0,0 +1,365 @@
+use crate::access_log::ClientIp;
+use crate::build_info::{self, ProcessInfo};
+use http::{Request, Response};
+use infra_repository_impl::circuit_breaker_data_source::CircuitBreakerMetrics;
+use infra_repository_impl::data_quality::DataQuality;
+use infra_repository_impl::last_known_good_data_source::Freshness;
+use infra_repository_impl::metered_data_source::FetchMetrics;
+use infra_repository_impl::mysql_data_source::{AcquireMetrics, ConnectionPoolStats};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower::{Layer, Service};
+
+/// メトリクスの取得時に値を読み出す、接続プロファイルごとのコネクションプール
+pub type ConnectionPoolStatsSource = Box<dyn Fn() -> ConnectionPoolStats + Send + Sync>;
+
+/// サーバー全体のメトリクス
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration_seconds: HistogramVec,
+    slow_requests: IntCounterVec,
+    in_flight_requests: IntGauge,
+    /// 処理中にパニックし、`INTERNAL` で応答したリクエストの数
+    pub request_panics: IntCounter,
+    /// サービス全体の状態。0なら通常どおり、1なら劣化している、2なら利用できない
+    pub service_health: IntGauge,
+    connection_pool_size: IntGaugeVec,
+    connection_pool_idle: IntGaugeVec,
+    connection_pool_max_size: IntGaugeVec,
+    pub fetch: FetchMetrics,
+    pub connection_acquire: AcquireMetrics,
+    pub circuit_breaker: CircuitBreakerMetrics,
+    pub freshness: Freshness,
+    pub data_quality: DataQuality,
+}
+
+impl Metrics {
+    pub fn new(process: &ProcessInfo) -> prometheus::Result<Self> {
+        let registry = Registry::new();
+
+        let requests = IntCounterVec::new(
+            Opts::new(
+                "seichi_game_api_requests_total",
+                "Number of handled API requests",
+            ),
+            &["route", "status"],
+        )?;
+        let request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "seichi_game_api_request_duration_seconds",
+                "Time taken to handle an API request",
+            ),
+            &["route"],
+        )?;
+        let slow_requests = IntCounterVec::new(
+            Opts::new(
+                "seichi_game_api_slow_requests_total",
+                "Number of API requests that took longer than the slow request threshold",
+            ),
+            &["route"],
+        )?;
+        let in_flight_requests = IntGauge::new(
+            "seichi_game_api_in_flight_requests",
+            "Number of API requests being handled",
+        )?;
+        let request_panics = IntCounter::new(
+            "seichi_game_api_request_panics_total",
+            "Number of API requests whose handler panicked",
+        )?;
+        let service_health = IntGauge::new(
+            "seichi_game_api_service_health",
+            "Health of the service (0 = ok, 1 = degraded, 2 = unavailable)",
+        )?;
+        let connection_pool_gauge = |name: &str, help: &str| {
+            IntGaugeVec::new(Opts::new(name, help), &["connection_profile"])
+        };
+        let connection_pool_size = connection_pool_gauge(
+            "seichi_game_api_connection_pool_size",
+            "Number of open connections to the source database, including those in use",
+        )?;
+        let connection_pool_idle = connection_pool_gauge(
+            "seichi_game_api_connection_pool_idle",
+            "Number of open but idle connections to the source database",
+        )?;
+        let connection_pool_max_size = connection_pool_gauge(
+            "seichi_game_api_connection_pool_max_size",
+            "Maximum number of connections to the source database",
+        )?;
+        let build_info = IntGaugeVec::new(
+            Opts::new(
+                "seichi_game_api_build_info",
+                "Version and build of the running server",
+            ),
+            &[
+                "version",
+                "git_commit",
+                "build_timestamp",
+                "rustc_version",
+                "features",
+                "profile",
+            ],
+        )?;
+        build_info
+            .with_label_values(&[
+                build_info::VERSION,
+                build_info::GIT_COMMIT,
+                &build_info::build_timestamp(),
+                build_info::RUSTC_VERSION,
+                &build_info::features().join(","),
+                process.profile().unwrap_or(""),
+            ])
+            .set(1);
+        let start_time_seconds = IntGauge::new(
+            "seichi_game_api_start_time_seconds",
+            "Start time of the server since the Unix epoch in seconds",
+        )?;
+        start_time_seconds.set(process.started_at().timestamp());
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(request_duration_seconds.clone()))?;
+        registry.register(Box::new(slow_requests.clone()))?;
+        registry.register(Box::new(in_flight_requests.clone()))?;
+        registry.register(Box::new(request_panics.clone()))?;
+        registry.register(Box::new(service_health.clone()))?;
+        registry.register(Box::new(connection_pool_size.clone()))?;
+        registry.register(Box::new(connection_pool_idle.clone()))?;
+        registry.register(Box::new(connection_pool_max_size.clone()))?;
+        registry.register(Box::new(build_info))?;
+        registry.register(Box::new(start_time_seconds))?;
+
+        let fetch = FetchMetrics::register(&registry)?;
+        let connection_acquire = AcquireMetrics::register(&registry)?;
+        let circuit_breaker = CircuitBreakerMetrics::register(&registry)?;
+        let freshness = Freshness::register(&registry)?;
+        let data_quality = DataQuality::register(&registry)?;
+
+        Ok(Self {
+            registry,
+            requests,
+            request_duration_seconds,
+            slow_requests,
+            in_flight_requests,
+            request_panics,
+            service_health,
+            connection_pool_size,
+            connection_pool_idle,
+            connection_pool_max_size,
+            fetch,
+            connection_acquire,
+            circuit_breaker,
+            freshness,
+            data_quality,
+        })
+    }
+
+    /// 設定によって公開するかが決まるメトリクスを登録するレジストリ
+    pub const fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// 取得時に値を読み出すメトリクスを更新し、全てのメトリクスをPrometheusのテキスト形式で書き出す
+    pub fn encode(
+        &self,
+        in_flight_requests: usize,
+        connection_pools: &[(String, ConnectionPoolStatsSource)],
+    ) -> Vec<u8> {
+        self.in_flight_requests
+            .set(i64::try_from(in_flight_requests).unwrap_or(i64::MAX));
+
+        for (profile, stats) in connection_pools {
+            let stats = stats();
+            self.connection_pool_size
+                .with_label_values(&[profile])
+                .set(i64::from(stats.size));
+            self.connection_pool_idle
+                .with_label_values(&[profile])
+                .set(i64::try_from(stats.idle).unwrap_or(i64::MAX));
+            self.connection_pool_max_size
+                .with_label_values(&[profile])
+                .set(i64::from(stats.max_size));
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Encoding metrics into a Vec");
+        buffer
+    }
+}
+
+/// メトリクスのラベルやログに使う、APIのパスの一覧。
+///
+/// カーディナリティを抑えるため、一覧に含まれないパスは全て `other` として扱う。
+#[derive(Clone)]
+pub struct Routes(Arc<[String]>);
+
+impl Routes {
+    pub fn new(routes: Vec<String>) -> Self {
+        Self(routes.into())
+    }
+
+    pub fn label(&self, path: &str) -> String {
+        if self.0.iter().any(|route| route == path) {
+            path.to_string()
+        } else {
+            "other".to_string()
+        }
+    }
+}
+
+/// APIリクエストの数と処理時間を記録し、処理に `slow_threshold` 以上かかったリクエストは WARN のログを出すレイヤー
+#[derive(Clone)]
+pub struct RequestMetricsLayer {
+    metrics: Arc<Metrics>,
+    routes: Routes,
+    slow_threshold: Duration,
+}
+
+impl RequestMetricsLayer {
+    pub fn new(metrics: Arc<Metrics>, routes: Routes, slow_threshold: Duration) -> Self {
+        Self {
+            metrics,
+            routes,
+            slow_threshold,
+        }
+    }
+}
+
+impl<S> Layer<S> for RequestMetricsLayer {
+    type Service = RequestMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestMetrics {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestMetrics<S> {
+    inner: S,
+    layer: RequestMetricsLayer,
+}
+
+impl<S, B, ResBody> Service<Request<B>> for RequestMetrics<S>
+where
+    S: Service<Request<B>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+    ResBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let route = self.layer.routes.label(request.uri().path());
+        let metrics = self.layer.metrics.clone();
+        let slow_threshold = self.layer.slow_threshold;
+        let client = request
+            .extensions()
+            .get::<ClientIp>()
+            .map(|ClientIp(address)| *address);
+        let started_at = Instant::now();
+        let response = self.inner.call(request);
+
+        Box::pin(async move {
+            let response = response.await;
+
+            let status = match &response {
+                Ok(response) => response.status().as_u16().to_string(),
+                Err(_) => "error".to_string(),
+            };
+            metrics.requests.with_label_values(&[&route, &status]).inc();
+            let elapsed = started_at.elapsed();
+            metrics
+                .request_duration_seconds
+                .with_label_values(&[&route])
+                .observe(elapsed.as_secs_f64());
+
+            if elapsed >= slow_threshold {
+                metrics.slow_requests.with_label_values(&[&route]).inc();
+                tracing::warn!(
+                    route = %route,
+                    status = %status,
+                    duration_ms = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+                    threshold_ms = u64::try_from(slow_threshold.as_millis()).unwrap_or(u64::MAX),
+                    client_ip = client.map(tracing::field::display),
+                    "slow request"
+                );
+            }
+
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn metric_families_are_exported_after_a_request() {
+        let metrics = Arc::new(Metrics::new(&ProcessInfo::new(None)).unwrap());
+        let service = RequestMetricsLayer::new(
+            metrics.clone(),
+            Routes::new(vec!["/known/Method".to_string()]),
+            Duration::ZERO,
+        )
+        .layer(tower::service_fn(|_: Request<()>| async {
+            Ok::<_, Infallible>(Response::new(()))
+        }));
+
+        for path in ["/known/Method", "/unknown/Method"] {
+            let request = Request::builder().uri(path).body(()).unwrap();
+            service.clone().oneshot(request).await.unwrap();
+        }
+
+        let stats: ConnectionPoolStatsSource = Box::new(|| ConnectionPoolStats {
+            size: 2,
+            idle: 1,
+            max_size: 5,
+        });
+        let text = String::from_utf8(metrics.encode(3, &[("default".to_string(), stats)])).unwrap();
+
+        for family in [
+            "seichi_game_api_requests_total",
+            "seichi_game_api_request_duration_seconds",
+            "seichi_game_api_slow_requests_total",
+            "seichi_game_api_in_flight_requests",
+            "seichi_game_api_service_health",
+            "seichi_game_api_connection_pool_size",
+            "seichi_game_api_connection_pool_idle",
+            "seichi_game_api_connection_pool_max_size",
+            "seichi_game_api_build_info",
+            "seichi_game_api_start_time_seconds",
+        ] {
+            assert!(
+                text.contains(&format!("# TYPE {family} ")),
+                "{family} is missing"
+            );
+        }
+        assert!(text
+            .contains(r#"seichi_game_api_requests_total{route="/known/Method",status="200"} 1"#));
+        assert!(text.contains(r#"seichi_game_api_requests_total{route="other",status="200"} 1"#));
+        assert!(text.contains(r#"seichi_game_api_slow_requests_total{route="other"} 1"#));
+        assert!(text.contains("seichi_game_api_in_flight_requests 3"));
+        assert!(text
+            .contains(r#"seichi_game_api_connection_pool_idle{connection_profile="default"} 1"#));
+    }
+}
@@ -0,0 +1,453 @@
+use crate::build_info::ProcessInfo;
+use crate::concurrency_limit::ConcurrencyLimitLayer;
+use crate::game_statistics::GameStatistics;
+use crate::health::{HealthReport, ServiceHealth};
+use crate::metrics::{ConnectionPoolStatsSource, Metrics};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use serde::Serialize;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 接続プロファイルのゲームDBが応答するかを確かめる
+pub type DatabasePing =
+    Box<dyn Fn() -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>> + Send + Sync>;
+
+/// 運用のためのHTTPエンドポイントが参照する状態
+pub struct OpsState {
+    pub process: ProcessInfo,
+    pub metrics: Arc<Metrics>,
+    pub concurrency_limit: ConcurrencyLimitLayer,
+    pub connection_pools: Vec<(String, ConnectionPoolStatsSource)>,
+    /// 終了の指示を受けたら `true` にする。以降 `/readyz` は失敗を返す
+    pub shutting_down: Arc<AtomicBool>,
+    /// `/readyz` で確かめる、接続プロファイルの名前とそのゲームDBへのping。確かめない場合は空にする
+    pub database_pings: Vec<(String, DatabasePing)>,
+    pub database_ping_timeout: Duration,
+    pub health: Arc<ServiceHealth>,
+    /// `/metrics` で公開するゲーム全体の集計値。公開しない場合は `None`
+    pub game_statistics: Option<Arc<GameStatistics>>,
+}
+
+#[derive(Serialize)]
+struct Check {
+    name: String,
+    ok: bool,
+}
+
+#[derive(Serialize)]
+struct Checks {
+    ok: bool,
+    checks: Vec<Check>,
+    /// サービス全体の状態。劣化していても応答はできるため、`ok` には影響しない
+    #[serde(skip_serializing_if = "Option::is_none")]
+    health: Option<HealthReport>,
+}
+
+fn json_response(status: StatusCode, body: &impl Serialize) -> Response<Body> {
+    let body = serde_json::to_vec(body).expect("Serializing a response body");
+
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .expect("Building a response from valid parts")
+}
+
+fn checks_response(checks: Vec<Check>, health: Option<HealthReport>) -> Response<Body> {
+    let ok = checks.iter().all(|check| check.ok);
+    let status = if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    json_response(status, &Checks { ok, checks, health })
+}
+
+/// このハンドラが実行されている時点でランタイムは応答しているため、他には何も確かめない
+fn liveness() -> Vec<Check> {
+    vec![Check {
+        name: "event_loop".to_string(),
+        ok: true,
+    }]
+}
+
+async fn readiness(state: &OpsState) -> Vec<Check> {
+    let mut checks = vec![Check {
+        name: "not_shutting_down".to_string(),
+        ok: !state.shutting_down.load(Ordering::SeqCst),
+    }];
+
+    for (profile, ping) in &state.database_pings {
+        // エラーの内容には接続先が含まれうるため、応答には含めずログにのみ出す
+        let ok = match tokio::time::timeout(state.database_ping_timeout, ping()).await {
+            Ok(Ok(())) => true,
+            Ok(Err(error)) => {
+                tracing::warn!(connection_profile = %profile, %error, "database ping failed");
+                false
+            }
+            Err(_) => {
+                tracing::warn!(connection_profile = %profile, "database ping timed out");
+                false
+            }
+        };
+
+        checks.push(Check {
+            name: format!("database:{profile}"),
+            ok,
+        });
+    }
+
+    checks
+}
+
+fn not_found() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::empty())
+        .expect("Building a response from valid parts")
+}
+
+/// `/schemas/{type}.json` で、応答の型のJSON Schemaを返す
+fn schema(path: &str) -> Response<Body> {
+    let schema = path
+        .strip_prefix("/schemas/")
+        .and_then(|file| file.strip_suffix(".json"))
+        .and_then(|name| domain::schema::schemas().remove(name));
+
+    match schema {
+        Some(schema) => json_response(StatusCode::OK, &schema),
+        None => not_found(),
+    }
+}
+
+async fn handle(state: &OpsState, request: &Request<Body>) -> Response<Body> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => {
+            // サービス全体の状態のメトリクスは求めたときに更新されるため、書き出す前に求めておく
+            state.health.report();
+            if let Some(game_statistics) = &state.game_statistics {
+                game_statistics.refresh_if_due().await;
+            }
+            Response::builder()
+                .header(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)
+                .body(Body::from(state.metrics.encode(
+                    state.concurrency_limit.in_flight_requests(),
+                    &state.connection_pools,
+                )))
+                .expect("Building a response from valid parts")
+        }
+        (&Method::GET, "/meta/info") => json_response(StatusCode::OK, &state.process.info()),
+        (&Method::GET, "/meta/data-quality") => {
+            json_response(StatusCode::OK, &state.metrics.data_quality.latest())
+        }
+        (&Method::GET, "/livez") => checks_response(liveness(), None),
+        (&Method::GET, "/readyz") => {
+            checks_response(readiness(state).await, Some(state.health.report()))
+        }
+        (&Method::GET, path) if path.starts_with("/schemas/") => schema(path),
+        _ => not_found(),
+    }
+}
+
+/// gRPCとは別のポートで、メトリクスなど運用のためのHTTPエンドポイントを提供するサーバーを起動する。
+///
+/// 待ち受けを始めたアドレスと、サーバーが終了するまで完了しないFutureを返す。
+pub fn bind(
+    address: SocketAddr,
+    state: OpsState,
+) -> hyper::Result<(SocketAddr, impl Future<Output = hyper::Result<()>>)> {
+    let state = Arc::new(state);
+
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(handle(&state, &request).await) }
+            }))
+        }
+    });
+
+    let server = hyper::Server::try_bind(&address)?.serve(make_service);
+
+    Ok((server.local_addr(), server))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::health::HealthThresholds;
+    use domain::app_models::{DataSourceError, VecDataSource};
+    use infra_repository_impl::circuit_breaker_data_source::{
+        CircuitBreaker, CircuitBreakerMetrics, CircuitBreakingDataSource,
+    };
+    use infra_repository_impl::data_quality::QualityReport;
+    use infra_repository_impl::last_known_good_data_source::LastKnownGoodDataSource;
+    use test_fixtures::FixedDataSource;
+
+    fn state(database_pings: Vec<(String, DatabasePing)>) -> OpsState {
+        state_with_breakers(database_pings, Vec::new())
+    }
+
+    fn state_with_breakers(
+        database_pings: Vec<(String, DatabasePing)>,
+        breakers: Vec<CircuitBreaker>,
+    ) -> OpsState {
+        let process = ProcessInfo::new(Some("staging".to_string()));
+        let metrics = Arc::new(Metrics::new(&process).unwrap());
+        let health = Arc::new(ServiceHealth::new(
+            metrics.freshness.clone(),
+            breakers,
+            HealthThresholds {
+                degraded_after_failures: 1,
+                unavailable_ratio: 1.0,
+            },
+            metrics.service_health.clone(),
+        ));
+
+        OpsState {
+            metrics,
+            health,
+            process,
+            concurrency_limit: ConcurrencyLimitLayer::new(1, "limited", 1),
+            connection_pools: Vec::new(),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            database_pings,
+            database_ping_timeout: Duration::from_secs(1),
+            game_statistics: None,
+        }
+    }
+
+    async fn get(state: &OpsState, path: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+        let response = handle(state, &request).await;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn readiness_fails_once_shutting_down_while_liveness_does_not() {
+        let state = state(Vec::new());
+        assert_eq!(get(&state, "/readyz").await.0, StatusCode::OK);
+
+        state.shutting_down.store(true, Ordering::SeqCst);
+
+        let (status, body) = get(&state, "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "ok": false,
+                "checks": [{ "name": "not_shutting_down", "ok": false }],
+                "health": {
+                    "state": "ok",
+                    "stale_resources": [],
+                    "unavailable_resources": [],
+                    "open_circuits": [],
+                },
+            })
+        );
+        assert_eq!(get(&state, "/livez").await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn readiness_reports_each_database_ping() {
+        let healthy: DatabasePing = Box::new(|| Box::pin(async { Ok(()) }));
+        let unreachable: DatabasePing =
+            Box::new(|| Box::pin(async { Err(anyhow::anyhow!("connection refused")) }));
+        let state = state(vec![
+            ("default".to_string(), healthy),
+            ("ranking".to_string(), unreachable),
+        ]);
+
+        let (status, body) = get(&state, "/readyz").await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            body["checks"][1],
+            serde_json::json!({ "name": "database:default", "ok": true })
+        );
+        assert_eq!(
+            body["checks"][2],
+            serde_json::json!({ "name": "database:ranking", "ok": false })
+        );
+    }
+
+    #[tokio::test]
+    async fn readiness_and_metrics_report_the_service_health() {
+        // 一度の失敗で開くサーキットブレーカーの後ろで、一度も成功していないリソース
+        let breaker = CircuitBreaker::new(
+            "ranking",
+            1,
+            Duration::from_secs(30),
+            CircuitBreakerMetrics::register(&prometheus::Registry::new()).unwrap(),
+        );
+        let state = state_with_breakers(Vec::new(), vec![breaker.clone()]);
+        let vote_counts = LastKnownGoodDataSource::new(
+            CircuitBreakingDataSource::new(
+                FixedDataSource::<u64>(Err(DataSourceError::Connection("refused".to_string()))),
+                breaker,
+            ),
+            "vote_counts",
+            state.metrics.freshness.clone(),
+        );
+        vote_counts.fetch().await.unwrap_err();
+
+        // 失敗していても、他のリクエストは処理できるため readiness は失敗させない
+        let (status, body) = get(&state, "/readyz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["health"],
+            serde_json::json!({
+                "state": "unavailable",
+                "stale_resources": [],
+                "unavailable_resources": ["vote_counts"],
+                "open_circuits": ["ranking"],
+            })
+        );
+
+        let request = Request::builder()
+            .uri("/metrics")
+            .body(Body::empty())
+            .unwrap();
+        let body = hyper::body::to_bytes(handle(&state, &request).await.into_body())
+            .await
+            .unwrap();
+        assert!(String::from_utf8(body.to_vec())
+            .unwrap()
+            .contains("seichi_game_api_service_health 2"));
+    }
+
+    #[tokio::test]
+    async fn data_quality_reports_the_latest_fetch_of_each_resource() {
+        let state = state(Vec::new());
+        assert_eq!(
+            get(&state, "/meta/data-quality").await.1,
+            serde_json::json!({})
+        );
+
+        state.metrics.data_quality.record(
+            "vote_counts",
+            QualityReport {
+                rows_fetched: 3,
+                rows_clamped: 1,
+                ..QualityReport::default()
+            },
+            1.0,
+        );
+
+        assert_eq!(
+            get(&state, "/meta/data-quality").await.1,
+            serde_json::json!({
+                "vote_counts": {
+                    "rows_fetched": 3,
+                    "rows_dropped": 0,
+                    "rows_clamped": 1,
+                    "duplicates_merged": 0,
+                    "parse_failures": 0,
+                    "names_sanitized": 0,
+                },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn schemas_are_published_by_type_name() {
+        let state = state(Vec::new());
+
+        let (status, body) = get(&state, "/schemas/PlayerBreakCount.json").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["title"], "PlayerBreakCount");
+
+        let request = Request::builder()
+            .uri("/schemas/Unknown.json")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            handle(&state, &request).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn info_reports_build_and_profile() {
+        let (status, body) = get(&state(Vec::new()), "/meta/info").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["profile"], "staging");
+        for field in [
+            "git_commit",
+            "build_timestamp",
+            "rustc_version",
+            "started_at",
+        ] {
+            assert!(body[field].is_string(), "{field} is missing");
+        }
+    }
+
+    /// 応答の状態コードと本文。実装によってマップのキーの順序が変わらないよう、キーを並べ替えて比べる
+    async fn assert_snapshot(name: &str, state: &OpsState, path: &str) {
+        let (status, body) = get(state, path).await;
+        let response = serde_json::json!({ "status": status.as_u16(), "body": body });
+
+        let mut settings = insta::Settings::clone_current();
+        settings.set_sort_maps(true);
+        settings.bind(|| {
+            // ビルドや起動のたびに変わる値は伏せる
+            insta::assert_json_snapshot!(name, response, {
+                ".body.version" => "[version]",
+                ".body.git_commit" => "[git_commit]",
+                ".body.build_timestamp" => "[build_timestamp]",
+                ".body.rustc_version" => "[rustc_version]",
+                ".body.features" => "[features]",
+                ".body.started_at" => "[started_at]",
+                ".body.uptime_seconds" => "[uptime_seconds]",
+            });
+        });
+    }
+
+    #[tokio::test]
+    async fn responses_match_their_snapshots() {
+        let healthy: DatabasePing = Box::new(|| Box::pin(async { Ok(()) }));
+        let unreachable: DatabasePing =
+            Box::new(|| Box::pin(async { Err(anyhow::anyhow!("connection refused")) }));
+        let state = state(vec![
+            ("default".to_string(), healthy),
+            ("ranking".to_string(), unreachable),
+        ]);
+        state.metrics.data_quality.record(
+            "break_counts",
+            QualityReport {
+                rows_fetched: 10,
+                rows_dropped: 1,
+                names_sanitized: 2,
+                ..QualityReport::default()
+            },
+            1.0,
+        );
+        state.metrics.data_quality.record(
+            "vote_counts",
+            QualityReport {
+                rows_fetched: 3,
+                rows_clamped: 1,
+                ..QualityReport::default()
+            },
+            1.0,
+        );
+
+        assert_snapshot("ops_livez", &state, "/livez").await;
+        assert_snapshot("ops_readyz", &state, "/readyz").await;
+        assert_snapshot("ops_meta_info", &state, "/meta/info").await;
+        assert_snapshot("ops_meta_data_quality", &state, "/meta/data-quality").await;
+    }
+}
@@ -0,0 +1,69 @@
+use prometheus::IntCounter;
+use std::any::Any;
+use std::panic::PanicInfo;
+use tower_http::catch_panic::CatchPanicLayer;
+
+/// パニックの内容を、文字列で渡されていればその文字列として取り出す
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("<non-string panic payload>")
+}
+
+fn log_panic(info: &PanicInfo<'_>) {
+    let location = info
+        .location()
+        .map(|location| format!("{}:{}", location.file(), location.line()));
+
+    // フックはパニックしたスレッドで呼ばれるため、リクエストのspanの中で起きたものにはリクエストIDが付く
+    tracing::error!(
+        panic = panic_message(info.payload()),
+        location = location.as_deref().unwrap_or("<unknown>"),
+        backtrace = ?backtrace::Backtrace::new(),
+        "panicked"
+    );
+}
+
+/// パニックをバックトレースと共に ERROR のログに出すフックを、既に設定されているフックの前に差し込む。
+///
+/// リクエストの処理中だけでなく、`tokio::spawn` したタスクのパニックも黙って消えずにログに残る。
+/// ログの出力を設定した後に呼ぶこと。
+pub fn install_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        log_panic(info);
+        previous(info);
+    }));
+}
+
+/// リクエストの処理中のパニックを捕まえ、接続を切らずに `INTERNAL` のステータスで応答するレイヤー。
+///
+/// パニックの内容は応答に含めない。捕まえたパニックは `panics` に数える。
+pub fn catch_panic_layer(
+    panics: IntCounter,
+) -> CatchPanicLayer<
+    impl Fn(Box<dyn Any + Send>) -> http::Response<tonic::body::BoxBody> + Clone + Send + Sync + 'static,
+> {
+    CatchPanicLayer::custom(move |_: Box<dyn Any + Send>| {
+        panics.inc();
+        tonic::Status::internal("the request handler panicked").to_http()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn panic_messages_are_extracted_from_strings_only() {
+        let literal: Box<dyn Any + Send> = Box::new("literal");
+        let formatted: Box<dyn Any + Send> = Box::new(format!("formatted {}", 1));
+        let other: Box<dyn Any + Send> = Box::new(1_u8);
+
+        assert_eq!(panic_message(&*literal), "literal");
+        assert_eq!(panic_message(&*formatted), "formatted 1");
+        assert_eq!(panic_message(&*other), "<non-string panic payload>");
+    }
+}
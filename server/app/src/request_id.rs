@@ -0,0 +1,130 @@
+use http::{HeaderValue, Request, Response};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+
+/// リクエストを識別するためのヘッダー。gRPCではメタデータ `x-request-id` として読み書きできる
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// クライアントから受け取ったIDは、ログに書き出しても崩れないものだけを使う
+fn is_valid_request_id(value: &HeaderValue) -> bool {
+    let bytes = value.as_bytes();
+
+    (1..=MAX_REQUEST_ID_LENGTH).contains(&bytes.len())
+        && bytes
+            .iter()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.'))
+}
+
+fn generate_request_id() -> HeaderValue {
+    HeaderValue::from_str(&uuid::Uuid::new_v4().to_string())
+        .expect("UUID is always a valid header value")
+}
+
+/// 全てのリクエストに `x-request-id` を付けるレイヤー。
+///
+/// クライアントが有効なIDを送ってきた場合はそれを、そうでなければ新しく生成したUUIDv4を使い、
+/// 内側のサービスへのリクエストと応答のヘッダーの両方に設定する。
+/// リクエストのspanはこのレイヤーより内側で作り、IDを含めること。
+#[derive(Clone, Default)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestId<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestId { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestId<S> {
+    inner: S,
+}
+
+impl<S, B, ResBody> Service<Request<B>> for RequestId<S>
+where
+    S: Service<Request<B>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .filter(|value| is_valid_request_id(value))
+            .cloned()
+            .unwrap_or_else(generate_request_id);
+        request
+            .headers_mut()
+            .insert(REQUEST_ID_HEADER, request_id.clone());
+
+        let response = self.inner.call(request);
+
+        Box::pin(async move {
+            let mut response = response.await?;
+            response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    /// 内側のサービスが受け取ったIDをそのまま応答の本文として返す
+    async fn round_trip(request_id: Option<&str>) -> (String, String) {
+        let service = RequestIdLayer.layer(tower::service_fn(|request: Request<()>| async move {
+            let seen = request.headers()[REQUEST_ID_HEADER]
+                .to_str()
+                .unwrap()
+                .to_string();
+            Ok::<_, Infallible>(Response::new(seen))
+        }));
+
+        let mut request = Request::builder();
+        if let Some(request_id) = request_id {
+            request = request.header(REQUEST_ID_HEADER, request_id);
+        }
+        let response = service.oneshot(request.body(()).unwrap()).await.unwrap();
+
+        let echoed = response.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        (response.into_body(), echoed)
+    }
+
+    #[tokio::test]
+    async fn valid_request_id_is_propagated_and_echoed() {
+        let (seen, echoed) = round_trip(Some("client-1.retry_2")).await;
+
+        assert_eq!(seen, "client-1.retry_2");
+        assert_eq!(echoed, "client-1.retry_2");
+    }
+
+    #[tokio::test]
+    async fn missing_or_invalid_request_id_is_replaced_with_uuid() {
+        let too_long = "a".repeat(MAX_REQUEST_ID_LENGTH + 1);
+
+        for request_id in [None, Some("has space"), Some(too_long.as_str())] {
+            let (seen, echoed) = round_trip(request_id).await;
+
+            assert_eq!(seen, echoed);
+            assert!(uuid::Uuid::parse_str(&seen).is_ok(), "{seen} is not a UUID");
+        }
+    }
+}
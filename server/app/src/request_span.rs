@@ -0,0 +1,45 @@
+use http::{Request, Response};
+use std::time::Duration;
+use tracing::Span;
+
+use crate::request_id::REQUEST_ID_HEADER;
+
+/// リクエストごとのspan。ゲームDBからの取得などのspanはこの下に作られる。
+///
+/// gRPCのパスは `/<サービス名>/<メソッド名>` なので、パスをそのままフィールドにしてもカーディナリティは抑えられる。
+/// `request_id` は外側の [`crate::request_id::RequestIdLayer`] が設定したものを記録する。
+pub fn make_span<B>(request: &Request<B>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok());
+
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = request.uri().path(),
+        request_id,
+        status = tracing::field::Empty,
+        grpc_status = tracing::field::Empty,
+    )
+}
+
+/// 応答のステータスをリクエストのspanに記録する。
+///
+/// 正常な応答の `grpc-status` はトレーラーで送られるため、ヘッダーに含まれるエラーの場合のみ記録される。
+pub fn on_response<B>(response: &Response<B>, latency: Duration, span: &Span) {
+    span.record("status", response.status().as_u16());
+
+    if let Some(grpc_status) = response
+        .headers()
+        .get("grpc-status")
+        .and_then(|value| value.to_str().ok())
+    {
+        span.record("grpc_status", grpc_status);
+    }
+
+    tracing::debug!(
+        latency_ms = u64::try_from(latency.as_millis()).unwrap_or(u64::MAX),
+        "finished processing request"
+    );
+}
@@ -0,0 +1,267 @@
+use config::{AppConfig, SourceDatabaseConfig};
+use infra_repository_impl::csv_data_source::CsvDataSource;
+use infra_repository_impl::mysql_data_source::{self, Probe};
+use serde::Serialize;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+
+/// 一つの確認の結果
+#[derive(Serialize, Debug)]
+pub struct CheckResult {
+    pub name: String,
+    pub ok: bool,
+    /// 失敗した理由
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// 全ての確認の結果
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub ok: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+impl CheckResult {
+    fn passed(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ok: true,
+            error: None,
+            duration_ms: 0,
+        }
+    }
+}
+
+impl Report {
+    fn new(checks: Vec<CheckResult>) -> Self {
+        Self {
+            ok: checks.iter().all(|check| check.ok),
+            checks,
+        }
+    }
+}
+
+/// `test` を `timeout` まで待ち、その結果を `name` の確認の結果とする
+async fn check<T>(
+    name: String,
+    timeout: Duration,
+    test: impl Future<Output = anyhow::Result<T>>,
+) -> (CheckResult, Option<T>) {
+    let started_at = Instant::now();
+    let result = match tokio::time::timeout(timeout, test).await {
+        Ok(result) => result.map_err(|error| format!("{error:#}")),
+        Err(_) => Err(format!("timed out after {} ms", timeout.as_millis())),
+    };
+    let duration_ms = u64::try_from(started_at.elapsed().as_millis()).unwrap_or(u64::MAX);
+
+    match result {
+        Ok(value) => (
+            CheckResult {
+                name,
+                ok: true,
+                error: None,
+                duration_ms,
+            },
+            Some(value),
+        ),
+        Err(error) => (
+            CheckResult {
+                name,
+                ok: false,
+                error: Some(error),
+                duration_ms,
+            },
+            None,
+        ),
+    }
+}
+
+/// `address` で待ち受けられるかを確かめる。確かめた後はすぐにソケットを閉じる
+async fn bindable(address: Result<SocketAddr, std::net::AddrParseError>) -> anyhow::Result<()> {
+    drop(TcpListener::bind(address?).await?);
+    Ok(())
+}
+
+/// 接続プロファイルのCA証明書が読み込め、PEMの証明書を含んでいるかを確かめる
+fn readable_ca(profile: &SourceDatabaseConfig) -> anyhow::Result<()> {
+    if let Some(path) = &profile.ssl_ca {
+        let content = std::fs::read_to_string(path)
+            .map_err(|error| anyhow::anyhow!("failed to read {}: {error}", path.display()))?;
+        anyhow::ensure!(
+            content.contains("-----BEGIN CERTIFICATE-----"),
+            "{} contains no PEM certificate",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// 読み込んで検証した設定で、サーバーが起動して応答できるかを確かめる。
+///
+/// 各確認は `timeout` で打ち切るため、ゲームDBに届かなくても確認の数に比例した時間で終わる。
+/// ゲームDBへは接続プロファイルごとに一つだけ接続し、最後に閉じる。待ち受けたソケットもすぐに閉じる。
+/// CSVファイルから読み出す場合は、有効なリソースのファイルを読み込んで検証する。
+pub async fn run(config: &AppConfig, timeout: Duration) -> Report {
+    let mut checks = vec![CheckResult::passed("config")];
+
+    let (result, _) = check(
+        "listen:grpc".to_string(),
+        timeout,
+        bindable(config.http_config.socket_address()),
+    )
+    .await;
+    checks.push(result);
+    if let Some(address) = config.ops_config.socket_address() {
+        let (result, _) = check("listen:ops".to_string(), timeout, bindable(address)).await;
+        checks.push(result);
+    }
+
+    let resources = &config.resources_config;
+    let last_quit_precision = resources.last_quits.timestamp_precision.unwrap_or_default();
+    if let Some(directory) = &config.csv_source_config.directory {
+        let data_source = CsvDataSource::new(directory, config.csv_source_config.strict);
+        for (resource, _) in resources.iter().filter(|(_, resource)| resource.enabled) {
+            let (result, _) = check(
+                format!("csv:{resource}"),
+                timeout,
+                data_source.load(resource),
+            )
+            .await;
+            checks.push(result);
+        }
+    }
+
+    let profiles = config
+        .source_database_config
+        .iter()
+        .map(|profile| ("default", profile))
+        .chain(
+            config
+                .source_database_profiles
+                .iter()
+                .map(|(name, profile)| (name.as_str(), profile)),
+        );
+
+    for (name, profile) in profiles {
+        // 有効なリソースが使わない接続プロファイルには、サーバーも接続しない
+        let enabled_resources = resources
+            .iter()
+            .filter(|(_, resource)| {
+                resource.enabled
+                    && resource.connection_profile.as_deref().unwrap_or("default") == name
+            })
+            .map(|(resource, _)| resource)
+            .collect::<Vec<_>>();
+        if enabled_resources.is_empty() {
+            continue;
+        }
+
+        if profile.ssl_ca.is_some() {
+            let (result, _) = check(format!("tls:{name}"), timeout, async {
+                readable_ca(profile)
+            })
+            .await;
+            checks.push(result);
+        }
+
+        let (result, probe) = check(
+            format!("database:{name}"),
+            timeout,
+            Probe::connect(profile, name),
+        )
+        .await;
+        checks.push(result);
+
+        let mut probe = probe;
+        for resource in enabled_resources {
+            let query = mysql_data_source::resource_query(resource, last_quit_precision)
+                .expect("Every resource has a query");
+            let (result, _) = check(format!("query:{resource}"), timeout, async {
+                match &mut probe {
+                    Some(probe) => probe.run_limited(query).await,
+                    None => Err(anyhow::anyhow!(
+                        "not run because connecting to connection profile {name:?} failed"
+                    )),
+                }
+            })
+            .await;
+            checks.push(result);
+        }
+
+        if let Some(probe) = probe {
+            // 行儀よく閉じられなかった接続も、破棄すればソケットは閉じる
+            let _ = tokio::time::timeout(timeout, probe.close()).await;
+        }
+    }
+
+    Report::new(checks)
+}
+
+/// 設定を読み込めなかったか、検証で問題が見つかったときの結果。他の確認は行わない
+pub fn invalid_config(error: &str) -> Report {
+    Report::new(vec![CheckResult {
+        name: "config".to_string(),
+        ok: false,
+        error: Some(error.to_string()),
+        duration_ms: 0,
+    }])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn checks_that_do_not_finish_are_cut_off() {
+        let (result, value) = check(
+            "database:default".to_string(),
+            Duration::from_secs(5),
+            std::future::pending::<anyhow::Result<()>>(),
+        )
+        .await;
+
+        assert!(!result.ok);
+        assert_eq!(result.error.as_deref(), Some("timed out after 5000 ms"));
+        assert!(value.is_none());
+    }
+
+    #[tokio::test]
+    async fn addresses_in_use_are_not_bindable() {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let address = listener.local_addr().unwrap();
+
+        assert!(bindable(Ok(address)).await.is_err());
+        drop(listener);
+        assert!(bindable(Ok(address)).await.is_ok());
+    }
+
+    #[test]
+    fn the_report_fails_if_any_check_fails() {
+        let report = Report::new(vec![
+            CheckResult::passed("config"),
+            CheckResult {
+                name: "listen:grpc".to_string(),
+                ok: false,
+                error: Some("address in use".to_string()),
+                duration_ms: 1,
+            },
+        ]);
+
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "ok": false,
+                "checks": [
+                    { "name": "config", "ok": true, "duration_ms": 0 },
+                    { "name": "listen:grpc", "ok": false, "error": "address in use", "duration_ms": 1 },
+                ],
+            })
+        );
+    }
+}
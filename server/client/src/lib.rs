@@ -0,0 +1,324 @@
+//! APIサーバーの `ReadService` を呼び出し、応答をドメインのモデルとして返すクライアント。
+//!
+//! メッセージはサーバーと同じく `infra_grpc` が生成した型で読み、`domain` のモデルに変換して返すため、
+//! サーバーとクライアントで定義が食い違うことはない。
+//!
+//! 既定の `transport` フィーチャーではHTTP/2でgRPCを話す `Channel` を使う。
+//! ブラウザで動かす場合は `default-features = false, features = ["wasm"]` とし、
+//! gRPC-Webを受け付けるサーバー (`HTTP_GRPC_WEB=true`) に `SeichiGameApiClient::web` で接続する。
+//!
+//! サーバーは上位の一部だけを返す口を持たず、各メソッドは全てのプレイヤーのレコードを返す。
+//! ランキングとして並べる場合は、同率の並びが常に同じになるよう `domain::models::sort_for_ranking` を使う。
+
+use chrono::{DateTime, Utc};
+use domain::models::{
+    NameValidation, Player, PlayerBreakCount, PlayerBuildCount, PlayerLastQuit, PlayerName,
+    PlayerPlayTicks, PlayerUuid, PlayerVoteCount,
+};
+use infra_grpc::buf_generated::gigantic_minecraft::seichi_game_data::v1;
+use infra_grpc::buf_generated::gigantic_minecraft::seichi_game_data::v1::read_service_server::ReadServiceServer;
+use infra_grpc::buf_generated::gigantic_minecraft::seichi_game_data::v1::{
+    BreakCountsResponse, BuildCountsResponse, LastQuitsResponse, PlayTicksResponse,
+    VoteCountsResponse,
+};
+use infra_grpc::read_service::ReadServiceImpl;
+use prost::Message;
+use std::fmt::{Display, Formatter};
+use tonic::body::BoxBody;
+use tonic::client::{Grpc, GrpcService};
+use tonic::codec::ProstCodec;
+use tonic::codegen::{Body, Bytes, StdError};
+use tonic::server::NamedService;
+#[cfg(feature = "transport")]
+use tonic::transport::Channel;
+use tonic::Code;
+
+/// 呼び出しが失敗した理由
+#[derive(Debug)]
+pub enum ClientError {
+    /// 接続先のURLを解釈できなかった
+    InvalidUrl(String),
+    /// サーバーに接続できなかった
+    Transport(StdError),
+    /// 要求したリソースはサーバーの設定で無効化されている (`UNIMPLEMENTED`)
+    Disabled(String),
+    /// サーバーがゲームDBから一時的に取得できない (`UNAVAILABLE`)
+    Unavailable(String),
+    /// その他の状態コードで失敗した
+    Status(tonic::Status),
+    /// 応答をモデルに変換できなかった
+    InvalidResponse(String),
+}
+
+impl ClientError {
+    /// 時間を置いて再び呼び出せば成功しうるかどうか
+    pub const fn is_retryable(&self) -> bool {
+        matches!(self, Self::Transport(_) | Self::Unavailable(_))
+    }
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUrl(detail) => write!(f, "invalid server URL: {detail}"),
+            Self::Transport(error) => write!(f, "failed to connect to the server: {error}"),
+            Self::Disabled(message) => write!(f, "the resource is disabled: {message}"),
+            Self::Unavailable(message) => write!(f, "the server is unavailable: {message}"),
+            Self::Status(status) => write!(
+                f,
+                "the server returned {:?}: {}",
+                status.code(),
+                status.message()
+            ),
+            Self::InvalidResponse(detail) => write!(f, "invalid response: {detail}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transport(error) => Some(error.as_ref()),
+            Self::Status(status) => Some(status),
+            _ => None,
+        }
+    }
+}
+
+impl From<tonic::Status> for ClientError {
+    fn from(status: tonic::Status) -> Self {
+        match status.code() {
+            Code::Unimplemented => Self::Disabled(status.message().to_string()),
+            Code::Unavailable => Self::Unavailable(status.message().to_string()),
+            _ => Self::Status(status),
+        }
+    }
+}
+
+fn to_player(player: Option<v1::Player>) -> Result<Player, ClientError> {
+    let player =
+        player.ok_or_else(|| ClientError::InvalidResponse("a record has no player".to_string()))?;
+    let invalid = |error: &dyn Display| ClientError::InvalidResponse(error.to_string());
+
+    Ok(Player {
+        uuid: PlayerUuid::try_from(player.uuid.as_str()).map_err(|error| invalid(&error))?,
+        // サーバーが正規化した名前なので、長さは確かめない
+        last_known_name: PlayerName::new(&player.last_known_name, NameValidation::Lenient)
+            .map_err(|error| invalid(&error))?,
+    })
+}
+
+fn to_last_quit(last_quit: v1::PlayerLastQuit) -> Result<PlayerLastQuit, ClientError> {
+    let date_time =
+        DateTime::parse_from_rfc3339(&last_quit.rfc_3339_date_time).map_err(|error| {
+            ClientError::InvalidResponse(format!(
+                "{:?} is not an RFC 3339 date-time: {error}",
+                last_quit.rfc_3339_date_time
+            ))
+        })?;
+
+    Ok(PlayerLastQuit {
+        player: to_player(last_quit.player)?,
+        last_quit: date_time.with_timezone(&Utc),
+    })
+}
+
+/// `ReadService` のクライアント。
+///
+/// `T` はリクエストを送る手段で、`Channel` か、`wasm` フィーチャーでは `tonic_web_wasm_client::Client` を使う。
+/// 複製したものは同じ接続を共有する。応答は全てのプレイヤーのレコードを含むため、受け取る大きさは制限しない。
+#[derive(Clone)]
+pub struct SeichiGameApiClient<T> {
+    grpc: Grpc<T>,
+}
+
+#[cfg(feature = "transport")]
+impl SeichiGameApiClient<Channel> {
+    /// `url` (例: `http://localhost:50051`) のサーバーに接続する
+    pub async fn connect(url: impl Into<String>) -> Result<Self, ClientError> {
+        let channel = Channel::from_shared(url.into())
+            .map_err(|error| ClientError::InvalidUrl(error.to_string()))?
+            .connect()
+            .await
+            .map_err(|error| ClientError::Transport(error.into()))?;
+
+        Ok(Self::new(channel))
+    }
+}
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+impl SeichiGameApiClient<tonic_web_wasm_client::Client> {
+    /// ブラウザの `fetch` で、`url` (例: `https://api.example.com`) のサーバーにgRPC-Webで要求を送る
+    pub fn web(url: impl Into<String>) -> Self {
+        Self::new(tonic_web_wasm_client::Client::new(url.into()))
+    }
+}
+
+impl<T> SeichiGameApiClient<T>
+where
+    T: GrpcService<BoxBody>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    /// 要求を `transport` で送るクライアント
+    pub fn new(transport: T) -> Self {
+        Self {
+            grpc: Grpc::new(transport).max_decoding_message_size(usize::MAX),
+        }
+    }
+
+    // サーバーはクライアントのコードを生成しないため、メソッドのパスを組み立てて呼び出す
+    async fn call<Response: Message + Default + 'static>(
+        &mut self,
+        method: &str,
+    ) -> Result<Response, ClientError> {
+        let path = format!(
+            "/{}/{method}",
+            <ReadServiceServer<ReadServiceImpl> as NamedService>::NAME
+        );
+
+        self.grpc
+            .ready()
+            .await
+            .map_err(|error| ClientError::Transport(error.into()))?;
+        let response = self
+            .grpc
+            .unary(
+                tonic::Request::new(pbjson_types::Empty {}),
+                path.try_into().expect("Method paths are valid"),
+                ProstCodec::<pbjson_types::Empty, Response>::default(),
+            )
+            .await?;
+
+        Ok(response.into_inner())
+    }
+
+    pub async fn last_quits(&mut self) -> Result<Vec<PlayerLastQuit>, ClientError> {
+        let response: LastQuitsResponse = self.call("LastQuits").await?;
+        response.results.into_iter().map(to_last_quit).collect()
+    }
+
+    pub async fn break_counts(&mut self) -> Result<Vec<PlayerBreakCount>, ClientError> {
+        let response: BreakCountsResponse = self.call("BreakCounts").await?;
+        response
+            .results
+            .into_iter()
+            .map(|break_count| {
+                Ok(PlayerBreakCount {
+                    player: to_player(break_count.player)?,
+                    break_count: break_count.break_count,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn build_counts(&mut self) -> Result<Vec<PlayerBuildCount>, ClientError> {
+        let response: BuildCountsResponse = self.call("BuildCounts").await?;
+        response
+            .results
+            .into_iter()
+            .map(|build_count| {
+                Ok(PlayerBuildCount {
+                    player: to_player(build_count.player)?,
+                    build_count: build_count.build_count,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn play_ticks(&mut self) -> Result<Vec<PlayerPlayTicks>, ClientError> {
+        let response: PlayTicksResponse = self.call("PlayTicks").await?;
+        response
+            .results
+            .into_iter()
+            .map(|play_ticks| {
+                Ok(PlayerPlayTicks {
+                    player: to_player(play_ticks.player)?,
+                    play_ticks: play_ticks.play_ticks,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn vote_counts(&mut self) -> Result<Vec<PlayerVoteCount>, ClientError> {
+        let response: VoteCountsResponse = self.call("VoteCounts").await?;
+        response
+            .results
+            .into_iter()
+            .map(|vote_count| {
+                Ok(PlayerVoteCount {
+                    player: to_player(vote_count.player)?,
+                    vote_count: vote_count.vote_count,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn player(uuid: &str) -> Option<v1::Player> {
+        Some(v1::Player {
+            uuid: uuid.to_string(),
+            last_known_name: "unchama".to_string(),
+        })
+    }
+
+    #[test]
+    fn last_quits_are_read_back_into_models() {
+        let last_quit = to_last_quit(v1::PlayerLastQuit {
+            player: player("b66cc3f6-a045-42ad-b4b8-320f20caf140"),
+            rfc_3339_date_time: "2023-04-01T12:34:56Z".to_string(),
+        })
+        .unwrap();
+
+        assert_eq!(
+            last_quit.player.uuid.to_string(),
+            "b66cc3f6-a045-42ad-b4b8-320f20caf140"
+        );
+        assert_eq!(last_quit.player.last_known_name.as_str(), "unchama");
+        assert_eq!(
+            domain::models::to_rfc_3339(&last_quit.last_quit),
+            "2023-04-01T12:34:56Z"
+        );
+    }
+
+    #[test]
+    fn malformed_records_are_invalid_responses() {
+        assert!(matches!(
+            to_player(None),
+            Err(ClientError::InvalidResponse(_))
+        ));
+        assert!(matches!(
+            to_player(player("not-a-uuid")),
+            Err(ClientError::InvalidResponse(_))
+        ));
+        assert!(matches!(
+            to_last_quit(v1::PlayerLastQuit {
+                player: player("b66cc3f6a04542adb4b8320f20caf140"),
+                rfc_3339_date_time: "yesterday".to_string(),
+            }),
+            Err(ClientError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn statuses_are_classified() {
+        let disabled = ClientError::from(tonic::Status::unimplemented(
+            "last_quits is disabled on this server",
+        ));
+        assert!(
+            matches!(&disabled, ClientError::Disabled(message) if message == "last_quits is disabled on this server")
+        );
+        assert!(!disabled.is_retryable());
+
+        assert!(ClientError::from(tonic::Status::unavailable("retry later")).is_retryable());
+        assert!(matches!(
+            ClientError::from(tonic::Status::internal("the request handler panicked")),
+            ClientError::Status(status) if status.code() == Code::Internal
+        ));
+    }
+}
@@ -0,0 +1,38 @@
+//! wasm32向けにビルドしたクライアントが、gRPC-Webで実際のサーバーから読めることを、Node.js上で確かめる。
+//!
+//! サーバーは `seichi-game-api` のテスト `the_wasm_client_reads_the_served_records_over_grpc_web` が同じプロセスで起動し、
+//! そのURLと期待する応答を環境変数としてこのテストのビルドに渡す。Node.js (18以降) と `wasm-pack` が必要なため、
+//! `cargo test -p seichi-game-api -- --ignored the_wasm_client` で実行する。
+
+#![cfg(all(feature = "wasm", target_arch = "wasm32"))]
+
+use client::{ClientError, SeichiGameApiClient};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+/// 起動したサーバーのURL
+const URL: Option<&str> = option_env!("SEICHI_API_WASM_SMOKE_URL");
+
+/// サーバーが返す整地量を `<UUID>:<整地量>` のカンマ区切りで並べたもの
+const EXPECTED_BREAK_COUNTS: Option<&str> = option_env!("SEICHI_API_WASM_SMOKE_BREAK_COUNTS");
+
+#[wasm_bindgen_test]
+async fn served_records_are_read_over_grpc_web() {
+    let (url, expected_break_counts) = URL.zip(EXPECTED_BREAK_COUNTS).expect(
+        "SEICHI_API_WASM_SMOKE_URL and SEICHI_API_WASM_SMOKE_BREAK_COUNTS are set by the seichi-game-api test that starts the server",
+    );
+    let mut client = SeichiGameApiClient::web(url);
+
+    let break_counts = client
+        .break_counts()
+        .await
+        .unwrap()
+        .iter()
+        .map(|record| format!("{}:{}", record.player.uuid, record.break_count))
+        .collect::<Vec<_>>();
+    assert_eq!(break_counts.join(","), expected_break_counts);
+
+    assert!(matches!(
+        client.last_quits().await,
+        Err(ClientError::Disabled(_))
+    ));
+}
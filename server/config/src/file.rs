@@ -0,0 +1,646 @@
+use crate::RESOURCE_NAMES;
+
+use toml::value::{Table, Value};
+
+/// 設定ファイルのセクションと、それに対応する環境変数の接頭辞・キーの対応
+struct Section {
+    name: &'static str,
+    env_prefix: &'static str,
+    layout: Layout,
+    keys: &'static [&'static str],
+}
+
+enum Layout {
+    /// `[name]` の表一つ。キーは `{env_prefix}{KEY}` に対応する
+    Single,
+    /// `[name.<表の名前>]` の形で、名前付きの表を並べるもの。キーは `{env_prefix}{表の名前}_{KEY}` に対応する
+    Named {
+        /// 表の名前の一覧をカンマ区切りで設定する環境変数
+        names_variable: Option<&'static str>,
+        /// 受け付ける表の名前。`None` なら任意の名前を受け付ける
+        allowed_names: Option<&'static [&'static str]>,
+    },
+}
+
+const SOURCE_DATABASE_KEYS: &[&str] = &[
+    "url",
+    "url_file",
+    "host",
+    "port",
+    "resolve_srv",
+    "database_name",
+    "user",
+    "password",
+    "password_file",
+    "max_connections",
+    "ssl_mode",
+    "ssl_ca",
+    "player_name_validation",
+    "out_of_range_counters",
+    "circuit_breaker_failures",
+    "circuit_breaker_cooldown_seconds",
+];
+
+const SECTIONS: &[Section] = &[
+    Section {
+        name: "source_database",
+        env_prefix: "DB_",
+        layout: Layout::Single,
+        keys: SOURCE_DATABASE_KEYS,
+    },
+    Section {
+        name: "source_database_profiles",
+        env_prefix: "DB_PROFILE_",
+        layout: Layout::Named {
+            names_variable: Some("DB_PROFILES"),
+            allowed_names: None,
+        },
+        keys: SOURCE_DATABASE_KEYS,
+    },
+    Section {
+        name: "http",
+        env_prefix: "HTTP_",
+        layout: Layout::Single,
+        // host, port は以前の名前
+        keys: &[
+            "listen_address",
+            "listen_port",
+            "max_concurrent_requests",
+            "retry_after_seconds",
+            "trusted_proxy_depth",
+            "drain_timeout_seconds",
+            "grpc_web",
+            "host",
+            "port",
+        ],
+    },
+    Section {
+        name: "ops",
+        env_prefix: "OPS_",
+        layout: Layout::Single,
+        keys: &[
+            "listen_address",
+            "listen_port",
+            "readiness_checks_database",
+            "readiness_database_timeout_millis",
+            "shutdown_delay_seconds",
+            "health_degraded_after_failures",
+            "health_unavailable_ratio",
+            "game_statistics",
+            "game_statistics_active_windows_days",
+            "game_statistics_interval_seconds",
+        ],
+    },
+    Section {
+        name: "logging",
+        env_prefix: "LOG_",
+        layout: Layout::Single,
+        keys: &[
+            "filter",
+            "format",
+            "file_directory",
+            "file_rotation",
+            "slow_fetch_threshold_millis",
+            "slow_request_threshold_millis",
+            "slow_acquire_threshold_millis",
+            "data_quality_warn_ratio",
+            "raw_player_names",
+            "access_log",
+            "access_log_excluded_paths",
+        ],
+    },
+    Section {
+        name: "tracing",
+        env_prefix: "TRACE_",
+        layout: Layout::Single,
+        keys: &["otlp_endpoint", "service_name", "sampling_ratio"],
+    },
+    Section {
+        name: "error_reporting",
+        env_prefix: "ERROR_REPORTING_",
+        layout: Layout::Single,
+        keys: &["dsn", "dsn_file", "environment", "dedup_window_seconds"],
+    },
+    Section {
+        name: "resources",
+        env_prefix: "RESOURCE_",
+        layout: Layout::Named {
+            names_variable: None,
+            allowed_names: Some(RESOURCE_NAMES),
+        },
+        keys: &["enabled", "connection_profile", "timestamp_precision"],
+    },
+    Section {
+        name: "csv_source",
+        env_prefix: "CSV_SOURCE_",
+        layout: Layout::Single,
+        keys: &["directory", "strict"],
+    },
+];
+
+struct Flattened {
+    pairs: Vec<(String, String)>,
+    unknown_keys: Vec<String>,
+}
+
+impl Flattened {
+    fn push_table(
+        &mut self,
+        path: &str,
+        env_prefix: &str,
+        keys: &[&str],
+        table: Table,
+    ) -> Result<(), String> {
+        for (key, value) in table {
+            if !keys.contains(&key.as_str()) {
+                self.unknown_keys.push(format!("{path}.{key}"));
+                continue;
+            }
+
+            let value = match value {
+                Value::String(value) => value,
+                value @ (Value::Integer(_)
+                | Value::Float(_)
+                | Value::Boolean(_)
+                | Value::Datetime(_)) => value.to_string(),
+                Value::Array(_) | Value::Table(_) => {
+                    return Err(format!(
+                        "`{path}.{key}` must be a string, a number or a boolean"
+                    ))
+                }
+            };
+
+            self.pairs
+                .push((format!("{env_prefix}{}", key.to_uppercase()), value));
+        }
+
+        Ok(())
+    }
+}
+
+fn into_table(path: &str, value: Value) -> Result<Table, String> {
+    match value {
+        Value::Table(table) => Ok(table),
+        _ => Err(format!("`{path}` must be a table")),
+    }
+}
+
+/// 全ての環境で共通の設定を置くセクション
+const DEFAULT_SECTION: &str = "default";
+
+/// 環境ごとの設定を `[profile.<名前>]` として置くセクション
+const PROFILE_SECTION: &str = "profile";
+
+/// `overrides` の値で `base` を上書きする。表同士は再帰的にまとめ、それ以外の値は置き換える
+fn merge(base: &mut Table, overrides: Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overrides)) => merge(base, overrides),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// `[default]` と `[profile.<名前>]` に分かれた設定ファイルから、`profile` で選ばれた環境の設定を取り出す。
+///
+/// どちらのセクションも無い設定ファイルは、全体を一つの環境の設定として扱う。
+fn select_profile(mut root: Table, profile: Option<&str>) -> Result<Table, String> {
+    let default = root.remove(DEFAULT_SECTION);
+    let profiles = root.remove(PROFILE_SECTION);
+
+    if default.is_none() && profiles.is_none() {
+        return match profile {
+            Some(profile) => Err(format!(
+                "profile {profile:?} is selected, but no [{PROFILE_SECTION}.<name>] sections are defined"
+            )),
+            None => Ok(root),
+        };
+    }
+
+    if let Some(section) = root.keys().next() {
+        return Err(format!(
+            "[{section}] must be placed under [{DEFAULT_SECTION}] or [{PROFILE_SECTION}.<name>] when profiles are used"
+        ));
+    }
+
+    let mut selected = match default {
+        Some(default) => into_table(DEFAULT_SECTION, default)?,
+        None => Table::new(),
+    };
+
+    let mut profiles = match profiles {
+        Some(profiles) => into_table(PROFILE_SECTION, profiles)?,
+        None => Table::new(),
+    };
+
+    if let Some(profile) = profile {
+        match profiles.remove(profile) {
+            Some(overrides) => merge(
+                &mut selected,
+                into_table(&format!("{PROFILE_SECTION}.{profile}"), overrides)?,
+            ),
+            None => {
+                return Err(format!(
+                    "unknown profile {profile:?} (defined profiles: {})",
+                    profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+                ))
+            }
+        }
+    }
+
+    Ok(selected)
+}
+
+/// TOML形式の設定ファイルの内容を、環境変数と同じ形式のキーと値の組に変換する。
+///
+/// `[default]` と `[profile.<名前>]` に分かれている場合は、`[default]` を `profile` で選ばれた環境の設定で上書きしたものを使う。
+/// 例えば `[source_database]` セクションの `host` は `DB_HOST` に、
+/// `[source_database_profiles.ranking]` セクションの `host` は `DB_PROFILE_RANKING_HOST` に対応する。
+/// 未知のセクションやキーは、`strict` であればエラーに、そうでなければ無視し、その一覧を組とともに返す。
+pub(crate) fn to_env_like_key_value_pairs(
+    content: &str,
+    profile: Option<&str>,
+    strict: bool,
+) -> Result<(Vec<(String, String)>, Vec<String>), String> {
+    let root = toml::from_str::<Table>(content).map_err(|error| error.to_string())?;
+    let root = select_profile(root, profile)?;
+
+    let mut flattened = Flattened {
+        pairs: Vec::new(),
+        unknown_keys: Vec::new(),
+    };
+
+    for (section_name, section_value) in root {
+        let section = match SECTIONS.iter().find(|section| section.name == section_name) {
+            Some(section) => section,
+            None => {
+                flattened.unknown_keys.push(section_name);
+                continue;
+            }
+        };
+
+        let section_table = into_table(&section_name, section_value)?;
+
+        match section.layout {
+            Layout::Single => {
+                flattened.push_table(
+                    &section_name,
+                    section.env_prefix,
+                    section.keys,
+                    section_table,
+                )?;
+            }
+            Layout::Named {
+                names_variable,
+                allowed_names,
+            } => {
+                let mut names = Vec::new();
+
+                for (name, value) in section_table {
+                    let path = format!("{section_name}.{name}");
+
+                    if allowed_names.map_or(false, |allowed| !allowed.contains(&name.as_str())) {
+                        flattened.unknown_keys.push(path);
+                        continue;
+                    }
+
+                    let env_prefix = format!("{}{}_", section.env_prefix, name.to_uppercase());
+                    flattened.push_table(
+                        &path,
+                        &env_prefix,
+                        section.keys,
+                        into_table(&path, value)?,
+                    )?;
+                    names.push(name);
+                }
+
+                if let Some(names_variable) = names_variable {
+                    flattened
+                        .pairs
+                        .push((names_variable.to_string(), names.join(",")));
+                }
+            }
+        }
+    }
+
+    if strict && !flattened.unknown_keys.is_empty() {
+        return Err(format!(
+            "unknown keys: {}",
+            flattened.unknown_keys.join(", ")
+        ));
+    }
+
+    Ok((flattened.pairs, flattened.unknown_keys))
+}
+
+/// 設定ファイルの構造に沿って名付けた環境変数の接頭辞
+const NESTED_ENV_PREFIX: &str = "SEICHI_API_";
+
+/// 設定ファイルの構造に沿って名付けた環境変数で、セクション・表の名前・キーを区切る文字列
+const NESTED_ENV_SEPARATOR: &str = "__";
+
+/// 読み替えたキーと値の組と、読み替えた後と前の名前の組
+pub(crate) type FlatNames = (Vec<(String, String)>, Vec<(String, String)>);
+
+/// 設定ファイルの構造に沿って名付けた環境変数の名前を、設定ファイルのキーを変換したときと同じ名前に読み替える。
+///
+/// 例えば `SEICHI_API_SOURCE_DATABASE__HOST` は `DB_HOST` に、
+/// `SEICHI_API_SOURCE_DATABASE_PROFILES__RANKING__HOST` は `DB_PROFILE_RANKING_HOST` に対応する。
+/// `SEICHI_API_` で始まっていても `__` を含まない変数 (`SEICHI_API_CONFIG` など) と、その他の変数はそのまま返す。
+/// 同じ項目が両方の名前で指定されている場合は、構造に沿った名前で指定された値を使う。
+/// 読み替えた組とともに、読み替えた後と前の名前の組を返す。
+/// 未知のセクションやキーは、その変数の名前を含めたエラーとする。
+pub(crate) fn with_flat_names(pairs: Vec<(String, String)>) -> Result<FlatNames, String> {
+    let mut flat = Vec::with_capacity(pairs.len());
+    let mut nested = Vec::new();
+
+    for (key, value) in pairs {
+        match key
+            .strip_prefix(NESTED_ENV_PREFIX)
+            .filter(|path| path.contains(NESTED_ENV_SEPARATOR))
+        {
+            Some(path) => {
+                let flat_name = flat_name(path).map_err(|error| format!("{key}: {error}"))?;
+                nested.push((flat_name, key, value));
+            }
+            None => flat.push((key, value)),
+        }
+    }
+
+    flat.retain(|(key, _)| !nested.iter().any(|(flat_name, _, _)| flat_name == key));
+    let mut renamed = Vec::with_capacity(nested.len());
+    for (flat_name, nested_name, value) in nested {
+        flat.push((flat_name.clone(), value));
+        renamed.push((flat_name, nested_name));
+    }
+
+    Ok((flat, renamed))
+}
+
+/// `SOURCE_DATABASE__HOST` のような、接頭辞を除いた構造に沿った名前を `DB_HOST` のような名前に変換する
+fn flat_name(path: &str) -> Result<String, String> {
+    let segments = path
+        .split(NESTED_ENV_SEPARATOR)
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    let expected = |layout: &str| format!("expected {NESTED_ENV_PREFIX}{layout}");
+
+    let section = SECTIONS
+        .iter()
+        .find(|section| section.name == segments[0])
+        .ok_or_else(|| format!("unknown section {:?}", segments[0]))?;
+
+    let (env_prefix, key) = match (&section.layout, segments.as_slice()) {
+        (Layout::Single, [_, key]) => (section.env_prefix.to_string(), key),
+        (Layout::Single, _) => return Err(expected("<SECTION>__<KEY>")),
+        (Layout::Named { allowed_names, .. }, [_, name, key]) => {
+            if allowed_names.map_or(false, |allowed| !allowed.contains(&name.as_str())) {
+                return Err(format!(
+                    "unknown name {name:?} in section {:?}",
+                    section.name
+                ));
+            }
+            (
+                format!("{}{}_", section.env_prefix, name.to_uppercase()),
+                key,
+            )
+        }
+        (Layout::Named { .. }, _) => return Err(expected("<SECTION>__<NAME>__<KEY>")),
+    };
+
+    if !section.keys.contains(&key.as_str()) {
+        return Err(format!("unknown key {key:?} in section {:?}", section.name));
+    }
+
+    Ok(format!("{env_prefix}{}", key.to_uppercase()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keys_are_mapped_to_env_variable_names() {
+        let pairs = to_env_like_key_value_pairs(
+            r#"
+            [source_database]
+            host = "db.example.com"
+            port = 3307
+            "#,
+            None,
+            true,
+        )
+        .unwrap()
+        .0;
+
+        assert_eq!(
+            pairs,
+            vec![
+                ("DB_HOST".to_string(), "db.example.com".to_string()),
+                ("DB_PORT".to_string(), "3307".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn named_tables_are_mapped_to_env_variable_names() {
+        let pairs = to_env_like_key_value_pairs(
+            r#"
+            [source_database_profiles.ranking]
+            user = "ranking"
+
+            [resources.break_counts]
+            connection_profile = "ranking"
+            "#,
+            None,
+            true,
+        )
+        .unwrap()
+        .0;
+
+        assert_eq!(
+            pairs,
+            vec![
+                (
+                    "RESOURCE_BREAK_COUNTS_CONNECTION_PROFILE".to_string(),
+                    "ranking".to_string()
+                ),
+                ("DB_PROFILE_RANKING_USER".to_string(), "ranking".to_string()),
+                ("DB_PROFILES".to_string(), "ranking".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_resource_is_an_unknown_key() {
+        assert_eq!(
+            to_env_like_key_value_pairs("[resources.brake_counts]", None, true).unwrap_err(),
+            "unknown keys: resources.brake_counts"
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_ignored_unless_strict() {
+        let content = r#"
+            [source_database]
+            max_conections = 10
+            "#;
+
+        assert_eq!(
+            to_env_like_key_value_pairs(content, None, false).unwrap(),
+            (vec![], vec!["source_database.max_conections".to_string()])
+        );
+        assert_eq!(
+            to_env_like_key_value_pairs(content, None, true).unwrap_err(),
+            "unknown keys: source_database.max_conections"
+        );
+    }
+
+    const PROFILES: &str = r#"
+        [default.http]
+        listen_address = "0.0.0.0"
+        listen_port = 8080
+
+        [default.resources.last_quits]
+        enabled = false
+
+        [default.resources.break_counts]
+        connection_profile = "ranking"
+
+        [profile.staging.http]
+        listen_port = 18080
+
+        [profile.staging.resources.last_quits]
+        timestamp_precision = "date"
+        "#;
+
+    fn sorted_pairs(profile: Option<&str>) -> Vec<(String, String)> {
+        let (mut pairs, _) = to_env_like_key_value_pairs(PROFILES, profile, true).unwrap();
+        pairs.sort();
+        pairs
+    }
+
+    #[test]
+    fn default_section_is_used_without_profile() {
+        assert_eq!(
+            sorted_pairs(None),
+            vec![
+                ("HTTP_LISTEN_ADDRESS".to_string(), "0.0.0.0".to_string()),
+                ("HTTP_LISTEN_PORT".to_string(), "8080".to_string()),
+                (
+                    "RESOURCE_BREAK_COUNTS_CONNECTION_PROFILE".to_string(),
+                    "ranking".to_string()
+                ),
+                (
+                    "RESOURCE_LAST_QUITS_ENABLED".to_string(),
+                    "false".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn profile_is_merged_into_default_section_table_by_table() {
+        assert_eq!(
+            sorted_pairs(Some("staging")),
+            vec![
+                ("HTTP_LISTEN_ADDRESS".to_string(), "0.0.0.0".to_string()),
+                ("HTTP_LISTEN_PORT".to_string(), "18080".to_string()),
+                (
+                    "RESOURCE_BREAK_COUNTS_CONNECTION_PROFILE".to_string(),
+                    "ranking".to_string()
+                ),
+                (
+                    "RESOURCE_LAST_QUITS_ENABLED".to_string(),
+                    "false".to_string()
+                ),
+                (
+                    "RESOURCE_LAST_QUITS_TIMESTAMP_PRECISION".to_string(),
+                    "date".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_profile_is_an_error() {
+        assert_eq!(
+            to_env_like_key_value_pairs(PROFILES, Some("prod"), true).unwrap_err(),
+            r#"unknown profile "prod" (defined profiles: staging)"#
+        );
+        assert!(to_env_like_key_value_pairs("[http]", Some("staging"), true).is_err());
+    }
+
+    #[test]
+    fn sections_outside_profiles_are_rejected_when_profiles_are_used() {
+        assert!(to_env_like_key_value_pairs("[http]\n[default.logging]", None, true).is_err());
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn nested_env_names_are_mapped_like_config_file_keys() {
+        let mut flat = with_flat_names(pairs(&[
+            ("SEICHI_API_SOURCE_DATABASE__HOST", "db.example.com"),
+            (
+                "SEICHI_API_SOURCE_DATABASE_PROFILES__RANKING__USER",
+                "ranking",
+            ),
+            ("SEICHI_API_RESOURCES__BREAK_COUNTS__ENABLED", "false"),
+            ("SEICHI_API_CONFIG", "/etc/seichi-api.toml"),
+            ("HTTP_LISTEN_PORT", "8080"),
+        ]))
+        .unwrap()
+        .0;
+        flat.sort();
+
+        assert_eq!(
+            flat,
+            pairs(&[
+                ("DB_HOST", "db.example.com"),
+                ("DB_PROFILE_RANKING_USER", "ranking"),
+                ("HTTP_LISTEN_PORT", "8080"),
+                ("RESOURCE_BREAK_COUNTS_ENABLED", "false"),
+                ("SEICHI_API_CONFIG", "/etc/seichi-api.toml"),
+            ])
+        );
+    }
+
+    #[test]
+    fn nested_env_names_take_precedence_over_flat_ones() {
+        let (flat, renamed) = with_flat_names(pairs(&[
+            ("SEICHI_API_HTTP__LISTEN_PORT", "80"),
+            ("HTTP_LISTEN_PORT", "8080"),
+        ]))
+        .unwrap();
+
+        assert_eq!(flat, pairs(&[("HTTP_LISTEN_PORT", "80")]));
+        assert_eq!(
+            renamed,
+            pairs(&[("HTTP_LISTEN_PORT", "SEICHI_API_HTTP__LISTEN_PORT")])
+        );
+    }
+
+    #[test]
+    fn unknown_nested_env_names_are_named_in_errors() {
+        assert_eq!(
+            with_flat_names(pairs(&[("SEICHI_API_HTTP__LISTEN_PROT", "80")])).unwrap_err(),
+            r#"SEICHI_API_HTTP__LISTEN_PROT: unknown key "listen_prot" in section "http""#
+        );
+        assert_eq!(
+            with_flat_names(pairs(&[("SEICHI_API_RESOURCES__ENABLED", "false")])).unwrap_err(),
+            "SEICHI_API_RESOURCES__ENABLED: expected SEICHI_API_<SECTION>__<NAME>__<KEY>"
+        );
+        assert_eq!(
+            with_flat_names(pairs(&[("SEICHI_API_DATABASE__HOST", "db")])).unwrap_err(),
+            r#"SEICHI_API_DATABASE__HOST: unknown section "database""#
+        );
+    }
+}
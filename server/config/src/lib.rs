@@ -1,72 +1,924 @@
+mod database_url;
+mod file;
+mod primitives;
+mod validation;
+
+pub use primitives::{DatabaseName, HostName, Port};
+pub use validation::{ValidationErrors, Violation};
+
 use anyhow::Result;
 use envy::Error;
 use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{AddrParseError, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// 設定ファイルのパスを指定する環境変数
+pub const CONFIG_FILE_VARIABLE: &str = "SEICHI_API_CONFIG";
+
+/// APIが提供するリソースの名前。リソースごとの設定は `RESOURCE_<名前>_` を接頭辞とする環境変数で指定する
+pub const RESOURCE_NAMES: &[&str] = &[
+    "last_quits",
+    "break_counts",
+    "build_counts",
+    "play_ticks",
+    "vote_counts",
+];
+
+/// 設定ファイル中のどの環境の設定 (`[profile.<名前>]`) を使うかを指定する環境変数
+pub const PROFILE_VARIABLE: &str = "SEICHI_API_PROFILE";
+
+/// 使う環境の設定の名前を、`profile` か、それが `None` なら環境変数 `SEICHI_API_PROFILE` から決める
+#[must_use]
+pub fn resolve_profile(profile: Option<&str>) -> Option<String> {
+    profile
+        .map(ToString::to_string)
+        .or_else(|| std::env::var(PROFILE_VARIABLE).ok())
+        .filter(|profile| !profile.is_empty())
+}
+
+/// `true` に設定されていると、設定ファイル中の未知のキーを警告ではなくエラーとして扱う環境変数
+pub const STRICT_CONFIG_FILE_VARIABLE: &str = "SEICHI_API_CONFIG_STRICT";
 
 pub trait FromEnv: Sized {
     fn from_env() -> Result<Self, Error>;
 }
 
+pub trait FromFileAndEnv: Sized {
+    /// TOML形式の設定ファイルを読み込み、その上に環境変数を重ねて設定を読み込む。
+    ///
+    /// 設定ファイルのパスは `config_file` か、それが `None` なら環境変数 `SEICHI_API_CONFIG` から決める。
+    /// どちらも無ければ環境変数のみから読み込む。
+    /// 設定ファイル中の `[profile.<名前>]` のうちどれを使うかは `profile` か、それが `None` なら環境変数 `SEICHI_API_PROFILE` から決める。
+    /// 同じ項目が複数の場所で設定されている場合、環境変数、設定ファイル中の環境の設定、`[default]`、既定値の順に優先される。
+    /// 環境変数は `DB_HOST` のような名前のほか、`SEICHI_API_SOURCE_DATABASE__HOST` のように
+    /// 接頭辞 `SEICHI_API_` を付け、設定ファイルのセクションとキーを `__` で区切った名前でも指定できる。
+    fn from_file_and_env(config_file: Option<&Path>, profile: Option<&str>) -> Result<Self, Error>;
+}
+
 trait FromEnvLikeKeyValuePairs: Sized {
     fn from_iter(iter: impl Iterator<Item = (String, String)> + Clone) -> Result<Self, Error>;
+
+    /// 設定ファイル中の、読み込まずに無視した未知のキーを受け取る
+    fn set_ignored_config_file_keys(&mut self, _keys: Vec<String>) {}
 }
 
 impl<T: FromEnvLikeKeyValuePairs> FromEnv for T {
     fn from_env() -> Result<Self, Error> {
         // std::env::Vars is not Clone
-        Self::from_iter(std::env::vars().collect::<Vec<_>>().into_iter())
+        let (pairs, renamed) = env_layer(std::env::vars().collect())?;
+
+        Self::from_iter(resolve_secret_files(pairs)?.into_iter())
+            .map_err(|error| name_nested_variables_in_error(&renamed, error))
+    }
+}
+
+impl<T: FromEnvLikeKeyValuePairs> FromFileAndEnv for T {
+    fn from_file_and_env(config_file: Option<&Path>, profile: Option<&str>) -> Result<Self, Error> {
+        let config_file = config_file
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var_os(CONFIG_FILE_VARIABLE).map(PathBuf::from));
+        let profile = resolve_profile(profile);
+
+        from_file_and_env_pairs(
+            config_file.as_deref(),
+            profile.as_deref(),
+            std::env::vars().collect(),
+        )
+    }
+}
+
+/// 設定ファイルと、環境変数として渡された `env_pairs` を重ねて設定を読み込む
+fn from_file_and_env_pairs<T: FromEnvLikeKeyValuePairs>(
+    config_file: Option<&Path>,
+    profile: Option<&str>,
+    env_pairs: Vec<(String, String)>,
+) -> Result<T, Error> {
+    let (file_pairs, ignored_keys) = match (config_file, profile) {
+        (Some(path), profile) => read_config_file(path, profile)?,
+        (None, Some(profile)) => {
+            return Err(Error::Custom(format!(
+                "profile {profile:?} is selected, but no config file is given"
+            )))
+        }
+        (None, None) => (Vec::new(), Vec::new()),
+    };
+
+    let (env_pairs, renamed) = env_layer(env_pairs)?;
+    let pairs = layered([with_current_names(file_pairs), env_pairs]);
+
+    let mut config = T::from_iter(resolve_secret_files(pairs)?.into_iter())
+        .map_err(|error| name_nested_variables_in_error(&renamed, error))?;
+    config.set_ignored_config_file_keys(ignored_keys);
+    Ok(config)
+}
+
+/// 環境変数を、設定ファイルのキーを変換したときと同じ名前に揃える。
+///
+/// `SEICHI_API_HTTP__LISTEN_PORT` のように設定ファイルの構造に沿って名付けた環境変数と、以前の名前を読み替える。
+/// 組とともに、設定ファイルの構造に沿った名前から読み替えた後と前の名前の組を返す。
+fn env_layer(pairs: Vec<(String, String)>) -> Result<file::FlatNames, Error> {
+    let (pairs, renamed) = file::with_flat_names(pairs)
+        .map_err(|error| Error::Custom(format!("invalid environment variable {error}")))?;
+    let renamed = renamed
+        .into_iter()
+        .map(|(flat_name, nested_name)| (current_name(flat_name), nested_name))
+        .collect();
+
+    Ok((with_current_names(pairs), renamed))
+}
+
+/// 設定ファイルの構造に沿った名前で指定された環境変数の値を読めなかった場合に、エラーの中の変数名をその名前に置き換える
+fn name_nested_variables_in_error(renamed: &[(String, String)], error: Error) -> Error {
+    match error {
+        Error::Custom(message) => match message.rsplit_once(" provided by ") {
+            Some((reason, variable)) => match renamed.iter().find(|(flat, _)| flat == variable) {
+                Some((_, nested)) => Error::Custom(format!("{reason} provided by {nested}")),
+                None => Error::Custom(message),
+            },
+            None => Error::Custom(message),
+        },
+        error @ Error::MissingValue(_) => error,
+    }
+}
+
+/// 設定ファイルを読み込み、環境変数と同じ形式のキーと値の組と、無視した未知のキーを返す
+fn read_config_file(
+    path: &Path,
+    profile: Option<&str>,
+) -> Result<(Vec<(String, String)>, Vec<String>), Error> {
+    let strict = std::env::var(STRICT_CONFIG_FILE_VARIABLE).map_or(false, |value| value == "true");
+
+    let content = std::fs::read_to_string(path).map_err(|error| {
+        Error::Custom(format!(
+            "failed to read config file {}: {error}",
+            path.display()
+        ))
+    })?;
+
+    file::to_env_like_key_value_pairs(&content, profile, strict)
+        .map_err(|error| Error::Custom(format!("invalid config file {}: {error}", path.display())))
+}
+
+/// 環境変数と同じ形式のキーと値の組を、後ろの層ほど優先されるように一つにまとめる
+fn layered(layers: impl IntoIterator<Item = Vec<(String, String)>>) -> Vec<(String, String)> {
+    let mut merged = HashMap::new();
+    for layer in layers {
+        merged.extend(layer);
+    }
+    merged.into_iter().collect()
+}
+
+/// 以前の名前などの別名と、現在の環境変数名の対応
+const RENAMED_VARIABLES: &[(&str, &str)] = &[
+    ("HTTP_HOST", "HTTP_LISTEN_ADDRESS"),
+    ("HTTP_PORT", "HTTP_LISTEN_PORT"),
+    // ホスティング環境から一つのURLとして渡される場合の名前
+    ("SOURCE_DATABASE_URL", "DB_URL"),
+];
+
+/// 以前の名前を現在の名前に読み替える
+fn current_name(name: String) -> String {
+    match RENAMED_VARIABLES.iter().find(|(old, _)| *old == name) {
+        Some((_, current)) => current.to_string(),
+        None => name,
+    }
+}
+
+/// 以前の名前で指定された項目を現在の名前に読み替える。
+///
+/// 同じ層で両方の名前が指定されている場合は、現在の名前で指定された値を使う。
+fn with_current_names(pairs: Vec<(String, String)>) -> Vec<(String, String)> {
+    let keys = pairs.iter().map(|(key, _)| key.clone()).collect::<Vec<_>>();
+
+    pairs
+        .into_iter()
+        .filter_map(
+            |(key, value)| match RENAMED_VARIABLES.iter().find(|(old, _)| *old == key) {
+                Some((_, current)) if keys.iter().any(|key| key == current) => None,
+                Some((_, current)) => Some((current.to_string(), value)),
+                None => Some((key, value)),
+            },
+        )
+        .collect()
+}
+
+/// 値の代わりに `_FILE` を付けた環境変数でファイルのパスを指定できる、秘匿情報の環境変数かどうか
+fn is_secret_variable(name: &str) -> bool {
+    // URLにもパスワードが含まれうる
+    let is_secret_field = |field: &str| field == "PASSWORD" || field == "URL";
+
+    name == "ERROR_REPORTING_DSN"
+        || name.strip_prefix("DB_").map_or(false, |field| {
+            is_secret_field(field)
+                || field
+                    .strip_prefix("PROFILE_")
+                    .and_then(|profile| profile.rsplit_once('_'))
+                    .map_or(false, |(_, field)| is_secret_field(field))
+        })
+}
+
+/// `DB_PASSWORD_FILE` のように秘匿情報がファイルのパスで指定されている場合、そのファイルの内容で値を置き換える。
+///
+/// ファイルから読み込んだ値は、直接指定された値より優先される。ファイル末尾の改行は取り除く。
+fn resolve_secret_files(pairs: Vec<(String, String)>) -> Result<Vec<(String, String)>, Error> {
+    let mut resolved = Vec::with_capacity(pairs.len());
+    let mut secrets_from_files = Vec::new();
+
+    for (key, value) in pairs {
+        let secret = key
+            .strip_suffix("_FILE")
+            .filter(|name| is_secret_variable(name))
+            .map(ToString::to_string);
+
+        match secret {
+            Some(secret) => {
+                secrets_from_files.push((secret, read_secret_file(&key, &value)?));
+            }
+            None => resolved.push((key, value)),
+        }
     }
+
+    resolved.retain(|(key, _)| !secrets_from_files.iter().any(|(secret, _)| secret == key));
+    resolved.extend(secrets_from_files);
+
+    Ok(resolved)
+}
+
+fn read_secret_file(variable: &str, path: &str) -> Result<String, Error> {
+    // 読み込んだ内容はエラーメッセージにも含めない
+    let content = std::fs::read_to_string(path).map_err(|error| {
+        Error::Custom(format!(
+            "failed to read {path} given by {variable}: {error}"
+        ))
+    })?;
+
+    Ok(content.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+/// `envy::prefixed`で読み込んだ際のエラーを、問題のある環境変数の名前(接頭辞込み)が分かるものに置き換える。
+///
+/// `envy`は接頭辞を取り除いて小文字化したフィールド名しか報告しないため、
+/// 例えば `DB_PORT` と `HTTP_PORT` のどちらが不正なのかが区別できない。
+fn name_variables_in_error(prefix: &str, error: Error) -> Error {
+    let variable_name = |field: &str| format!("{prefix}{}", field.to_uppercase());
+
+    match error {
+        Error::MissingValue(field) => Error::Custom(format!(
+            "missing environment variable {}",
+            variable_name(field)
+        )),
+        Error::Custom(message) => match message.rsplit_once(" provided by ") {
+            Some((reason, field)) => {
+                Error::Custom(format!("{reason} provided by {}", variable_name(field)))
+            }
+            None => Error::Custom(message),
+        },
+    }
+}
+
+fn from_prefixed_iter<T: serde::de::DeserializeOwned>(
+    prefix: &str,
+    iter: impl Iterator<Item = (String, String)>,
+) -> Result<T, Error> {
+    envy::prefixed(prefix)
+        .from_iter(iter)
+        .map_err(|error| name_variables_in_error(prefix, error))
 }
 
 #[derive(Deserialize, Debug)]
 pub struct AppConfig {
-    pub source_database_config: SourceDatabaseConfig,
+    /// 既定の接続プロファイル。`csv_source_config` でCSVファイルから読み出す場合は読み込まず、`None` とする
+    pub source_database_config: Option<SourceDatabaseConfig>,
+    /// 名前付きの接続プロファイル
+    pub source_database_profiles: BTreeMap<String, SourceDatabaseConfig>,
     pub http_config: HttpConfig,
+    pub ops_config: OpsConfig,
+    pub logging_config: LoggingConfig,
+    pub tracing_config: TracingConfig,
+    pub error_reporting_config: ErrorReportingConfig,
+    pub resources_config: ResourcesConfig,
+    pub csv_source_config: CsvSourceConfig,
+    /// 設定ファイル中の、読み込まずに無視した未知のキー。ログの設定を済ませてから警告する
+    #[serde(skip)]
+    pub ignored_config_file_keys: Vec<String>,
 }
 
 impl FromEnvLikeKeyValuePairs for AppConfig {
     fn from_iter(iter: impl Iterator<Item = (String, String)> + Clone) -> Result<Self, Error> {
+        // 以前の名前だけで渡された項目も読めるよう、ここでも読み替える
+        let iter = with_current_names(iter.collect()).into_iter();
+        let csv_source_config = CsvSourceConfig::from_iter(iter.clone())?;
+
         Ok(Self {
-            source_database_config: SourceDatabaseConfig::from_iter(iter.clone())?,
-            http_config: HttpConfig::from_iter(iter)?,
+            source_database_config: match csv_source_config.directory {
+                Some(_) => None,
+                None => Some(SourceDatabaseConfig::from_iter(iter.clone())?),
+            },
+            source_database_profiles: read_source_database_profiles(iter.clone())?,
+            http_config: HttpConfig::from_iter(iter.clone())?,
+            ops_config: OpsConfig::from_iter(iter.clone())?,
+            logging_config: LoggingConfig::from_iter(iter.clone())?,
+            tracing_config: TracingConfig::from_iter(iter.clone())?,
+            error_reporting_config: ErrorReportingConfig::from_iter(iter.clone())?,
+            resources_config: ResourcesConfig::from_iter(iter)?,
+            csv_source_config,
+            ignored_config_file_keys: Vec::new(),
         })
     }
+
+    fn set_ignored_config_file_keys(&mut self, keys: Vec<String>) {
+        self.ignored_config_file_keys = keys;
+    }
 }
 
-#[derive(Deserialize, Debug)]
+/// 名前付きの接続プロファイルの一覧をカンマ区切りで設定する環境変数
+const SOURCE_DATABASE_PROFILES_VARIABLE: &str = "DB_PROFILES";
+
+/// `DB_PROFILES` に列挙された接続プロファイルを、それぞれ `DB_PROFILE_<名前>_` を接頭辞とする環境変数から読み込む
+fn read_source_database_profiles(
+    iter: impl Iterator<Item = (String, String)> + Clone,
+) -> Result<BTreeMap<String, SourceDatabaseConfig>, Error> {
+    let names = iter
+        .clone()
+        .find(|(key, _)| key == SOURCE_DATABASE_PROFILES_VARIABLE)
+        .map(|(_, names)| names)
+        .unwrap_or_default();
+
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let prefix = format!("DB_PROFILE_{}_", name.to_uppercase());
+            Ok((
+                name.to_string(),
+                SourceDatabaseConfig::from_prefixed_iter(&prefix, iter.clone())?,
+            ))
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
 pub struct SourceDatabaseConfig {
-    pub host: String,
+    pub host: HostName,
+    #[serde(default = "default_source_database_port")]
     pub port: Port,
-    pub database_name: String,
+    /// `true` なら `_mysql._tcp.{host}` のSRVレコードを引き、そこに書かれたホストとポートに接続する。`port` は使わない
+    #[serde(default)]
+    pub resolve_srv: bool,
+    pub database_name: DatabaseName,
     pub user: String,
     pub password: String,
+    /// コネクションプールが保持する接続数の上限
+    #[serde(default = "default_max_connections")]
+    pub max_connections: u32,
+    /// 指定しなければ接続ライブラリの既定 (TLSが使えれば使う) に従う
+    pub ssl_mode: Option<SslMode>,
+    /// サーバー証明書を検証するためのCA証明書のパス
+    pub ssl_ca: Option<PathBuf>,
+    /// プレイヤーの名前の長さをどこまで検証するか
+    #[serde(default)]
+    pub player_name_validation: PlayerNameValidation,
+    /// 負の値など、カウンタとして表せない値をどう扱うか
+    #[serde(default)]
+    pub out_of_range_counters: OutOfRangeCounters,
+    /// 再試行すれば成功しうる取得の失敗がこの回数続いたら、しばらくゲームDBに問い合わせない。0ならそうしない
+    #[serde(default = "default_circuit_breaker_failures")]
+    pub circuit_breaker_failures: u32,
+    /// ゲームDBに問い合わせなくなってから、試しに問い合わせるまでの秒数
+    #[serde(default = "default_circuit_breaker_cooldown_seconds")]
+    pub circuit_breaker_cooldown_seconds: u64,
+}
+
+// パスワードがログなどに出力されないよう、Debug出力では伏せる
+impl std::fmt::Debug for SourceDatabaseConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SourceDatabaseConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("resolve_srv", &self.resolve_srv)
+            .field("database_name", &self.database_name)
+            .field("user", &self.user)
+            .field("password", &"<redacted>")
+            .field("max_connections", &self.max_connections)
+            .field("ssl_mode", &self.ssl_mode)
+            .field("ssl_ca", &self.ssl_ca)
+            .field("player_name_validation", &self.player_name_validation)
+            .field("out_of_range_counters", &self.out_of_range_counters)
+            .field("circuit_breaker_failures", &self.circuit_breaker_failures)
+            .field(
+                "circuit_breaker_cooldown_seconds",
+                &self.circuit_breaker_cooldown_seconds,
+            )
+            .finish()
+    }
+}
+
+const fn default_source_database_port() -> Port {
+    Port::MYSQL_DEFAULT
+}
+
+const fn default_max_connections() -> u32 {
+    5
+}
+
+const fn default_circuit_breaker_failures() -> u32 {
+    5
+}
+
+const fn default_circuit_breaker_cooldown_seconds() -> u64 {
+    30
+}
+
+impl SourceDatabaseConfig {
+    pub const fn circuit_breaker_cooldown(&self) -> Duration {
+        Duration::from_secs(self.circuit_breaker_cooldown_seconds)
+    }
+
+    /// `{prefix}HOST` などの個別の項目か、`{prefix}URL` から読み込む
+    fn from_prefixed_iter(
+        prefix: &str,
+        iter: impl Iterator<Item = (String, String)>,
+    ) -> Result<Self, Error> {
+        let pairs = database_url::expand_database_url(prefix, iter.collect())?;
+
+        from_prefixed_iter(prefix, pairs.into_iter())
+    }
 }
 
 impl FromEnvLikeKeyValuePairs for SourceDatabaseConfig {
     fn from_iter(iter: impl Iterator<Item = (String, String)>) -> Result<Self, Error> {
-        envy::prefixed("DB_").from_iter(iter)
+        Self::from_prefixed_iter("DB_", iter)
     }
 }
 
+/// ゲームDBとの接続にTLSを使うかどうか。MySQLの `--ssl-mode` と同じ意味を持つ
+#[derive(Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SslMode {
+    Disabled,
+    Preferred,
+    Required,
+    VerifyCa,
+    VerifyIdentity,
+}
+
+/// ゲームDBから読み出したプレイヤーの名前の長さをどこまで検証するか
+#[derive(Deserialize, Default, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayerNameValidation {
+    /// 1文字以上16文字以下でない名前の行を捨てる
+    Strict,
+    /// 古いデータに残る、長さの合わない名前もそのまま提供する
+    #[default]
+    Lenient,
+}
+
+/// ゲームDBから読み出したカウンタが負の値など、表せない値だったときにどう扱うか
+#[derive(Deserialize, Default, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum OutOfRangeCounters {
+    /// 最も近い表せる値に補正して提供する
+    #[default]
+    Clamp,
+    /// その行を提供しない
+    Skip,
+}
+
 #[allow(clippy::module_name_repetitions)]
 #[derive(Deserialize, Debug)]
 pub struct HttpConfig {
-    pub host: String,
-    pub port: Port,
+    /// gRPCサーバーが待ち受けるIPアドレス (以前の名前は `HTTP_HOST`)
+    pub listen_address: String,
+    /// gRPCサーバーが待ち受けるポート (以前の名前は `HTTP_PORT`)
+    pub listen_port: Port,
+    /// 同時に処理するAPIリクエストの上限。これを超えたリクエストは `503 Service Unavailable` で断る。
+    /// 指定しなければ制限しない
+    pub max_concurrent_requests: Option<usize>,
+    /// リクエストを断るときに `Retry-After` として返す秒数
+    #[serde(default = "default_retry_after_seconds")]
+    pub retry_after_seconds: u64,
+    /// 前段にある、`X-Forwarded-For` を付け加える信頼できるプロキシの段数。
+    /// ログに記録するリクエストの送信元を決めるのに使い、0 なら `X-Forwarded-For` は無視する
+    #[serde(default)]
+    pub trusted_proxy_depth: usize,
+    /// 接続を閉じ始めてから、処理中のリクエストが終わるのを待つ秒数。過ぎたら打ち切って終了する
+    #[serde(default = "default_drain_timeout_seconds")]
+    pub drain_timeout_seconds: u64,
+    /// ブラウザからのgRPC-Webのリクエストも受け付けるかどうか。受け付ける場合はHTTP/1.1の接続も受け付ける
+    #[serde(default)]
+    pub grpc_web: bool,
 }
 
-#[derive(Deserialize, Eq, PartialEq, Debug)]
-pub struct Port(pub u16);
+const fn default_retry_after_seconds() -> u64 {
+    1
+}
+
+const fn default_drain_timeout_seconds() -> u64 {
+    30
+}
+
+impl HttpConfig {
+    pub const fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.drain_timeout_seconds)
+    }
+
+    /// gRPCサーバーが待ち受けるソケットアドレス
+    pub fn socket_address(&self) -> Result<SocketAddr, AddrParseError> {
+        Ok(SocketAddr::new(
+            self.listen_address.parse()?,
+            self.listen_port.get(),
+        ))
+    }
+}
 
 impl FromEnvLikeKeyValuePairs for HttpConfig {
     fn from_iter(iter: impl Iterator<Item = (String, String)>) -> Result<Self, Error> {
-        envy::prefixed("HTTP_").from_iter(iter)
+        from_prefixed_iter("HTTP_", iter)
+    }
+}
+
+/// メトリクスなど、運用のためのHTTPエンドポイントを提供するサーバーの設定
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize, Debug)]
+pub struct OpsConfig {
+    /// 運用のためのHTTPサーバーが待ち受けるIPアドレス
+    #[serde(default = "default_ops_listen_address")]
+    pub listen_address: String,
+    /// 運用のためのHTTPサーバーが待ち受けるポート。指定しなければこのサーバーは起動しない
+    pub listen_port: Option<Port>,
+    /// `/readyz` で、全ての接続プロファイルのゲームDBが応答するかも確かめるかどうか
+    #[serde(default)]
+    pub readiness_checks_database: bool,
+    /// `/readyz` でゲームDBの応答を待つ時間
+    #[serde(default = "default_readiness_database_timeout_millis")]
+    pub readiness_database_timeout_millis: u64,
+    /// 終了の指示を受けてから、`/readyz` が失敗を返すようにした上で接続を閉じ始めるまで待つ秒数。
+    /// ロードバランサーがこの間に新しいリクエストを送らなくなることを期待する
+    #[serde(default = "default_shutdown_delay_seconds")]
+    pub shutdown_delay_seconds: u64,
+    /// リソースの取得がこの回数続けて失敗したら、サービスが劣化しているとみなす
+    #[serde(default = "default_health_degraded_after_failures")]
+    pub health_degraded_after_failures: u64,
+    /// 提供しているリソースのうち、取得に失敗していて代わりに返せるレコードも無いものの割合がこれ以上なら、
+    /// サービスを利用できないとみなす
+    #[serde(default = "default_health_unavailable_ratio")]
+    pub health_unavailable_ratio: f64,
+    /// `/metrics` で公開するゲーム全体の集計値 (`GAME_STATISTICS` のいずれか)。空なら集計しない
+    #[serde(default)]
+    pub game_statistics: Vec<String>,
+    /// `active_players` で数える、最後にログアウトしてからの日数
+    #[serde(default = "default_game_statistics_active_windows_days")]
+    pub game_statistics_active_windows_days: Vec<u64>,
+    /// ゲーム全体の集計値を集計し直すまでの秒数。この間の `/metrics` には前回集計した値を返す
+    #[serde(default = "default_game_statistics_interval_seconds")]
+    pub game_statistics_interval_seconds: u64,
+}
+
+fn default_ops_listen_address() -> String {
+    "0.0.0.0".to_string()
+}
+
+const fn default_readiness_database_timeout_millis() -> u64 {
+    1000
+}
+
+const fn default_shutdown_delay_seconds() -> u64 {
+    5
+}
+
+const fn default_health_degraded_after_failures() -> u64 {
+    1
+}
+
+const fn default_health_unavailable_ratio() -> f64 {
+    1.0
+}
+
+fn default_game_statistics_active_windows_days() -> Vec<u64> {
+    vec![1, 7, 30]
+}
+
+const fn default_game_statistics_interval_seconds() -> u64 {
+    300
+}
+
+impl OpsConfig {
+    /// `game_statistics` に指定できる集計値
+    pub const GAME_STATISTICS: &'static [&'static str] = &[
+        "total_break_count",
+        "total_play_ticks",
+        "total_vote_count",
+        "players_total",
+        "active_players",
+    ];
+
+    /// ゲーム全体の集計値を集計し直すまでの時間
+    pub const fn game_statistics_interval(&self) -> Duration {
+        Duration::from_secs(self.game_statistics_interval_seconds)
+    }
+
+    /// 運用のためのHTTPサーバーが待ち受けるソケットアドレス。サーバーを起動しない場合は `None`
+    pub fn socket_address(&self) -> Option<Result<SocketAddr, AddrParseError>> {
+        self.listen_port
+            .map(|port| Ok(SocketAddr::new(self.listen_address.parse()?, port.get())))
+    }
+}
+
+impl FromEnvLikeKeyValuePairs for OpsConfig {
+    fn from_iter(iter: impl Iterator<Item = (String, String)>) -> Result<Self, Error> {
+        from_prefixed_iter("OPS_", iter)
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize, Debug)]
+pub struct LoggingConfig {
+    /// `tracing_subscriber::EnvFilter` の書式のフィルタ (例: `info,sqlx=warn`)。
+    /// 指定されていなければ環境変数 `RUST_LOG` を使う
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub format: LogFormat,
+    /// 指定されている場合、標準エラー出力に加えてこのディレクトリ内のファイルにもログを書き出す
+    pub file_directory: Option<PathBuf>,
+    #[serde(default)]
+    pub file_rotation: LogFileRotation,
+    /// ゲームDBからの一回の取得にこれ以上かかった場合、WARN のログを出す
+    #[serde(default = "default_slow_fetch_threshold_millis")]
+    pub slow_fetch_threshold_millis: u64,
+    /// 一つのAPIリクエストの処理にこれ以上かかった場合、WARN のログを出す
+    #[serde(default = "default_slow_request_threshold_millis")]
+    pub slow_request_threshold_millis: u64,
+    /// コネクションプールから接続を取り出すまでにこれ以上待った場合、WARN のログを出す
+    #[serde(default = "default_slow_acquire_threshold_millis")]
+    pub slow_acquire_threshold_millis: u64,
+    /// 一回の取得で、捨てたり補正したりした行の割合がこれを超えた場合、WARN のログを出す
+    #[serde(default = "default_data_quality_warn_ratio")]
+    pub data_quality_warn_ratio: f64,
+    /// プレイヤーの名前から色コードなどを取り除いたときに、元の値を DEBUG のログに出すかどうか
+    #[serde(default)]
+    pub raw_player_names: bool,
+    /// 完了したリクエストごとにアクセスログを出すかどうか
+    #[serde(default = "default_access_log")]
+    pub access_log: bool,
+    /// アクセスログを出さないリクエストのパス (例: `/grpc.health.v1.Health/Check`)
+    #[serde(default)]
+    pub access_log_excluded_paths: Vec<String>,
+}
+
+const fn default_access_log() -> bool {
+    true
+}
+
+const fn default_slow_fetch_threshold_millis() -> u64 {
+    2000
+}
+
+const fn default_slow_request_threshold_millis() -> u64 {
+    3000
+}
+
+const fn default_slow_acquire_threshold_millis() -> u64 {
+    500
+}
+
+const fn default_data_quality_warn_ratio() -> f64 {
+    0.01
+}
+
+impl LoggingConfig {
+    pub const fn slow_fetch_threshold(&self) -> Duration {
+        Duration::from_millis(self.slow_fetch_threshold_millis)
+    }
+
+    pub const fn slow_request_threshold(&self) -> Duration {
+        Duration::from_millis(self.slow_request_threshold_millis)
+    }
+
+    pub const fn slow_acquire_threshold(&self) -> Duration {
+        Duration::from_millis(self.slow_acquire_threshold_millis)
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// 人間が読むための形式
+    #[default]
+    Text,
+    /// 一行に一つのJSONオブジェクトを書き出す形式 (Lokiなどのログ基盤向け)
+    Json,
+}
+
+/// ログファイルを新しいファイルに切り替える間隔
+#[derive(Deserialize, Default, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFileRotation {
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+impl FromEnvLikeKeyValuePairs for LoggingConfig {
+    fn from_iter(iter: impl Iterator<Item = (String, String)>) -> Result<Self, Error> {
+        from_prefixed_iter("LOG_", iter)
+    }
+}
+
+/// OpenTelemetryによるトレースの送信の設定
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize, Debug)]
+pub struct TracingConfig {
+    /// トレースを送るOTLP (gRPC) のエンドポイント (例: `http://tempo:4317`)。指定しなければトレースは送らない
+    pub otlp_endpoint: Option<String>,
+    /// トレースに付けるサービス名
+    #[serde(default = "default_tracing_service_name")]
+    pub service_name: String,
+    /// 送るトレースの割合 (0.0 から 1.0)
+    #[serde(default = "default_tracing_sampling_ratio")]
+    pub sampling_ratio: f64,
+}
+
+fn default_tracing_service_name() -> String {
+    "seichi-game-api".to_string()
+}
+
+const fn default_tracing_sampling_ratio() -> f64 {
+    1.0
+}
+
+impl FromEnvLikeKeyValuePairs for TracingConfig {
+    fn from_iter(iter: impl Iterator<Item = (String, String)>) -> Result<Self, Error> {
+        from_prefixed_iter("TRACE_", iter)
+    }
+}
+
+/// Sentryへのエラーの報告の設定
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+pub struct ErrorReportingConfig {
+    /// 報告先のSentryのDSN。指定しなければエラーは報告しない
+    pub dsn: Option<String>,
+    /// 報告に付ける環境の名前 (例: `production`)
+    pub environment: Option<String>,
+    /// 同じリソースについてのエラーを、一度報告してから次に報告するまでに空ける秒数
+    #[serde(default = "default_error_reporting_dedup_window_seconds")]
+    pub dedup_window_seconds: u64,
+}
+
+const fn default_error_reporting_dedup_window_seconds() -> u64 {
+    600
+}
+
+// DSNには認証のための鍵が含まれる
+impl std::fmt::Debug for ErrorReportingConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ErrorReportingConfig")
+            .field("dsn", &self.dsn.as_ref().map(|_| "<redacted>"))
+            .field("environment", &self.environment)
+            .field("dedup_window_seconds", &self.dedup_window_seconds)
+            .finish()
+    }
+}
+
+impl FromEnvLikeKeyValuePairs for ErrorReportingConfig {
+    fn from_iter(iter: impl Iterator<Item = (String, String)>) -> Result<Self, Error> {
+        from_prefixed_iter("ERROR_REPORTING_", iter)
+    }
+}
+
+/// ゲームDBの代わりに、`export` がCSVで書き出したファイルから読み出す設定
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize, Debug)]
+pub struct CsvSourceConfig {
+    /// `<リソースの名前>.csv` を置いたディレクトリ。指定するとゲームDBの設定は読み込まず、一切接続しない
+    pub directory: Option<PathBuf>,
+    /// `true` なら読めない行が一つでもあるファイルを読み込まない。`false` ならその行を飛ばして警告する
+    #[serde(default = "default_csv_source_strict")]
+    pub strict: bool,
+}
+
+const fn default_csv_source_strict() -> bool {
+    true
+}
+
+impl FromEnvLikeKeyValuePairs for CsvSourceConfig {
+    fn from_iter(iter: impl Iterator<Item = (String, String)>) -> Result<Self, Error> {
+        from_prefixed_iter("CSV_SOURCE_", iter)
+    }
+}
+
+/// リソースごとの設定
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize, Debug)]
+pub struct ResourcesConfig {
+    pub last_quits: ResourceConfig,
+    pub break_counts: ResourceConfig,
+    pub build_counts: ResourceConfig,
+    pub play_ticks: ResourceConfig,
+    pub vote_counts: ResourceConfig,
+}
+
+impl ResourcesConfig {
+    /// 時刻を含み、`timestamp_precision` を指定できるリソース
+    pub const RESOURCES_WITH_TIMESTAMPS: &'static [&'static str] = &["last_quits"];
+
+    /// リソースの名前とその設定の組を、`RESOURCE_NAMES` の順に並べたもの
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &ResourceConfig)> {
+        [
+            ("last_quits", &self.last_quits),
+            ("break_counts", &self.break_counts),
+            ("build_counts", &self.build_counts),
+            ("play_ticks", &self.play_ticks),
+            ("vote_counts", &self.vote_counts),
+        ]
+        .into_iter()
+    }
+}
+
+impl FromEnvLikeKeyValuePairs for ResourcesConfig {
+    fn from_iter(iter: impl Iterator<Item = (String, String)> + Clone) -> Result<Self, Error> {
+        let read = |name: &str| {
+            from_prefixed_iter(&format!("RESOURCE_{}_", name.to_uppercase()), iter.clone())
+        };
+
+        Ok(Self {
+            last_quits: read("last_quits")?,
+            break_counts: read("break_counts")?,
+            build_counts: read("build_counts")?,
+            play_ticks: read("play_ticks")?,
+            vote_counts: read("vote_counts")?,
+        })
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize, Debug)]
+pub struct ResourceConfig {
+    /// `false` にすると、このリソースはゲームDBに問い合わせず、APIでも提供しない
+    #[serde(default = "default_resource_enabled")]
+    pub enabled: bool,
+    /// このリソースの取得に使う接続プロファイルの名前。指定しなければ既定の接続プロファイル (`DB_*`) を使う
+    pub connection_profile: Option<String>,
+    /// 時刻を含むリソース (`last_quits`) で、時刻をどの精度で提供するか。指定しなければ `full`
+    pub timestamp_precision: Option<TimestampPrecision>,
+}
+
+const fn default_resource_enabled() -> bool {
+    true
+}
+
+impl Default for ResourceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_resource_enabled(),
+            connection_profile: None,
+            timestamp_precision: None,
+        }
     }
 }
 
+#[derive(Deserialize, Default, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampPrecision {
+    /// ゲームDBに記録されている精度のまま提供する
+    #[default]
+    Full,
+    /// 日付のみを提供する。時刻の部分はゲームDBから読み出さない
+    Date,
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn valid_setting() -> Vec<(String, String)> {
+        [
+            ("HTTP_LISTEN_PORT", "12345"),
+            ("HTTP_LISTEN_ADDRESS", "127.0.0.1"),
+            ("DB_HOST", "example.com"),
+            ("DB_PORT", "3307"),
+            ("DB_DATABASE_NAME", "db"),
+            ("DB_USER", "bff"),
+            ("DB_PASSWORD", "$tr0ngpAssw0rd"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+    }
+
+    fn setting_with(key: &str, value: Option<&str>) -> Vec<(String, String)> {
+        let mut setting = valid_setting();
+        setting.retain(|(existing_key, _)| existing_key != key);
+        if let Some(value) = value {
+            setting.push((key.to_string(), value.to_string()));
+        }
+        setting
+    }
+
     #[test]
     fn read_config_from_iterator() {
         let setting = [
@@ -81,4 +933,371 @@ mod test {
 
         AppConfig::from_iter(setting.into_iter()).unwrap();
     }
+
+    #[test]
+    fn read_config_with_current_names() {
+        AppConfig::from_iter(valid_setting().into_iter()).unwrap();
+    }
+
+    #[test]
+    fn logging_config_is_optional() {
+        let config = AppConfig::from_iter(valid_setting().into_iter()).unwrap();
+
+        assert_eq!(config.logging_config.filter, None);
+        assert_eq!(config.logging_config.format, LogFormat::Text);
+        assert_eq!(config.logging_config.file_directory, None);
+    }
+
+    #[test]
+    fn logging_config_is_read_with_prefix() {
+        let mut setting = valid_setting();
+        setting.push(("LOG_FILTER".to_string(), "info,sqlx=warn".to_string()));
+        setting.push(("LOG_FORMAT".to_string(), "json".to_string()));
+        setting.push(("LOG_FILE_ROTATION".to_string(), "hourly".to_string()));
+
+        let config = AppConfig::from_iter(setting.into_iter()).unwrap();
+
+        assert_eq!(
+            config.logging_config.filter.as_deref(),
+            Some("info,sqlx=warn")
+        );
+        assert_eq!(config.logging_config.format, LogFormat::Json);
+        assert_eq!(config.logging_config.file_rotation, LogFileRotation::Hourly);
+    }
+
+    #[test]
+    fn resources_use_default_profile_unless_assigned() {
+        let mut setting = valid_setting();
+        setting.extend(
+            [
+                ("DB_PROFILES", "ranking"),
+                ("DB_PROFILE_RANKING_HOST", "replica.example.com"),
+                ("DB_PROFILE_RANKING_DATABASE_NAME", "db"),
+                ("DB_PROFILE_RANKING_USER", "ranking"),
+                ("DB_PROFILE_RANKING_PASSWORD", "r4nking"),
+                ("DB_PROFILE_RANKING_MAX_CONNECTIONS", "2"),
+                ("RESOURCE_BREAK_COUNTS_CONNECTION_PROFILE", "ranking"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string())),
+        );
+
+        let config = AppConfig::from_iter(setting.into_iter()).unwrap();
+
+        let ranking = &config.source_database_profiles["ranking"];
+        assert_eq!(ranking.host.as_str(), "replica.example.com");
+        assert_eq!(ranking.max_connections, 2);
+        assert_eq!(config.source_database_config.unwrap().max_connections, 5);
+        assert_eq!(
+            config
+                .resources_config
+                .break_counts
+                .connection_profile
+                .as_deref(),
+            Some("ranking")
+        );
+        assert_eq!(config.resources_config.vote_counts.connection_profile, None);
+    }
+
+    #[test]
+    fn resources_are_enabled_by_default() {
+        let config = AppConfig::from_iter(
+            setting_with("RESOURCE_LAST_QUITS_ENABLED", Some("false")).into_iter(),
+        )
+        .unwrap();
+
+        assert!(!config.resources_config.last_quits.enabled);
+        assert!(config.resources_config.break_counts.enabled);
+    }
+
+    #[test]
+    fn ops_server_is_disabled_unless_port_is_given() {
+        let config = AppConfig::from_iter(valid_setting().into_iter()).unwrap();
+        assert!(config.ops_config.socket_address().is_none());
+
+        let config =
+            AppConfig::from_iter(setting_with("OPS_LISTEN_PORT", Some("9090")).into_iter())
+                .unwrap();
+        assert_eq!(
+            config.ops_config.socket_address().unwrap().unwrap(),
+            "0.0.0.0:9090".parse::<SocketAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn slow_thresholds_have_defaults() {
+        let config = AppConfig::from_iter(valid_setting().into_iter()).unwrap();
+        assert_eq!(
+            config.logging_config.slow_fetch_threshold(),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            config.logging_config.slow_acquire_threshold(),
+            Duration::from_millis(500)
+        );
+
+        let config = AppConfig::from_iter(
+            setting_with("LOG_SLOW_REQUEST_THRESHOLD_MILLIS", Some("500")).into_iter(),
+        )
+        .unwrap();
+        assert_eq!(
+            config.logging_config.slow_request_threshold(),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn missing_variable_is_named_with_prefix() {
+        let error =
+            AppConfig::from_iter(setting_with("DB_PASSWORD", None).into_iter()).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "missing environment variable DB_PASSWORD"
+        );
+    }
+
+    #[test]
+    fn later_layers_take_precedence() {
+        let (file_pairs, _) = file::to_env_like_key_value_pairs(
+            "[http]\nlisten_address = \"10.0.0.1\"\nlisten_port = 80",
+            None,
+            true,
+        )
+        .unwrap();
+        let env_pairs = setting_with("HTTP_LISTEN_ADDRESS", Some("127.0.0.1"));
+
+        let config = AppConfig::from_iter(layered([file_pairs, env_pairs]).into_iter()).unwrap();
+
+        assert_eq!(config.http_config.listen_address, "127.0.0.1");
+        assert_eq!(config.http_config.listen_port.get(), 12345);
+    }
+
+    #[test]
+    fn previous_variable_names_are_still_accepted() {
+        let mut setting = setting_with("HTTP_LISTEN_ADDRESS", None);
+        setting.push(("HTTP_HOST".to_string(), "::1".to_string()));
+
+        let config = AppConfig::from_iter(with_current_names(setting).into_iter()).unwrap();
+
+        assert_eq!(
+            config.http_config.socket_address().unwrap(),
+            "[::1]:12345".parse::<SocketAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn current_variable_names_take_precedence_over_previous_ones() {
+        let mut setting = valid_setting();
+        setting.push(("HTTP_PORT".to_string(), "80".to_string()));
+
+        let config = AppConfig::from_iter(with_current_names(setting).into_iter()).unwrap();
+
+        assert_eq!(config.http_config.listen_port.get(), 12345);
+    }
+
+    #[test]
+    fn source_database_port_defaults_to_3306() {
+        let config = AppConfig::from_iter(setting_with("DB_PORT", None).into_iter()).unwrap();
+
+        assert_eq!(
+            config.source_database_config.unwrap().port,
+            Port::MYSQL_DEFAULT
+        );
+    }
+
+    #[test]
+    fn source_database_is_not_read_when_serving_csv_files() {
+        let setting = [
+            ("CSV_SOURCE_DIRECTORY", "/srv/season-1"),
+            ("CSV_SOURCE_STRICT", "false"),
+        ]
+        .map(|(key, value)| (key.to_string(), value.to_string()));
+
+        let config = AppConfig::from_iter(setting.into_iter()).unwrap();
+
+        assert!(config.source_database_config.is_none());
+        assert_eq!(
+            config.csv_source_config.directory,
+            Some(PathBuf::from("/srv/season-1"))
+        );
+        assert!(!config.csv_source_config.strict);
+    }
+
+    #[test]
+    fn example_config_file_is_complete() {
+        let (pairs, _) =
+            file::to_env_like_key_value_pairs(include_str!("../example-config.toml"), None, true)
+                .unwrap();
+
+        AppConfig::from_iter(pairs.into_iter()).unwrap();
+    }
+
+    /// `content` を書いた一時ファイル。破棄すると削除される
+    fn write_temporary_secret(content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, content.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn secret_file_takes_precedence_over_inline_value() {
+        let file = write_temporary_secret("p@ssw0rd-from-file\n");
+        let setting = setting_with("DB_PASSWORD_FILE", file.path().to_str());
+
+        let config =
+            AppConfig::from_iter(resolve_secret_files(setting).unwrap().into_iter()).unwrap();
+
+        assert_eq!(
+            config.source_database_config.unwrap().password,
+            "p@ssw0rd-from-file"
+        );
+    }
+
+    #[test]
+    fn unreadable_secret_file_is_reported_with_its_path() {
+        let setting = setting_with("DB_PASSWORD_FILE", Some("/nonexistent/db-password"));
+
+        let error = resolve_secret_files(setting).unwrap_err();
+
+        assert!(error
+            .to_string()
+            .starts_with("failed to read /nonexistent/db-password given by DB_PASSWORD_FILE"));
+    }
+
+    #[test]
+    fn password_is_not_shown_in_debug_output() {
+        let config = AppConfig::from_iter(valid_setting().into_iter()).unwrap();
+
+        assert!(!format!("{config:?}").contains("$tr0ngpAssw0rd"));
+    }
+
+    #[test]
+    fn unparsable_variable_is_named_with_prefix() {
+        let error =
+            AppConfig::from_iter(setting_with("HTTP_LISTEN_PORT", Some("eighty")).into_iter())
+                .unwrap_err();
+
+        assert!(error.to_string().ends_with("provided by HTTP_LISTEN_PORT"));
+    }
+
+    #[test]
+    fn invalid_primitive_is_rejected_while_reading() {
+        let error = AppConfig::from_iter(setting_with("DB_PORT", Some("0")).into_iter())
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.starts_with("port must be between 1 and 65535, but was 0"));
+        assert!(error.ends_with("provided by DB_PORT"));
+    }
+
+    /// `content` を書いた一時的な設定ファイル。破棄すると削除される
+    fn write_temporary_config_file(content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        std::io::Write::write_all(&mut file, content.as_bytes()).unwrap();
+        file
+    }
+
+    const LAYERED_CONFIG_FILE: &str = r#"
+        [default.source_database]
+        host = "db.example.com"
+        database_name = "seichi"
+        user = "bff"
+        password = "from-file"
+
+        [default.http]
+        listen_address = "0.0.0.0"
+        listen_port = 8080
+        retry_after_seconds = 5
+
+        [profile.staging.http]
+        listen_port = 18080
+        "#;
+
+    fn env(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn env_takes_precedence_over_config_file_and_defaults() {
+        let file = write_temporary_config_file(LAYERED_CONFIG_FILE);
+
+        let config: AppConfig = from_file_and_env_pairs(
+            Some(file.path()),
+            Some("staging"),
+            env(&[
+                ("SEICHI_API_SOURCE_DATABASE__PASSWORD", "from-env"),
+                ("DB_MAX_CONNECTIONS", "20"),
+            ]),
+        )
+        .unwrap();
+        let source_database = config.source_database_config.unwrap();
+
+        // 環境変数
+        assert_eq!(source_database.password, "from-env");
+        assert_eq!(source_database.max_connections, 20);
+        // 設定ファイル中の環境の設定、`[default]`
+        assert_eq!(config.http_config.listen_port.get(), 18080);
+        assert_eq!(config.http_config.retry_after_seconds, 5);
+        assert_eq!(source_database.user, "bff");
+        // 既定値
+        assert_eq!(source_database.port, Port::MYSQL_DEFAULT);
+        assert_eq!(config.http_config.drain_timeout_seconds, 30);
+    }
+
+    #[test]
+    fn nested_env_names_take_precedence_over_flat_ones_and_the_config_file() {
+        let file = write_temporary_config_file(LAYERED_CONFIG_FILE);
+
+        let config: AppConfig = from_file_and_env_pairs(
+            Some(file.path()),
+            None,
+            env(&[
+                ("HTTP_LISTEN_PORT", "9000"),
+                ("SEICHI_API_HTTP__LISTEN_PORT", "9001"),
+                ("SEICHI_API_HTTP__HOST", "127.0.0.1"),
+                ("SEICHI_API_RESOURCES__BREAK_COUNTS__ENABLED", "false"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.http_config.socket_address().unwrap(),
+            "127.0.0.1:9001".parse::<SocketAddr>().unwrap()
+        );
+        assert!(!config.resources_config.break_counts.enabled);
+    }
+
+    #[test]
+    fn unparsable_nested_env_variable_is_named_as_given() {
+        let file = write_temporary_config_file(LAYERED_CONFIG_FILE);
+
+        let error = from_file_and_env_pairs::<AppConfig>(
+            Some(file.path()),
+            None,
+            env(&[("SEICHI_API_HTTP__LISTEN_PORT", "eighty")]),
+        )
+        .unwrap_err();
+
+        assert!(error
+            .to_string()
+            .ends_with("provided by SEICHI_API_HTTP__LISTEN_PORT"));
+    }
+
+    #[test]
+    fn unknown_nested_env_variable_is_rejected() {
+        let error = from_file_and_env_pairs::<AppConfig>(
+            None,
+            None,
+            env(&[("SEICHI_API_HTTP__LISTEN_PROT", "80")]),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            r#"invalid environment variable SEICHI_API_HTTP__LISTEN_PROT: unknown key "listen_prot" in section "http""#
+        );
+    }
 }
@@ -0,0 +1,198 @@
+//! 設定値に使う、値の範囲を型で保証するプリミティブ。
+//!
+//! いずれも設定の読み込み時に検証されるため、不正な値は接続文字列を組み立てる段階ではなく起動時に報告される。
+
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, Ipv6Addr};
+
+/// 1から65535までのポート番号
+#[derive(Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(try_from = "u16")]
+pub struct Port(u16);
+
+impl Port {
+    /// MySQLの既定のポート
+    pub const MYSQL_DEFAULT: Self = Self(3306);
+
+    #[must_use]
+    pub const fn get(self) -> u16 {
+        self.0
+    }
+}
+
+impl TryFrom<u16> for Port {
+    type Error = String;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        if value == 0 {
+            Err("port must be between 1 and 65535, but was 0".to_string())
+        } else {
+            Ok(Self(value))
+        }
+    }
+}
+
+impl Display for Port {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// 空白のみでないホスト名 (またはIPアドレス)。
+///
+/// IPv6アドレスは `::1` とも `[::1]` とも書け、括弧を外して持つ。ポートは含められない
+#[derive(Deserialize, Clone, Eq, PartialEq, Debug)]
+#[serde(try_from = "String")]
+pub struct HostName(String);
+
+impl HostName {
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// IPアドレスで指定されていれば、そのアドレス
+    #[must_use]
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.0.parse().ok()
+    }
+}
+
+impl TryFrom<String> for HostName {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value.trim().is_empty() {
+            Err(format!("host name must not be empty, but was {value:?}"))
+        } else if value.chars().any(char::is_whitespace) {
+            Err(format!(
+                "host name must not contain whitespace, but was {value:?}"
+            ))
+        } else if let Some(bracketed) = value
+            .strip_prefix('[')
+            .and_then(|value| value.strip_suffix(']'))
+        {
+            bracketed
+                .parse::<Ipv6Addr>()
+                .map(|address| Self(address.to_string()))
+                .map_err(|_| format!("{value:?} is not a bracketed IPv6 address"))
+        } else if value.contains(':') && value.parse::<Ipv6Addr>().is_err() {
+            Err(format!(
+                "host name must not contain a port (give it separately), but was {value:?}"
+            ))
+        } else {
+            Ok(Self(value))
+        }
+    }
+}
+
+impl Display for HostName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// MySQLのスキーマ名として使える名前
+#[derive(Deserialize, Clone, Eq, PartialEq, Debug)]
+#[serde(try_from = "String")]
+pub struct DatabaseName(String);
+
+impl DatabaseName {
+    /// MySQLの識別子の最大長
+    const MAX_LENGTH: usize = 64;
+
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+// https://dev.mysql.com/doc/refman/8.0/en/identifiers.html のうち、
+// 接続文字列にそのまま埋め込める「引用符なしの識別子」に使える文字に加え、よく使われる `-` を受け付ける
+const fn is_allowed_in_database_name(c: char) -> bool {
+    matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '$' | '_' | '-' | '\u{0080}'..='\u{FFFF}')
+}
+
+impl TryFrom<String> for DatabaseName {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value.is_empty() {
+            return Err("database name must not be empty".to_string());
+        }
+
+        if value.chars().count() > Self::MAX_LENGTH {
+            return Err(format!(
+                "database name must be at most {} characters, but was {value:?}",
+                Self::MAX_LENGTH
+            ));
+        }
+
+        match value.chars().find(|c| !is_allowed_in_database_name(*c)) {
+            Some(c) => Err(format!(
+                "database name must not contain {c:?}, but was {value:?}"
+            )),
+            None => Ok(Self(value)),
+        }
+    }
+}
+
+impl Display for DatabaseName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn port_rejects_zero() {
+        assert_eq!(Port::try_from(3306).map(Port::get), Ok(3306));
+        assert_eq!(
+            Port::try_from(0).unwrap_err(),
+            "port must be between 1 and 65535, but was 0"
+        );
+    }
+
+    #[test]
+    fn host_name_rejects_blank_values() {
+        assert!(HostName::try_from("db.example.com".to_string()).is_ok());
+        assert!(HostName::try_from("::1".to_string()).is_ok());
+        assert!(HostName::try_from(" ".to_string()).is_err());
+        assert!(HostName::try_from("db example.com".to_string()).is_err());
+    }
+
+    #[test]
+    fn host_name_accepts_ipv6_literals_with_or_without_brackets() {
+        for host in ["2001:db8::10", "[2001:db8::10]", "[2001:0db8:0:0:0:0:0:10]"] {
+            let host = HostName::try_from(host.to_string()).unwrap();
+            assert_eq!(host.as_str(), "2001:db8::10");
+            assert_eq!(host.ip(), "2001:db8::10".parse().ok());
+        }
+        assert_eq!(
+            HostName::try_from("db.example.com".to_string())
+                .unwrap()
+                .ip(),
+            None
+        );
+
+        assert!(HostName::try_from("[db.example.com]".to_string()).is_err());
+        assert!(HostName::try_from("db.example.com:3306".to_string()).is_err());
+        assert!(HostName::try_from("[2001:db8::10]:3306".to_string()).is_err());
+    }
+
+    #[test]
+    fn database_name_rejects_characters_not_allowed_in_schema_names() {
+        assert!(DatabaseName::try_from("seichiassist".to_string()).is_ok());
+        assert!(DatabaseName::try_from("seichi-assist_2$".to_string()).is_ok());
+        assert!(DatabaseName::try_from(String::new()).is_err());
+        assert!(DatabaseName::try_from("a".repeat(65)).is_err());
+        assert_eq!(
+            DatabaseName::try_from("seichi/assist".to_string()).unwrap_err(),
+            r#"database name must not contain '/', but was "seichi/assist""#
+        );
+    }
+}
@@ -0,0 +1,523 @@
+use crate::{
+    AppConfig, HttpConfig, LoggingConfig, OpsConfig, ResourcesConfig, SourceDatabaseConfig,
+    TracingConfig,
+};
+
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+
+/// 設定値の検証で見つかった一つの問題
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// 設定ファイル中での位置 (例: `source_database.port`)
+    pub field: String,
+    /// 対応する環境変数 (例: `DB_PORT`)
+    pub variable: String,
+    pub message: String,
+}
+
+/// 設定値の検証で見つかった全ての問題
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationErrors(pub Vec<Violation>);
+
+impl Display for ValidationErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid configuration:")?;
+        for violation in &self.0 {
+            write!(
+                f,
+                "\n  {} ({}): {}",
+                violation.field, violation.variable, violation.message
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+struct Violations(Vec<Violation>);
+
+impl Violations {
+    fn require(
+        &mut self,
+        condition: bool,
+        field: impl Into<String>,
+        variable: impl Into<String>,
+        message: impl Into<String>,
+    ) {
+        if !condition {
+            self.0.push(Violation {
+                field: field.into(),
+                variable: variable.into(),
+                message: message.into(),
+            });
+        }
+    }
+}
+
+impl AppConfig {
+    /// 読み込んだ設定値を検証し、見つかった問題を最初の一つで止めずに全てまとめて返す。
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut violations = Violations(Vec::new());
+
+        if let Some(source_database_config) = &self.source_database_config {
+            source_database_config.validate("source_database", "DB_", &mut violations);
+        }
+        violations.require(
+            self.csv_source_config.directory.is_none() || self.source_database_profiles.is_empty(),
+            "csv_source.directory",
+            "CSV_SOURCE_DIRECTORY",
+            "connection profiles cannot be used while serving CSV files",
+        );
+
+        for (name, profile) in &self.source_database_profiles {
+            let field_prefix = format!("source_database_profiles.{name}");
+            let env_prefix = format!("DB_PROFILE_{}_", name.to_uppercase());
+
+            violations.require(
+                !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                &field_prefix,
+                "DB_PROFILES",
+                format!("profile name must consist of ASCII letters, digits and underscores, but was {name:?}"),
+            );
+            profile.validate(&field_prefix, &env_prefix, &mut violations);
+        }
+
+        self.http_config.validate(&mut violations);
+        self.ops_config.validate(&mut violations);
+        self.logging_config.validate(&mut violations);
+        self.tracing_config.validate(&mut violations);
+
+        for (resource, resource_config) in self.resources_config.iter() {
+            violations.require(
+                resource_config.timestamp_precision.is_none()
+                    || ResourcesConfig::RESOURCES_WITH_TIMESTAMPS.contains(&resource),
+                format!("resources.{resource}.timestamp_precision"),
+                format!("RESOURCE_{}_TIMESTAMP_PRECISION", resource.to_uppercase()),
+                format!("{resource} has no timestamps to truncate"),
+            );
+
+            if let Some(profile) = &resource_config.connection_profile {
+                violations.require(
+                    self.source_database_profiles.contains_key(profile),
+                    format!("resources.{resource}.connection_profile"),
+                    format!("RESOURCE_{}_CONNECTION_PROFILE", resource.to_uppercase()),
+                    format!("refers to undefined connection profile {profile:?}"),
+                );
+            }
+        }
+
+        if violations.0.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(violations.0))
+        }
+    }
+}
+
+impl SourceDatabaseConfig {
+    /// `field_prefix` は設定ファイル中のセクション (例: `source_database`)、
+    /// `env_prefix` は環境変数の接頭辞 (例: `DB_`)
+    fn validate(&self, field_prefix: &str, env_prefix: &str, violations: &mut Violations) {
+        let mut require = |condition: bool, key: &str, message: &str| {
+            violations.require(
+                condition,
+                format!("{field_prefix}.{key}"),
+                format!("{env_prefix}{}", key.to_uppercase()),
+                message,
+            );
+        };
+
+        require(!self.user.trim().is_empty(), "user", "must not be empty");
+        require(
+            self.max_connections != 0,
+            "max_connections",
+            "must be at least 1",
+        );
+        require(
+            self.circuit_breaker_cooldown_seconds != 0,
+            "circuit_breaker_cooldown_seconds",
+            "must be at least 1",
+        );
+        require(
+            !(self.resolve_srv && self.host.ip().is_some()),
+            "resolve_srv",
+            "requires host to be a DNS name, not an IP address",
+        );
+    }
+}
+
+impl HttpConfig {
+    fn validate(&self, violations: &mut Violations) {
+        violations.require(
+            self.listen_address.parse::<IpAddr>().is_ok(),
+            "http.listen_address",
+            "HTTP_LISTEN_ADDRESS",
+            format!(
+                "must be an IPv4 or IPv6 address, but was {:?}",
+                self.listen_address
+            ),
+        );
+        violations.require(
+            self.max_concurrent_requests != Some(0),
+            "http.max_concurrent_requests",
+            "HTTP_MAX_CONCURRENT_REQUESTS",
+            "must be at least 1",
+        );
+    }
+}
+
+impl OpsConfig {
+    fn validate(&self, violations: &mut Violations) {
+        violations.require(
+            self.listen_address.parse::<IpAddr>().is_ok(),
+            "ops.listen_address",
+            "OPS_LISTEN_ADDRESS",
+            format!(
+                "must be an IPv4 or IPv6 address, but was {:?}",
+                self.listen_address
+            ),
+        );
+        violations.require(
+            self.health_degraded_after_failures != 0,
+            "ops.health_degraded_after_failures",
+            "OPS_HEALTH_DEGRADED_AFTER_FAILURES",
+            "must be at least 1",
+        );
+        violations.require(
+            self.health_unavailable_ratio > 0.0 && self.health_unavailable_ratio <= 1.0,
+            "ops.health_unavailable_ratio",
+            "OPS_HEALTH_UNAVAILABLE_RATIO",
+            format!(
+                "must be greater than 0.0 and at most 1.0, but was {}",
+                self.health_unavailable_ratio
+            ),
+        );
+        for statistic in &self.game_statistics {
+            violations.require(
+                Self::GAME_STATISTICS.contains(&statistic.as_str()),
+                "ops.game_statistics",
+                "OPS_GAME_STATISTICS",
+                format!(
+                    "must be one of {}, but was {statistic:?}",
+                    Self::GAME_STATISTICS.join(", ")
+                ),
+            );
+        }
+        violations.require(
+            !self.game_statistics_active_windows_days.contains(&0),
+            "ops.game_statistics_active_windows_days",
+            "OPS_GAME_STATISTICS_ACTIVE_WINDOWS_DAYS",
+            "every window must be at least 1 day",
+        );
+    }
+}
+
+impl LoggingConfig {
+    fn validate(&self, violations: &mut Violations) {
+        violations.require(
+            (0.0..=1.0).contains(&self.data_quality_warn_ratio),
+            "logging.data_quality_warn_ratio",
+            "LOG_DATA_QUALITY_WARN_RATIO",
+            format!(
+                "must be between 0.0 and 1.0, but was {}",
+                self.data_quality_warn_ratio
+            ),
+        );
+    }
+}
+
+impl TracingConfig {
+    fn validate(&self, violations: &mut Violations) {
+        if let Some(endpoint) = &self.otlp_endpoint {
+            violations.require(
+                url::Url::parse(endpoint)
+                    .map_or(false, |url| matches!(url.scheme(), "http" | "https")),
+                "tracing.otlp_endpoint",
+                "TRACE_OTLP_ENDPOINT",
+                format!("must be an http or https URL, but was {endpoint:?}"),
+            );
+        }
+        violations.require(
+            (0.0..=1.0).contains(&self.sampling_ratio),
+            "tracing.sampling_ratio",
+            "TRACE_SAMPLING_RATIO",
+            format!(
+                "must be between 0.0 and 1.0, but was {}",
+                self.sampling_ratio
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        AppConfig, CsvSourceConfig, DatabaseName, ErrorReportingConfig, HostName, HttpConfig,
+        LoggingConfig, OpsConfig, Port, ResourceConfig, ResourcesConfig, SourceDatabaseConfig,
+        TimestampPrecision, TracingConfig,
+    };
+    use std::collections::BTreeMap;
+
+    fn valid_source_database_config() -> SourceDatabaseConfig {
+        SourceDatabaseConfig {
+            host: HostName::try_from("db.example.com".to_string()).unwrap(),
+            port: Port::MYSQL_DEFAULT,
+            resolve_srv: false,
+            database_name: DatabaseName::try_from("seichiassist".to_string()).unwrap(),
+            user: "bff".to_string(),
+            password: "$tr0ngpAssw0rd".to_string(),
+            max_connections: 5,
+            ssl_mode: None,
+            ssl_ca: None,
+            player_name_validation: Default::default(),
+            out_of_range_counters: Default::default(),
+            circuit_breaker_failures: 5,
+            circuit_breaker_cooldown_seconds: 30,
+        }
+    }
+
+    fn valid_config() -> AppConfig {
+        AppConfig {
+            source_database_config: Some(valid_source_database_config()),
+            source_database_profiles: BTreeMap::new(),
+            http_config: HttpConfig {
+                listen_address: "0.0.0.0".to_string(),
+                listen_port: Port::try_from(8080).unwrap(),
+                max_concurrent_requests: None,
+                retry_after_seconds: 1,
+                trusted_proxy_depth: 0,
+                drain_timeout_seconds: 30,
+                grpc_web: false,
+            },
+            ops_config: OpsConfig {
+                listen_address: "0.0.0.0".to_string(),
+                listen_port: None,
+                readiness_checks_database: false,
+                readiness_database_timeout_millis: 1000,
+                shutdown_delay_seconds: 5,
+                health_degraded_after_failures: 1,
+                health_unavailable_ratio: 1.0,
+                game_statistics: Vec::new(),
+                game_statistics_active_windows_days: vec![1, 7, 30],
+                game_statistics_interval_seconds: 300,
+            },
+            logging_config: LoggingConfig {
+                filter: None,
+                format: Default::default(),
+                file_directory: None,
+                file_rotation: Default::default(),
+                slow_fetch_threshold_millis: 2000,
+                slow_request_threshold_millis: 3000,
+                slow_acquire_threshold_millis: 500,
+                data_quality_warn_ratio: 0.01,
+                raw_player_names: false,
+                access_log: true,
+                access_log_excluded_paths: Vec::new(),
+            },
+            tracing_config: TracingConfig {
+                otlp_endpoint: None,
+                service_name: "seichi-game-api".to_string(),
+                sampling_ratio: 1.0,
+            },
+            error_reporting_config: ErrorReportingConfig {
+                dsn: None,
+                environment: None,
+                dedup_window_seconds: 600,
+            },
+            resources_config: ResourcesConfig {
+                last_quits: ResourceConfig::default(),
+                break_counts: ResourceConfig::default(),
+                build_counts: ResourceConfig::default(),
+                play_ticks: ResourceConfig::default(),
+                vote_counts: ResourceConfig::default(),
+            },
+            csv_source_config: CsvSourceConfig {
+                directory: None,
+                strict: true,
+            },
+            ignored_config_file_keys: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn valid_config_passes() {
+        assert_eq!(valid_config().validate(), Ok(()));
+    }
+
+    #[test]
+    fn all_violations_are_reported_together() {
+        let mut config = valid_config();
+        let source_database_config = config.source_database_config.as_mut().unwrap();
+        source_database_config.user = " ".to_string();
+        source_database_config.max_connections = 0;
+        source_database_config.circuit_breaker_cooldown_seconds = 0;
+        config.http_config.listen_address = "localhost".to_string();
+
+        let violations = config.validate().unwrap_err().0;
+
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.field.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "source_database.user",
+                "source_database.max_connections",
+                "source_database.circuit_breaker_cooldown_seconds",
+                "http.listen_address"
+            ]
+        );
+    }
+
+    #[test]
+    fn srv_records_are_only_looked_up_for_names() {
+        let mut config = valid_config();
+        let source_database_config = config.source_database_config.as_mut().unwrap();
+        source_database_config.resolve_srv = true;
+        assert_eq!(config.validate(), Ok(()));
+
+        config.source_database_config.as_mut().unwrap().host =
+            HostName::try_from("[::1]".to_string()).unwrap();
+        let violations = config.validate().unwrap_err().0;
+
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.variable.as_str())
+                .collect::<Vec<_>>(),
+            vec!["DB_RESOLVE_SRV"]
+        );
+    }
+
+    #[test]
+    fn profiles_are_validated_with_their_own_names() {
+        let mut config = valid_config();
+        let mut ranking = valid_source_database_config();
+        ranking.max_connections = 0;
+        config
+            .source_database_profiles
+            .insert("ranking".to_string(), ranking);
+        config.resources_config.vote_counts.connection_profile = Some("rankng".to_string());
+
+        let violations = config.validate().unwrap_err().0;
+
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| (violation.field.as_str(), violation.variable.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "source_database_profiles.ranking.max_connections",
+                    "DB_PROFILE_RANKING_MAX_CONNECTIONS"
+                ),
+                (
+                    "resources.vote_counts.connection_profile",
+                    "RESOURCE_VOTE_COUNTS_CONNECTION_PROFILE"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn connection_profiles_are_rejected_while_serving_csv_files() {
+        let mut config = valid_config();
+        config.source_database_config = None;
+        config.csv_source_config.directory = Some("/srv/season-1".into());
+        assert_eq!(config.validate(), Ok(()));
+
+        config
+            .source_database_profiles
+            .insert("ranking".to_string(), valid_source_database_config());
+        let violations = config.validate().unwrap_err().0;
+
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.variable.as_str())
+                .collect::<Vec<_>>(),
+            vec!["CSV_SOURCE_DIRECTORY"]
+        );
+    }
+
+    #[test]
+    fn timestamp_precision_is_only_accepted_for_resources_with_timestamps() {
+        let mut config = valid_config();
+        config.resources_config.last_quits.timestamp_precision = Some(TimestampPrecision::Date);
+        config.resources_config.vote_counts.timestamp_precision = Some(TimestampPrecision::Date);
+
+        let violations = config.validate().unwrap_err().0;
+
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.variable.as_str())
+                .collect::<Vec<_>>(),
+            vec!["RESOURCE_VOTE_COUNTS_TIMESTAMP_PRECISION"]
+        );
+    }
+
+    #[test]
+    fn health_thresholds_are_checked() {
+        let mut config = valid_config();
+        config.ops_config.health_degraded_after_failures = 0;
+        config.ops_config.health_unavailable_ratio = 0.0;
+
+        let violations = config.validate().unwrap_err().0;
+
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.variable.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "OPS_HEALTH_DEGRADED_AFTER_FAILURES",
+                "OPS_HEALTH_UNAVAILABLE_RATIO"
+            ]
+        );
+    }
+
+    #[test]
+    fn game_statistics_are_checked() {
+        let mut config = valid_config();
+        config.ops_config.game_statistics =
+            vec!["total_break_count".to_string(), "total_blocks".to_string()];
+        config.ops_config.game_statistics_active_windows_days = vec![7, 0];
+
+        let violations = config.validate().unwrap_err().0;
+
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.variable.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "OPS_GAME_STATISTICS",
+                "OPS_GAME_STATISTICS_ACTIVE_WINDOWS_DAYS"
+            ]
+        );
+    }
+
+    #[test]
+    fn tracing_endpoint_and_sampling_ratio_are_checked() {
+        let mut config = valid_config();
+        config.tracing_config.otlp_endpoint = Some("tempo:4317".to_string());
+        config.tracing_config.sampling_ratio = 1.5;
+
+        let violations = config.validate().unwrap_err().0;
+
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.variable.as_str())
+                .collect::<Vec<_>>(),
+            vec!["TRACE_OTLP_ENDPOINT", "TRACE_SAMPLING_RATIO"]
+        );
+    }
+}
@@ -1,7 +1,66 @@
-use anyhow::Result;
+use crate::summary::CounterSummary;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::fmt::{Display, Formatter};
+
+/// データソースからの取得が失敗した理由。
+///
+/// 呼び出し側が再試行すれば成功しうるかを判断できるよう、原因ごとに分ける。
+/// 取得の結果を複数の呼び出しで共有できるよう、元のエラーは文字列として持つ。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataSourceError {
+    /// データソースに接続できないか、接続が途中で切れた
+    Connection(String),
+    /// データソースが時間内に応答しなかった
+    Timeout(String),
+    /// 読み出した値をモデルに変換できなかった
+    Decode {
+        column: String,
+        detail: String,
+    },
+    Other(String),
+}
+
+impl DataSourceError {
+    /// 時間を置いて再び取得すれば成功しうるかどうか
+    pub const fn is_transient(&self) -> bool {
+        matches!(self, Self::Connection(_) | Self::Timeout(_))
+    }
+}
+
+impl Display for DataSourceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connection(detail) => write!(f, "failed to connect to the data source: {detail}"),
+            Self::Timeout(detail) => write!(f, "the data source timed out: {detail}"),
+            Self::Decode { column, detail } => write!(f, "failed to decode {column}: {detail}"),
+            Self::Other(detail) => write!(f, "{detail}"),
+        }
+    }
+}
+
+impl std::error::Error for DataSourceError {}
+
+impl From<anyhow::Error> for DataSourceError {
+    fn from(error: anyhow::Error) -> Self {
+        Self::Other(format!("{error:#}"))
+    }
+}
 
 #[async_trait]
 pub trait VecDataSource<T> {
-    async fn fetch(&self) -> Result<Vec<T>>;
+    async fn fetch(&self) -> Result<Vec<T>, DataSourceError>;
+}
+
+/// 全てのレコードを取得せずに、データソースの側で `T` の値を集計できるデータソース
+#[async_trait]
+pub trait AggregateDataSource<T> {
+    async fn summarize(&self) -> Result<CounterSummary, DataSourceError>;
+}
+
+/// 最後にログアウトした時刻でプレイヤーを数えられるデータソース
+#[async_trait]
+pub trait ActivePlayersDataSource {
+    /// `since` 以降に最後にログアウトしたプレイヤーの数
+    async fn count_active_since(&self, since: DateTime<Utc>) -> Result<u64, DataSourceError>;
 }
@@ -0,0 +1,132 @@
+//! ゲームDBの数値の列を、APIで提供するカウンタの値に変換する。
+
+/// カウンタとして表せない値 (負の値や `u64` に収まらない値) をどう扱うか
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutOfRange {
+    /// 最も近い表せる値に補正して提供する
+    #[default]
+    Clamp,
+    /// その行を提供しない
+    Skip,
+}
+
+/// 変換した値と、範囲外の値を補正したかどうか。提供しない場合はその理由
+pub type Conversion = Result<(u64, bool), &'static str>;
+
+fn out_of_range(clamped: u64, policy: OutOfRange, reason: &'static str) -> Conversion {
+    match policy {
+        OutOfRange::Clamp => Ok((clamped, true)),
+        OutOfRange::Skip => Err(reason),
+    }
+}
+
+pub fn counter_from_i64(raw: i64, policy: OutOfRange) -> Conversion {
+    u64::try_from(raw).map_or_else(
+        |_| out_of_range(0, policy, "negative"),
+        |count| Ok((count, false)),
+    )
+}
+
+pub fn counter_from_i32(raw: i32, policy: OutOfRange) -> Conversion {
+    counter_from_i64(i64::from(raw), policy)
+}
+
+/// 小数は四捨五入する。NaNや無限大は数として扱えないため、`policy` によらず提供しない
+pub fn counter_from_f64(raw: f64, policy: OutOfRange) -> Conversion {
+    if !raw.is_finite() {
+        return Err("not a finite number");
+    }
+
+    let rounded = raw.round();
+    // u64::MAX は f64 では 2^64 に丸められるため、それ以上を範囲外とする
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    let conversion = if rounded < 0.0 {
+        out_of_range(0, policy, "negative")
+    } else if rounded >= u64::MAX as f64 {
+        out_of_range(u64::MAX, policy, "too large")
+    } else {
+        Ok((rounded as u64, false))
+    };
+
+    conversion
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn policy() -> impl Strategy<Value = OutOfRange> {
+        prop_oneof![Just(OutOfRange::Clamp), Just(OutOfRange::Skip)]
+    }
+
+    /// 二つの方針の結果が、補正するかしないかの違いのみであること
+    fn assert_policies_agree(clamp: Conversion, skip: Conversion) -> Result<(), TestCaseError> {
+        match clamp {
+            Ok((_, true)) => prop_assert!(skip.is_err()),
+            clamp => prop_assert_eq!(clamp, skip),
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn nothing_panics(
+            int in any::<i64>(),
+            small_int in any::<i32>(),
+            float in proptest::num::f64::ANY,
+            policy in policy(),
+        ) {
+            let _ = counter_from_i64(int, policy);
+            let _ = counter_from_i32(small_int, policy);
+            let _ = counter_from_f64(float, policy);
+        }
+
+        #[test]
+        fn integers_are_kept_or_clamped_to_zero(raw in any::<i64>()) {
+            let converted = counter_from_i64(raw, OutOfRange::Clamp).unwrap();
+
+            if raw < 0 {
+                prop_assert_eq!(converted, (0, true));
+            } else {
+                prop_assert_eq!(converted, (raw as u64, false));
+            }
+            assert_policies_agree(Ok(converted), counter_from_i64(raw, OutOfRange::Skip))?;
+        }
+
+        #[test]
+        fn i32_counters_convert_like_i64_ones(raw in any::<i32>(), policy in policy()) {
+            prop_assert_eq!(counter_from_i32(raw, policy), counter_from_i64(i64::from(raw), policy));
+        }
+
+        #[test]
+        fn integer_valued_doubles_below_2_53_convert_exactly(raw in 0..(1_u64 << 53), policy in policy()) {
+            prop_assert_eq!(counter_from_f64(raw as f64, policy), Ok((raw, false)));
+        }
+
+        #[test]
+        fn finite_doubles_are_rounded_within_bounds(raw in proptest::num::f64::NORMAL | proptest::num::f64::ZERO) {
+            let (count, clamped) = counter_from_f64(raw, OutOfRange::Clamp).unwrap();
+
+            if clamped {
+                prop_assert!((count == 0 && raw < 0.0) || (count == u64::MAX && raw > 0.0));
+            } else {
+                prop_assert!((count as f64 - raw).abs() <= 0.5);
+            }
+            assert_policies_agree(Ok((count, clamped)), counter_from_f64(raw, OutOfRange::Skip))?;
+        }
+    }
+
+    #[test]
+    fn non_finite_doubles_are_never_provided() {
+        for policy in [OutOfRange::Clamp, OutOfRange::Skip] {
+            for raw in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+                assert_eq!(counter_from_f64(raw, policy), Err("not a finite number"));
+            }
+        }
+    }
+}
@@ -1,2 +1,9 @@
 pub mod app_models;
+pub mod conversion;
 pub mod models;
+pub mod pagination;
+pub mod schema;
+pub mod summary;
+pub mod time;
+#[cfg(test)]
+mod wire;
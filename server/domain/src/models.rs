@@ -1,35 +1,931 @@
-#[derive(Debug, Clone)]
+use crate::conversion::{
+    counter_from_f64, counter_from_i32, counter_from_i64, Conversion, OutOfRange,
+};
+use chrono::{DateTime, Datelike, SecondsFormat, Timelike, Utc};
+use rayon::slice::ParallelSliceMut;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Reverse;
+use std::fmt::{Display, Formatter, Write};
+use std::sync::Arc;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+use uuid::Uuid;
+
+/// プレイヤーのUUID。
+///
+/// ハイフン付きとハイフン無しのどちらの表記からも作れ、常にハイフン付きの小文字で書き出す。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PlayerUuid(Uuid);
+
+/// UUIDとして解釈できない文字列が渡された
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidPlayerUuid(String);
+
+impl Display for InvalidPlayerUuid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} is not a valid player UUID", self.0)
+    }
+}
+
+impl std::error::Error for InvalidPlayerUuid {}
+
+impl TryFrom<&str> for PlayerUuid {
+    type Error = InvalidPlayerUuid;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        // uuid クレートは波括弧やURNの表記も受け付けるが、ゲームDBに入りうるのはこの二つのみ
+        let invalid = || InvalidPlayerUuid(value.to_string());
+        match value.len() {
+            32 | 36 => Uuid::try_parse(value).map(Self).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl PlayerUuid {
+    pub const fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(Uuid::from_bytes(bytes))
+    }
+
+    pub const fn as_bytes(&self) -> &[u8; 16] {
+        self.0.as_bytes()
+    }
+}
+
+impl From<Uuid> for PlayerUuid {
+    fn from(uuid: Uuid) -> Self {
+        Self(uuid)
+    }
+}
+
+impl Display for PlayerUuid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0.hyphenated(), f)
+    }
+}
+
+impl JsonSchema for PlayerUuid {
+    fn schema_name() -> String {
+        "PlayerUuid".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            format: Some("uuid".to_string()),
+            ..SchemaObject::default()
+        }
+        .into()
+    }
+}
+
+impl Serialize for PlayerUuid {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PlayerUuid {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        Self::try_from(value.as_ref()).map_err(serde::de::Error::custom)
+    }
+}
+
+/// プレイヤーの名前の長さをどこまで検証するか
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NameValidation {
+    /// Minecraftの名前として有効な、1文字以上16文字以下の名前のみを受け付ける
+    Strict,
+    /// 古いデータに残る、長さの合わない名前も受け付ける
+    #[default]
+    Lenient,
+}
+
+/// 色コードを表す文字。続く一文字が色や書式の種類を表す
+const COLOR_CODE_PREFIX: char = '\u{a7}';
+const MAX_NAME_LENGTH: usize = 16;
+
+/// プレイヤーの名前。
+///
+/// CSVなどに書き出しても崩れないよう、古いデータに残る色コード (`§` とそれに続く一文字) を取り除き、NFCに正規化して持つ。
+/// それによって値が変わった場合は、調査のために元の値も持つ。
+///
+/// 一度の取得の結果は呼び出しごとに複製されるため、複製しても文字列を確保し直さないよう `Arc<str>` で持つ。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerName {
+    name: Arc<str>,
+    raw: Option<Arc<str>>,
+}
+
+impl PlayerName {
+    /// ゲームDBに記録されていた名前 `raw` を正規化し、`validation` に従って長さを確かめる
+    pub fn new(raw: &str, validation: NameValidation) -> Result<Self, DomainValidationError> {
+        // ほとんどの名前は正規化しても変わらないため、その場合は文字列を一度だけ確保する
+        let name: Arc<str> = if !raw.contains(COLOR_CODE_PREFIX) && is_nfc(raw) {
+            Arc::from(raw)
+        } else {
+            let mut stripped = String::with_capacity(raw.len());
+            let mut chars = raw.chars();
+            while let Some(character) = chars.next() {
+                if character == COLOR_CODE_PREFIX {
+                    chars.next();
+                } else {
+                    stripped.push(character);
+                }
+            }
+            Arc::from(stripped.nfc().collect::<String>())
+        };
+
+        if validation == NameValidation::Strict
+            && !(1..=MAX_NAME_LENGTH).contains(&name.chars().count())
+        {
+            return Err(DomainValidationError {
+                field: "name",
+                value: format!("{raw:?}"),
+                reason: "not between 1 and 16 characters long",
+            });
+        }
+
+        let raw = (*name != *raw).then(|| Arc::from(raw));
+        Ok(Self { name, raw })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+
+    /// 正規化によって値が変わった場合の、ゲームDBに記録されていた元の値
+    pub fn raw(&self) -> Option<&str> {
+        self.raw.as_deref()
+    }
+
+    pub const fn was_sanitized(&self) -> bool {
+        self.raw.is_some()
+    }
+}
+
+impl From<PlayerName> for String {
+    fn from(name: PlayerName) -> Self {
+        name.name.as_ref().to_string()
+    }
+}
+
+impl Display for PlayerName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl JsonSchema for PlayerName {
+    fn schema_name() -> String {
+        "PlayerName".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+// 元の値は調査のためのものなので、提供しない
+impl Serialize for PlayerName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.name)
+    }
+}
+
+// 直列化したときのフィールド名はゲーム内のプラグインなどが依存する契約なので、
+// Rustでの名前を変えても変わらないよう全て明示し、`wire` のテストで固定する
+#[derive(Serialize, JsonSchema, Debug, Clone)]
 pub struct Player {
-    pub uuid: String,
-    pub last_known_name: String,
+    #[serde(rename = "uuid")]
+    pub uuid: PlayerUuid,
+    #[serde(rename = "last_known_name")]
+    pub last_known_name: PlayerName,
+}
+
+/// 退出時刻をAPIで提供するときの表記。
+///
+/// 秒までの精度で、UTCを `Z` で表すRFC 3339の形式 (`2023-04-01T12:34:56Z`) とする。
+/// 行ごとに書き出すため、書式の文字列を解釈せず、一時的な文字列も作らずに書き出す。
+pub struct Rfc3339Seconds<'a>(pub &'a DateTime<Utc>);
+
+impl Display for Rfc3339Seconds<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let date_time = self.0;
+        // 4桁に収まらない年はRFC 3339では表せないため、chronoの表記に任せる
+        if !(0..=9999).contains(&date_time.year()) {
+            return f.write_str(&date_time.to_rfc3339_opts(SecondsFormat::Secs, true));
+        }
+
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            date_time.year(),
+            date_time.month(),
+            date_time.day(),
+            date_time.hour(),
+            date_time.minute(),
+            date_time.second()
+        )
+    }
+}
+
+/// `Rfc3339Seconds` の表記の文字列。gRPCのメッセージのように、文字列として持たせる場合に使う
+pub fn to_rfc_3339(date_time: &DateTime<Utc>) -> String {
+    let mut formatted = String::with_capacity("0000-00-00T00:00:00Z".len());
+    write!(formatted, "{}", Rfc3339Seconds(date_time)).expect("Writing to a String never fails");
+    formatted
 }
 
-#[derive(Debug, Clone)]
+fn serialize_rfc_3339<S: Serializer>(
+    date_time: &DateTime<Utc>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&Rfc3339Seconds(date_time))
+}
+
+fn serialize_optional_rfc_3339<S: Serializer>(
+    date_time: &Option<DateTime<Utc>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match date_time {
+        Some(date_time) => serializer.collect_str(&Rfc3339Seconds(date_time)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// 退出時刻には、大きいほど上位という意味が無いため [`Ranked`] を実装しない。
+/// 最近の退出順に並べる場合は、用途に合わせて同時刻の扱いを決めること。
+#[derive(Serialize, JsonSchema, Debug, Clone)]
 pub struct PlayerLastQuit {
+    #[serde(rename = "player")]
     pub player: Player,
-    pub rfc_3339_date_time: String,
+    #[serde(rename = "rfc_3339_date_time", serialize_with = "serialize_rfc_3339")]
+    pub last_quit: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, JsonSchema, Debug, Clone)]
 pub struct PlayerBreakCount {
+    #[serde(rename = "player")]
     pub player: Player,
+    #[serde(rename = "break_count")]
     pub break_count: u64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, JsonSchema, Debug, Clone)]
 pub struct PlayerBuildCount {
+    #[serde(rename = "player")]
     pub player: Player,
+    #[serde(rename = "build_count")]
     pub build_count: u64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, JsonSchema, Debug, Clone)]
 pub struct PlayerPlayTicks {
+    #[serde(rename = "player")]
     pub player: Player,
+    #[serde(rename = "play_ticks")]
     pub play_ticks: u64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, JsonSchema, Debug, Clone)]
 pub struct PlayerVoteCount {
+    #[serde(rename = "player")]
     pub player: Player,
+    #[serde(rename = "vote_count")]
     pub vote_count: u64,
 }
+
+/// ゲームDBの値からレコードを作れなかった理由
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomainValidationError {
+    pub field: &'static str,
+    pub value: String,
+    pub reason: &'static str,
+}
+
+impl Display for DomainValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} = {} was rejected: {}",
+            self.field, self.value, self.reason
+        )
+    }
+}
+
+impl std::error::Error for DomainValidationError {}
+
+/// ゲームDBの値から作ったレコードと、範囲外の値を補正したかどうか
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validated<T> {
+    pub record: T,
+    pub clamped: bool,
+}
+
+/// カウンタの値を変換し、提供しない場合はその理由を `field` の検証のエラーとする
+fn counter(
+    field: &'static str,
+    raw: impl ToString,
+    conversion: Conversion,
+) -> Result<(u64, bool), DomainValidationError> {
+    conversion.map_err(|reason| DomainValidationError {
+        field,
+        value: raw.to_string(),
+        reason,
+    })
+}
+
+impl PlayerLastQuit {
+    /// 一度も退出していないプレイヤーの退出時刻は無いため、そのレコードは作らない
+    pub fn new(
+        player: Player,
+        last_quit: Option<DateTime<Utc>>,
+    ) -> Result<Validated<Self>, DomainValidationError> {
+        let last_quit = last_quit.ok_or(DomainValidationError {
+            field: "last_quit",
+            value: "NULL".to_string(),
+            reason: "the player has never quit",
+        })?;
+
+        Ok(Validated {
+            record: Self { player, last_quit },
+            clamped: false,
+        })
+    }
+}
+
+impl PlayerBreakCount {
+    /// 負の値は `policy` に従って0に補正するか、作らない
+    pub fn new(
+        player: Player,
+        raw: i64,
+        policy: OutOfRange,
+    ) -> Result<Validated<Self>, DomainValidationError> {
+        let (break_count, clamped) = counter("break_count", raw, counter_from_i64(raw, policy))?;
+
+        Ok(Validated {
+            record: Self {
+                player,
+                break_count,
+            },
+            clamped,
+        })
+    }
+}
+
+impl PlayerBuildCount {
+    /// ゲームDBでは小数として持つため、四捨五入する。
+    ///
+    /// 負の値や `u64` に収まらない値は `policy` に従って補正するか作らず、NaNや無限大は数として扱えないため作らない。
+    pub fn new(
+        player: Player,
+        raw: f64,
+        policy: OutOfRange,
+    ) -> Result<Validated<Self>, DomainValidationError> {
+        let (build_count, clamped) = counter("build_count", raw, counter_from_f64(raw, policy))?;
+
+        Ok(Validated {
+            record: Self {
+                player,
+                build_count,
+            },
+            clamped,
+        })
+    }
+}
+
+impl PlayerPlayTicks {
+    /// 負の値は `policy` に従って0に補正するか、作らない
+    pub fn new(
+        player: Player,
+        raw: i64,
+        policy: OutOfRange,
+    ) -> Result<Validated<Self>, DomainValidationError> {
+        let (play_ticks, clamped) = counter("play_ticks", raw, counter_from_i64(raw, policy))?;
+
+        Ok(Validated {
+            record: Self { player, play_ticks },
+            clamped,
+        })
+    }
+}
+
+impl PlayerVoteCount {
+    /// 負の値は `policy` に従って0に補正するか、作らない
+    pub fn new(
+        player: Player,
+        raw: i32,
+        policy: OutOfRange,
+    ) -> Result<Validated<Self>, DomainValidationError> {
+        let (vote_count, clamped) = counter("vote_count", raw, counter_from_i32(raw, policy))?;
+
+        Ok(Validated {
+            record: Self { player, vote_count },
+            clamped,
+        })
+    }
+}
+
+/// ランキングでの並び順を表すキー。小さいほど上位になる
+pub type RankingKey = (Reverse<u64>, PlayerUuid);
+
+/// ランキングに並べられるカウンタのレコード。
+///
+/// 値の大きい順に並べ、同じ値ならUUIDの正規の表記の昇順とすることで、同率の並びを常に同じにする。
+pub trait Ranked {
+    fn ranking_key(&self) -> RankingKey;
+}
+
+/// `sort_for_ranking` が並列に並べ替え始めるレコードの数。
+///
+/// これより少なければスレッドに分ける手間の方が大きいため、一つのスレッドで並べ替える。
+pub const PARALLEL_SORT_THRESHOLD: usize = 10_000;
+
+/// `records` をランキングの順に並べる
+pub fn sort_for_ranking<T: Ranked + Send>(records: &mut [T]) {
+    sort_for_ranking_with_threshold(records, PARALLEL_SORT_THRESHOLD);
+}
+
+/// `records` をランキングの順に並べる。`parallel_threshold` 件以上あれば、rayonの共有のスレッドプールで並列に並べる。
+///
+/// どちらも安定ソートでキーも同じため、結果は並列かどうかによらない。
+pub fn sort_for_ranking_with_threshold<T: Ranked + Send>(
+    records: &mut [T],
+    parallel_threshold: usize,
+) {
+    if records.len() >= parallel_threshold {
+        records.par_sort_by_key(Ranked::ranking_key);
+    } else {
+        records.sort_by_key(Ranked::ranking_key);
+    }
+}
+
+impl Ranked for PlayerBreakCount {
+    fn ranking_key(&self) -> RankingKey {
+        (Reverse(self.break_count), self.player.uuid)
+    }
+}
+
+impl Ranked for PlayerBuildCount {
+    fn ranking_key(&self) -> RankingKey {
+        (Reverse(self.build_count), self.player.uuid)
+    }
+}
+
+impl Ranked for PlayerPlayTicks {
+    fn ranking_key(&self) -> RankingKey {
+        (Reverse(self.play_ticks), self.player.uuid)
+    }
+}
+
+impl Ranked for PlayerVoteCount {
+    fn ranking_key(&self) -> RankingKey {
+        (Reverse(self.vote_count), self.player.uuid)
+    }
+}
+
+/// 一人のプレイヤーの全ての数値。
+///
+/// 複数のデータソースから集めた値を `merge` でまとめる。取得できなかった値は `Default` の0や `None` とする。
+#[derive(Serialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlayerStats {
+    #[serde(rename = "break_count")]
+    pub break_count: u64,
+    #[serde(rename = "build_count")]
+    pub build_count: u64,
+    #[serde(rename = "play_ticks")]
+    pub play_ticks: u64,
+    #[serde(rename = "vote_count")]
+    pub vote_count: u64,
+    #[serde(rename = "last_quit", serialize_with = "serialize_optional_rfc_3339")]
+    pub last_quit: Option<DateTime<Utc>>,
+}
+
+impl PlayerStats {
+    /// カウンタは足し合わせ (上限で飽和させる)、退出時刻は遅い方を取る
+    #[must_use]
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            break_count: self.break_count.saturating_add(other.break_count),
+            build_count: self.build_count.saturating_add(other.build_count),
+            play_ticks: self.play_ticks.saturating_add(other.play_ticks),
+            vote_count: self.vote_count.saturating_add(other.vote_count),
+            last_quit: self.last_quit.max(other.last_quit),
+        }
+    }
+}
+
+impl From<&PlayerBreakCount> for PlayerStats {
+    fn from(record: &PlayerBreakCount) -> Self {
+        Self {
+            break_count: record.break_count,
+            ..Self::default()
+        }
+    }
+}
+
+impl From<&PlayerBuildCount> for PlayerStats {
+    fn from(record: &PlayerBuildCount) -> Self {
+        Self {
+            build_count: record.build_count,
+            ..Self::default()
+        }
+    }
+}
+
+impl From<&PlayerPlayTicks> for PlayerStats {
+    fn from(record: &PlayerPlayTicks) -> Self {
+        Self {
+            play_ticks: record.play_ticks,
+            ..Self::default()
+        }
+    }
+}
+
+impl From<&PlayerVoteCount> for PlayerStats {
+    fn from(record: &PlayerVoteCount) -> Self {
+        Self {
+            vote_count: record.vote_count,
+            ..Self::default()
+        }
+    }
+}
+
+impl From<&PlayerLastQuit> for PlayerStats {
+    fn from(record: &PlayerLastQuit) -> Self {
+        Self {
+            last_quit: Some(record.last_quit),
+            ..Self::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+    use proptest::prelude::*;
+
+    fn player_stats() -> impl Strategy<Value = PlayerStats> {
+        // 飽和する場合も確かめられるよう、上限付近の値も選ばせる
+        let counter = prop_oneof![0..1_000_000_u64, (u64::MAX - 1_000_000)..=u64::MAX].boxed();
+        (
+            counter.clone(),
+            counter.clone(),
+            counter.clone(),
+            counter,
+            proptest::option::of(0..2_000_000_000_i64),
+        )
+            .prop_map(
+                |(break_count, build_count, play_ticks, vote_count, last_quit)| PlayerStats {
+                    break_count,
+                    build_count,
+                    play_ticks,
+                    vote_count,
+                    last_quit: last_quit.map(|seconds| Utc.timestamp_opt(seconds, 0).unwrap()),
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn merge_is_commutative(a in player_stats(), b in player_stats()) {
+            prop_assert_eq!(a.merge(b), b.merge(a));
+        }
+
+        #[test]
+        fn merge_is_associative(a in player_stats(), b in player_stats(), c in player_stats()) {
+            prop_assert_eq!(a.merge(b).merge(c), a.merge(b.merge(c)));
+        }
+
+        #[test]
+        fn default_is_the_identity_of_merge(a in player_stats()) {
+            prop_assert_eq!(a.merge(PlayerStats::default()), a);
+        }
+    }
+
+    fn notch() -> Player {
+        Player {
+            uuid: PlayerUuid::try_from("069a79f444e94726a5befca90e38aaf5").unwrap(),
+            last_known_name: PlayerName::new("Notch", NameValidation::Strict).unwrap(),
+        }
+    }
+
+    #[test]
+    fn negative_counters_are_clamped_to_zero() {
+        let clamped = PlayerBreakCount::new(notch(), -1, OutOfRange::Clamp).unwrap();
+        assert_eq!(clamped.record.break_count, 0);
+        assert!(clamped.clamped);
+
+        let kept = PlayerBreakCount::new(notch(), 0, OutOfRange::Clamp).unwrap();
+        assert_eq!(kept.record.break_count, 0);
+        assert!(!kept.clamped);
+
+        let max = PlayerPlayTicks::new(notch(), i64::MAX, OutOfRange::Clamp).unwrap();
+        assert_eq!(max.record.play_ticks, i64::MAX as u64);
+        assert!(!max.clamped);
+        assert!(
+            PlayerPlayTicks::new(notch(), i64::MIN, OutOfRange::Clamp)
+                .unwrap()
+                .clamped
+        );
+
+        let votes = PlayerVoteCount::new(notch(), i32::MIN, OutOfRange::Clamp).unwrap();
+        assert_eq!(votes.record.vote_count, 0);
+        assert!(votes.clamped);
+        assert_eq!(
+            PlayerVoteCount::new(notch(), i32::MAX, OutOfRange::Clamp)
+                .unwrap()
+                .record
+                .vote_count,
+            i32::MAX as u64
+        );
+    }
+
+    #[test]
+    fn build_counts_are_rounded_and_clamped() {
+        let build_count = |raw| {
+            PlayerBuildCount::new(notch(), raw, OutOfRange::Clamp)
+                .map(|validated| (validated.record.build_count, validated.clamped))
+        };
+
+        assert_eq!(build_count(2.5), Ok((3, false)));
+        assert_eq!(build_count(-0.4), Ok((0, false)));
+        assert_eq!(build_count(-0.5), Ok((0, true)));
+        assert_eq!(build_count(1e30), Ok((u64::MAX, true)));
+
+        let error = build_count(f64::NAN).unwrap_err();
+        assert_eq!(error.field, "build_count");
+        assert_eq!(
+            error.to_string(),
+            "build_count = NaN was rejected: not a finite number"
+        );
+        assert!(build_count(f64::INFINITY).is_err());
+    }
+
+    #[test]
+    fn out_of_range_counters_are_skipped_by_policy() {
+        let error = PlayerVoteCount::new(notch(), -1, OutOfRange::Skip).unwrap_err();
+        assert_eq!(error.to_string(), "vote_count = -1 was rejected: negative");
+
+        let error = PlayerBuildCount::new(notch(), 1e30, OutOfRange::Skip).unwrap_err();
+        assert_eq!(error.field, "build_count");
+        assert_eq!(error.reason, "too large");
+
+        assert!(
+            !PlayerPlayTicks::new(notch(), 7, OutOfRange::Skip)
+                .unwrap()
+                .clamped
+        );
+    }
+
+    #[test]
+    fn last_quit_is_required() {
+        let error = PlayerLastQuit::new(notch(), None).unwrap_err();
+        assert_eq!(error.field, "last_quit");
+        assert_eq!(error.value, "NULL");
+
+        assert!(PlayerLastQuit::new(notch(), Some(Utc::now())).is_ok());
+    }
+
+    #[test]
+    fn ties_are_ranked_by_uuid() {
+        let break_count = |uuid: &str, break_count| PlayerBreakCount {
+            player: Player {
+                uuid: PlayerUuid::try_from(uuid).unwrap(),
+                last_known_name: PlayerName::new(&uuid.to_string(), NameValidation::Lenient)
+                    .unwrap(),
+            },
+            break_count,
+        };
+        let mut records = vec![
+            break_count("f0000000000000000000000000000000", 10),
+            break_count("00000000000000000000000000000002", 5),
+            break_count("a0000000000000000000000000000000", 10),
+            break_count("00000000000000000000000000000001", 5),
+            break_count("00000000000000000000000000000003", 20),
+        ];
+        let expected = [
+            "00000000-0000-0000-0000-000000000003",
+            "a0000000-0000-0000-0000-000000000000",
+            "f0000000-0000-0000-0000-000000000000",
+            "00000000-0000-0000-0000-000000000001",
+            "00000000-0000-0000-0000-000000000002",
+        ];
+
+        sort_for_ranking(&mut records);
+        let ranked = records
+            .iter()
+            .map(|record| record.player.uuid.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(ranked, expected);
+
+        // 入力の順序によらず同じ並びになる
+        records.reverse();
+        sort_for_ranking(&mut records);
+        let ranked_again = records
+            .iter()
+            .map(|record| record.player.uuid.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(ranked_again, expected);
+    }
+
+    #[test]
+    fn hyphenated_and_compact_forms_are_the_same_uuid() {
+        let hyphenated = PlayerUuid::try_from("069A79F4-44E9-4726-A5BE-FCA90E38AAF5").unwrap();
+        let compact = PlayerUuid::try_from("069a79f444e94726a5befca90e38aaf5").unwrap();
+
+        assert_eq!(hyphenated, compact);
+        assert_eq!(
+            hyphenated.to_string(),
+            "069a79f4-44e9-4726-a5be-fca90e38aaf5"
+        );
+        assert_eq!(
+            serde_json::to_string(&compact).unwrap(),
+            r#""069a79f4-44e9-4726-a5be-fca90e38aaf5""#
+        );
+    }
+
+    #[test]
+    fn last_quits_are_formatted_to_the_second_in_utc() {
+        let player = Player {
+            uuid: PlayerUuid::try_from("069a79f444e94726a5befca90e38aaf5").unwrap(),
+            last_known_name: PlayerName::new("Notch", NameValidation::Strict).unwrap(),
+        };
+        let last_quit = |rfc_3339: &str| PlayerLastQuit {
+            player: player.clone(),
+            last_quit: DateTime::parse_from_rfc3339(rfc_3339)
+                .unwrap()
+                .with_timezone(&Utc),
+        };
+
+        assert_eq!(
+            serde_json::to_string(&last_quit("2023-04-01T12:34:56+09:00")).unwrap(),
+            r#"{"player":{"uuid":"069a79f4-44e9-4726-a5be-fca90e38aaf5","last_known_name":"Notch"},"rfc_3339_date_time":"2023-04-01T03:34:56Z"}"#
+        );
+        for (input, expected) in [
+            ("2023-04-01T00:00:00.250Z", "2023-04-01T00:00:00Z"),
+            ("2024-02-29T23:59:59Z", "2024-02-29T23:59:59Z"),
+            ("2023-12-31T23:59:59.999999999Z", "2023-12-31T23:59:59Z"),
+            ("2024-01-01T08:59:59+09:00", "2023-12-31T23:59:59Z"),
+            ("0001-01-01T00:00:00Z", "0001-01-01T00:00:00Z"),
+            ("9999-12-31T23:59:59Z", "9999-12-31T23:59:59Z"),
+        ] {
+            let formatted = to_rfc_3339(&last_quit(input).last_quit);
+
+            assert_eq!(formatted, expected, "{input}");
+            assert!(DateTime::parse_from_rfc3339(&formatted).is_ok(), "{input}");
+        }
+    }
+
+    /// `rfc_3339_date_time` は以前 `+00:00` のオフセットと秒の端数を含んでいたため、利用者が読む今の表記をスナップショットで固定する
+    #[test]
+    fn last_quits_match_their_snapshot() {
+        let last_quit = PlayerLastQuit {
+            player: Player {
+                uuid: PlayerUuid::try_from("069a79f444e94726a5befca90e38aaf5").unwrap(),
+                last_known_name: PlayerName::new("Notch", NameValidation::Strict).unwrap(),
+            },
+            last_quit: DateTime::parse_from_rfc3339("2023-04-01T21:34:56.789+09:00")
+                .unwrap()
+                .with_timezone(&Utc),
+        };
+
+        insta::assert_json_snapshot!("last_quit", last_quit);
+    }
+
+    #[test]
+    fn names_and_other_uuid_forms_are_rejected() {
+        for value in [
+            "Notch",
+            "",
+            "{069a79f4-44e9-4726-a5be-fca90e38aaf5}",
+            "urn:uuid:069a79f4-44e9-4726-a5be-fca90e38aaf5",
+        ] {
+            assert!(PlayerUuid::try_from(value).is_err(), "{value} was accepted");
+        }
+    }
+
+    #[test]
+    fn color_codes_are_stripped_from_names() {
+        let name = PlayerName::new("\u{a7}aNotch\u{a7}r", NameValidation::Strict).unwrap();
+
+        assert_eq!(name.as_str(), "Notch");
+        assert_eq!(name.raw(), Some("\u{a7}aNotch\u{a7}r"));
+        assert!(PlayerName::new("Notch\u{a7}", NameValidation::Strict).is_ok());
+        assert!(!PlayerName::new("Notch", NameValidation::Strict)
+            .unwrap()
+            .was_sanitized());
+    }
+
+    #[test]
+    fn cjk_names_are_normalized_to_nfc() {
+        // 「が」を「か」と濁点の結合文字で表したもの
+        let name =
+            PlayerName::new("\u{304b}\u{3099}\u{3093}\u{305d}", NameValidation::Strict).unwrap();
+
+        assert_eq!(name.as_str(), "\u{304c}\u{3093}\u{305d}");
+        assert!(name.was_sanitized());
+
+        let full_width =
+            PlayerName::new("\u{6574}\u{5730}\u{ff21}", NameValidation::Strict).unwrap();
+        assert_eq!(full_width.as_str(), "\u{6574}\u{5730}\u{ff21}");
+        assert!(!full_width.was_sanitized());
+    }
+
+    #[test]
+    fn name_lengths_are_checked_only_in_strict_mode() {
+        for raw in ["", "\u{a7}a", "a_name_longer_than_16"] {
+            let error = PlayerName::new(raw, NameValidation::Strict).unwrap_err();
+            assert_eq!(error.field, "name");
+
+            assert!(PlayerName::new(raw, NameValidation::Lenient).is_ok());
+        }
+        // 長さは正規化した後の文字数で数える
+        assert!(PlayerName::new("\u{a7}a0123456789abcdef", NameValidation::Strict).is_ok());
+        assert!(PlayerName::new("\u{6574}".repeat(16).as_str(), NameValidation::Strict).is_ok());
+    }
+
+    #[test]
+    fn names_are_serialized_without_their_raw_value() {
+        let name = PlayerName::new("\u{a7}aNotch", NameValidation::Strict).unwrap();
+
+        assert_eq!(serde_json::to_string(&name).unwrap(), r#""Notch""#);
+    }
+
+    // ゲームDBやクライアントから来る任意の文字列を与えても、パニックせず検証で弾くこと
+    proptest! {
+        #[test]
+        fn parallel_and_sequential_rankings_are_identical(
+            entries in proptest::collection::vec((0..8_u8, 0..4_u64), 0..2000),
+        ) {
+            // UUIDと値の重複が多く、同じUUIDの行は名前で見分けられるようにする
+            let records = entries
+                .iter()
+                .enumerate()
+                .map(|(index, (uuid, break_count))| PlayerBreakCount {
+                    player: Player {
+                        uuid: PlayerUuid::from_bytes([*uuid; 16]),
+                        last_known_name: PlayerName::new(&index.to_string(), NameValidation::Strict)
+                            .unwrap(),
+                    },
+                    break_count: *break_count,
+                })
+                .collect::<Vec<_>>();
+            let mut sequential = records.clone();
+            let mut parallel = records;
+
+            sort_for_ranking_with_threshold(&mut sequential, usize::MAX);
+            sort_for_ranking_with_threshold(&mut parallel, 0);
+
+            let ranked = |records: &[PlayerBreakCount]| {
+                records
+                    .iter()
+                    .map(|record| (record.ranking_key(), record.player.last_known_name.to_string()))
+                    .collect::<Vec<_>>()
+            };
+            prop_assert_eq!(ranked(&parallel), ranked(&sequential));
+        }
+
+        #[test]
+        fn arbitrary_strings_never_panic_as_uuids(value in any::<String>()) {
+            if let Ok(uuid) = PlayerUuid::try_from(value.as_str()) {
+                prop_assert_eq!(
+                    uuid.to_string().replace('-', ""),
+                    value.replace('-', "").to_lowercase()
+                );
+            }
+        }
+
+        #[test]
+        fn every_hex_form_of_a_uuid_is_the_same_uuid(
+            bytes in any::<[u8; 16]>(),
+            uppercase in any::<bool>(),
+        ) {
+            let uuid = PlayerUuid::from_bytes(bytes);
+            let hyphenated = uuid.to_string();
+            let hyphenated = if uppercase { hyphenated.to_uppercase() } else { hyphenated };
+
+            prop_assert_eq!(PlayerUuid::try_from(hyphenated.as_str()), Ok(uuid));
+            prop_assert_eq!(
+                PlayerUuid::try_from(hyphenated.replace('-', "").as_str()),
+                Ok(uuid)
+            );
+        }
+
+        #[test]
+        fn arbitrary_names_never_keep_color_codes(raw in any::<String>()) {
+            let name = PlayerName::new(&raw, NameValidation::Lenient).unwrap();
+
+            prop_assert!(!name.as_str().contains(COLOR_CODE_PREFIX));
+            if let Ok(strict) = PlayerName::new(&raw, NameValidation::Strict) {
+                let length = strict.as_str().chars().count();
+                prop_assert!((1..=MAX_NAME_LENGTH).contains(&length));
+            }
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use crate::models::PlayerUuid;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+
+/// 件数の指定が無い場合に一度に返す件数
+pub const DEFAULT_LIMIT: usize = 100;
+/// 一度に返す件数の上限。これより大きい指定はこの値に切り詰める
+pub const MAX_LIMIT: usize = 1000;
+
+/// 指定された件数を、0件でなく上限を超えない値にする
+pub fn limit_or_default(limit: Option<usize>) -> usize {
+    limit.map_or(DEFAULT_LIMIT, |limit| limit.clamp(1, MAX_LIMIT))
+}
+
+/// 件数と位置で指定された、一覧の一部
+#[derive(Serialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+pub struct Paginated<T> {
+    #[serde(rename = "items")]
+    pub items: Vec<T>,
+    /// 一覧全体の件数
+    #[serde(rename = "total")]
+    pub total: usize,
+    #[serde(rename = "limit")]
+    pub limit: usize,
+    #[serde(rename = "offset")]
+    pub offset: usize,
+}
+
+impl<T: Clone> Paginated<T> {
+    /// `records` の `offset` 件目から最大 `limit` 件を取り出す
+    pub fn slice(records: &[T], offset: usize, limit: Option<usize>) -> Self {
+        let limit = limit_or_default(limit);
+        let start = offset.min(records.len());
+        let end = start.saturating_add(limit).min(records.len());
+
+        Self {
+            items: records[start..end].to_vec(),
+            total: records.len(),
+            limit,
+            offset,
+        }
+    }
+}
+
+/// カーソルを解釈できなかった理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidCursor {
+    /// このサーバーが発行したものではないか、書き換えられている
+    Malformed,
+    /// 発行した後にデータが取得し直されているため、続きの位置が定まらない
+    Stale,
+}
+
+impl Display for InvalidCursor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "the cursor is malformed"),
+            Self::Stale => write!(f, "the cursor was issued for an older snapshot"),
+        }
+    }
+}
+
+impl std::error::Error for InvalidCursor {}
+
+/// 一覧の続きの位置。直前のページの最後のプレイヤーと、その一覧を取得した世代からなる
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub generation: u64,
+    pub last: PlayerUuid,
+}
+
+const CURSOR_LENGTH: usize = 8 + 16 + 4;
+
+/// 書き換えを検出するためのFNV-1aによるチェックサム。秘密の値を使わないため、偽造は防げない
+fn checksum(bytes: &[u8]) -> [u8; 4] {
+    let hash = bytes.iter().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    });
+    hash.to_be_bytes()
+}
+
+impl Cursor {
+    /// URLにそのまま含められる、中身を意識させない文字列にする
+    pub fn encode(&self) -> String {
+        let mut bytes = Vec::with_capacity(CURSOR_LENGTH);
+        bytes.extend_from_slice(&self.generation.to_be_bytes());
+        bytes.extend_from_slice(self.last.as_bytes());
+        let checksum = checksum(&bytes);
+        bytes.extend_from_slice(&checksum);
+
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// `encode` した文字列を読み、現在の世代 `generation` のものであることを確かめる
+    pub fn decode(cursor: &str, generation: u64) -> Result<Self, InvalidCursor> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(cursor)
+            .map_err(|_| InvalidCursor::Malformed)?;
+        if bytes.len() != CURSOR_LENGTH || checksum(&bytes[..24]) != bytes[24..] {
+            return Err(InvalidCursor::Malformed);
+        }
+
+        let mut generation_bytes = [0; 8];
+        generation_bytes.copy_from_slice(&bytes[..8]);
+        let mut uuid_bytes = [0; 16];
+        uuid_bytes.copy_from_slice(&bytes[8..24]);
+
+        let cursor = Self {
+            generation: u64::from_be_bytes(generation_bytes),
+            last: PlayerUuid::from_bytes(uuid_bytes),
+        };
+        if cursor.generation == generation {
+            Ok(cursor)
+        } else {
+            Err(InvalidCursor::Stale)
+        }
+    }
+}
+
+/// カーソルで続きを辿る一覧の一部
+#[derive(Serialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+pub struct CursorPage<T> {
+    #[serde(rename = "items")]
+    pub items: Vec<T>,
+    /// 続きがあれば、それを取得するためのカーソル
+    #[serde(rename = "next_cursor")]
+    pub next_cursor: Option<String>,
+}
+
+impl<T: Clone> CursorPage<T> {
+    /// 世代 `generation` の一覧 `records` のうち、`after` の後から最大 `limit` 件を取り出す。
+    ///
+    /// `records` は同じ世代の間は同じ順序で並んでいなければならない。
+    pub fn slice(
+        records: &[T],
+        generation: u64,
+        after: Option<&Cursor>,
+        limit: Option<usize>,
+        uuid_of: impl Fn(&T) -> PlayerUuid,
+    ) -> Result<Self, InvalidCursor> {
+        let limit = limit_or_default(limit);
+        let start = match after {
+            None => 0,
+            Some(cursor) if cursor.generation != generation => return Err(InvalidCursor::Stale),
+            Some(cursor) => {
+                records
+                    .iter()
+                    .position(|record| uuid_of(record) == cursor.last)
+                    .ok_or(InvalidCursor::Malformed)?
+                    + 1
+            }
+        };
+        let end = start.saturating_add(limit).min(records.len());
+        let items = records[start..end].to_vec();
+
+        let next_cursor = match items.last() {
+            Some(last) if end < records.len() => Some(
+                Cursor {
+                    generation,
+                    last: uuid_of(last),
+                }
+                .encode(),
+            ),
+            _ => None,
+        };
+
+        Ok(Self { items, next_cursor })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn uuid(n: u8) -> PlayerUuid {
+        PlayerUuid::from_bytes([n; 16])
+    }
+
+    #[test]
+    fn cursor_round_trips_within_its_generation_only() {
+        let cursor = Cursor {
+            generation: 7,
+            last: uuid(1),
+        };
+        let encoded = cursor.encode();
+
+        assert_eq!(Cursor::decode(&encoded, 7), Ok(cursor));
+        assert_eq!(Cursor::decode(&encoded, 8), Err(InvalidCursor::Stale));
+    }
+
+    #[test]
+    fn altered_cursors_are_rejected() {
+        let encoded = Cursor {
+            generation: 7,
+            last: uuid(1),
+        }
+        .encode();
+
+        let mut altered = encoded.clone().into_bytes();
+        altered[3] = if altered[3] == b'A' { b'B' } else { b'A' };
+        let altered = String::from_utf8(altered).unwrap();
+
+        assert_eq!(Cursor::decode(&altered, 7), Err(InvalidCursor::Malformed));
+        assert_eq!(
+            Cursor::decode("not a cursor", 7),
+            Err(InvalidCursor::Malformed)
+        );
+        assert_eq!(
+            Cursor::decode(&encoded[..encoded.len() - 2], 7),
+            Err(InvalidCursor::Malformed)
+        );
+    }
+
+    #[test]
+    fn cursor_pages_cover_every_record_once() {
+        let records = (0..5).map(uuid).collect::<Vec<_>>();
+
+        let mut seen = Vec::new();
+        let mut after = None;
+        loop {
+            let page =
+                CursorPage::slice(&records, 3, after.as_ref(), Some(2), |uuid| *uuid).unwrap();
+            seen.extend(page.items);
+            match page.next_cursor {
+                Some(next) => after = Some(Cursor::decode(&next, 3).unwrap()),
+                None => break,
+            }
+        }
+
+        assert_eq!(seen, records);
+    }
+
+    #[test]
+    fn offset_pages_are_clamped_to_the_records() {
+        let records = (0..5).collect::<Vec<u32>>();
+
+        assert_eq!(
+            Paginated::slice(&records, 3, Some(10)),
+            Paginated {
+                items: vec![3, 4],
+                total: 5,
+                limit: 10,
+                offset: 3,
+            }
+        );
+        assert!(Paginated::slice(&records, 10, None).items.is_empty());
+        assert_eq!(Paginated::slice(&records, 0, Some(0)).limit, 1);
+        assert_eq!(Paginated::slice(&records, 0, Some(5000)).limit, MAX_LIMIT);
+    }
+
+    // カーソルはクライアントから来るため、任意の入力でパニックせず `InvalidCursor` で弾くこと
+    proptest! {
+        #[test]
+        fn arbitrary_strings_never_panic_as_cursors(
+            cursor in any::<String>(),
+            generation in any::<u64>(),
+        ) {
+            if let Ok(decoded) = Cursor::decode(&cursor, generation) {
+                prop_assert_eq!(decoded.encode(), cursor);
+            }
+        }
+
+        #[test]
+        fn arbitrary_bytes_are_rejected_as_cursors(
+            bytes in proptest::collection::vec(any::<u8>(), 0..64),
+        ) {
+            prop_assume!(
+                bytes.len() != CURSOR_LENGTH || checksum(&bytes[..24]) != bytes[24..]
+            );
+
+            prop_assert_eq!(
+                Cursor::decode(&URL_SAFE_NO_PAD.encode(&bytes), 0),
+                Err(InvalidCursor::Malformed)
+            );
+        }
+
+        #[test]
+        fn cursors_round_trip(generation in any::<u64>(), bytes in any::<[u8; 16]>()) {
+            let cursor = Cursor {
+                generation,
+                last: PlayerUuid::from_bytes(bytes),
+            };
+
+            prop_assert_eq!(Cursor::decode(&cursor.encode(), generation), Ok(cursor));
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::models::{
+    Player, PlayerBreakCount, PlayerBuildCount, PlayerLastQuit, PlayerPlayTicks, PlayerStats,
+    PlayerVoteCount,
+};
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+use std::collections::BTreeMap;
+
+/// 公開する応答の型の名前と、そのJSON Schema。
+///
+/// クライアントの生成に使えるよう、型を直列化したときの形 (`fetch` の出力など) を表す。
+pub fn schemas() -> BTreeMap<&'static str, RootSchema> {
+    BTreeMap::from([
+        ("Player", schema_for!(Player)),
+        ("PlayerLastQuit", schema_for!(PlayerLastQuit)),
+        ("PlayerBreakCount", schema_for!(PlayerBreakCount)),
+        ("PlayerBuildCount", schema_for!(PlayerBuildCount)),
+        ("PlayerPlayTicks", schema_for!(PlayerPlayTicks)),
+        ("PlayerVoteCount", schema_for!(PlayerVoteCount)),
+        ("PlayerStats", schema_for!(PlayerStats)),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::{NameValidation, PlayerName, PlayerUuid};
+    use chrono::{TimeZone, Utc};
+    use jsonschema::JSONSchema;
+    use serde::Serialize;
+
+    fn assert_valid(name: &str, value: &impl Serialize) {
+        let schema = serde_json::to_value(&schemas()[name]).unwrap();
+        let schema = JSONSchema::compile(&schema).unwrap();
+        let instance = serde_json::to_value(value).unwrap();
+
+        assert!(schema.is_valid(&instance), "{instance} violates {name}");
+    }
+
+    #[test]
+    fn serialized_records_follow_their_schemas() {
+        let player = Player {
+            uuid: PlayerUuid::try_from("069a79f444e94726a5befca90e38aaf5").unwrap(),
+            last_known_name: PlayerName::new("Notch", NameValidation::Strict).unwrap(),
+        };
+        let last_quit = Utc.with_ymd_and_hms(2023, 4, 1, 12, 0, 0).unwrap();
+
+        assert_valid("Player", &player);
+        assert_valid(
+            "PlayerLastQuit",
+            &PlayerLastQuit {
+                player: player.clone(),
+                last_quit,
+            },
+        );
+        assert_valid(
+            "PlayerBreakCount",
+            &PlayerBreakCount {
+                player: player.clone(),
+                break_count: 1,
+            },
+        );
+        assert_valid(
+            "PlayerBuildCount",
+            &PlayerBuildCount {
+                player: player.clone(),
+                build_count: 2,
+            },
+        );
+        assert_valid(
+            "PlayerPlayTicks",
+            &PlayerPlayTicks {
+                player: player.clone(),
+                play_ticks: 3,
+            },
+        );
+        assert_valid(
+            "PlayerVoteCount",
+            &PlayerVoteCount {
+                player,
+                vote_count: 4,
+            },
+        );
+        assert_valid(
+            "PlayerStats",
+            &PlayerStats {
+                last_quit: Some(last_quit),
+                ..PlayerStats::default()
+            },
+        );
+    }
+
+    #[test]
+    fn renamed_fields_are_published_under_their_serialized_name() {
+        let schema = serde_json::to_value(&schemas()["PlayerLastQuit"]).unwrap();
+
+        assert!(schema["properties"]["rfc_3339_date_time"].is_object());
+        assert!(schema["properties"].get("last_quit").is_none());
+    }
+}
@@ -0,0 +1,41 @@
+/// カウンタのレコード全体についての集計
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CounterSummary {
+    /// 集計したプレイヤーの数
+    pub count: u64,
+    /// 値の合計。全員の値が `u64` に収まっていても、合計は収まらないことがある
+    pub sum: u128,
+    /// 値の最大値。プレイヤーがいなければ0
+    pub max: u64,
+}
+
+impl CounterSummary {
+    /// 取得済みのレコードの値 `values` から集計する
+    pub fn of(values: impl IntoIterator<Item = u64>) -> Self {
+        values
+            .into_iter()
+            .fold(Self::default(), |summary, value| Self {
+                count: summary.count + 1,
+                sum: summary.sum + u128::from(value),
+                max: summary.max.max(value),
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sums_may_exceed_u64() {
+        assert_eq!(
+            CounterSummary::of([u64::MAX, u64::MAX, 1]),
+            CounterSummary {
+                count: 3,
+                sum: u128::from(u64::MAX) * 2 + 1,
+                max: u64::MAX,
+            }
+        );
+        assert_eq!(CounterSummary::of([]), CounterSummary::default());
+    }
+}
@@ -0,0 +1,95 @@
+use chrono::Duration;
+
+/// ゲーム内で1秒あたりに進むtickの数
+const TICKS_PER_SECOND: u64 = 20;
+const MILLISECONDS_PER_TICK: u64 = 1000 / TICKS_PER_SECOND;
+
+/// `ticks_to_duration` が飽和せずに変換できる最大のtick数
+const MAX_EXACT_TICKS: u64 = i64::MAX as u64 / MILLISECONDS_PER_TICK;
+
+/// tick数を時間に直す。`chrono::Duration` で表せない長さは、その最大値に飽和させる
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let milliseconds = ticks
+        .checked_mul(MILLISECONDS_PER_TICK)
+        .and_then(|milliseconds| i64::try_from(milliseconds).ok())
+        .unwrap_or(i64::MAX);
+
+    Duration::milliseconds(milliseconds)
+}
+
+/// 時間をtick数に直す。1tickに満たない端数は切り捨て、負の時間は0とする
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    u64::try_from(duration.num_milliseconds())
+        .map_or(0, |milliseconds| milliseconds / MILLISECONDS_PER_TICK)
+}
+
+/// tick数を `3d 4h 12m` のような、言語によらない表記にする。
+///
+/// 分に満たない端数は切り捨て、0の単位は省く。全て0なら `0m` とする。
+pub fn format_ticks(ticks: u64) -> String {
+    let minutes = ticks / TICKS_PER_SECOND / 60;
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+
+    let parts = [(days, "d"), (hours, "h"), (minutes, "m")]
+        .into_iter()
+        .filter(|(value, _)| *value != 0)
+        .map(|(value, unit)| format!("{value}{unit}"))
+        .collect::<Vec<_>>();
+
+    if parts.is_empty() {
+        "0m".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn ticks_are_formatted_in_days_hours_and_minutes() {
+        let ticks = |days: u64, hours: u64, minutes: u64, seconds: u64| {
+            (((days * 24 + hours) * 60 + minutes) * 60 + seconds) * TICKS_PER_SECOND
+        };
+
+        assert_eq!(format_ticks(ticks(3, 4, 12, 59)), "3d 4h 12m");
+        assert_eq!(format_ticks(ticks(1, 0, 5, 0)), "1d 5m");
+        assert_eq!(format_ticks(ticks(0, 0, 0, 59)), "0m");
+        assert_eq!(format_ticks(u64::MAX), "10675199116730d 1h 33m");
+    }
+
+    #[test]
+    fn extreme_values_saturate() {
+        assert_eq!(
+            ticks_to_duration(u64::MAX),
+            Duration::milliseconds(i64::MAX)
+        );
+        assert_eq!(
+            ticks_to_duration(MAX_EXACT_TICKS + 1),
+            Duration::milliseconds(i64::MAX)
+        );
+        assert_eq!(duration_to_ticks(Duration::milliseconds(-1)), 0);
+        assert_eq!(
+            duration_to_ticks(Duration::milliseconds(i64::MAX)),
+            MAX_EXACT_TICKS
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn ticks_round_trip_exactly(ticks in 0..=MAX_EXACT_TICKS) {
+            prop_assert_eq!(duration_to_ticks(ticks_to_duration(ticks)), ticks);
+        }
+
+        #[test]
+        fn durations_round_trip_within_a_tick(milliseconds in 0..=i64::MAX) {
+            let duration = Duration::milliseconds(milliseconds);
+            let error = duration - ticks_to_duration(duration_to_ticks(duration));
+
+            prop_assert!(error >= Duration::zero());
+            prop_assert!(error < Duration::milliseconds(MILLISECONDS_PER_TICK as i64));
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! 外部に公開する型を直列化したJSONの形を固定するテスト。
+//!
+//! フィールドの並びは問わないが、名前と値の型が変わればテストが失敗する。
+//! フィールドの追加のみを許し、追加したときはここの期待値も意図して更新すること。
+
+use crate::models::{
+    NameValidation, Player, PlayerBreakCount, PlayerBuildCount, PlayerLastQuit, PlayerName,
+    PlayerPlayTicks, PlayerStats, PlayerUuid, PlayerVoteCount,
+};
+use crate::pagination::{CursorPage, Paginated};
+use chrono::{TimeZone, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+fn player() -> Player {
+    Player {
+        uuid: PlayerUuid::try_from("069a79f444e94726a5befca90e38aaf5").unwrap(),
+        last_known_name: PlayerName::new("Notch", NameValidation::Strict).unwrap(),
+    }
+}
+
+fn player_json() -> Value {
+    json!({ "uuid": "069a79f4-44e9-4726-a5be-fca90e38aaf5", "last_known_name": "Notch" })
+}
+
+fn assert_wire(value: &impl Serialize, expected: &Value) {
+    assert_eq!(&serde_json::to_value(value).unwrap(), expected);
+}
+
+#[test]
+fn players() {
+    assert_wire(&player(), &player_json());
+}
+
+#[test]
+fn records() {
+    let last_quit = Utc.with_ymd_and_hms(2023, 4, 1, 12, 34, 56).unwrap();
+
+    assert_wire(
+        &PlayerLastQuit {
+            player: player(),
+            last_quit,
+        },
+        &json!({ "player": player_json(), "rfc_3339_date_time": "2023-04-01T12:34:56Z" }),
+    );
+    assert_wire(
+        &PlayerBreakCount {
+            player: player(),
+            break_count: 1,
+        },
+        &json!({ "player": player_json(), "break_count": 1 }),
+    );
+    assert_wire(
+        &PlayerBuildCount {
+            player: player(),
+            build_count: 2,
+        },
+        &json!({ "player": player_json(), "build_count": 2 }),
+    );
+    assert_wire(
+        &PlayerPlayTicks {
+            player: player(),
+            play_ticks: 3,
+        },
+        &json!({ "player": player_json(), "play_ticks": 3 }),
+    );
+    assert_wire(
+        &PlayerVoteCount {
+            player: player(),
+            vote_count: 4,
+        },
+        &json!({ "player": player_json(), "vote_count": 4 }),
+    );
+}
+
+#[test]
+fn player_stats() {
+    assert_wire(
+        &PlayerStats {
+            break_count: 1,
+            build_count: 2,
+            play_ticks: 3,
+            vote_count: 4,
+            last_quit: Some(Utc.with_ymd_and_hms(2023, 4, 1, 0, 0, 0).unwrap()),
+        },
+        &json!({
+            "break_count": 1,
+            "build_count": 2,
+            "play_ticks": 3,
+            "vote_count": 4,
+            "last_quit": "2023-04-01T00:00:00Z",
+        }),
+    );
+    assert_wire(
+        &PlayerStats::default(),
+        &json!({
+            "break_count": 0,
+            "build_count": 0,
+            "play_ticks": 0,
+            "vote_count": 0,
+            "last_quit": null,
+        }),
+    );
+}
+
+#[test]
+fn pages() {
+    assert_wire(
+        &Paginated {
+            items: vec![1],
+            total: 3,
+            limit: 1,
+            offset: 2,
+        },
+        &json!({ "items": [1], "total": 3, "limit": 1, "offset": 2 }),
+    );
+    assert_wire(
+        &CursorPage {
+            items: vec![1],
+            next_cursor: None,
+        },
+        &json!({ "items": [1], "next_cursor": null }),
+    );
+}
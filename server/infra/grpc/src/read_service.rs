@@ -4,17 +4,18 @@ use crate::buf_generated::gigantic_minecraft::seichi_game_data::v1::{
     PlayTicksResponse, VoteCountsResponse,
 };
 
-use domain::app_models::VecDataSource;
+use domain::app_models::{DataSourceError, VecDataSource};
 use domain::models::{
-    Player, PlayerBreakCount, PlayerBuildCount, PlayerLastQuit, PlayerPlayTicks, PlayerVoteCount,
+    to_rfc_3339, Player, PlayerBreakCount, PlayerBuildCount, PlayerLastQuit, PlayerPlayTicks,
+    PlayerVoteCount,
 };
 
 use async_trait::async_trait;
 
 fn to_tonic_player(model: Player) -> seichi_game_data::v1::Player {
     seichi_game_data::v1::Player {
-        uuid: model.uuid,
-        last_known_name: model.last_known_name,
+        uuid: model.uuid.to_string(),
+        last_known_name: model.last_known_name.into(),
     }
 }
 
@@ -24,7 +25,7 @@ fn to_tonic_last_quit_response(model: Vec<PlayerLastQuit>) -> tonic::Response<La
             .into_iter()
             .map(|last_quit| seichi_game_data::v1::PlayerLastQuit {
                 player: Some(to_tonic_player(last_quit.player)),
-                rfc_3339_date_time: last_quit.rfc_3339_date_time,
+                rfc_3339_date_time: to_rfc_3339(&last_quit.last_quit),
             })
             .collect(),
     })
@@ -84,20 +85,45 @@ fn to_tonic_vote_counts_response(
     })
 }
 
-fn to_tonic_error_status(anyhow_error: anyhow::Error) -> tonic::Status {
+fn to_tonic_error_status(resource: &str, error: &DataSourceError) -> tonic::Status {
     use tonic::*;
 
-    log::error!("Received an error from data source: {}", anyhow_error);
+    tracing::error!(
+        resource,
+        error = %error,
+        "Received an error from data source"
+    );
+
+    // 接続できない・時間切れの場合はクライアントが再試行できるよう UNAVAILABLE とする
+    if error.is_transient() {
+        Status::unavailable("The data source is temporarily unavailable. Please retry later.")
+    } else {
+        Status::unknown("Unknown error. See the server log for more details.")
+    }
+}
 
-    Status::unknown("Unknown error. See the server log for more details.")
+/// 設定で無効化されたリソースは `None` とし、ゲームDBに問い合わせずに `UNIMPLEMENTED` を返す
+async fn fetch_if_enabled<T>(
+    data_source: Option<&(dyn VecDataSource<T> + Send + Sync)>,
+    resource: &str,
+) -> Result<Vec<T>, tonic::Status> {
+    match data_source {
+        Some(data_source) => data_source
+            .fetch()
+            .await
+            .map_err(|error| to_tonic_error_status(resource, &error)),
+        None => Err(tonic::Status::unimplemented(format!(
+            "{resource} is disabled on this server"
+        ))),
+    }
 }
 
 pub struct ReadServiceImpl {
-    pub last_quit_data_source: Box<dyn VecDataSource<PlayerLastQuit> + Send + Sync>,
-    pub break_counts_data_source: Box<dyn VecDataSource<PlayerBreakCount> + Send + Sync>,
-    pub build_counts_data_source: Box<dyn VecDataSource<PlayerBuildCount> + Send + Sync>,
-    pub play_ticks_data_source: Box<dyn VecDataSource<PlayerPlayTicks> + Send + Sync>,
-    pub vote_counts_data_source: Box<dyn VecDataSource<PlayerVoteCount> + Send + Sync>,
+    pub last_quit_data_source: Option<Box<dyn VecDataSource<PlayerLastQuit> + Send + Sync>>,
+    pub break_counts_data_source: Option<Box<dyn VecDataSource<PlayerBreakCount> + Send + Sync>>,
+    pub build_counts_data_source: Option<Box<dyn VecDataSource<PlayerBuildCount> + Send + Sync>>,
+    pub play_ticks_data_source: Option<Box<dyn VecDataSource<PlayerPlayTicks> + Send + Sync>>,
+    pub vote_counts_data_source: Option<Box<dyn VecDataSource<PlayerVoteCount> + Send + Sync>>,
 }
 
 #[async_trait]
@@ -106,54 +132,44 @@ impl ReadService for ReadServiceImpl {
         &self,
         _request: tonic::Request<pbjson_types::Empty>,
     ) -> Result<tonic::Response<LastQuitsResponse>, tonic::Status> {
-        self.last_quit_data_source
-            .fetch()
+        fetch_if_enabled(self.last_quit_data_source.as_deref(), "last_quits")
             .await
             .map(to_tonic_last_quit_response)
-            .map_err(to_tonic_error_status)
     }
 
     async fn break_counts(
         &self,
         _request: tonic::Request<pbjson_types::Empty>,
     ) -> Result<tonic::Response<BreakCountsResponse>, tonic::Status> {
-        self.break_counts_data_source
-            .fetch()
+        fetch_if_enabled(self.break_counts_data_source.as_deref(), "break_counts")
             .await
             .map(to_tonic_break_counts_response)
-            .map_err(to_tonic_error_status)
     }
 
     async fn build_counts(
         &self,
         _request: tonic::Request<pbjson_types::Empty>,
     ) -> Result<tonic::Response<BuildCountsResponse>, tonic::Status> {
-        self.build_counts_data_source
-            .fetch()
+        fetch_if_enabled(self.build_counts_data_source.as_deref(), "build_counts")
             .await
             .map(to_tonic_build_counts_response)
-            .map_err(to_tonic_error_status)
     }
 
     async fn play_ticks(
         &self,
         _request: tonic::Request<pbjson_types::Empty>,
     ) -> Result<tonic::Response<PlayTicksResponse>, tonic::Status> {
-        self.play_ticks_data_source
-            .fetch()
+        fetch_if_enabled(self.play_ticks_data_source.as_deref(), "play_ticks")
             .await
             .map(to_tonic_play_ticks_response)
-            .map_err(to_tonic_error_status)
     }
 
     async fn vote_counts(
         &self,
         _request: tonic::Request<pbjson_types::Empty>,
     ) -> Result<tonic::Response<VoteCountsResponse>, tonic::Status> {
-        self.vote_counts_data_source
-            .fetch()
+        fetch_if_enabled(self.vote_counts_data_source.as_deref(), "vote_counts")
             .await
             .map(to_tonic_vote_counts_response)
-            .map_err(to_tonic_error_status)
     }
 }
@@ -0,0 +1,103 @@
+//! 取得した行の変換、ランキングの並べ替え、結果の複製、JSONへの直列化、ページの切り出しのベンチマーク。
+//!
+//! 入力は `test_fixtures` で固定したシードから生成し、ゲームDBが無くても `cargo bench` で実行できる。
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use domain::conversion::OutOfRange;
+use domain::models::{sort_for_ranking, sort_for_ranking_with_threshold, PlayerBreakCount};
+use domain::pagination::{Cursor, CursorPage, Paginated};
+use infra_repository_impl::data_quality::{self, Checked};
+use std::convert::Infallible;
+use test_fixtures::{playerdata_rows, PlayerdataRow};
+
+const SEED: u64 = 150;
+const SNAPSHOT_SIZE: usize = 100_000;
+
+fn break_counts(rows: &[PlayerdataRow]) -> Vec<PlayerBreakCount> {
+    rows.iter().map(PlayerdataRow::break_count).collect()
+}
+
+/// ゲームDBから読み出した値を、データソースと同じ検証と重複の除去を通してレコードにする
+fn decode(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("decode_break_counts");
+    for size in [1_000, SNAPSHOT_SIZE] {
+        let rows = playerdata_rows(SEED, size);
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &rows, |bencher, rows| {
+            bencher.iter(|| {
+                data_quality::collect(
+                    rows.iter().map(|row| {
+                        Ok::<_, Infallible>(Checked::from(PlayerBreakCount::new(
+                            row.player(),
+                            row.totalbreaknum,
+                            OutOfRange::Clamp,
+                        )))
+                    }),
+                    |record| record.player.uuid,
+                )
+            });
+        });
+    }
+    group.finish();
+}
+
+fn rank(criterion: &mut Criterion) {
+    let records = break_counts(&playerdata_rows(SEED, SNAPSHOT_SIZE));
+
+    criterion.bench_function("sort_for_ranking_100k", |bencher| {
+        bencher.iter_batched(
+            || records.clone(),
+            |mut records| sort_for_ranking(&mut records),
+            BatchSize::LargeInput,
+        );
+    });
+    criterion.bench_function("sort_for_ranking_100k_sequential", |bencher| {
+        bencher.iter_batched(
+            || records.clone(),
+            |mut records| sort_for_ranking_with_threshold(&mut records, usize::MAX),
+            BatchSize::LargeInput,
+        );
+    });
+}
+
+/// 単一の問い合わせにまとめた取得の結果を、呼び出しごとに複製する
+fn clone_snapshot(criterion: &mut Criterion) {
+    let records = break_counts(&playerdata_rows(SEED, SNAPSHOT_SIZE));
+
+    criterion.bench_function("clone_snapshot_100k", |bencher| {
+        bencher.iter(|| records.clone());
+    });
+}
+
+fn serialize(criterion: &mut Criterion) {
+    let records = break_counts(&playerdata_rows(SEED, SNAPSHOT_SIZE));
+
+    criterion.bench_function("serialize_json_100k", |bencher| {
+        bencher.iter(|| serde_json::to_vec(&records).unwrap());
+    });
+}
+
+fn paginate(criterion: &mut Criterion) {
+    let records = break_counts(&playerdata_rows(SEED, SNAPSHOT_SIZE));
+    let middle = Cursor {
+        generation: 1,
+        last: records[SNAPSHOT_SIZE / 2].player.uuid,
+    };
+
+    let mut group = criterion.benchmark_group("paginate_100k");
+    group.bench_function("offset", |bencher| {
+        bencher.iter(|| Paginated::slice(&records, SNAPSHOT_SIZE / 2, Some(100)));
+    });
+    group.bench_function("cursor", |bencher| {
+        bencher.iter(|| {
+            CursorPage::slice(&records, 1, Some(&middle), Some(100), |record| {
+                record.player.uuid
+            })
+            .unwrap()
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode, rank, clone_snapshot, serialize, paginate);
+criterion_main!(benches);
@@ -0,0 +1,366 @@
+use domain::app_models::{DataSourceError, VecDataSource};
+
+use async_trait::async_trait;
+use prometheus::{IntCounterVec, IntGaugeVec, Opts, Registry};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// サーキットブレーカーの状態が変わった回数と現在の状態のメトリクス。ラベルは接続プロファイルの名前と状態のみとする
+#[derive(Clone)]
+pub struct CircuitBreakerMetrics {
+    /// 0なら閉じている、1なら開いている、2なら試しの問い合わせを通している
+    state: IntGaugeVec,
+    transitions: IntCounterVec,
+}
+
+impl CircuitBreakerMetrics {
+    pub fn register(registry: &Registry) -> prometheus::Result<Self> {
+        let state = IntGaugeVec::new(
+            Opts::new(
+                "seichi_game_api_source_circuit_state",
+                "State of the circuit breaker in front of the source database (0 = closed, 1 = open, 2 = half-open)",
+            ),
+            &["connection_profile"],
+        )?;
+        let transitions = IntCounterVec::new(
+            Opts::new(
+                "seichi_game_api_source_circuit_transitions_total",
+                "Number of times the circuit breaker in front of the source database changed its state",
+            ),
+            &["connection_profile", "state"],
+        )?;
+
+        registry.register(Box::new(state.clone()))?;
+        registry.register(Box::new(transitions.clone()))?;
+
+        Ok(Self { state, transitions })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// 問い合わせを通し、連続して失敗した回数を数えている
+    Closed { consecutive_failures: u32 },
+    /// `until` まで問い合わせを通さずに失敗させる
+    Open { until: Instant },
+    /// 回復したかを確かめる一回の問い合わせを通しており、その結果を待っている
+    HalfOpen,
+}
+
+impl State {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Closed { .. } => "closed",
+            Self::Open { .. } => "open",
+            Self::HalfOpen => "half_open",
+        }
+    }
+
+    const fn gauge(self) -> i64 {
+        match self {
+            Self::Closed { .. } => 0,
+            Self::Open { .. } => 1,
+            Self::HalfOpen => 2,
+        }
+    }
+}
+
+/// 一つの接続プロファイルのゲームDBへの問い合わせを、連続した失敗の後にしばらく止めるサーキットブレーカー。
+///
+/// 再試行すれば成功しうるエラー (`DataSourceError::is_transient`) が `failure_threshold` 回続くと開き、
+/// `cooldown` の間は問い合わせずに失敗させる。その後の最初の呼び出しだけを試しに通し、成功すれば閉じ、失敗すれば再び開く。
+/// 複製したものは状態を共有するため、同じプロファイルを使う全てのリソースに同じものを渡す。
+///
+/// 時刻には `tokio::time::Instant` を使うため、時間を止めたテスト (`start_paused`) では `cooldown` を実際には待たない。
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    profile: Arc<str>,
+    /// 0なら開かない
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Arc<Mutex<State>>,
+    metrics: CircuitBreakerMetrics,
+}
+
+/// 問い合わせを通してよいことを表す。結果を `record` せずに捨てられた試しの問い合わせは、失敗したものとして扱う
+struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+    recorded: bool,
+}
+
+impl Permit<'_> {
+    fn record(mut self, result: &Result<(), &DataSourceError>) {
+        self.recorded = true;
+        self.breaker
+            .record(self.probe, result.map_err(|error| error.is_transient()));
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.probe && !self.recorded {
+            self.breaker.record(true, Err(true));
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(
+        profile: impl Into<Arc<str>>,
+        failure_threshold: u32,
+        cooldown: Duration,
+        metrics: CircuitBreakerMetrics,
+    ) -> Self {
+        let breaker = Self {
+            profile: profile.into(),
+            failure_threshold,
+            cooldown,
+            state: Arc::new(Mutex::new(State::Closed {
+                consecutive_failures: 0,
+            })),
+            metrics,
+        };
+        breaker
+            .metrics
+            .state
+            .with_label_values(&[&*breaker.profile])
+            .set(0);
+
+        breaker
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .expect("Circuit states are never poisoned")
+    }
+
+    /// 接続プロファイルの名前
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    /// 開いているか、試しの問い合わせの結果を待っていて、通常どおりには問い合わせていないかどうか
+    pub fn is_open(&self) -> bool {
+        !matches!(*self.state(), State::Closed { .. })
+    }
+
+    fn transition(&self, state: &mut State, next: State) {
+        if state.name() != next.name() {
+            self.metrics
+                .state
+                .with_label_values(&[&*self.profile])
+                .set(next.gauge());
+            self.metrics
+                .transitions
+                .with_label_values(&[&*self.profile, next.name()])
+                .inc();
+            match next {
+                State::Open { .. } => tracing::warn!(
+                    connection_profile = %self.profile,
+                    cooldown_ms = u64::try_from(self.cooldown.as_millis()).unwrap_or(u64::MAX),
+                    "opened the circuit to the source database"
+                ),
+                State::HalfOpen => tracing::info!(
+                    connection_profile = %self.profile,
+                    "probing the source database"
+                ),
+                State::Closed { .. } => tracing::info!(
+                    connection_profile = %self.profile,
+                    "closed the circuit to the source database"
+                ),
+            }
+        }
+        *state = next;
+    }
+
+    /// 問い合わせてよければその許可を返す
+    fn try_acquire(&self) -> Result<Permit<'_>, DataSourceError> {
+        let mut state = self.state();
+        let current = *state;
+        let probe = match current {
+            State::Closed { .. } => false,
+            State::Open { until } if Instant::now() >= until => {
+                self.transition(&mut state, State::HalfOpen);
+                true
+            }
+            State::Open { .. } | State::HalfOpen => {
+                return Err(DataSourceError::Connection(format!(
+                    "the circuit to the source database of connection profile {} is open",
+                    self.profile
+                )))
+            }
+        };
+
+        Ok(Permit {
+            breaker: self,
+            probe,
+            recorded: false,
+        })
+    }
+
+    /// 問い合わせの結果を記録する。失敗の場合は、再試行すれば成功しうるエラーだったかどうか
+    fn record(&self, probe: bool, result: Result<(), bool>) {
+        let mut state = self.state();
+        let open = State::Open {
+            until: Instant::now() + self.cooldown,
+        };
+
+        let next = match (*state, result) {
+            // 試しの問い合わせの結果だけが、半開きの状態を終わらせる
+            (State::HalfOpen, _) if !probe => return,
+            (State::HalfOpen, Ok(())) => State::Closed {
+                consecutive_failures: 0,
+            },
+            (State::HalfOpen, Err(_)) => open,
+            (State::Closed { .. }, Ok(())) | (State::Closed { .. }, Err(false)) => State::Closed {
+                consecutive_failures: 0,
+            },
+            (
+                State::Closed {
+                    consecutive_failures,
+                },
+                Err(true),
+            ) => {
+                let consecutive_failures = consecutive_failures.saturating_add(1);
+                if self.failure_threshold != 0 && consecutive_failures >= self.failure_threshold {
+                    open
+                } else {
+                    State::Closed {
+                        consecutive_failures,
+                    }
+                }
+            }
+            // 開く前に始まっていた問い合わせの結果は、開いている期間を変えない
+            (State::Open { .. }, _) => return,
+        };
+        self.transition(&mut state, next);
+    }
+}
+
+/// 内側のデータソースへの問い合わせを `CircuitBreaker` で止める`VecDataSource`
+pub struct CircuitBreakingDataSource<D> {
+    inner: D,
+    breaker: CircuitBreaker,
+}
+
+impl<D> CircuitBreakingDataSource<D> {
+    pub const fn new(inner: D, breaker: CircuitBreaker) -> Self {
+        Self { inner, breaker }
+    }
+}
+
+#[async_trait]
+impl<T: Send, D: VecDataSource<T> + Send + Sync> VecDataSource<T> for CircuitBreakingDataSource<D> {
+    async fn fetch(&self) -> Result<Vec<T>, DataSourceError> {
+        let permit = self.breaker.try_acquire()?;
+        let result = self.inner.fetch().await;
+        permit.record(&result.as_ref().map(|_| ()));
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use test_fixtures::fault_injection::{FaultInjectingDataSource, Faults};
+    use test_fixtures::FixedDataSource;
+
+    fn refused() -> DataSourceError {
+        DataSourceError::Connection("connection refused".to_string())
+    }
+
+    /// 3回続けて失敗すると30秒開くデータソースと、それが使うメトリクス
+    fn breaking_data_source() -> (
+        CircuitBreakingDataSource<FaultInjectingDataSource<u64>>,
+        Faults,
+        CircuitBreakerMetrics,
+    ) {
+        let inner = FaultInjectingDataSource::new(FixedDataSource::ok(vec![1, 2, 3]));
+        let faults = inner.faults();
+        let metrics = CircuitBreakerMetrics::register(&Registry::new()).unwrap();
+        let breaker = CircuitBreaker::new("ranking", 3, Duration::from_secs(30), metrics.clone());
+
+        (
+            CircuitBreakingDataSource::new(inner, breaker),
+            faults,
+            metrics,
+        )
+    }
+
+    fn transitions(metrics: &CircuitBreakerMetrics, state: &str) -> u64 {
+        metrics
+            .transitions
+            .with_label_values(&["ranking", state])
+            .get()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn consecutive_failures_open_the_circuit_until_a_probe_succeeds() {
+        let (data_source, faults, metrics) = breaking_data_source();
+        faults.fail_next(3, refused());
+
+        for _ in 0..3 {
+            assert_eq!(data_source.fetch().await, Err(refused()));
+        }
+        // 開いている間は問い合わせない
+        assert!(matches!(
+            data_source.fetch().await,
+            Err(DataSourceError::Connection(_))
+        ));
+        assert_eq!(faults.calls(), 3);
+        assert_eq!(metrics.state.with_label_values(&["ranking"]).get(), 1);
+        assert!(data_source.breaker.is_open());
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(data_source.fetch().await, Ok(vec![1, 2, 3]));
+        assert_eq!(data_source.fetch().await, Ok(vec![1, 2, 3]));
+        assert_eq!(faults.calls(), 5);
+        assert!(!data_source.breaker.is_open());
+        assert_eq!(transitions(&metrics, "open"), 1);
+        assert_eq!(transitions(&metrics, "half_open"), 1);
+        assert_eq!(transitions(&metrics, "closed"), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_failed_probe_opens_the_circuit_again() {
+        let (data_source, faults, metrics) = breaking_data_source();
+        faults.fail_next(4, refused());
+        for _ in 0..3 {
+            data_source.fetch().await.unwrap_err();
+        }
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(data_source.fetch().await, Err(refused()));
+        tokio::time::advance(Duration::from_secs(29)).await;
+        data_source.fetch().await.unwrap_err();
+
+        assert_eq!(faults.calls(), 4);
+        assert_eq!(transitions(&metrics, "open"), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn only_consecutive_transient_failures_count() {
+        let (data_source, faults, _) = breaking_data_source();
+
+        faults.fail_next(2, refused());
+        data_source.fetch().await.unwrap_err();
+        data_source.fetch().await.unwrap_err();
+        data_source.fetch().await.unwrap();
+        faults.fail_next(2, refused());
+        data_source.fetch().await.unwrap_err();
+        data_source.fetch().await.unwrap_err();
+        faults.fail_next(3, DataSourceError::Other("syntax error".to_string()));
+        for _ in 0..3 {
+            data_source.fetch().await.unwrap_err();
+        }
+
+        faults.clear();
+        assert_eq!(data_source.fetch().await, Ok(vec![1, 2, 3]));
+        assert_eq!(faults.calls(), 9);
+    }
+}
@@ -0,0 +1,635 @@
+//! `export` がCSVで書き出したファイルを、ゲームDBの代わりに読み出すデータソース。
+//!
+//! ディレクトリに `<リソースの名前>.csv` を置き、見出しの行は `uuid,last_known_name,<値の列>` とする。
+//! 値の列の名前は `export` と同じく、`last_quits` は `rfc_3339_date_time`、それ以外はリソースの名前の単数形
+//! (`break_count` など) とする。
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveTime, Utc};
+use config::TimestampPrecision;
+use domain::app_models::{
+    ActivePlayersDataSource, AggregateDataSource, DataSourceError, VecDataSource,
+};
+use domain::models::{
+    NameValidation, Player, PlayerBreakCount, PlayerBuildCount, PlayerLastQuit, PlayerName,
+    PlayerPlayTicks, PlayerUuid, PlayerVoteCount,
+};
+use domain::summary::CounterSummary;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// CSVの一つのレコードを、引用符で囲まれた値を戻しながら区切る (RFC 4180)
+pub fn split_csv(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+
+    while let Some(character) = chars.next() {
+        match (quoted, character) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => fields.push(std::mem::take(&mut field)),
+            (_, character) => field.push(character),
+        }
+    }
+    if quoted {
+        return Err("unterminated quoted field".to_string());
+    }
+    fields.push(field);
+
+    Ok(fields)
+}
+
+/// CSVのレコードを一つずつ読む。引用符で囲まれた値の中の改行では区切らない (RFC 4180)
+pub struct CsvRecords<R> {
+    reader: R,
+    line: usize,
+}
+
+/// `reader` のCSVのレコードを、その最初の行の番号 (1から数える) と、末尾の改行を除いたレコードの組として読む
+pub fn csv_records<R: BufRead>(reader: R) -> CsvRecords<R> {
+    CsvRecords { reader, line: 0 }
+}
+
+impl<R: BufRead> Iterator for CsvRecords<R> {
+    type Item = std::io::Result<(usize, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first_line = self.line + 1;
+        let mut record = String::new();
+        loop {
+            match self.reader.read_line(&mut record) {
+                Ok(0) => break,
+                Ok(_) => {
+                    self.line += 1;
+                    // 引用符の数が奇数であれば、引用符で囲まれた値の途中で改行している
+                    if record.matches('"').count() % 2 == 0 {
+                        break;
+                    }
+                }
+                Err(error) => return Some(Err(error)),
+            }
+        }
+        if record.is_empty() {
+            return None;
+        }
+
+        if record.ends_with('\n') {
+            record.pop();
+            if record.ends_with('\r') {
+                record.pop();
+            }
+        }
+        Some(Ok((first_line, record)))
+    }
+}
+
+/// CSVファイルの一行として読み出せるレコード
+pub trait CsvRecord: Clone + Send + Sync + 'static {
+    /// リソースの名前。ファイルの名前は `<RESOURCE>.csv` とする
+    const RESOURCE: &'static str;
+    /// 見出しの行での、値の列の名前
+    const VALUE_COLUMN: &'static str;
+
+    fn parse(player: Player, value: &str) -> Result<Self, String>;
+
+    fn uuid(&self) -> PlayerUuid;
+}
+
+fn parse_count(value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{value:?} is not a non-negative integer"))
+}
+
+impl CsvRecord for PlayerLastQuit {
+    const RESOURCE: &'static str = "last_quits";
+    const VALUE_COLUMN: &'static str = "rfc_3339_date_time";
+
+    fn parse(player: Player, value: &str) -> Result<Self, String> {
+        let last_quit = DateTime::parse_from_rfc3339(value)
+            .map_err(|_| format!("{value:?} is not an RFC 3339 date-time"))?;
+
+        Ok(Self {
+            player,
+            last_quit: last_quit.with_timezone(&Utc),
+        })
+    }
+
+    fn uuid(&self) -> PlayerUuid {
+        self.player.uuid
+    }
+}
+
+impl CsvRecord for PlayerBreakCount {
+    const RESOURCE: &'static str = "break_counts";
+    const VALUE_COLUMN: &'static str = "break_count";
+
+    fn parse(player: Player, value: &str) -> Result<Self, String> {
+        Ok(Self {
+            player,
+            break_count: parse_count(value)?,
+        })
+    }
+
+    fn uuid(&self) -> PlayerUuid {
+        self.player.uuid
+    }
+}
+
+impl CsvRecord for PlayerBuildCount {
+    const RESOURCE: &'static str = "build_counts";
+    const VALUE_COLUMN: &'static str = "build_count";
+
+    fn parse(player: Player, value: &str) -> Result<Self, String> {
+        Ok(Self {
+            player,
+            build_count: parse_count(value)?,
+        })
+    }
+
+    fn uuid(&self) -> PlayerUuid {
+        self.player.uuid
+    }
+}
+
+impl CsvRecord for PlayerPlayTicks {
+    const RESOURCE: &'static str = "play_ticks";
+    const VALUE_COLUMN: &'static str = "play_ticks";
+
+    fn parse(player: Player, value: &str) -> Result<Self, String> {
+        Ok(Self {
+            player,
+            play_ticks: parse_count(value)?,
+        })
+    }
+
+    fn uuid(&self) -> PlayerUuid {
+        self.player.uuid
+    }
+}
+
+impl CsvRecord for PlayerVoteCount {
+    const RESOURCE: &'static str = "vote_counts";
+    const VALUE_COLUMN: &'static str = "vote_count";
+
+    fn parse(player: Player, value: &str) -> Result<Self, String> {
+        Ok(Self {
+            player,
+            vote_count: parse_count(value)?,
+        })
+    }
+
+    fn uuid(&self) -> PlayerUuid {
+        self.player.uuid
+    }
+}
+
+/// 読めなかった行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalformedLine {
+    /// 1から数えた行番号。複数の行にわたるレコードでは、その最初の行とする
+    pub line: usize,
+    pub error: String,
+}
+
+/// CSVの一つのレコードを読む。名前は `export` が書き出す前に正規化と検証を済ませているため、長さは確かめない
+fn parse_line<T: CsvRecord>(line: &str) -> Result<T, String> {
+    match split_csv(line)?.as_slice() {
+        [uuid, name, value] => {
+            let player = Player {
+                uuid: PlayerUuid::try_from(uuid.as_str()).map_err(|error| error.to_string())?,
+                last_known_name: PlayerName::new(name, NameValidation::Lenient)
+                    .map_err(|error| error.to_string())?,
+            };
+            T::parse(player, value)
+        }
+        fields => Err(format!("{} fields instead of 3", fields.len())),
+    }
+}
+
+/// `content` の全てのレコードを読み、読めたレコードと読めなかった行を返す。
+///
+/// 見出しの行が `T` のものでなければ、どの行も読まずに失敗する。同じUUIDの二つ目以降の行は読めなかった行とする。
+fn parse_csv<T: CsvRecord>(content: impl BufRead) -> anyhow::Result<(Vec<T>, Vec<MalformedLine>)> {
+    let mut lines = csv_records(content);
+    let (_, header) = lines
+        .next()
+        .transpose()?
+        .ok_or_else(|| anyhow::anyhow!("1: no header line"))?;
+    let expected = format!("uuid,last_known_name,{}", T::VALUE_COLUMN);
+    anyhow::ensure!(
+        split_csv(&header).ok() == split_csv(&expected).ok(),
+        "1: {header:?} is not the header {expected:?}"
+    );
+
+    let mut records = Vec::new();
+    let mut malformed = Vec::new();
+    let mut seen = HashMap::new();
+    for line in lines {
+        let (line_number, line) = line?;
+        let parsed = parse_line::<T>(&line).and_then(|record| match seen.entry(record.uuid()) {
+            Entry::Vacant(entry) => {
+                entry.insert(line_number);
+                Ok(record)
+            }
+            Entry::Occupied(entry) => Err(format!(
+                "{} is already on line {}",
+                record.uuid(),
+                entry.get()
+            )),
+        });
+        match parsed {
+            Ok(record) => records.push(record),
+            Err(error) => malformed.push(MalformedLine {
+                line: line_number,
+                error,
+            }),
+        }
+    }
+
+    Ok((records, malformed))
+}
+
+/// 一度に書き出す、読めなかった行の数の上限
+const REPORTED_MALFORMED_LINES: usize = 10;
+
+/// ファイルが書き換えられたかを見分けるための、更新時刻と大きさ。
+///
+/// 更新時刻の精度が粗いファイルシステムでは、同じ時刻のうちに書き換えられても大きさで見分けられる
+type Version = (SystemTime, u64);
+
+/// ある時点のファイルから読み込んだレコード
+struct Snapshot<T> {
+    version: Version,
+    records: Arc<Vec<T>>,
+}
+
+/// 一つのリソースのCSVファイルと、最後に読み込んだ内容
+struct CsvFile<T> {
+    path: PathBuf,
+    strict: bool,
+    snapshot: Mutex<Option<Snapshot<T>>>,
+}
+
+impl<T: CsvRecord> CsvFile<T> {
+    fn new(directory: &Path, strict: bool) -> Self {
+        Self {
+            path: directory.join(format!("{}.csv", T::RESOURCE)),
+            strict,
+            snapshot: Mutex::new(None),
+        }
+    }
+
+    /// ファイルを読み込み、`strict` なら読めない行が一つでもあれば失敗する。そうでなければ読めない行を飛ばして警告する
+    fn load(&self) -> Result<Vec<T>, DataSourceError> {
+        let path = self.path.display();
+        let file = std::fs::File::open(&self.path)
+            .map_err(|error| DataSourceError::Other(format!("failed to open {path}: {error}")))?;
+        let (records, malformed) = parse_csv::<T>(BufReader::new(file))
+            .map_err(|error| DataSourceError::Other(format!("{path}:{error:#}")))?;
+
+        if self.strict && !malformed.is_empty() {
+            let lines = malformed
+                .iter()
+                .take(REPORTED_MALFORMED_LINES)
+                .map(|line| format!("{path}:{}: {}", line.line, line.error))
+                .collect::<Vec<_>>()
+                .join("; ");
+            return Err(DataSourceError::Decode {
+                column: T::RESOURCE.to_string(),
+                detail: format!("{} malformed lines: {lines}", malformed.len()),
+            });
+        }
+        for line in &malformed {
+            tracing::warn!("skipped {path}:{}: {}", line.line, line.error);
+        }
+        tracing::info!(
+            "loaded {} records from {path} ({} malformed lines skipped)",
+            records.len(),
+            malformed.len()
+        );
+
+        Ok(records)
+    }
+
+    /// 最後に読み込んでからファイルの更新時刻か大きさが変わっていれば読み直し、その内容を返す
+    fn records(&self) -> Result<Arc<Vec<T>>, DataSourceError> {
+        let version = std::fs::metadata(&self.path)
+            .and_then(|metadata| Ok((metadata.modified()?, metadata.len())))
+            .map_err(|error| {
+                DataSourceError::Other(format!("failed to stat {}: {error}", self.path.display()))
+            })?;
+
+        if let Some(snapshot) = &*self.snapshot.lock().expect("CSV snapshot lock poisoned") {
+            if snapshot.version == version {
+                return Ok(snapshot.records.clone());
+            }
+        }
+
+        let records = Arc::new(self.load()?);
+        *self.snapshot.lock().expect("CSV snapshot lock poisoned") = Some(Snapshot {
+            version,
+            records: records.clone(),
+        });
+        Ok(records)
+    }
+}
+
+/// ファイルの読み込みは同期的に行うため、非同期のタスクを止めないよう別のスレッドで行う
+async fn records_of<T: CsvRecord>(file: &Arc<CsvFile<T>>) -> Result<Arc<Vec<T>>, DataSourceError> {
+    let file = file.clone();
+    tokio::task::spawn_blocking(move || file.records())
+        .await
+        .map_err(|error| DataSourceError::Other(format!("failed to read a CSV file: {error}")))?
+}
+
+/// ディレクトリに置いたCSVファイルを、更新時刻が変わるたびに読み直して提供するデータソース
+#[derive(Clone)]
+pub struct CsvDataSource {
+    last_quits: Arc<CsvFile<PlayerLastQuit>>,
+    break_counts: Arc<CsvFile<PlayerBreakCount>>,
+    build_counts: Arc<CsvFile<PlayerBuildCount>>,
+    play_ticks: Arc<CsvFile<PlayerPlayTicks>>,
+    vote_counts: Arc<CsvFile<PlayerVoteCount>>,
+    last_quit_precision: TimestampPrecision,
+}
+
+impl CsvDataSource {
+    /// `directory` のファイルはまだ読まない。読み込んで検証するには [`CsvDataSource::load`] を呼ぶ
+    pub fn new(directory: &Path, strict: bool) -> Self {
+        Self {
+            last_quits: Arc::new(CsvFile::new(directory, strict)),
+            break_counts: Arc::new(CsvFile::new(directory, strict)),
+            build_counts: Arc::new(CsvFile::new(directory, strict)),
+            play_ticks: Arc::new(CsvFile::new(directory, strict)),
+            vote_counts: Arc::new(CsvFile::new(directory, strict)),
+            last_quit_precision: TimestampPrecision::Full,
+        }
+    }
+
+    /// 同じファイルを読み、`PlayerLastQuit` の時刻を `precision` の精度で提供するデータソース
+    #[must_use]
+    pub fn with_last_quit_precision(&self, precision: TimestampPrecision) -> Self {
+        Self {
+            last_quit_precision: precision,
+            ..self.clone()
+        }
+    }
+
+    /// `resource` のファイルを読み込んで検証する
+    pub async fn load(&self, resource: &str) -> anyhow::Result<()> {
+        match resource {
+            "last_quits" => drop(records_of(&self.last_quits).await?),
+            "break_counts" => drop(records_of(&self.break_counts).await?),
+            "build_counts" => drop(records_of(&self.build_counts).await?),
+            "play_ticks" => drop(records_of(&self.play_ticks).await?),
+            "vote_counts" => drop(records_of(&self.vote_counts).await?),
+            _ => anyhow::bail!("unknown resource {resource:?}"),
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl VecDataSource<PlayerLastQuit> for CsvDataSource {
+    async fn fetch(&self) -> Result<Vec<PlayerLastQuit>, DataSourceError> {
+        let records = records_of(&self.last_quits).await?;
+
+        Ok(match self.last_quit_precision {
+            TimestampPrecision::Full => records.as_ref().clone(),
+            // その日の0時
+            TimestampPrecision::Date => records
+                .iter()
+                .map(|record| PlayerLastQuit {
+                    player: record.player.clone(),
+                    last_quit: DateTime::from_naive_utc_and_offset(
+                        record.last_quit.date_naive().and_time(NaiveTime::MIN),
+                        Utc,
+                    ),
+                })
+                .collect(),
+        })
+    }
+}
+
+#[async_trait]
+impl VecDataSource<PlayerBreakCount> for CsvDataSource {
+    async fn fetch(&self) -> Result<Vec<PlayerBreakCount>, DataSourceError> {
+        Ok(records_of(&self.break_counts).await?.as_ref().clone())
+    }
+}
+
+#[async_trait]
+impl VecDataSource<PlayerBuildCount> for CsvDataSource {
+    async fn fetch(&self) -> Result<Vec<PlayerBuildCount>, DataSourceError> {
+        Ok(records_of(&self.build_counts).await?.as_ref().clone())
+    }
+}
+
+#[async_trait]
+impl VecDataSource<PlayerPlayTicks> for CsvDataSource {
+    async fn fetch(&self) -> Result<Vec<PlayerPlayTicks>, DataSourceError> {
+        Ok(records_of(&self.play_ticks).await?.as_ref().clone())
+    }
+}
+
+#[async_trait]
+impl VecDataSource<PlayerVoteCount> for CsvDataSource {
+    async fn fetch(&self) -> Result<Vec<PlayerVoteCount>, DataSourceError> {
+        Ok(records_of(&self.vote_counts).await?.as_ref().clone())
+    }
+}
+
+#[async_trait]
+impl AggregateDataSource<PlayerBreakCount> for CsvDataSource {
+    async fn summarize(&self) -> Result<CounterSummary, DataSourceError> {
+        let records = records_of(&self.break_counts).await?;
+        Ok(CounterSummary::of(
+            records.iter().map(|record| record.break_count),
+        ))
+    }
+}
+
+#[async_trait]
+impl AggregateDataSource<PlayerPlayTicks> for CsvDataSource {
+    async fn summarize(&self) -> Result<CounterSummary, DataSourceError> {
+        let records = records_of(&self.play_ticks).await?;
+        Ok(CounterSummary::of(
+            records.iter().map(|record| record.play_ticks),
+        ))
+    }
+}
+
+#[async_trait]
+impl AggregateDataSource<PlayerVoteCount> for CsvDataSource {
+    async fn summarize(&self) -> Result<CounterSummary, DataSourceError> {
+        let records = records_of(&self.vote_counts).await?;
+        Ok(CounterSummary::of(
+            records.iter().map(|record| record.vote_count),
+        ))
+    }
+}
+
+#[async_trait]
+impl ActivePlayersDataSource for CsvDataSource {
+    async fn count_active_since(&self, since: DateTime<Utc>) -> Result<u64, DataSourceError> {
+        let records = records_of(&self.last_quits).await?;
+        let count = records
+            .iter()
+            .filter(|record| record.last_quit >= since)
+            .count();
+        Ok(u64::try_from(count).unwrap_or(u64::MAX))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ALICE: &str = "b66cc3f6-a045-42ad-b4b8-320f20caf140";
+    const BOB: &str = "0b6ad70e-c79c-4e4f-9b7c-60a3f2e0bd4a";
+
+    fn write_directory(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("seichi-game-api-csv-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        for (file, content) in files {
+            std::fs::write(directory.join(file), content).unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn quoted_fields_are_split_back() {
+        assert_eq!(
+            split_csv("a,\"b,\"\"c\"\"\",d"),
+            Ok(vec![
+                "a".to_string(),
+                "b,\"c\"".to_string(),
+                "d".to_string()
+            ])
+        );
+        assert!(split_csv("\"unterminated,12").is_err());
+    }
+
+    #[test]
+    fn malformed_and_duplicate_lines_are_reported_by_line_number() {
+        let content = format!(
+            "uuid,last_known_name,break_count\n{ALICE},alice,12\n{BOB},bob,-1\nnot-a-uuid,carol,3\n{ALICE},alice2,5\n"
+        );
+
+        let (records, malformed) = parse_csv::<PlayerBreakCount>(content.as_bytes()).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].break_count, 12);
+        assert_eq!(
+            malformed.iter().map(|line| line.line).collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+        assert_eq!(malformed[2].error, format!("{ALICE} is already on line 2"));
+        assert!(parse_csv::<PlayerBuildCount>(content.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn quoted_line_breaks_stay_in_their_record() {
+        let content = format!(
+            "uuid,last_known_name,break_count\r\n{ALICE},\"ali\r\nce\n\"\"x\"\"\",12\r\nnot-a-uuid,carol,3\n{BOB},\"unterminated,5\n"
+        );
+
+        assert_eq!(
+            csv_records(content.as_bytes())
+                .map(|record| record.unwrap().0)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 5, 6]
+        );
+
+        let (records, malformed) = parse_csv::<PlayerBreakCount>(content.as_bytes()).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].player.last_known_name.as_str(),
+            "ali\r\nce\n\"x\""
+        );
+        assert_eq!(
+            malformed.iter().map(|line| line.line).collect::<Vec<_>>(),
+            vec![5, 6]
+        );
+    }
+
+    #[tokio::test]
+    async fn strict_files_with_malformed_lines_are_rejected() {
+        let directory = write_directory(
+            "strict",
+            &[(
+                "vote_counts.csv",
+                &format!("uuid,last_known_name,vote_count\n{ALICE},alice,3\n{BOB},bob,x\n"),
+            )],
+        );
+
+        let strict = CsvDataSource::new(&directory, true);
+        let lenient = CsvDataSource::new(&directory, false);
+
+        assert!(matches!(
+            VecDataSource::<PlayerVoteCount>::fetch(&strict).await,
+            Err(DataSourceError::Decode { .. })
+        ));
+        assert_eq!(
+            VecDataSource::<PlayerVoteCount>::fetch(&lenient)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(lenient.load("play_ticks").await.is_err());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn files_are_read_again_when_they_are_modified() {
+        let directory = write_directory(
+            "reload",
+            &[(
+                "last_quits.csv",
+                &format!(
+                    "uuid,last_known_name,rfc_3339_date_time\n{ALICE},alice,2023-04-01T12:34:56Z\n"
+                ),
+            )],
+        );
+        let source = CsvDataSource::new(&directory, true);
+        source.load("last_quits").await.unwrap();
+
+        let path = directory.join("last_quits.csv");
+        std::fs::write(
+            &path,
+            format!(
+                "uuid,last_known_name,rfc_3339_date_time\n{ALICE},alice,2023-04-01T12:34:56Z\n{BOB},bob,2023-04-02T01:00:00Z\n"
+            ),
+        )
+        .unwrap();
+
+        let records = VecDataSource::<PlayerLastQuit>::fetch(
+            &source.with_last_quit_precision(TimestampPrecision::Date),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.last_quit.to_rfc3339())
+                .collect::<Vec<_>>(),
+            vec!["2023-04-01T00:00:00+00:00", "2023-04-02T00:00:00+00:00"]
+        );
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+use domain::models::{DomainValidationError, Validated};
+use prometheus::{IntCounterVec, Opts, Registry};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Display;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+/// 一行を検証した結果
+pub enum Checked<T> {
+    /// そのまま提供する
+    Valid(T),
+    /// 範囲外の値を補正して提供する
+    Clamped(T),
+    /// 提供しない
+    Dropped,
+}
+
+impl<T> From<Result<Validated<T>, DomainValidationError>> for Checked<T> {
+    fn from(validated: Result<Validated<T>, DomainValidationError>) -> Self {
+        match validated {
+            Ok(Validated {
+                record,
+                clamped: false,
+            }) => Self::Valid(record),
+            Ok(Validated {
+                record,
+                clamped: true,
+            }) => Self::Clamped(record),
+            Err(error) => {
+                tracing::debug!(%error, "dropped a row");
+                Self::Dropped
+            }
+        }
+    }
+}
+
+/// 一回の取得で、ゲームDBから読み出した行をどれだけ提供できたか
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QualityReport {
+    /// ゲームDBから読み出した行の数
+    pub rows_fetched: usize,
+    /// 検証を通らず捨てた行の数
+    pub rows_dropped: usize,
+    /// 範囲外の値を補正した行の数
+    pub rows_clamped: usize,
+    /// UUIDが既に出てきたため、最初の行にまとめて捨てた行の数
+    pub duplicates_merged: usize,
+    /// 型が合わず読み出せなかった行の数
+    pub parse_failures: usize,
+    /// 提供した行のうち、プレイヤーの名前から色コードを取り除いたりNFCに正規化したりした行の数
+    pub names_sanitized: usize,
+}
+
+impl QualityReport {
+    /// 捨てたり補正したりした行の数。
+    ///
+    /// 名前の正規化は古いデータでは当たり前に起こり、値の意味も変えないため含めない。
+    pub const fn problems(&self) -> usize {
+        self.rows_dropped + self.rows_clamped + self.duplicates_merged + self.parse_failures
+    }
+}
+
+/// 検証した行を集め、捨てる行を除いて `key` が重複しないレコードの一覧とその品質報告を返す。
+///
+/// 読み出せなかった行は取得全体を失敗させず、その行だけを捨てる。
+pub fn collect<T, K, E>(
+    rows: impl ExactSizeIterator<Item = Result<Checked<T>, E>>,
+    key: impl Fn(&T) -> K,
+) -> (Vec<T>, QualityReport)
+where
+    K: Eq + Hash,
+    E: Display,
+{
+    let mut report = QualityReport {
+        rows_fetched: rows.len(),
+        ..QualityReport::default()
+    };
+    let mut seen = HashSet::new();
+    let mut records = Vec::with_capacity(rows.len());
+
+    for row in rows {
+        let record = match row {
+            Ok(Checked::Valid(record)) => record,
+            Ok(Checked::Clamped(record)) => {
+                report.rows_clamped += 1;
+                record
+            }
+            Ok(Checked::Dropped) => {
+                report.rows_dropped += 1;
+                continue;
+            }
+            Err(error) => {
+                tracing::debug!(%error, "failed to parse a row");
+                report.parse_failures += 1;
+                continue;
+            }
+        };
+
+        if seen.insert(key(&record)) {
+            records.push(record);
+        } else {
+            report.duplicates_merged += 1;
+        }
+    }
+
+    (records, report)
+}
+
+/// リソースごとの最新の品質報告と、その累計のメトリクス
+#[derive(Clone)]
+pub struct DataQuality {
+    latest: Arc<Mutex<BTreeMap<&'static str, QualityReport>>>,
+    rows: IntCounterVec,
+}
+
+impl DataQuality {
+    pub fn register(registry: &Registry) -> prometheus::Result<Self> {
+        let rows = IntCounterVec::new(
+            Opts::new(
+                "seichi_game_api_source_rows_total",
+                "Number of rows read from the source, by what was done with them",
+            ),
+            &["resource", "outcome"],
+        )?;
+
+        registry.register(Box::new(rows.clone()))?;
+
+        Ok(Self {
+            latest: Arc::new(Mutex::new(BTreeMap::new())),
+            rows,
+        })
+    }
+
+    /// 取得ごとに報告を置き換え、問題のあった行の割合が `warn_ratio` を超えていれば WARN のログを出す
+    pub fn record(&self, resource: &'static str, report: QualityReport, warn_ratio: f64) {
+        for (outcome, count) in [
+            ("fetched", report.rows_fetched),
+            ("dropped", report.rows_dropped),
+            ("clamped", report.rows_clamped),
+            ("merged", report.duplicates_merged),
+            ("parse_failed", report.parse_failures),
+            ("name_sanitized", report.names_sanitized),
+        ] {
+            self.rows
+                .with_label_values(&[resource, outcome])
+                .inc_by(count as u64);
+        }
+
+        #[allow(clippy::cast_precision_loss)]
+        let ratio = if report.rows_fetched == 0 {
+            0.0
+        } else {
+            report.problems() as f64 / report.rows_fetched as f64
+        };
+        if ratio > warn_ratio {
+            tracing::warn!(
+                resource,
+                rows_fetched = report.rows_fetched,
+                rows_dropped = report.rows_dropped,
+                rows_clamped = report.rows_clamped,
+                duplicates_merged = report.duplicates_merged,
+                parse_failures = report.parse_failures,
+                names_sanitized = report.names_sanitized,
+                "poor source data quality"
+            );
+        }
+
+        self.latest
+            .lock()
+            .expect("Data quality reports are never poisoned")
+            .insert(resource, report);
+    }
+
+    /// `resource` の直近の取得で読み出した行の数。まだ取得していなければ `None`
+    pub fn rows_fetched(&self, resource: &str) -> Option<usize> {
+        self.latest
+            .lock()
+            .expect("Data quality reports are never poisoned")
+            .get(resource)
+            .map(|report| report.rows_fetched)
+    }
+
+    /// リソースごとの最新の品質報告。まだ取得していないリソースは含まない
+    pub fn latest(&self) -> BTreeMap<&'static str, QualityReport> {
+        self.latest
+            .lock()
+            .expect("Data quality reports are never poisoned")
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rows_are_dropped_clamped_and_merged() {
+        let rows: Vec<Result<Checked<(&str, u64)>, String>> = vec![
+            Ok(Checked::Valid(("a", 1))),
+            Ok(Checked::Clamped(("b", 0))),
+            Ok(Checked::Dropped),
+            Err("not an integer".to_string()),
+            Ok(Checked::Valid(("a", 2))),
+        ];
+
+        let (records, report) = collect(rows.into_iter(), |(uuid, _)| *uuid);
+
+        assert_eq!(records, vec![("a", 1), ("b", 0)]);
+        assert_eq!(
+            report,
+            QualityReport {
+                rows_fetched: 5,
+                rows_dropped: 1,
+                rows_clamped: 1,
+                duplicates_merged: 1,
+                parse_failures: 1,
+                names_sanitized: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn latest_report_replaces_the_previous_one_while_counters_accumulate() {
+        let quality = DataQuality::register(&Registry::new()).unwrap();
+        let report = |rows_dropped| QualityReport {
+            rows_fetched: 10,
+            rows_dropped,
+            ..QualityReport::default()
+        };
+
+        quality.record("break_counts", report(3), 0.01);
+        quality.record("break_counts", report(0), 0.01);
+
+        assert_eq!(quality.latest()["break_counts"], report(0));
+        assert_eq!(quality.rows_fetched("break_counts"), Some(10));
+        assert_eq!(quality.rows_fetched("vote_counts"), None);
+        assert_eq!(
+            quality
+                .rows
+                .with_label_values(&["break_counts", "dropped"])
+                .get(),
+            3
+        );
+        assert_eq!(
+            quality
+                .rows
+                .with_label_values(&["break_counts", "fetched"])
+                .get(),
+            20
+        );
+    }
+}
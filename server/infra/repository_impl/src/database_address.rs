@@ -0,0 +1,201 @@
+use config::{SourceDatabaseConfig, SslMode};
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use trust_dns_resolver::TokioAsyncResolver;
+
+/// 接続を試す一つの接続先
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    /// IPアドレスか、アドレスに解決しないまま接続するホスト名
+    pub host: String,
+    pub port: u16,
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(address)) => write!(f, "[{address}]:{}", self.port),
+            _ => write!(f, "{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// SRVレコード一つ分の接続先
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SrvTarget {
+    priority: u16,
+    weight: u16,
+    host: String,
+    port: u16,
+}
+
+/// SRVレコードを、優先度の値が小さい順、同じ優先度では重みの大きい順に並べる。
+///
+/// RFC 2782 は同じ優先度の中を重みによって無作為に選ぶよう定めているが、
+/// 接続を試す順を起動ごとに変えないよう、重みの順に決める。
+fn order_srv_targets(mut targets: Vec<SrvTarget>) -> Vec<Endpoint> {
+    targets.sort_by_key(|target| (target.priority, std::cmp::Reverse(target.weight)));
+    targets
+        .into_iter()
+        .map(|target| Endpoint {
+            host: target.host,
+            port: target.port,
+        })
+        .collect()
+}
+
+/// 接続プロファイルのゲームDBの接続先。接続を作り直すたびに解決し直す
+#[derive(Debug, Clone)]
+pub struct DatabaseAddress {
+    host: String,
+    port: u16,
+    resolve_srv: bool,
+    /// ホスト名をIPアドレスに解決してから接続するかどうか。
+    /// サーバー証明書のホスト名を確かめる場合は、ホスト名のまま接続する
+    pin_addresses: bool,
+}
+
+impl DatabaseAddress {
+    pub fn from_config(config: &SourceDatabaseConfig) -> Self {
+        Self {
+            host: config.host.as_str().to_string(),
+            port: config.port.get(),
+            resolve_srv: config.resolve_srv,
+            pin_addresses: config.ssl_mode != Some(SslMode::VerifyIdentity),
+        }
+    }
+
+    /// SRVレコードを引くときの名前
+    fn srv_name(&self) -> String {
+        format!("_mysql._tcp.{}", self.host.trim_end_matches('.'))
+    }
+
+    /// 接続先を解決し、接続を試す順に並べる。
+    ///
+    /// ホスト名が複数のアドレスに解決された場合は、システムのリゾルバーが返した順 (IPv6のみのものを含む) に並べる。
+    pub async fn resolve(&self) -> anyhow::Result<Vec<Endpoint>> {
+        let targets = if self.resolve_srv {
+            let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+            let name = self.srv_name();
+            let lookup = resolver.srv_lookup(name.as_str()).await.map_err(|error| {
+                anyhow::anyhow!("failed to look up SRV records of {name}: {error}")
+            })?;
+            order_srv_targets(
+                lookup
+                    .iter()
+                    .map(|srv| SrvTarget {
+                        priority: srv.priority(),
+                        weight: srv.weight(),
+                        host: srv.target().to_utf8().trim_end_matches('.').to_string(),
+                        port: srv.port(),
+                    })
+                    .collect(),
+            )
+        } else {
+            vec![Endpoint {
+                host: self.host.clone(),
+                port: self.port,
+            }]
+        };
+
+        if !self.pin_addresses {
+            return Ok(targets);
+        }
+
+        let mut endpoints = Vec::new();
+        for target in targets {
+            if target.host.parse::<IpAddr>().is_ok() {
+                endpoints.push(target);
+                continue;
+            }
+
+            let addresses = tokio::net::lookup_host((target.host.as_str(), target.port))
+                .await
+                .map_err(|error| anyhow::anyhow!("failed to resolve {target}: {error}"))?;
+            for address in addresses {
+                let endpoint = Endpoint {
+                    host: address.ip().to_string(),
+                    port: address.port(),
+                };
+                if !endpoints.contains(&endpoint) {
+                    endpoints.push(endpoint);
+                }
+            }
+        }
+
+        anyhow::ensure!(
+            !endpoints.is_empty(),
+            "{} resolved to no addresses",
+            self.host
+        );
+        Ok(endpoints)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn target(priority: u16, weight: u16, host: &str) -> SrvTarget {
+        SrvTarget {
+            priority,
+            weight,
+            host: host.to_string(),
+            port: 3306,
+        }
+    }
+
+    #[test]
+    fn srv_targets_are_tried_by_priority_then_weight() {
+        let endpoints = order_srv_targets(vec![
+            target(20, 100, "backup.db.example.com"),
+            target(10, 10, "replica.db.example.com"),
+            target(10, 60, "primary.db.example.com"),
+        ]);
+
+        assert_eq!(
+            endpoints
+                .iter()
+                .map(|endpoint| endpoint.host.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "primary.db.example.com",
+                "replica.db.example.com",
+                "backup.db.example.com"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn ip_literals_are_used_without_resolution() {
+        let address = DatabaseAddress {
+            host: "2001:db8::10".to_string(),
+            port: 3307,
+            resolve_srv: false,
+            pin_addresses: true,
+        };
+
+        let endpoints = address.resolve().await.unwrap();
+
+        assert_eq!(
+            endpoints,
+            vec![Endpoint {
+                host: "2001:db8::10".to_string(),
+                port: 3307,
+            }]
+        );
+        assert_eq!(endpoints[0].to_string(), "[2001:db8::10]:3307");
+    }
+
+    #[test]
+    fn srv_records_are_looked_up_under_the_mysql_service_name() {
+        let address = DatabaseAddress {
+            host: "db.example.com.".to_string(),
+            port: 3306,
+            resolve_srv: true,
+            pin_addresses: true,
+        };
+
+        assert_eq!(address.srv_name(), "_mysql._tcp.db.example.com");
+    }
+}
@@ -0,0 +1,221 @@
+use domain::app_models::{DataSourceError, VecDataSource};
+
+use async_trait::async_trait;
+use prometheus::{IntCounterVec, IntGaugeVec, Opts, Registry};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// 一つのリソースの、直近の取得の状況
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceStatus {
+    /// 続けて失敗している取得の回数。直近の取得が成功していれば0
+    pub consecutive_failures: u64,
+    /// 一度でも取得に成功し、失敗しても代わりに返せるレコードがあるかどうか
+    pub has_records: bool,
+}
+
+/// リソースごとの直近の取得の状況と、直近に成功した取得の結果で代わりに応答した回数などのメトリクス。
+/// メトリクスのラベルはリソース名のみとする
+#[derive(Clone)]
+pub struct Freshness {
+    latest: Arc<Mutex<BTreeMap<&'static str, ResourceStatus>>>,
+    stale_responses: IntCounterVec,
+    consecutive_failures: IntGaugeVec,
+}
+
+impl Freshness {
+    pub fn register(registry: &Registry) -> prometheus::Result<Self> {
+        let stale_responses = IntCounterVec::new(
+            Opts::new(
+                "seichi_game_api_stale_responses_total",
+                "Number of fetches answered with the last successfully fetched records because the source failed",
+            ),
+            &["resource"],
+        )?;
+        let consecutive_failures = IntGaugeVec::new(
+            Opts::new(
+                "seichi_game_api_source_consecutive_failures",
+                "Number of fetches of a resource from the source that failed in a row",
+            ),
+            &["resource"],
+        )?;
+
+        registry.register(Box::new(stale_responses.clone()))?;
+        registry.register(Box::new(consecutive_failures.clone()))?;
+
+        Ok(Self {
+            latest: Arc::new(Mutex::new(BTreeMap::new())),
+            stale_responses,
+            consecutive_failures,
+        })
+    }
+
+    fn record(&self, resource: &'static str, succeeded: bool) -> u64 {
+        let mut latest = self.latest.lock().unwrap();
+        let status = latest.entry(resource).or_default();
+        if succeeded {
+            *status = ResourceStatus {
+                consecutive_failures: 0,
+                has_records: true,
+            };
+        } else {
+            status.consecutive_failures += 1;
+        }
+        self.consecutive_failures
+            .with_label_values(&[resource])
+            .set(i64::try_from(status.consecutive_failures).unwrap_or(i64::MAX));
+
+        status.consecutive_failures
+    }
+
+    /// `LastKnownGoodDataSource` で包んだリソースごとの、直近の取得の状況
+    pub fn latest(&self) -> BTreeMap<&'static str, ResourceStatus> {
+        self.latest.lock().unwrap().clone()
+    }
+}
+
+/// 内側のデータソースからの取得に失敗したとき、直近に成功した取得の結果を代わりに返す`VecDataSource`。
+///
+/// リソースごとに包むため、一つのリソースのクエリだけが失敗しても他のリソースには影響しない。
+/// 一度も成功していなければ、そのエラーをそのまま返す。
+pub struct LastKnownGoodDataSource<T, D> {
+    inner: D,
+    resource: &'static str,
+    freshness: Freshness,
+    last_known_good: Mutex<Option<Arc<Vec<T>>>>,
+}
+
+impl<T, D> LastKnownGoodDataSource<T, D> {
+    pub fn new(inner: D, resource: &'static str, freshness: Freshness) -> Self {
+        freshness
+            .latest
+            .lock()
+            .unwrap()
+            .entry(resource)
+            .or_default();
+        freshness
+            .consecutive_failures
+            .with_label_values(&[resource])
+            .set(0);
+
+        Self {
+            inner,
+            resource,
+            freshness,
+            last_known_good: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Clone + Send + Sync, D: VecDataSource<T> + Send + Sync> VecDataSource<T>
+    for LastKnownGoodDataSource<T, D>
+{
+    async fn fetch(&self) -> Result<Vec<T>, DataSourceError> {
+        match self.inner.fetch().await {
+            Ok(records) => {
+                self.freshness.record(self.resource, true);
+                *self.last_known_good.lock().unwrap() = Some(Arc::new(records.clone()));
+                Ok(records)
+            }
+            Err(error) => {
+                let consecutive_failures = self.freshness.record(self.resource, false);
+                let last_known_good = self.last_known_good.lock().unwrap().clone();
+                match last_known_good {
+                    Some(records) => {
+                        tracing::warn!(
+                            resource = self.resource,
+                            %error,
+                            consecutive_failures,
+                            "serving the last successfully fetched records"
+                        );
+                        self.freshness
+                            .stale_responses
+                            .with_label_values(&[self.resource])
+                            .inc();
+                        Ok(Vec::clone(&records))
+                    }
+                    None => Err(error),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use test_fixtures::fault_injection::{FaultInjectingDataSource, Faults};
+    use test_fixtures::FixedDataSource;
+
+    fn refused() -> DataSourceError {
+        DataSourceError::Connection("connection refused".to_string())
+    }
+
+    fn data_source() -> (
+        LastKnownGoodDataSource<u64, FaultInjectingDataSource<u64>>,
+        Faults,
+        Freshness,
+    ) {
+        let inner = FaultInjectingDataSource::new(FixedDataSource::ok(vec![1, 2, 3]));
+        let faults = inner.faults();
+        let freshness = Freshness::register(&Registry::new()).unwrap();
+
+        (
+            LastKnownGoodDataSource::new(inner, "vote_counts", freshness.clone()),
+            faults,
+            freshness,
+        )
+    }
+
+    #[tokio::test]
+    async fn failures_are_answered_with_the_last_good_records() {
+        let (data_source, faults, freshness) = data_source();
+
+        assert_eq!(data_source.fetch().await, Ok(vec![1, 2, 3]));
+        faults.truncate(Some(1));
+        assert_eq!(data_source.fetch().await, Ok(vec![1]));
+        faults.fail_next(2, DataSourceError::Other("unknown column".to_string()));
+        assert_eq!(data_source.fetch().await, Ok(vec![1]));
+        assert_eq!(data_source.fetch().await, Ok(vec![1]));
+
+        let consecutive_failures = freshness
+            .consecutive_failures
+            .with_label_values(&["vote_counts"]);
+        assert_eq!(consecutive_failures.get(), 2);
+        assert_eq!(
+            freshness
+                .stale_responses
+                .with_label_values(&["vote_counts"])
+                .get(),
+            2
+        );
+
+        assert_eq!(data_source.fetch().await, Ok(vec![1]));
+        assert_eq!(consecutive_failures.get(), 0);
+        assert_eq!(
+            freshness.latest()["vote_counts"],
+            ResourceStatus {
+                consecutive_failures: 0,
+                has_records: true,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn failures_before_any_success_are_returned() {
+        let (data_source, faults, freshness) = data_source();
+        faults.fail_next(1, refused());
+
+        assert_eq!(data_source.fetch().await, Err(refused()));
+        assert_eq!(
+            freshness.latest()["vote_counts"],
+            ResourceStatus {
+                consecutive_failures: 1,
+                has_records: false,
+            }
+        );
+        assert_eq!(data_source.fetch().await, Ok(vec![1, 2, 3]));
+    }
+}
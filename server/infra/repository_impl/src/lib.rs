@@ -1 +1,8 @@
+pub mod circuit_breaker_data_source;
+pub mod csv_data_source;
+pub mod data_quality;
+pub mod database_address;
+pub mod last_known_good_data_source;
+pub mod metered_data_source;
 pub mod mysql_data_source;
+pub mod single_flight_data_source;
@@ -0,0 +1,195 @@
+use domain::app_models::{DataSourceError, VecDataSource};
+
+use async_trait::async_trait;
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry};
+use std::time::{Duration, Instant};
+
+/// データソースからの取得にかかった時間と件数のメトリクス。ラベルはリソース名のみとする
+#[derive(Clone)]
+pub struct FetchMetrics {
+    duration_seconds: HistogramVec,
+    rows: HistogramVec,
+    errors: IntCounterVec,
+    slow_fetches: IntCounterVec,
+}
+
+impl FetchMetrics {
+    pub fn register(registry: &Registry) -> prometheus::Result<Self> {
+        let duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "seichi_game_api_source_fetch_duration_seconds",
+                "Time taken to fetch all records of a resource from the source",
+            )
+            .buckets(vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]),
+            &["resource"],
+        )?;
+        let rows = HistogramVec::new(
+            HistogramOpts::new(
+                "seichi_game_api_source_fetch_rows",
+                "Number of records fetched from the source at once",
+            )
+            .buckets(prometheus::exponential_buckets(100.0, 4.0, 8)?),
+            &["resource"],
+        )?;
+        let errors = IntCounterVec::new(
+            Opts::new(
+                "seichi_game_api_source_fetch_errors_total",
+                "Number of failed fetches from the source",
+            ),
+            &["resource"],
+        )?;
+        let slow_fetches = IntCounterVec::new(
+            Opts::new(
+                "seichi_game_api_slow_source_fetches_total",
+                "Number of fetches from the source that took longer than the slow fetch threshold",
+            ),
+            &["resource"],
+        )?;
+
+        registry.register(Box::new(duration_seconds.clone()))?;
+        registry.register(Box::new(rows.clone()))?;
+        registry.register(Box::new(errors.clone()))?;
+        registry.register(Box::new(slow_fetches.clone()))?;
+
+        Ok(Self {
+            duration_seconds,
+            rows,
+            errors,
+            slow_fetches,
+        })
+    }
+}
+
+/// 内側のデータソースからの取得を `FetchMetrics` に記録する`VecDataSource`。
+///
+/// 取得に `slow_threshold` 以上かかった場合は WARN のログを出す。
+pub struct MeteredDataSource<D> {
+    inner: D,
+    resource: &'static str,
+    metrics: FetchMetrics,
+    slow_threshold: Duration,
+}
+
+impl<D> MeteredDataSource<D> {
+    pub fn new(
+        inner: D,
+        resource: &'static str,
+        metrics: FetchMetrics,
+        slow_threshold: Duration,
+    ) -> Self {
+        Self {
+            inner,
+            resource,
+            metrics,
+            slow_threshold,
+        }
+    }
+}
+
+#[async_trait]
+impl<T, D> VecDataSource<T> for MeteredDataSource<D>
+where
+    T: Send,
+    D: VecDataSource<T> + Send + Sync,
+{
+    async fn fetch(&self) -> Result<Vec<T>, DataSourceError> {
+        let started_at = Instant::now();
+        let result = self.inner.fetch().await;
+        let elapsed = started_at.elapsed();
+
+        self.metrics
+            .duration_seconds
+            .with_label_values(&[self.resource])
+            .observe(elapsed.as_secs_f64());
+
+        if elapsed >= self.slow_threshold {
+            self.metrics
+                .slow_fetches
+                .with_label_values(&[self.resource])
+                .inc();
+            // アラートの条件に使えるよう、値は文字列に埋め込まずフィールドとして出す
+            tracing::warn!(
+                resource = self.resource,
+                duration_ms = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+                threshold_ms = u64::try_from(self.slow_threshold.as_millis()).unwrap_or(u64::MAX),
+                rows = result.as_ref().ok().map(Vec::len),
+                succeeded = result.is_ok(),
+                "slow source fetch"
+            );
+        }
+
+        match &result {
+            #[allow(clippy::cast_precision_loss)]
+            Ok(records) => self
+                .metrics
+                .rows
+                .with_label_values(&[self.resource])
+                .observe(records.len() as f64),
+            Err(_) => self
+                .metrics
+                .errors
+                .with_label_values(&[self.resource])
+                .inc(),
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_fixtures::FixedDataSource;
+
+    #[tokio::test]
+    async fn fetches_are_recorded_per_resource() {
+        let registry = Registry::new();
+        let metrics = FetchMetrics::register(&registry).unwrap();
+
+        let ok = MeteredDataSource::new(
+            FixedDataSource::ok(vec![1, 2, 3]),
+            "ok",
+            metrics.clone(),
+            Duration::from_secs(60),
+        );
+        let failing = MeteredDataSource::new(
+            FixedDataSource::<u32>(Err(DataSourceError::Connection("unavailable".to_string()))),
+            "failing",
+            metrics.clone(),
+            Duration::from_secs(60),
+        );
+
+        ok.fetch().await.unwrap();
+        failing.fetch().await.unwrap_err();
+
+        let rows = metrics.rows.with_label_values(&["ok"]);
+        assert_eq!(rows.get_sample_count(), 1);
+        assert_eq!(rows.get_sample_sum(), 3.0);
+        assert_eq!(metrics.errors.with_label_values(&["failing"]).get(), 1);
+        assert_eq!(metrics.errors.with_label_values(&["ok"]).get(), 0);
+        assert_eq!(
+            metrics
+                .duration_seconds
+                .with_label_values(&["failing"])
+                .get_sample_count(),
+            1
+        );
+        assert_eq!(metrics.slow_fetches.with_label_values(&["ok"]).get(), 0);
+    }
+
+    #[tokio::test]
+    async fn fetches_over_the_threshold_are_counted_as_slow() {
+        let registry = Registry::new();
+        let metrics = FetchMetrics::register(&registry).unwrap();
+
+        let slow = MeteredDataSource::new(
+            FixedDataSource::ok(vec![1]),
+            "slow",
+            metrics.clone(),
+            Duration::ZERO,
+        );
+        slow.fetch().await.unwrap();
+
+        assert_eq!(metrics.slow_fetches.with_label_values(&["slow"]).get(), 1);
+    }
+}
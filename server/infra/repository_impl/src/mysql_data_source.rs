@@ -1,173 +1,1018 @@
-use domain::app_models::VecDataSource;
+use domain::app_models::{
+    ActivePlayersDataSource, AggregateDataSource, DataSourceError, VecDataSource,
+};
+use domain::conversion::OutOfRange;
 use domain::models::{
-    Player, PlayerBreakCount, PlayerBuildCount, PlayerLastQuit, PlayerPlayTicks, PlayerVoteCount,
+    DomainValidationError, NameValidation, Player, PlayerBreakCount, PlayerBuildCount,
+    PlayerLastQuit, PlayerName, PlayerPlayTicks, PlayerUuid, PlayerVoteCount, Validated,
 };
+use domain::summary::CounterSummary;
 
-use config::SourceDatabaseConfig;
+use config::{
+    OutOfRangeCounters, PlayerNameValidation, SourceDatabaseConfig, SslMode, TimestampPrecision,
+};
 
-use anyhow::anyhow;
+use crate::data_quality::{self, Checked, DataQuality};
+use crate::database_address::DatabaseAddress;
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
-use sqlx::mysql::MySqlPoolOptions;
-use sqlx::{MySql, Pool, Row};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use futures::TryStreamExt;
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry};
+use sqlx::mysql::{
+    MySqlConnectOptions, MySqlConnection, MySqlDatabaseError, MySqlPoolOptions, MySqlRow,
+    MySqlSslMode,
+};
+use sqlx::pool::PoolConnection;
+use sqlx::{Connection, MySql, Pool, Row};
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{Instrument, Span};
 
-async fn create_mysql_connection_pool(
-    config: &SourceDatabaseConfig,
-) -> Result<Pool<MySql>, anyhow::Error> {
-    const MAX_CONNS: u32 = 5;
-
-    Ok(MySqlPoolOptions::new()
-        .max_connections(MAX_CONNS)
-        .connect(
-            format!(
-                "mysql://{user}:{pass}@{host}:{port}/{db}",
-                user = config.user,
-                pass = config.password,
-                host = config.host,
-                port = config.port.0,
-                db = config.database_name
-            )
-            .as_str(),
+fn connect_options(config: &SourceDatabaseConfig) -> MySqlConnectOptions {
+    // 接続文字列を組み立てるとパスワードなどに含まれる記号のエスケープが必要になるため、項目ごとに指定する
+    let mut options = MySqlConnectOptions::new()
+        .host(config.host.as_str())
+        .port(config.port.get())
+        .database(config.database_name.as_str())
+        .username(&config.user)
+        .password(&config.password);
+
+    if let Some(ssl_mode) = config.ssl_mode {
+        options = options.ssl_mode(match ssl_mode {
+            SslMode::Disabled => MySqlSslMode::Disabled,
+            SslMode::Preferred => MySqlSslMode::Preferred,
+            SslMode::Required => MySqlSslMode::Required,
+            SslMode::VerifyCa => MySqlSslMode::VerifyCa,
+            SslMode::VerifyIdentity => MySqlSslMode::VerifyIdentity,
+        });
+    }
+
+    if let Some(ssl_ca) = &config.ssl_ca {
+        options = options.ssl_ca(ssl_ca);
+    }
+
+    options
+}
+
+/// `address` を解決し、解決した順に接続先を `connect` で試して、最初に接続できたものを返す。
+///
+/// 解決した接続先と、接続できた接続先をログに残す。`profile` は接続プロファイルの名前
+async fn connect_in_order<T, F>(
+    options: &MySqlConnectOptions,
+    address: &DatabaseAddress,
+    profile: &str,
+    connect: impl Fn(MySqlConnectOptions) -> F,
+) -> anyhow::Result<T>
+where
+    F: Future<Output = Result<T, sqlx::Error>>,
+{
+    let endpoints = address.resolve().await?;
+    tracing::info!(
+        connection_profile = profile,
+        addresses = endpoints
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", "),
+        "resolved the source database host"
+    );
+
+    let mut last_error = None;
+    for endpoint in &endpoints {
+        match connect(options.clone().host(&endpoint.host).port(endpoint.port)).await {
+            Ok(connected) => {
+                tracing::info!(
+                    connection_profile = profile,
+                    address = %endpoint,
+                    "connected to the source database"
+                );
+                return Ok(connected);
+            }
+            Err(error) => {
+                tracing::warn!(
+                    connection_profile = profile,
+                    address = %endpoint,
+                    %error,
+                    "failed to connect to the source database; trying the next address"
+                );
+                last_error = Some(error);
+            }
+        }
+    }
+
+    Err(last_error.map_or_else(
+        || anyhow::anyhow!("no address of the source database to connect to"),
+        anyhow::Error::from,
+    ))
+}
+
+/// 接続プロファイルのパスワードを読み直す。秘匿情報の管理によって入れ替えられていれば、新しいパスワードを返す
+pub type PasswordSource = Arc<dyn Fn() -> anyhow::Result<String> + Send + Sync>;
+
+/// 接続プロファイルのコネクションプール。認証に失敗したら、パスワードを読み直して作り直す。
+///
+/// プールは作るときに接続できたアドレスに接続し続け、作り直すときにホストを解決し直す。
+/// 複製したものは同じプールを共有する。
+#[derive(Clone)]
+struct ConnectionPool {
+    /// 現在のプールと、それを作り直した回数
+    current: Arc<RwLock<(u64, Pool<MySql>)>>,
+    /// 同時に認証に失敗した取得が、それぞれプールを作り直さないようにする
+    rebuilding: Arc<tokio::sync::Mutex<()>>,
+    connect_options: MySqlConnectOptions,
+    address: DatabaseAddress,
+    max_connections: u32,
+    /// `None` ならパスワードを読み直さない
+    password_source: Option<PasswordSource>,
+}
+
+impl ConnectionPool {
+    async fn connect(
+        config: &SourceDatabaseConfig,
+        profile: &str,
+        password_source: Option<PasswordSource>,
+    ) -> anyhow::Result<Self> {
+        let connect_options = connect_options(config);
+        let address = DatabaseAddress::from_config(config);
+        let pool =
+            Self::connect_pool(&connect_options, &address, config.max_connections, profile).await?;
+
+        Ok(Self {
+            current: Arc::new(RwLock::new((0, pool))),
+            rebuilding: Arc::new(tokio::sync::Mutex::new(())),
+            connect_options,
+            address,
+            max_connections: config.max_connections,
+            password_source,
+        })
+    }
+
+    async fn connect_pool(
+        options: &MySqlConnectOptions,
+        address: &DatabaseAddress,
+        max_connections: u32,
+        profile: &str,
+    ) -> anyhow::Result<Pool<MySql>> {
+        connect_in_order(options, address, profile, |options| {
+            MySqlPoolOptions::new()
+                .max_connections(max_connections)
+                .connect_with(options)
+        })
+        .await
+    }
+
+    /// 現在のプールと、それを作り直した回数
+    fn current(&self) -> (u64, Pool<MySql>) {
+        self.current
+            .read()
+            .expect("Connection pools are never poisoned")
+            .clone()
+    }
+
+    /// `generation` 回目に作り直したプールで認証に失敗したので、パスワードを読み直してプールを作り直す。
+    ///
+    /// 作り直したか、他の取得が既に作り直していて、再び接続を試す価値があれば `true` を返す。
+    async fn rotate(&self, generation: u64, recorder: &AcquireRecorder) -> bool {
+        let password_source = match &self.password_source {
+            Some(password_source) => password_source,
+            None => return false,
+        };
+        let _rebuilding = self.rebuilding.lock().await;
+        if self.current().0 != generation {
+            return true;
+        }
+
+        let password = match password_source() {
+            Ok(password) => password,
+            Err(error) => {
+                recorder.record_credential_reload("unreadable");
+                tracing::warn!(
+                    connection_profile = %recorder.profile,
+                    error = format!("{error:#}"),
+                    "failed to reload the password of the source database"
+                );
+                return false;
+            }
+        };
+        let rebuilt = Self::connect_pool(
+            &self.connect_options.clone().password(&password),
+            &self.address,
+            self.max_connections,
+            &recorder.profile,
         )
-        .await?)
+        .await;
+        match rebuilt {
+            Ok(pool) => {
+                let (_, previous) = std::mem::replace(
+                    &mut *self
+                        .current
+                        .write()
+                        .expect("Connection pools are never poisoned"),
+                    (generation + 1, pool),
+                );
+                // 古いプールの接続を使っている取得は、そのまま終わらせる
+                tokio::spawn(async move { previous.close().await });
+                recorder.record_credential_reload("rotated");
+                tracing::info!(
+                    connection_profile = %recorder.profile,
+                    "reloaded the password of the source database and rebuilt the connection pool"
+                );
+                true
+            }
+            Err(error) => {
+                recorder.record_credential_reload("failed");
+                let rejected = error
+                    .downcast_ref::<sqlx::Error>()
+                    .map_or(false, is_access_denied);
+                if rejected {
+                    tracing::warn!(
+                        connection_profile = %recorder.profile,
+                        error = format!("{error:#}"),
+                        "authentication to the source database failed even after reloading the password"
+                    );
+                } else {
+                    tracing::warn!(
+                        connection_profile = %recorder.profile,
+                        error = format!("{error:#}"),
+                        "failed to rebuild the connection pool with the reloaded password"
+                    );
+                }
+                false
+            }
+        }
+    }
+
+    async fn close(&self) {
+        // 作り直している途中のプールを閉じ損ねないよう、作り直しが終わるのを待つ
+        let _rebuilding = self.rebuilding.lock().await;
+        self.current().1.close().await;
+    }
+}
+
+/// パスワードが誤っているために、ゲームDBに認証されなかったかどうか
+fn is_access_denied(error: &sqlx::Error) -> bool {
+    match error {
+        // ER_ACCESS_DENIED_ERROR
+        sqlx::Error::Database(database_error) => database_error
+            .try_downcast_ref::<MySqlDatabaseError>()
+            .map_or(false, |error| error.number() == 1045),
+        _ => false,
+    }
 }
 
-#[derive(Debug, Clone)]
+/// ゲームDBからの一回の取得を表すspan。取得できた行数を `rows` に記録する。
+///
+/// プレイヤーのUUIDのようにカーディナリティの高い値はフィールドに含めない。
+fn fetch_span(resource: &'static str) -> Span {
+    tracing::info_span!("source_fetch", resource, rows = tracing::field::Empty)
+}
+
+/// コネクションプールから接続を取り出すまでに待った時間と、パスワードを読み直した回数のメトリクス。
+/// ラベルは接続プロファイルの名前と、読み直した結果のみとする
+#[derive(Clone)]
+pub struct AcquireMetrics {
+    wait_seconds: HistogramVec,
+    slow_acquires: IntCounterVec,
+    credential_reloads: IntCounterVec,
+}
+
+impl AcquireMetrics {
+    pub fn register(registry: &Registry) -> prometheus::Result<Self> {
+        let wait_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "seichi_game_api_connection_pool_acquire_wait_seconds",
+                "Time spent waiting to acquire a connection to the source database from the pool",
+            )
+            .buckets(vec![
+                0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 30.0,
+            ]),
+            &["connection_profile"],
+        )?;
+        let slow_acquires = IntCounterVec::new(
+            Opts::new(
+                "seichi_game_api_slow_connection_acquires_total",
+                "Number of connection acquisitions that waited longer than the slow acquire threshold",
+            ),
+            &["connection_profile"],
+        )?;
+        let credential_reloads = IntCounterVec::new(
+            Opts::new(
+                "seichi_game_api_source_credential_reloads_total",
+                "Number of times the password of the source database was reloaded after an authentication failure",
+            ),
+            &["connection_profile", "outcome"],
+        )?;
+
+        registry.register(Box::new(wait_seconds.clone()))?;
+        registry.register(Box::new(slow_acquires.clone()))?;
+        registry.register(Box::new(credential_reloads.clone()))?;
+
+        Ok(Self {
+            wait_seconds,
+            slow_acquires,
+            credential_reloads,
+        })
+    }
+}
+
+/// 接続プロファイルのコネクションプールから接続を取り出すときの待ち時間を記録する
+#[derive(Clone)]
+struct AcquireRecorder {
+    profile: Arc<str>,
+    metrics: AcquireMetrics,
+    slow_threshold: Duration,
+}
+
+impl AcquireRecorder {
+    fn record_credential_reload(&self, outcome: &str) {
+        self.metrics
+            .credential_reloads
+            .with_label_values(&[&*self.profile, outcome])
+            .inc();
+    }
+
+    fn record(&self, waited: Duration, stats: ConnectionPoolStats) {
+        self.metrics
+            .wait_seconds
+            .with_label_values(&[&*self.profile])
+            .observe(waited.as_secs_f64());
+
+        if waited >= self.slow_threshold {
+            self.metrics
+                .slow_acquires
+                .with_label_values(&[&*self.profile])
+                .inc();
+            // 接続が足りていないのか、開くのが遅いのかを区別できるよう、プールの状態も出す
+            tracing::warn!(
+                connection_profile = %self.profile,
+                wait_ms = u64::try_from(waited.as_millis()).unwrap_or(u64::MAX),
+                threshold_ms = u64::try_from(self.slow_threshold.as_millis()).unwrap_or(u64::MAX),
+                pool_size = stats.size,
+                pool_idle = stats.idle,
+                pool_max_size = stats.max_size,
+                "slow connection acquire"
+            );
+        }
+    }
+}
+
+#[derive(Clone)]
 struct MySqlDataSource {
-    connection_pool: Pool<MySql>,
+    connection_pool: ConnectionPool,
+    acquire_recorder: AcquireRecorder,
+    data_quality: DataQuality,
+    data_quality_warn_ratio: f64,
+    raw_player_names: bool,
+    player_name_validation: NameValidation,
+    out_of_range: OutOfRange,
+    last_quit_precision: TimestampPrecision,
+}
+
+impl MySqlDataSource {
+    /// 待ち時間を記録しながら、コネクションプールから接続を一つ取り出す。
+    ///
+    /// 認証に失敗した場合は、パスワードを読み直してプールを作り直し、一度だけ取り出し直す
+    async fn acquire(&self) -> Result<PoolConnection<MySql>, DataSourceError> {
+        let started_at = Instant::now();
+        let (generation, pool) = self.connection_pool.current();
+        let connection = match pool.acquire().await {
+            Err(error)
+                if is_access_denied(&error)
+                    && self
+                        .connection_pool
+                        .rotate(generation, &self.acquire_recorder)
+                        .await =>
+            {
+                self.connection_pool.current().1.acquire().await
+            }
+            connection => connection,
+        };
+        self.acquire_recorder
+            .record(started_at.elapsed(), self.connection_pool_stats());
+
+        connection.map_err(classify)
+    }
 }
 
-// 利用するゲームDBのテーブル定義は
-// https://github.com/GiganticMinecraft/SeichiAssist/blob/2994a7269edb0427bd9d59c8ec822742638609c2/src/main/resources/db/migration/V1.0.0__Create_static_tables_and_columns.sql
-// を参照されたい。
+/// 利用するゲームDBのテーブル定義を持つ、SeichiAssist のコミット。
+///
+/// 定義は https://github.com/GiganticMinecraft/SeichiAssist/blob/2994a7269edb0427bd9d59c8ec822742638609c2/src/main/resources/db/migration/V1.0.0__Create_static_tables_and_columns.sql
+/// を参照されたい。上げるときは `tests/fixtures/seichiassist_schema.sql` も同じコミットのものにし、
+/// その上で全てのクエリを実行するテストを通すこと。
+pub const SEICHIASSIST_SCHEMA_REVISION: &str = "2994a7269edb0427bd9d59c8ec822742638609c2";
+
+pub const LAST_QUITS_QUERY: &str = "SELECT name, uuid, lastquit From playerdata";
+pub const LAST_QUIT_DATES_QUERY: &str =
+    "SELECT name, uuid, DATE(lastquit) AS lastquit From playerdata";
+pub const BREAK_COUNTS_QUERY: &str = "SELECT name, uuid, totalbreaknum From playerdata";
+pub const BUILD_COUNTS_QUERY: &str = "SELECT name, uuid, build_count From playerdata";
+pub const PLAY_TICKS_QUERY: &str = "SELECT name, uuid, playtick From playerdata";
+pub const VOTE_COUNTS_QUERY: &str = "SELECT playerdata.name, playerdata.uuid, vote_number From vote INNER JOIN playerdata ON vote.uuid = playerdata.uuid";
+
+// 集計のクエリは、負の値を0として合計し、負の値の行数を別に数える。
+// 合計は `bigint` に収まらないことがあるため、文字列にして読み出す
+pub const BREAK_COUNTS_SUMMARY_QUERY: &str = "SELECT COUNT(totalbreaknum) AS players, COUNT(CASE WHEN totalbreaknum < 0 THEN 1 END) AS negative_players, CAST(COALESCE(SUM(GREATEST(totalbreaknum, 0)), 0) AS CHAR) AS total, CAST(GREATEST(COALESCE(MAX(totalbreaknum), 0), 0) AS SIGNED) AS maximum From playerdata";
+pub const PLAY_TICKS_SUMMARY_QUERY: &str = "SELECT COUNT(playtick) AS players, COUNT(CASE WHEN playtick < 0 THEN 1 END) AS negative_players, CAST(COALESCE(SUM(GREATEST(playtick, 0)), 0) AS CHAR) AS total, CAST(GREATEST(COALESCE(MAX(playtick), 0), 0) AS SIGNED) AS maximum From playerdata";
+pub const VOTE_COUNTS_SUMMARY_QUERY: &str = "SELECT COUNT(vote_number) AS players, COUNT(CASE WHEN vote_number < 0 THEN 1 END) AS negative_players, CAST(COALESCE(SUM(GREATEST(vote_number, 0)), 0) AS CHAR) AS total, CAST(GREATEST(COALESCE(MAX(vote_number), 0), 0) AS SIGNED) AS maximum From vote INNER JOIN playerdata ON vote.uuid = playerdata.uuid";
+/// 最後にログアウトした時刻が、束縛した時刻以降のプレイヤーを数える
+pub const ACTIVE_PLAYERS_QUERY: &str =
+    "SELECT COUNT(*) AS players From playerdata WHERE lastquit >= ?";
+
+/// ゲームDBに発行する全てのクエリ
+pub const QUERIES: [&str; 10] = [
+    LAST_QUITS_QUERY,
+    LAST_QUIT_DATES_QUERY,
+    BREAK_COUNTS_QUERY,
+    BUILD_COUNTS_QUERY,
+    PLAY_TICKS_QUERY,
+    VOTE_COUNTS_QUERY,
+    BREAK_COUNTS_SUMMARY_QUERY,
+    PLAY_TICKS_SUMMARY_QUERY,
+    VOTE_COUNTS_SUMMARY_QUERY,
+    ACTIVE_PLAYERS_QUERY,
+];
+
+/// リソースの名前 (例: `break_counts`) と、`last_quits` の時刻の精度から、そのリソースを取得するクエリを決める
+pub fn resource_query(
+    resource: &str,
+    last_quit_precision: TimestampPrecision,
+) -> Option<&'static str> {
+    match resource {
+        "last_quits" => Some(match last_quit_precision {
+            TimestampPrecision::Full => LAST_QUITS_QUERY,
+            TimestampPrecision::Date => LAST_QUIT_DATES_QUERY,
+        }),
+        "break_counts" => Some(BREAK_COUNTS_QUERY),
+        "build_counts" => Some(BUILD_COUNTS_QUERY),
+        "play_ticks" => Some(PLAY_TICKS_QUERY),
+        "vote_counts" => Some(VOTE_COUNTS_QUERY),
+        _ => None,
+    }
+}
+
+/// 接続プロファイルの設定でゲームDBに一つだけ接続し、クエリを実行できるかを確かめる。
+///
+/// コネクションプールを作らないため、`close` するか破棄すれば接続は残らない。
+pub struct Probe {
+    connection: MySqlConnection,
+}
+
+impl Probe {
+    /// 接続プロファイル `profile` の設定 `config` で、サーバーと同じ順に接続先を試して接続する
+    pub async fn connect(config: &SourceDatabaseConfig, profile: &str) -> anyhow::Result<Self> {
+        let connection = connect_in_order(
+            &connect_options(config),
+            &DatabaseAddress::from_config(config),
+            profile,
+            |options| async move { MySqlConnection::connect_with(&options).await },
+        )
+        .await?;
+
+        Ok(Self { connection })
+    }
+
+    /// `query` を、読み出す行を一行に限って実行する
+    pub async fn run_limited(&mut self, query: &str) -> anyhow::Result<()> {
+        sqlx::query(&format!("{query} LIMIT 1"))
+            .fetch_optional(&mut self.connection)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn close(self) -> anyhow::Result<()> {
+        Ok(self.connection.close().await?)
+    }
+}
+
+/// sqlxのエラーを、再試行すれば成功しうるかどうかが分かるよう分類する
+fn classify(error: sqlx::Error) -> DataSourceError {
+    match error {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::Protocol(_)
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => DataSourceError::Connection(error.to_string()),
+        sqlx::Error::PoolTimedOut => DataSourceError::Timeout(error.to_string()),
+        sqlx::Error::ColumnDecode { index, source } => DataSourceError::Decode {
+            column: index,
+            detail: source.to_string(),
+        },
+        sqlx::Error::ColumnNotFound(column) => DataSourceError::Decode {
+            column,
+            detail: "no such column".to_string(),
+        },
+        sqlx::Error::Database(ref database_error) => {
+            // https://dev.mysql.com/doc/mysql-errors/8.0/en/server-error-reference.html
+            match database_error
+                .try_downcast_ref::<MySqlDatabaseError>()
+                .map(MySqlDatabaseError::number)
+            {
+                // ER_CON_COUNT_ERROR, ER_SERVER_SHUTDOWN, ER_CONNECTION_KILLED
+                Some(1040 | 1053 | 1927) => DataSourceError::Connection(error.to_string()),
+                // ER_LOCK_WAIT_TIMEOUT, ER_QUERY_TIMEOUT
+                Some(1205 | 3024) => DataSourceError::Timeout(error.to_string()),
+                _ => DataSourceError::Other(error.to_string()),
+            }
+        }
+        _ => DataSourceError::Other(error.to_string()),
+    }
+}
+
+/// 一行からプレイヤーを作る。名前が検証を通らなければその理由
+fn player(
+    row: &MySqlRow,
+    name_validation: NameValidation,
+) -> Result<Result<Player, DomainValidationError>, sqlx::Error> {
+    // varchar(128) -> PlayerUuid
+    let uuid = PlayerUuid::try_from(row.try_get::<&str, _>("uuid")?).map_err(|error| {
+        sqlx::Error::ColumnDecode {
+            index: "uuid".to_string(),
+            source: Box::new(error),
+        }
+    })?;
+
+    // varchar(30) -> PlayerName
+    Ok(
+        PlayerName::new(row.try_get("name")?, name_validation).map(|last_known_name| Player {
+            uuid,
+            last_known_name,
+        }),
+    )
+}
+
+/// 一行から作ったレコード。値が検証を通らなければその理由
+type RowRecord<T> = Result<Validated<T>, DomainValidationError>;
+
+impl MySqlDataSource {
+    /// `query` で読み出した各行のプレイヤーと、その行から `record` でレコードを作り、UUIDの重複を除いたものを返す。
+    ///
+    /// 取得ごとの品質報告を `data_quality` に記録する。
+    async fn fetch_checked<T: Send>(
+        &self,
+        resource: &'static str,
+        query: &str,
+        record: impl Fn(&MySqlRow, Player) -> Result<RowRecord<T>, sqlx::Error>,
+        player_of: impl Fn(&T) -> &Player,
+    ) -> Result<Vec<T>, DataSourceError> {
+        let span = fetch_span(resource);
+        let mut connection = self.acquire().instrument(span.clone()).await?;
+        // 行数は取得ごとにほとんど変わらないため、前回の行数だけ先に確保して確保し直しを避ける
+        let capacity = self.data_quality.rows_fetched(resource).unwrap_or_default();
+        let rows = async {
+            let mut rows = Vec::with_capacity(capacity);
+            let mut stream = sqlx::query::<MySql>(query).fetch(&mut *connection);
+            while let Some(row) = stream.try_next().await? {
+                rows.push(row);
+            }
+            Ok::<_, sqlx::Error>(rows)
+        }
+        .instrument(span.clone())
+        .await
+        .map_err(classify)?;
+
+        let checked = |row: &MySqlRow| match player(row, self.player_name_validation)? {
+            Ok(player) => record(row, player),
+            Err(error) => Ok(Err(error)),
+        };
+        let (records, mut report) = data_quality::collect(
+            rows.iter().map(|row| checked(row).map(Checked::from)),
+            |record| player_of(record).uuid,
+        );
+
+        for player in records.iter().map(&player_of) {
+            if let Some(raw) = player.last_known_name.raw() {
+                report.names_sanitized += 1;
+                if self.raw_player_names {
+                    tracing::debug!(
+                        resource,
+                        uuid = %player.uuid,
+                        name = %player.last_known_name,
+                        raw,
+                        "sanitized a player name"
+                    );
+                }
+            }
+        }
+        self.data_quality
+            .record(resource, report, self.data_quality_warn_ratio);
+
+        span.record("rows", records.len());
+        Ok(records)
+    }
+}
+
+/// 集計のクエリが文字列で返した合計を読む
+fn parse_total(total: &str) -> Result<u128, DataSourceError> {
+    total
+        .parse()
+        .map_err(|error: std::num::ParseIntError| DataSourceError::Decode {
+            column: "total".to_string(),
+            detail: error.to_string(),
+        })
+}
+
+impl MySqlDataSource {
+    /// 集計のクエリ `query` を実行する。
+    ///
+    /// ゲームDBの行をそのまま集計するため、UUIDが重複する行や名前が検証を通らない行も数え、
+    /// `fetch` の結果から集計した値とは一致しないことがある。負の値は `out_of_range` に従い、0として数えるか数えない。
+    async fn summarize_counter(
+        &self,
+        resource: &'static str,
+        query: &str,
+    ) -> Result<CounterSummary, DataSourceError> {
+        let span = fetch_span(resource);
+        let mut connection = self.acquire().instrument(span.clone()).await?;
+        let row = sqlx::query::<MySql>(query)
+            .fetch_one(&mut *connection)
+            .instrument(span)
+            .await
+            .map_err(classify)?;
+
+        let decode = |column: &str, detail: String| DataSourceError::Decode {
+            column: column.to_string(),
+            detail,
+        };
+        let players = row.try_get::<i64, _>("players").map_err(classify)?;
+        let negative_players = row
+            .try_get::<i64, _>("negative_players")
+            .map_err(classify)?;
+        let count = match self.out_of_range {
+            OutOfRange::Clamp => players,
+            OutOfRange::Skip => players - negative_players,
+        };
+
+        Ok(CounterSummary {
+            count: u64::try_from(count).map_err(|error| decode("players", error.to_string()))?,
+            sum: parse_total(row.try_get("total").map_err(classify)?)?,
+            max: u64::try_from(row.try_get::<i64, _>("maximum").map_err(classify)?)
+                .map_err(|error| decode("maximum", error.to_string()))?,
+        })
+    }
+}
 
 #[async_trait]
 impl VecDataSource<PlayerLastQuit> for MySqlDataSource {
-    async fn fetch(&self) -> anyhow::Result<Vec<PlayerLastQuit>> {
-        sqlx::query::<MySql>("SELECT name, uuid, lastquit From playerdata")
-            .try_map(|row| {
-                Ok(PlayerLastQuit {
-                    player: Player {
-                        // varchar(128) -> String
-                        uuid: row.try_get("uuid")?,
-                        // varchar(30) -> String
-                        last_known_name: row.try_get("name")?,
-                    },
-                    // datetime -> String
-                    rfc_3339_date_time: row.try_get::<DateTime<Utc>, _>("lastquit")?.to_rfc3339(),
-                })
-            })
-            .fetch_all(&self.connection_pool)
-            .await
-            .map_err(|e| anyhow!(e))
+    async fn fetch(&self) -> Result<Vec<PlayerLastQuit>, DataSourceError> {
+        // 日付の精度で提供する場合は、時刻の部分をゲームDBから読み出さない
+        let query = match self.last_quit_precision {
+            TimestampPrecision::Full => LAST_QUITS_QUERY,
+            TimestampPrecision::Date => LAST_QUIT_DATES_QUERY,
+        };
+        let precision = self.last_quit_precision;
+
+        self.fetch_checked(
+            "last_quits",
+            query,
+            move |row, player| {
+                let last_quit = match precision {
+                    // datetime -> DateTime<Utc>
+                    TimestampPrecision::Full => {
+                        row.try_get::<Option<DateTime<Utc>>, _>("lastquit")?
+                    }
+                    // date -> DateTime<Utc> (その日の0時)
+                    TimestampPrecision::Date => row
+                        .try_get::<Option<NaiveDate>, _>("lastquit")?
+                        .map(|date| {
+                            DateTime::from_naive_utc_and_offset(date.and_time(NaiveTime::MIN), Utc)
+                        }),
+                };
+
+                Ok(PlayerLastQuit::new(player, last_quit))
+            },
+            |record| &record.player,
+        )
+        .await
     }
 }
 
 #[async_trait]
 impl VecDataSource<PlayerBreakCount> for MySqlDataSource {
-    async fn fetch(&self) -> anyhow::Result<Vec<PlayerBreakCount>> {
-        sqlx::query::<MySql>("SELECT name, uuid, totalbreaknum From playerdata")
-            .try_map(|row| {
-                Ok(PlayerBreakCount {
-                    player: Player {
-                        // varchar(128) -> String
-                        uuid: row.try_get("uuid")?,
-                        // varchar(30) -> String
-                        last_known_name: row.try_get("name")?,
-                    },
-                    // bigint -> i64 -> u64
-                    // because bigint corresponds to i64 (https://docs.rs/sqlx/0.6.1/sqlx/mysql/types/index.html)
-                    break_count: row.try_get::<i64, _>("totalbreaknum")? as u64,
-                })
-            })
-            .fetch_all(&self.connection_pool)
-            .await
-            .map_err(|e| anyhow!(e))
+    async fn fetch(&self) -> Result<Vec<PlayerBreakCount>, DataSourceError> {
+        self.fetch_checked(
+            "break_counts",
+            BREAK_COUNTS_QUERY,
+            |row, player| {
+                // bigint corresponds to i64 (https://docs.rs/sqlx/0.6.1/sqlx/mysql/types/index.html)
+                Ok(PlayerBreakCount::new(
+                    player,
+                    row.try_get("totalbreaknum")?,
+                    self.out_of_range,
+                ))
+            },
+            |record| &record.player,
+        )
+        .await
     }
 }
 
 #[async_trait]
 impl VecDataSource<PlayerBuildCount> for MySqlDataSource {
-    async fn fetch(&self) -> anyhow::Result<Vec<PlayerBuildCount>> {
-        sqlx::query::<MySql>("SELECT name, uuid, build_count From playerdata")
-            .try_map(|row| {
-                Ok(PlayerBuildCount {
-                    player: Player {
-                        // varchar(128) -> String
-                        uuid: row.try_get("uuid")?,
-                        // varchar(30) -> String
-                        last_known_name: row.try_get("name")?,
-                    },
-                    // double -> u64
-                    build_count: row.try_get::<f64, _>("build_count")?.round() as u64,
-                })
-            })
-            .fetch_all(&self.connection_pool)
-            .await
-            .map_err(|e| anyhow!(e))
+    async fn fetch(&self) -> Result<Vec<PlayerBuildCount>, DataSourceError> {
+        self.fetch_checked(
+            "build_counts",
+            BUILD_COUNTS_QUERY,
+            // double -> f64
+            |row, player| {
+                Ok(PlayerBuildCount::new(
+                    player,
+                    row.try_get("build_count")?,
+                    self.out_of_range,
+                ))
+            },
+            |record| &record.player,
+        )
+        .await
     }
 }
 
 #[async_trait]
 impl VecDataSource<PlayerPlayTicks> for MySqlDataSource {
-    async fn fetch(&self) -> anyhow::Result<Vec<PlayerPlayTicks>> {
-        sqlx::query::<MySql>("SELECT name, uuid, playtick From playerdata")
-            .try_map(|row| {
-                Ok(PlayerPlayTicks {
-                    player: Player {
-                        // varchar(128) -> String
-                        uuid: row.try_get("uuid")?,
-                        // varchar(30) -> String
-                        last_known_name: row.try_get("name")?,
-                    },
-                    // i64 -> u64
-                    play_ticks: row.try_get::<i64, _>("playtick")? as u64,
-                })
-            })
-            .fetch_all(&self.connection_pool)
-            .await
-            .map_err(|e| anyhow!(e))
+    async fn fetch(&self) -> Result<Vec<PlayerPlayTicks>, DataSourceError> {
+        self.fetch_checked(
+            "play_ticks",
+            PLAY_TICKS_QUERY,
+            |row, player| {
+                Ok(PlayerPlayTicks::new(
+                    player,
+                    row.try_get("playtick")?,
+                    self.out_of_range,
+                ))
+            },
+            |record| &record.player,
+        )
+        .await
     }
 }
 
 #[async_trait]
 impl VecDataSource<PlayerVoteCount> for MySqlDataSource {
-    async fn fetch(&self) -> anyhow::Result<Vec<PlayerVoteCount>> {
-        sqlx::query::<MySql>("SELECT playerdata.name, playerdata.uuid, vote_number From vote INNER JOIN playerdata ON vote.uuid = playerdata.uuid")
-            .try_map(|row| {
-                Ok(PlayerVoteCount {
-                    player: Player {
-                        // varchar(128) -> String
-                        uuid: row.try_get("uuid")?,
-                        // varchar(30) -> String
-                        last_known_name: row.try_get("name")?,
-                    },
-                    // i32 -> u64
-                    vote_count: row.try_get::<i32, _>("vote_number")? as u64,
-                })
-            })
-            .fetch_all(&self.connection_pool)
+    async fn fetch(&self) -> Result<Vec<PlayerVoteCount>, DataSourceError> {
+        self.fetch_checked(
+            "vote_counts",
+            VOTE_COUNTS_QUERY,
+            // int -> i32
+            |row, player| {
+                Ok(PlayerVoteCount::new(
+                    player,
+                    row.try_get("vote_number")?,
+                    self.out_of_range,
+                ))
+            },
+            |record| &record.player,
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl AggregateDataSource<PlayerBreakCount> for MySqlDataSource {
+    async fn summarize(&self) -> Result<CounterSummary, DataSourceError> {
+        self.summarize_counter("break_counts_summary", BREAK_COUNTS_SUMMARY_QUERY)
+            .await
+    }
+}
+
+#[async_trait]
+impl AggregateDataSource<PlayerPlayTicks> for MySqlDataSource {
+    async fn summarize(&self) -> Result<CounterSummary, DataSourceError> {
+        self.summarize_counter("play_ticks_summary", PLAY_TICKS_SUMMARY_QUERY)
+            .await
+    }
+}
+
+#[async_trait]
+impl AggregateDataSource<PlayerVoteCount> for MySqlDataSource {
+    async fn summarize(&self) -> Result<CounterSummary, DataSourceError> {
+        self.summarize_counter("vote_counts_summary", VOTE_COUNTS_SUMMARY_QUERY)
+            .await
+    }
+}
+
+#[async_trait]
+impl ActivePlayersDataSource for MySqlDataSource {
+    async fn count_active_since(&self, since: DateTime<Utc>) -> Result<u64, DataSourceError> {
+        let span = fetch_span("active_players");
+        let mut connection = self.acquire().instrument(span.clone()).await?;
+        let players = sqlx::query_scalar::<MySql, i64>(ACTIVE_PLAYERS_QUERY)
+            .bind(since)
+            .fetch_one(&mut *connection)
+            .instrument(span)
             .await
-            .map_err(|e| anyhow!(e))
+            .map_err(classify)?;
+
+        u64::try_from(players).map_err(|error| DataSourceError::Decode {
+            column: "players".to_string(),
+            detail: error.to_string(),
+        })
     }
 }
 
+#[async_trait]
 pub trait CombinedDataSource:
     VecDataSource<PlayerLastQuit>
     + VecDataSource<PlayerBreakCount>
     + VecDataSource<PlayerBuildCount>
     + VecDataSource<PlayerPlayTicks>
     + VecDataSource<PlayerVoteCount>
+    + AggregateDataSource<PlayerBreakCount>
+    + AggregateDataSource<PlayerPlayTicks>
+    + AggregateDataSource<PlayerVoteCount>
+    + ActivePlayersDataSource
     + Clone
     + Send
     + Sync
     + 'static
 {
+    /// 同じコネクションプールを使い、`PlayerLastQuit` の時刻を `precision` の精度で取得するデータソース
+    #[must_use]
+    fn with_last_quit_precision(&self, precision: TimestampPrecision) -> Self;
+
+    fn connection_pool_stats(&self) -> ConnectionPoolStats;
+
+    /// コネクションプールから接続を一つ取り出し、ゲームDBが応答するかを確かめる
+    async fn ping(&self) -> anyhow::Result<()>;
+
+    /// 使用中の接続が返されるのを待ってから、コネクションプールの全ての接続を閉じる。閉じた後の取得は失敗する
+    async fn close(&self);
 }
 
-impl CombinedDataSource for MySqlDataSource {}
+/// コネクションプールの現在の状態
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionPoolStats {
+    /// 開いている接続の数 (使用中のものを含む)
+    pub size: u32,
+    /// 開いているが使われていない接続の数
+    pub idle: usize,
+    /// 開くことのできる接続の上限
+    pub max_size: u32,
+}
+
+#[async_trait]
+impl CombinedDataSource for MySqlDataSource {
+    fn with_last_quit_precision(&self, precision: TimestampPrecision) -> Self {
+        Self {
+            connection_pool: self.connection_pool.clone(),
+            acquire_recorder: self.acquire_recorder.clone(),
+            data_quality: self.data_quality.clone(),
+            data_quality_warn_ratio: self.data_quality_warn_ratio,
+            raw_player_names: self.raw_player_names,
+            player_name_validation: self.player_name_validation,
+            out_of_range: self.out_of_range,
+            last_quit_precision: precision,
+        }
+    }
+
+    fn connection_pool_stats(&self) -> ConnectionPoolStats {
+        let (_, pool) = self.connection_pool.current();
+        ConnectionPoolStats {
+            size: pool.size(),
+            idle: pool.num_idle(),
+            max_size: pool.options().get_max_connections(),
+        }
+    }
+
+    async fn ping(&self) -> anyhow::Result<()> {
+        let mut connection = self.acquire().await?;
+        connection.ping().await?;
+        Ok(())
+    }
 
-pub async fn from_config(config: &SourceDatabaseConfig) -> anyhow::Result<impl CombinedDataSource> {
-    let connection_pool = create_mysql_connection_pool(config).await?;
-    Ok(MySqlDataSource { connection_pool })
+    async fn close(&self) {
+        self.connection_pool.close().await;
+    }
+}
+
+/// 全ての接続プロファイルのデータソースで共有する、計測とログの設定
+#[derive(Clone)]
+pub struct Instrumentation {
+    pub acquire_metrics: AcquireMetrics,
+    /// 接続を取り出すまでにこれ以上待った場合は WARN のログを出す
+    pub slow_acquire_threshold: Duration,
+    pub data_quality: DataQuality,
+    /// 一回の取得で問題のあった行の割合がこれを超えた場合は WARN のログを出す
+    pub data_quality_warn_ratio: f64,
+    /// プレイヤーの名前を正規化したときに、元の値を DEBUG のログに出すかどうか
+    pub raw_player_names: bool,
+}
+
+/// 接続プロファイル `profile` の設定からデータソースを作る。
+///
+/// `password_source` を渡すと、認証に失敗したときにパスワードをそこから読み直し、再起動せずに入れ替えられる。
+pub async fn from_config(
+    config: &SourceDatabaseConfig,
+    profile: &str,
+    instrumentation: &Instrumentation,
+    password_source: Option<PasswordSource>,
+) -> anyhow::Result<impl CombinedDataSource> {
+    let connection_pool = ConnectionPool::connect(config, profile, password_source).await?;
+    Ok(MySqlDataSource {
+        connection_pool,
+        acquire_recorder: AcquireRecorder {
+            profile: profile.into(),
+            metrics: instrumentation.acquire_metrics.clone(),
+            slow_threshold: instrumentation.slow_acquire_threshold,
+        },
+        data_quality: instrumentation.data_quality.clone(),
+        data_quality_warn_ratio: instrumentation.data_quality_warn_ratio,
+        raw_player_names: instrumentation.raw_player_names,
+        player_name_validation: match config.player_name_validation {
+            PlayerNameValidation::Strict => NameValidation::Strict,
+            PlayerNameValidation::Lenient => NameValidation::Lenient,
+        },
+        out_of_range: match config.out_of_range_counters {
+            OutOfRangeCounters::Clamp => OutOfRange::Clamp,
+            OutOfRangeCounters::Skip => OutOfRange::Skip,
+        },
+        last_quit_precision: TimestampPrecision::Full,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sqlx_errors_are_classified() {
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+
+        assert!(matches!(
+            classify(sqlx::Error::Io(refused)),
+            DataSourceError::Connection(_)
+        ));
+        assert!(matches!(
+            classify(sqlx::Error::PoolTimedOut),
+            DataSourceError::Timeout(_)
+        ));
+        assert_eq!(
+            classify(sqlx::Error::ColumnDecode {
+                index: "uuid".to_string(),
+                source: "not a UUID".into(),
+            }),
+            DataSourceError::Decode {
+                column: "uuid".to_string(),
+                detail: "not a UUID".to_string(),
+            }
+        );
+        let other = classify(sqlx::Error::RowNotFound);
+        assert!(matches!(other, DataSourceError::Other(_)));
+        assert!(!other.is_transient());
+    }
+
+    #[test]
+    fn every_resource_is_fetched_by_one_of_the_queries() {
+        for resource in config::RESOURCE_NAMES {
+            for precision in [TimestampPrecision::Full, TimestampPrecision::Date] {
+                let query = resource_query(resource, precision)
+                    .unwrap_or_else(|| panic!("{resource} has no query"));
+                assert!(QUERIES.contains(&query), "{resource}");
+            }
+        }
+        assert_eq!(resource_query("unknown", TimestampPrecision::Full), None);
+    }
+
+    #[test]
+    fn summary_totals_may_exceed_bigint() {
+        assert_eq!(parse_total("0"), Ok(0));
+        assert_eq!(
+            parse_total("18446744073709551616"),
+            Ok(u128::from(u64::MAX) + 1)
+        );
+        assert!(matches!(
+            parse_total("-1"),
+            Err(DataSourceError::Decode { column, .. }) if column == "total"
+        ));
+    }
+
+    #[test]
+    fn acquire_waits_are_recorded_per_profile() {
+        let registry = Registry::new();
+        let metrics = AcquireMetrics::register(&registry).unwrap();
+        let recorder = AcquireRecorder {
+            profile: "ranking".into(),
+            metrics: metrics.clone(),
+            slow_threshold: Duration::from_millis(500),
+        };
+        let stats = ConnectionPoolStats {
+            size: 5,
+            idle: 0,
+            max_size: 5,
+        };
+
+        recorder.record(Duration::from_millis(10), stats);
+        recorder.record(Duration::from_secs(1), stats);
+
+        let wait_seconds = metrics.wait_seconds.with_label_values(&["ranking"]);
+        assert_eq!(wait_seconds.get_sample_count(), 2);
+        assert!((wait_seconds.get_sample_sum() - 1.01).abs() < 1e-9);
+        assert_eq!(
+            metrics.slow_acquires.with_label_values(&["ranking"]).get(),
+            1
+        );
+        assert_eq!(
+            metrics.slow_acquires.with_label_values(&["default"]).get(),
+            0
+        );
+    }
 }
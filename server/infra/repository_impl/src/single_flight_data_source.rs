@@ -0,0 +1,185 @@
+use domain::app_models::{DataSourceError, VecDataSource};
+
+use async_trait::async_trait;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::sync::{Arc, Mutex};
+use tracing::Instrument;
+
+type SharedFetch<T> = Shared<BoxFuture<'static, Result<Arc<Vec<T>>, DataSourceError>>>;
+
+/// 同時に要求された`fetch`を、内側のデータソースへの一回の問い合わせにまとめる`VecDataSource`。
+///
+/// 問い合わせが実行中に来た呼び出しは、新しく問い合わせを発行せずに実行中のものの結果(エラーを含む)を待つ。
+/// 問い合わせが完了した後の呼び出しは、再び内側のデータソースへ問い合わせる。
+pub struct SingleFlightDataSource<T> {
+    inner: Arc<dyn VecDataSource<T> + Send + Sync>,
+    flights: Mutex<Flights<T>>,
+}
+
+struct Flights<T> {
+    // 実行中の問い合わせと、それを識別するための通し番号
+    in_flight: Option<(u64, SharedFetch<T>)>,
+    last_flight_id: u64,
+}
+
+impl<T> SingleFlightDataSource<T> {
+    pub fn new(inner: impl VecDataSource<T> + Send + Sync + 'static) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            flights: Mutex::new(Flights {
+                in_flight: None,
+                last_flight_id: 0,
+            }),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> SingleFlightDataSource<T> {
+    fn join_or_start_flight(&self) -> (u64, SharedFetch<T>) {
+        let mut flights = self.flights.lock().unwrap();
+
+        if let Some((id, flight)) = flights.in_flight.as_ref() {
+            tracing::debug!(flight_id = *id, "joining an in-flight fetch");
+            return (*id, flight.clone());
+        }
+
+        flights.last_flight_id += 1;
+        let id = flights.last_flight_id;
+        tracing::debug!(flight_id = id, "starting a new fetch");
+
+        // 問い合わせはそれを待つどの呼び出しからも進められうるため、始めた呼び出しのspanの下に置く
+        let inner = self.inner.clone();
+        let flight = async move { inner.fetch().await.map(Arc::new) }
+            .instrument(tracing::Span::current())
+            .boxed()
+            .shared();
+
+        flights.in_flight = Some((id, flight.clone()));
+        (id, flight)
+    }
+
+    fn finish_flight(&self, id: u64) {
+        let mut flights = self.flights.lock().unwrap();
+
+        // 既に別の問い合わせが始まっている場合はそちらを消さない
+        if matches!(flights.in_flight.as_ref(), Some((in_flight_id, _)) if *in_flight_id == id) {
+            flights.in_flight = None;
+        }
+    }
+}
+
+/// 問い合わせがパニックした場合にも、その問い合わせを実行中のものから外す。
+///
+/// 外さなければ、以降の呼び出しが全てパニックした問い合わせを待とうとしてパニックする。
+struct FinishOnPanic<'a, T: Clone + Send + Sync + 'static> {
+    data_source: &'a SingleFlightDataSource<T>,
+    id: u64,
+}
+
+impl<T: Clone + Send + Sync + 'static> Drop for FinishOnPanic<'_, T> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.data_source.finish_flight(self.id);
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Clone + Send + Sync + 'static> VecDataSource<T> for SingleFlightDataSource<T> {
+    async fn fetch(&self) -> Result<Vec<T>, DataSourceError> {
+        let (id, flight) = self.join_or_start_flight();
+        let guard = FinishOnPanic {
+            data_source: self,
+            id,
+        };
+        let result = flight.await;
+        drop(guard);
+        self.finish_flight(id);
+
+        result.map(|records| Vec::clone(&records))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+    use test_fixtures::fault_injection::{FaultInjectingDataSource, Faults};
+    use test_fixtures::FixedDataSource;
+
+    fn refused() -> DataSourceError {
+        DataSourceError::Connection("connection refused".to_string())
+    }
+
+    /// 問い合わせに100ミリ秒かかるデータソース。時間を止めたテストでは、全ての呼び出しが始まってから完了する
+    fn slow_data_source() -> (SingleFlightDataSource<u64>, Faults) {
+        let inner = FaultInjectingDataSource::new(FixedDataSource::ok(vec![1, 2, 3]));
+        let faults = inner.faults();
+        faults.delay(Duration::from_millis(100));
+
+        (SingleFlightDataSource::new(inner), faults)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_fetches_share_one_query() {
+        let (data_source, faults) = slow_data_source();
+
+        let results = futures::future::join_all((0..16).map(|_| data_source.fetch())).await;
+
+        assert_eq!(faults.calls(), 1);
+        for result in results {
+            assert_eq!(result.unwrap(), vec![1, 2, 3]);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_fetches_share_one_error() {
+        let (data_source, faults) = slow_data_source();
+        faults.fail_next(1, refused());
+
+        let results = futures::future::join_all((0..16).map(|_| data_source.fetch())).await;
+
+        assert_eq!(faults.calls(), 1);
+        for result in results {
+            assert_eq!(result.unwrap_err(), refused());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sequential_fetches_query_again() {
+        let (data_source, faults) = slow_data_source();
+
+        data_source.fetch().await.unwrap();
+        data_source.fetch().await.unwrap();
+
+        assert_eq!(faults.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn panics_are_not_kept_after_the_query_panics() {
+        let inner = FaultInjectingDataSource::new(FixedDataSource::ok(vec![1, 2, 3]));
+        let faults = inner.faults();
+        faults.panic_next(1);
+        let data_source = Arc::new(SingleFlightDataSource::new(inner));
+
+        let panicking = data_source.clone();
+        let panic = tokio::spawn(async move { panicking.fetch().await })
+            .await
+            .unwrap_err();
+
+        assert!(panic.is_panic());
+        assert_eq!(data_source.fetch().await, Ok(vec![1, 2, 3]));
+        assert_eq!(faults.calls(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn errors_are_not_kept_after_the_query_completes() {
+        let (data_source, faults) = slow_data_source();
+        faults.fail_next(1, refused());
+
+        assert_eq!(data_source.fetch().await, Err(refused()));
+        assert_eq!(data_source.fetch().await, Ok(vec![1, 2, 3]));
+        assert_eq!(faults.calls(), 2);
+    }
+}
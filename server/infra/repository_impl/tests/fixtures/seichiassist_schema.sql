@@ -0,0 +1,18 @@
+-- SeichiAssist の V1.0.0__Create_static_tables_and_columns.sql のうち、このAPIが読み出すテーブルと列の定義
+-- https://github.com/GiganticMinecraft/SeichiAssist/blob/2994a7269edb0427bd9d59c8ec822742638609c2/src/main/resources/db/migration/V1.0.0__Create_static_tables_and_columns.sql
+-- 型と NULL を許すかどうかは元のマイグレーションに合わせ、このAPIが読み出さない列は省いている。
+
+CREATE TABLE IF NOT EXISTS playerdata(
+    name varchar(30),
+    uuid varchar(128) unique,
+    lastquit datetime,
+    totalbreaknum bigint default 0,
+    playtick int default 0,
+    build_count double default 0,
+    index index_playerdata_uuid(uuid)
+);
+
+CREATE TABLE IF NOT EXISTS vote(
+    uuid varchar(128) not null primary key,
+    vote_number int not null
+);
@@ -0,0 +1,418 @@
+//! ゲームDBのテーブル定義に対して、各リソースのクエリが期待どおりの値を返すかを確かめる。
+//!
+//! コンテナを使うテストはDockerが必要なため `#[ignore]` を付けている。
+//! `cargo test -p infra_repository_impl -- --ignored` で実行する。
+
+mod common;
+
+use chrono::{DateTime, TimeZone, Utc};
+use common::{seed_playerdata, PlayerdataRow, SourceDatabase, SCHEMA};
+use config::TimestampPrecision;
+use domain::app_models::{ActivePlayersDataSource, AggregateDataSource, VecDataSource};
+use domain::models::{
+    PlayerBreakCount, PlayerBuildCount, PlayerLastQuit, PlayerPlayTicks, PlayerVoteCount,
+};
+use domain::summary::CounterSummary;
+use infra_repository_impl::data_quality::QualityReport;
+use infra_repository_impl::mysql_data_source::{self, CombinedDataSource};
+use testcontainers::clients::Cli;
+
+const NOTCH: &str = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+const JEB: &str = "853c80ef-3c37-49fd-aa49-938b674adae6";
+const DINNERBONE: &str = "61699b2e-d327-4a01-9f1e-0ea8c3f06bc6";
+
+async fn fetch<T, S: VecDataSource<T>>(source: &S) -> Vec<T> {
+    source.fetch().await.unwrap()
+}
+
+/// UUID、名前、値の組をUUIDの順に並べる
+fn sorted<T, V>(
+    records: Vec<T>,
+    entry: impl Fn(&T) -> (String, String, V),
+) -> Vec<(String, String, V)> {
+    let mut entries = records.iter().map(entry).collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries
+}
+
+fn named<V>(uuid: &str, name: &str, value: V) -> (String, String, V) {
+    (uuid.to_string(), name.to_string(), value)
+}
+
+/// 固定したテーブル定義のFNV-1aによるハッシュ。改行コードの違いは無視する
+const SCHEMA_CHECKSUM: u64 = 0x3144_dcea_42f9_ad60;
+
+// テーブル定義を書き換えたら、それに合わせてクエリとテストを見直した上でこの値を更新すること
+#[test]
+fn vendored_schema_is_the_pinned_revision() {
+    let checksum = SCHEMA
+        .bytes()
+        .filter(|byte| *byte != b'\r')
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+
+    assert_eq!(
+        checksum, SCHEMA_CHECKSUM,
+        "the vendored schema was edited; update SCHEMA_CHECKSUM once the queries are reviewed"
+    );
+    assert!(
+        SCHEMA.contains(mysql_data_source::SEICHIASSIST_SCHEMA_REVISION),
+        "the vendored schema is not taken from SEICHIASSIST_SCHEMA_REVISION"
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn every_query_runs_against_the_vendored_schema() {
+    let docker = Cli::default();
+    let database = SourceDatabase::start(&docker).await;
+    seed_playerdata(&database.pool, &test_fixtures::playerdata_rows(1, 10)).await;
+
+    for query in mysql_data_source::QUERIES {
+        let mut statement = sqlx::query(query);
+        if query == mysql_data_source::ACTIVE_PLAYERS_QUERY {
+            statement = statement.bind(Utc::now());
+        }
+        statement
+            .fetch_all(&database.pool)
+            .await
+            .unwrap_or_else(|error| panic!("{query}: {error}"));
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn every_resource_reads_the_seichiassist_schema() {
+    let docker = Cli::default();
+    let database = SourceDatabase::start(&docker).await;
+
+    seed_playerdata(
+        &database.pool,
+        &[
+            PlayerdataRow {
+                name: "Notch".to_string(),
+                uuid: NOTCH.to_string(),
+                lastquit: Some(Utc.with_ymd_and_hms(2023, 4, 1, 12, 34, 56).unwrap()),
+                totalbreaknum: 100,
+                playtick: 72_000,
+                build_count: 2.5,
+                vote_number: Some(10),
+            },
+            // ハイフン無しのUUID、色コード付きの名前、一度も退出していないプレイヤー、負のカウンタ
+            PlayerdataRow {
+                name: "\u{a7}ajeb_".to_string(),
+                uuid: "853c80ef3c3749fdaa49938b674adae6".to_string(),
+                lastquit: None,
+                totalbreaknum: -5,
+                playtick: 0,
+                build_count: 10.0,
+                vote_number: None,
+            },
+        ],
+    )
+    .await;
+    sqlx::query(
+        "INSERT INTO playerdata (name, uuid, lastquit, totalbreaknum, playtick, build_count) VALUES (?, ?, NULL, NULL, 20, 0)",
+    )
+    .bind("Dinnerbone")
+    .bind(DINNERBONE)
+    .execute(&database.pool)
+    .await
+    .unwrap();
+
+    let instrumentation = common::instrumentation();
+    let source =
+        mysql_data_source::from_config(&database.config(), "default", &instrumentation, None)
+            .await
+            .unwrap();
+
+    assert_eq!(
+        sorted(fetch::<PlayerLastQuit, _>(&source).await, |record| (
+            record.player.uuid.to_string(),
+            record.player.last_known_name.to_string(),
+            record.last_quit,
+        )),
+        vec![named(
+            NOTCH,
+            "Notch",
+            Utc.with_ymd_and_hms(2023, 4, 1, 12, 34, 56).unwrap()
+        )]
+    );
+    assert_eq!(
+        fetch::<PlayerLastQuit, _>(&source.with_last_quit_precision(TimestampPrecision::Date))
+            .await
+            .into_iter()
+            .map(|record| record.last_quit)
+            .collect::<Vec<DateTime<Utc>>>(),
+        vec![Utc.with_ymd_and_hms(2023, 4, 1, 0, 0, 0).unwrap()]
+    );
+    assert_eq!(
+        sorted(fetch::<PlayerBreakCount, _>(&source).await, |record| (
+            record.player.uuid.to_string(),
+            record.player.last_known_name.to_string(),
+            record.break_count,
+        )),
+        vec![named(NOTCH, "Notch", 100), named(JEB, "jeb_", 0)]
+    );
+    assert_eq!(
+        sorted(fetch::<PlayerBuildCount, _>(&source).await, |record| (
+            record.player.uuid.to_string(),
+            record.player.last_known_name.to_string(),
+            record.build_count,
+        )),
+        vec![
+            named(NOTCH, "Notch", 3),
+            named(DINNERBONE, "Dinnerbone", 0),
+            named(JEB, "jeb_", 10),
+        ]
+    );
+    assert_eq!(
+        sorted(fetch::<PlayerPlayTicks, _>(&source).await, |record| (
+            record.player.uuid.to_string(),
+            record.player.last_known_name.to_string(),
+            record.play_ticks,
+        )),
+        vec![
+            named(NOTCH, "Notch", 72_000),
+            named(DINNERBONE, "Dinnerbone", 20),
+            named(JEB, "jeb_", 0),
+        ]
+    );
+    assert_eq!(
+        sorted(fetch::<PlayerVoteCount, _>(&source).await, |record| (
+            record.player.uuid.to_string(),
+            record.player.last_known_name.to_string(),
+            record.vote_count,
+        )),
+        vec![named(NOTCH, "Notch", 10)]
+    );
+
+    assert_eq!(
+        instrumentation.data_quality.latest()["break_counts"],
+        QualityReport {
+            rows_fetched: 3,
+            rows_clamped: 1,
+            parse_failures: 1,
+            names_sanitized: 1,
+            ..QualityReport::default()
+        }
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn generated_rows_are_read_as_the_fixtures_convert_them() {
+    let seed = 20_231_001;
+    let rows = test_fixtures::playerdata_rows(seed, 200);
+    let docker = Cli::default();
+    let database = SourceDatabase::start(&docker).await;
+    seed_playerdata(&database.pool, &rows).await;
+
+    let source = mysql_data_source::from_config(
+        &database.config(),
+        "default",
+        &common::instrumentation(),
+        None,
+    )
+    .await
+    .unwrap();
+    let break_count = |record: &PlayerBreakCount| {
+        (
+            record.player.uuid.to_string(),
+            record.player.last_known_name.to_string(),
+            record.break_count,
+        )
+    };
+
+    assert_eq!(
+        sorted(fetch::<PlayerBreakCount, _>(&source).await, break_count),
+        sorted(
+            rows.iter().map(PlayerdataRow::break_count).collect(),
+            break_count
+        ),
+        "seed = {seed}"
+    );
+    assert_eq!(
+        fetch::<PlayerVoteCount, _>(&source).await.len(),
+        rows.iter().filter_map(PlayerdataRow::vote_count).count(),
+        "seed = {seed}"
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn summaries_match_the_fetched_records() {
+    let seed = 20_231_002;
+    let rows = test_fixtures::playerdata_rows(seed, 200);
+    let docker = Cli::default();
+    let database = SourceDatabase::start(&docker).await;
+    seed_playerdata(&database.pool, &rows).await;
+
+    let source = mysql_data_source::from_config(
+        &database.config(),
+        "default",
+        &common::instrumentation(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        AggregateDataSource::<PlayerBreakCount>::summarize(&source)
+            .await
+            .unwrap(),
+        CounterSummary::of(
+            fetch::<PlayerBreakCount, _>(&source)
+                .await
+                .iter()
+                .map(|record| record.break_count)
+        ),
+        "seed = {seed}"
+    );
+    assert_eq!(
+        AggregateDataSource::<PlayerPlayTicks>::summarize(&source)
+            .await
+            .unwrap(),
+        CounterSummary::of(
+            fetch::<PlayerPlayTicks, _>(&source)
+                .await
+                .iter()
+                .map(|record| record.play_ticks)
+        ),
+        "seed = {seed}"
+    );
+    assert_eq!(
+        AggregateDataSource::<PlayerVoteCount>::summarize(&source)
+            .await
+            .unwrap(),
+        CounterSummary::of(
+            fetch::<PlayerVoteCount, _>(&source)
+                .await
+                .iter()
+                .map(|record| record.vote_count)
+        ),
+        "seed = {seed}"
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn active_players_are_counted_by_their_last_quit() {
+    let seed = 20_231_014;
+    let rows = test_fixtures::playerdata_rows(seed, 200);
+    let docker = Cli::default();
+    let database = SourceDatabase::start(&docker).await;
+    seed_playerdata(&database.pool, &rows).await;
+
+    let source = mysql_data_source::from_config(
+        &database.config(),
+        "default",
+        &common::instrumentation(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let mut last_quits = rows
+        .iter()
+        .filter_map(|row| row.lastquit)
+        .collect::<Vec<_>>();
+    last_quits.sort();
+    let since = last_quits[last_quits.len() / 2];
+    assert_eq!(
+        source.count_active_since(since).await.unwrap(),
+        rows.iter()
+            .filter(|row| row.lastquit.map_or(false, |lastquit| lastquit >= since))
+            .count()
+            .try_into()
+            .unwrap_or(u64::MAX),
+        "seed = {seed}"
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn rotated_passwords_are_reloaded_without_restarting() {
+    use sqlx::Executor;
+    use std::sync::{Arc, Mutex};
+
+    let docker = Cli::default();
+    let database = SourceDatabase::start(&docker).await;
+    seed_playerdata(&database.pool, &test_fixtures::playerdata_rows(1, 10)).await;
+    database
+        .pool
+        .execute("CREATE USER 'api'@'%' IDENTIFIED BY 'before'; GRANT SELECT ON seichiassist.* TO 'api'@'%'")
+        .await
+        .unwrap();
+
+    let mut config = database.config();
+    config.user = "api".to_string();
+    config.password = "before".to_string();
+    let password = Arc::new(Mutex::new("before".to_string()));
+    let password_source: mysql_data_source::PasswordSource = {
+        let password = password.clone();
+        Arc::new(move || Ok(password.lock().unwrap().clone()))
+    };
+    let registry = prometheus::Registry::new();
+    let instrumentation = mysql_data_source::Instrumentation {
+        acquire_metrics: mysql_data_source::AcquireMetrics::register(&registry).unwrap(),
+        ..common::instrumentation()
+    };
+    let source =
+        mysql_data_source::from_config(&config, "default", &instrumentation, Some(password_source))
+            .await
+            .unwrap();
+    assert_eq!(fetch::<PlayerBreakCount, _>(&source).await.len(), 10);
+
+    // 秘匿情報の管理がパスワードを入れ替え、開いていた接続も切れた状況にする
+    database
+        .pool
+        .execute("ALTER USER 'api'@'%' IDENTIFIED BY 'after'")
+        .await
+        .unwrap();
+    *password.lock().unwrap() = "after".to_string();
+    let sessions: Vec<(u64,)> =
+        sqlx::query_as("SELECT id FROM information_schema.processlist WHERE user = 'api'")
+            .fetch_all(&database.pool)
+            .await
+            .unwrap();
+    for (id,) in sessions {
+        database
+            .pool
+            .execute(format!("KILL {id}").as_str())
+            .await
+            .unwrap();
+    }
+
+    // 同時に認証に失敗しても、プールを作り直すのは一度だけで、どの取得も入れ替えたパスワードで成功する
+    let (first, second) = tokio::join!(
+        VecDataSource::<PlayerBreakCount>::fetch(&source),
+        VecDataSource::<PlayerPlayTicks>::fetch(&source),
+    );
+    assert_eq!(first.unwrap().len(), 10);
+    assert_eq!(second.unwrap().len(), 10);
+    let rotations = registry
+        .gather()
+        .into_iter()
+        .find(|family| family.get_name() == "seichi_game_api_source_credential_reloads_total")
+        .unwrap();
+    assert_eq!(
+        rotations
+            .get_metric()
+            .iter()
+            .map(|metric| {
+                let outcome = metric
+                    .get_label()
+                    .iter()
+                    .find(|label| label.get_name() == "outcome")
+                    .unwrap();
+                (
+                    outcome.get_value().to_string(),
+                    metric.get_counter().get_value(),
+                )
+            })
+            .collect::<Vec<_>>(),
+        vec![("rotated".to_string(), 1.0)]
+    );
+}
@@ -0,0 +1,240 @@
+mod report;
+
+use crate::report::{MethodStats, Report};
+use clap::{Parser, ValueEnum};
+use domain::app_models::VecDataSource;
+use infra_grpc::buf_generated::gigantic_minecraft::seichi_game_data::v1::read_service_server::ReadServiceServer;
+use infra_grpc::buf_generated::gigantic_minecraft::seichi_game_data::v1::{
+    BreakCountsResponse, BuildCountsResponse, LastQuitsResponse, PlayTicksResponse,
+    VoteCountsResponse,
+};
+use infra_grpc::read_service::ReadServiceImpl;
+use prost::Message;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use test_fixtures::{FixedDataSource, PlayerdataRow};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::client::Grpc;
+use tonic::codec::ProstCodec;
+use tonic::server::NamedService;
+use tonic::transport::{Channel, Server};
+
+/// APIサーバーに並行してリクエストを送り、メソッドごとの遅延の分位点とエラー率を報告する負荷試験
+#[derive(Parser, Debug)]
+struct Cli {
+    /// 試験するサーバーのURL (例: `http://localhost:50051`)。
+    /// 指定しなければ、生成したデータを提供するサーバーをこのプロセス内で起動して試験する
+    #[arg(long, value_name = "URL")]
+    target: Option<String>,
+
+    /// 並行してリクエストを送るクライアントの数。クライアントはそれぞれ別の接続を使う
+    #[arg(long, default_value_t = 16)]
+    concurrency: usize,
+
+    /// リクエストを送り続ける秒数
+    #[arg(long, default_value_t = 30)]
+    duration_seconds: u64,
+
+    /// 呼び出すメソッド。複数回指定したメソッドは、その回数に比例した割合で呼び出す。
+    /// 指定しなければ全てのメソッドを同じ割合で呼び出す
+    #[arg(long = "method", value_enum, value_name = "METHOD")]
+    methods: Vec<Method>,
+
+    /// プロセス内で起動するサーバーが提供するプレイヤーの数
+    #[arg(long, default_value_t = 100_000)]
+    players: usize,
+
+    /// プロセス内で起動するサーバーが提供するデータを生成するシード
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+/// 呼び出す `ReadService` のメソッド
+#[derive(ValueEnum, Clone, Copy, Debug)]
+#[value(rename_all = "snake_case")]
+enum Method {
+    LastQuits,
+    BreakCounts,
+    BuildCounts,
+    PlayTicks,
+    VoteCounts,
+}
+
+impl Method {
+    const ALL: [Self; 5] = [
+        Self::LastQuits,
+        Self::BreakCounts,
+        Self::BuildCounts,
+        Self::PlayTicks,
+        Self::VoteCounts,
+    ];
+
+    /// gRPCでのメソッドの名前
+    const fn grpc_name(self) -> &'static str {
+        match self {
+            Self::LastQuits => "LastQuits",
+            Self::BreakCounts => "BreakCounts",
+            Self::BuildCounts => "BuildCounts",
+            Self::PlayTicks => "PlayTicks",
+            Self::VoteCounts => "VoteCounts",
+        }
+    }
+
+    /// 呼び出し、成功すれば応答をエンコードしたときの大きさを返す
+    async fn call(self, client: &mut Grpc<Channel>) -> Result<usize, tonic::Status> {
+        match self {
+            Self::LastQuits => call::<LastQuitsResponse>(client, self.grpc_name()).await,
+            Self::BreakCounts => call::<BreakCountsResponse>(client, self.grpc_name()).await,
+            Self::BuildCounts => call::<BuildCountsResponse>(client, self.grpc_name()).await,
+            Self::PlayTicks => call::<PlayTicksResponse>(client, self.grpc_name()).await,
+            Self::VoteCounts => call::<VoteCountsResponse>(client, self.grpc_name()).await,
+        }
+    }
+}
+
+// サーバーはクライアントのコードを生成しないため、メソッドのパスを組み立てて呼び出す
+async fn call<Response: Message + Default + 'static>(
+    client: &mut Grpc<Channel>,
+    method: &str,
+) -> Result<usize, tonic::Status> {
+    let path = format!(
+        "/{}/{method}",
+        <ReadServiceServer<ReadServiceImpl> as NamedService>::NAME
+    );
+
+    client
+        .ready()
+        .await
+        .map_err(|error| tonic::Status::unavailable(error.to_string()))?;
+    let response = client
+        .unary(
+            tonic::Request::new(pbjson_types::Empty {}),
+            path.try_into().expect("Method paths are valid"),
+            ProstCodec::<pbjson_types::Empty, Response>::default(),
+        )
+        .await?;
+
+    Ok(response.into_inner().encoded_len())
+}
+
+/// 一つのクライアントとして、`deadline` まで `methods` を順に呼び出し続ける。
+///
+/// クライアントごとに始める位置を `first` でずらし、割合を保ったまま同じメソッドに呼び出しが偏らないようにする。
+async fn run_client(
+    target: String,
+    methods: Vec<Method>,
+    first: usize,
+    deadline: Instant,
+) -> anyhow::Result<BTreeMap<&'static str, MethodStats>> {
+    let channel = Channel::from_shared(target)?.connect().await?;
+    // 一覧の応答は既定の上限の4MiBを超えうる
+    let mut client = Grpc::new(channel).max_decoding_message_size(usize::MAX);
+    let mut stats = BTreeMap::<_, MethodStats>::new();
+
+    for method in methods.iter().cycle().skip(first) {
+        if Instant::now() >= deadline {
+            break;
+        }
+
+        let started_at = Instant::now();
+        let result = method.call(&mut client).await;
+        let method_stats = stats.entry(method.grpc_name()).or_default();
+        match result {
+            Ok(response_bytes) => method_stats.record_success(started_at.elapsed(), response_bytes),
+            Err(status) => method_stats.record_error(status.code()),
+        }
+    }
+
+    Ok(stats)
+}
+
+fn fixed<T: Clone + Send + Sync + 'static>(
+    records: Vec<T>,
+) -> Option<Box<dyn VecDataSource<T> + Send + Sync>> {
+    Some(Box::new(FixedDataSource::ok(records)))
+}
+
+/// `seed` から生成した `players` 人分のデータを全てのメソッドで提供するサーバーを空いているポートで起動し、そのURLを返す。
+///
+/// ゲームDBを使わないため、サーバー自体の処理と直列化の上限を測れる。
+async fn start_in_process_server(players: usize, seed: u64) -> anyhow::Result<String> {
+    let rows = test_fixtures::playerdata_rows(seed, players);
+    let service = ReadServiceImpl {
+        last_quit_data_source: fixed(rows.iter().filter_map(PlayerdataRow::last_quit).collect()),
+        break_counts_data_source: fixed(rows.iter().map(PlayerdataRow::break_count).collect()),
+        build_counts_data_source: fixed(
+            rows.iter().filter_map(PlayerdataRow::build_count).collect(),
+        ),
+        play_ticks_data_source: fixed(rows.iter().map(PlayerdataRow::play_ticks).collect()),
+        vote_counts_data_source: fixed(rows.iter().filter_map(PlayerdataRow::vote_count).collect()),
+    };
+
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+    let address = listener.local_addr()?;
+    tokio::spawn(async move {
+        if let Err(error) = Server::builder()
+            .add_service(ReadServiceServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+        {
+            eprintln!("the in-process server stopped: {error}");
+        }
+    });
+
+    Ok(format!("http://{address}"))
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let target = match cli.target {
+        Some(target) => target,
+        None => {
+            eprintln!(
+                "serving {} generated players (seed = {}) in process",
+                cli.players, cli.seed
+            );
+            start_in_process_server(cli.players, cli.seed).await?
+        }
+    };
+    let methods = if cli.methods.is_empty() {
+        Method::ALL.to_vec()
+    } else {
+        cli.methods
+    };
+
+    eprintln!(
+        "sending requests to {target} from {} clients for {} seconds",
+        cli.concurrency, cli.duration_seconds
+    );
+    let started_at = Instant::now();
+    let deadline = started_at + Duration::from_secs(cli.duration_seconds);
+    let clients = (0..cli.concurrency)
+        .map(|client| {
+            tokio::spawn(run_client(
+                target.clone(),
+                methods.clone(),
+                client,
+                deadline,
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    let mut stats = BTreeMap::<_, MethodStats>::new();
+    for client in clients {
+        for (method, client_stats) in client.await?? {
+            stats.entry(method).or_default().merge(client_stats);
+        }
+    }
+
+    print!(
+        "{}",
+        Report {
+            elapsed: started_at.elapsed(),
+            methods: stats,
+        }
+    );
+    Ok(())
+}
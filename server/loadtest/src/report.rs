@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// 一つのメソッドへの呼び出しの結果を集めたもの
+#[derive(Debug, Default)]
+pub struct MethodStats {
+    latencies: Vec<Duration>,
+    /// 失敗した呼び出しの数を、gRPCのステータスコードごとに数えたもの
+    errors: BTreeMap<String, usize>,
+    /// 成功した応答をエンコードしたときの大きさの合計
+    response_bytes: usize,
+}
+
+impl MethodStats {
+    pub fn record_success(&mut self, latency: Duration, response_bytes: usize) {
+        self.latencies.push(latency);
+        self.response_bytes += response_bytes;
+    }
+
+    pub fn record_error(&mut self, code: tonic::Code) {
+        *self.errors.entry(format!("{code:?}")).or_default() += 1;
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        self.latencies.extend(other.latencies);
+        for (code, count) in other.errors {
+            *self.errors.entry(code).or_default() += count;
+        }
+        self.response_bytes += other.response_bytes;
+    }
+
+    fn requests(&self) -> usize {
+        self.latencies.len() + self.errors.values().sum::<usize>()
+    }
+}
+
+/// 成功した呼び出しの遅延の `quantile` 分位点 (最近傍法)。成功した呼び出しが無ければ `None`
+fn percentile(sorted: &[Duration], quantile: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (quantile * sorted.len() as f64).ceil() as usize;
+
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// 負荷試験全体の結果
+pub struct Report {
+    pub elapsed: Duration,
+    pub methods: BTreeMap<&'static str, MethodStats>,
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let millis = |latency: Option<Duration>| {
+            latency.map_or_else(
+                || "-".to_string(),
+                |latency| format!("{:.1}", latency.as_secs_f64() * 1000.0),
+            )
+        };
+
+        writeln!(
+            f,
+            "{:<12} {:>9} {:>9} {:>8} {:>8} {:>8} {:>8} {:>8} {:>10}",
+            "method",
+            "requests",
+            "rps",
+            "errors",
+            "p50 ms",
+            "p90 ms",
+            "p99 ms",
+            "max ms",
+            "avg KiB"
+        )?;
+        for (method, stats) in &self.methods {
+            let mut sorted = stats.latencies.clone();
+            sorted.sort_unstable();
+            let requests = stats.requests();
+            let error_ratio = if requests == 0 {
+                0.0
+            } else {
+                (requests - sorted.len()) as f64 / requests as f64
+            };
+            let average_kib = if sorted.is_empty() {
+                0.0
+            } else {
+                stats.response_bytes as f64 / sorted.len() as f64 / 1024.0
+            };
+
+            writeln!(
+                f,
+                "{:<12} {:>9} {:>9.1} {:>7.2}% {:>8} {:>8} {:>8} {:>8} {:>10.1}",
+                method,
+                requests,
+                requests as f64 / self.elapsed.as_secs_f64(),
+                error_ratio * 100.0,
+                millis(percentile(&sorted, 0.5)),
+                millis(percentile(&sorted, 0.9)),
+                millis(percentile(&sorted, 0.99)),
+                millis(sorted.last().copied()),
+                average_kib,
+            )?;
+            for (code, count) in &stats.errors {
+                writeln!(f, "{:<12} {count} x {code}", "")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let sorted = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+
+        assert_eq!(percentile(&sorted, 0.5), Some(Duration::from_millis(50)));
+        assert_eq!(percentile(&sorted, 0.99), Some(Duration::from_millis(99)));
+        assert_eq!(percentile(&sorted, 1.0), Some(Duration::from_millis(100)));
+        assert_eq!(percentile(&sorted, 0.0), Some(Duration::from_millis(1)));
+        assert_eq!(percentile(&[], 0.5), None);
+    }
+
+    #[test]
+    fn merged_stats_keep_every_call() {
+        let mut stats = MethodStats::default();
+        stats.record_success(Duration::from_millis(1), 10);
+        let mut other = MethodStats::default();
+        other.record_error(tonic::Code::Unavailable);
+        other.record_error(tonic::Code::Unavailable);
+
+        stats.merge(other);
+
+        assert_eq!(stats.requests(), 3);
+        assert_eq!(stats.errors["Unavailable"], 2);
+    }
+}
@@ -0,0 +1,190 @@
+use async_trait::async_trait;
+use domain::app_models::{DataSourceError, VecDataSource};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// 内側のデータソースの代わりに起こす障害
+#[derive(Debug, Clone, Default)]
+struct Script {
+    /// この回数だけ、内側のデータソースに問い合わせずに `error` で失敗する
+    failures: usize,
+    error: Option<DataSourceError>,
+    /// この回数だけ、内側のデータソースに問い合わせずにパニックする
+    panics: usize,
+    /// 結果を返す前に待つ時間
+    latency: Duration,
+    /// 取得できたレコードを、先頭からこの件数に切り詰める
+    truncate_to: Option<usize>,
+    /// 障害を起こしたかどうかによらない、`fetch` が呼ばれた回数
+    calls: usize,
+}
+
+/// `FaultInjectingDataSource` が起こす障害を、テストの途中で切り替えるための操作
+#[derive(Debug, Clone, Default)]
+pub struct Faults(Arc<Mutex<Script>>);
+
+impl Faults {
+    fn script(&self) -> MutexGuard<'_, Script> {
+        self.0.lock().expect("Fault scripts are never poisoned")
+    }
+
+    /// 次の `n` 回の取得を `error` で失敗させる。前の指定で残っている回数は置き換える
+    pub fn fail_next(&self, n: usize, error: DataSourceError) {
+        let mut script = self.script();
+        script.failures = n;
+        script.error = Some(error);
+    }
+
+    /// 次の `n` 回の取得をパニックさせる。失敗させる指定よりも先に使われる
+    pub fn panic_next(&self, n: usize) {
+        self.script().panics = n;
+    }
+
+    /// 以降の取得で、成功か失敗かによらず結果を返す前に `latency` だけ待つ
+    pub fn delay(&self, latency: Duration) {
+        self.script().latency = latency;
+    }
+
+    /// 以降の取得で、成功したときのレコードを先頭の `len` 件に切り詰める。`None` で元に戻す
+    pub fn truncate(&self, len: Option<usize>) {
+        self.script().truncate_to = len;
+    }
+
+    /// 全ての障害を取り除く。呼ばれた回数は数え直さない
+    pub fn clear(&self) {
+        let calls = self.script().calls;
+        *self.script() = Script {
+            calls,
+            ..Script::default()
+        };
+    }
+
+    /// これまでに `fetch` が呼ばれた回数
+    pub fn calls(&self) -> usize {
+        self.script().calls
+    }
+}
+
+/// 内側の `VecDataSource` に、指示された障害を差し込むデータソース。
+///
+/// 待ち時間には `tokio::time::sleep` を使うため、時間を止めたテスト (`start_paused`) では実際には待たない。
+pub struct FaultInjectingDataSource<T> {
+    inner: Arc<dyn VecDataSource<T> + Send + Sync>,
+    faults: Faults,
+}
+
+impl<T> FaultInjectingDataSource<T> {
+    /// 障害を起こさない状態で `inner` を包む
+    pub fn new(inner: impl VecDataSource<T> + Send + Sync + 'static) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            faults: Faults::default(),
+        }
+    }
+
+    /// 起こす障害を切り替えるための操作。データソースを別のタスクに渡した後も使える
+    pub fn faults(&self) -> Faults {
+        self.faults.clone()
+    }
+}
+
+#[async_trait]
+impl<T: Send + 'static> VecDataSource<T> for FaultInjectingDataSource<T> {
+    async fn fetch(&self) -> Result<Vec<T>, DataSourceError> {
+        let (failure, latency, truncate_to) = {
+            let mut script = self.faults.script();
+            script.calls += 1;
+            if script.panics > 0 {
+                script.panics -= 1;
+                // ロックを持ったままパニックすると、以降の操作が全て失敗するため先に手放す
+                drop(script);
+                panic!("injected panic");
+            }
+            let failure = if script.failures > 0 {
+                script.failures -= 1;
+                script.error.clone()
+            } else {
+                None
+            };
+            (failure, script.latency, script.truncate_to)
+        };
+
+        let result = match failure {
+            Some(error) => Err(error),
+            None => self.inner.fetch().await.map(|mut records| {
+                if let Some(len) = truncate_to {
+                    records.truncate(len);
+                }
+                records
+            }),
+        };
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::FixedDataSource;
+
+    fn refused() -> DataSourceError {
+        DataSourceError::Connection("connection refused".to_string())
+    }
+
+    #[tokio::test]
+    async fn scripted_failures_run_out() {
+        let data_source = FaultInjectingDataSource::new(FixedDataSource::ok(vec![1, 2, 3]));
+        let faults = data_source.faults();
+
+        faults.fail_next(2, refused());
+
+        assert_eq!(data_source.fetch().await, Err(refused()));
+        assert_eq!(data_source.fetch().await, Err(refused()));
+        assert_eq!(data_source.fetch().await, Ok(vec![1, 2, 3]));
+        assert_eq!(faults.calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn records_are_truncated_until_cleared() {
+        let data_source = FaultInjectingDataSource::new(FixedDataSource::ok(vec![1, 2, 3]));
+        let faults = data_source.faults();
+
+        faults.truncate(Some(1));
+        assert_eq!(data_source.fetch().await, Ok(vec![1]));
+
+        faults.clear();
+        assert_eq!(data_source.fetch().await, Ok(vec![1, 2, 3]));
+        assert_eq!(faults.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn scripted_panics_run_out() {
+        let data_source = Arc::new(FaultInjectingDataSource::new(FixedDataSource::ok(vec![1])));
+        data_source.faults().panic_next(1);
+
+        let panicking = data_source.clone();
+        let panic = tokio::spawn(async move { panicking.fetch().await })
+            .await
+            .unwrap_err();
+
+        assert!(panic.is_panic());
+        assert_eq!(data_source.fetch().await, Ok(vec![1]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn latency_is_added_to_every_fetch() {
+        let data_source = FaultInjectingDataSource::new(FixedDataSource::ok(vec![1]));
+        data_source.faults().delay(Duration::from_secs(30));
+        data_source.faults().fail_next(1, refused());
+
+        let started_at = tokio::time::Instant::now();
+        assert_eq!(data_source.fetch().await, Err(refused()));
+        assert_eq!(data_source.fetch().await, Ok(vec![1]));
+
+        assert_eq!(started_at.elapsed(), Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,321 @@
+//! テストのための、それらしいプレイヤーのデータを生成する補助。
+//!
+//! 同じシードからは常に同じデータを生成する。失敗したテストを再現できるよう、
+//! 生成したデータについてのアサーションのメッセージにはシードを含めること。
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use domain::app_models::{DataSourceError, VecDataSource};
+use domain::conversion::OutOfRange;
+use domain::models::{
+    NameValidation, Player, PlayerBreakCount, PlayerBuildCount, PlayerLastQuit, PlayerName,
+    PlayerPlayTicks, PlayerUuid, PlayerVoteCount,
+};
+
+pub mod fault_injection;
+
+/// 生成に使う擬似乱数。依存するクレートの版によって生成するデータが変わらないよう、SplitMix64を自前で持つ
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// 0以上1未満の一様な値
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    /// 0以上 `max` 以下の一様な値
+    fn up_to(&mut self, max: u64) -> u64 {
+        match max.checked_add(1) {
+            Some(bound) => self.next_u64() % bound,
+            None => self.next_u64(),
+        }
+    }
+}
+
+/// シード `seed` から決まる、UUIDと名前を持つプレイヤー
+pub fn fake_player(seed: u64) -> Player {
+    let mut rng = SplitMix64(seed);
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&rng.next_u64().to_be_bytes());
+    bytes[8..].copy_from_slice(&rng.next_u64().to_be_bytes());
+    // バージョン4 (ランダム) のUUIDとする
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    Player {
+        uuid: PlayerUuid::from_bytes(bytes),
+        // Minecraftの名前の上限の16文字に収める
+        last_known_name: PlayerName::new(
+            &format!("player_{:x}", seed & 0xffff_ffff),
+            NameValidation::Strict,
+        )
+        .expect("Fake names are at most 15 characters long"),
+    }
+}
+
+/// カウンタの値の分布
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Counts {
+    /// 0以上 `max` 以下から一様に選ぶ
+    Uniform { max: u64 },
+    /// 少数のプレイヤーが大きな値を持つ、実際のランキングに近い分布。`median` はおおよその中央値
+    LongTail { median: u64 },
+    /// 全員が同じ値を持つ。同じ値のプレイヤーの並びを確かめるのに使う
+    Constant(u64),
+}
+
+impl Counts {
+    fn sample(self, rng: &mut SplitMix64) -> u64 {
+        match self {
+            Self::Uniform { max } => rng.up_to(max),
+            Self::LongTail { median } => {
+                // u / (1 - u) の中央値は1
+                let u = rng.next_f64();
+                (median as f64 * u / (1.0 - u)) as u64
+            }
+            Self::Constant(value) => value,
+        }
+    }
+}
+
+/// 最終ログアウト日時の分布
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LastQuits {
+    /// 一度も退出していない (`lastquit` が NULL の) プレイヤーの割合
+    pub never_quit_ratio: f64,
+    /// この時刻以降から一様に選ぶ
+    pub since: DateTime<Utc>,
+    /// この時刻より前から一様に選ぶ
+    pub until: DateTime<Utc>,
+}
+
+/// `playerdata_rows` で生成する各列の分布
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Distributions {
+    pub break_count: Counts,
+    pub build_count: Counts,
+    pub play_ticks: Counts,
+    pub vote_count: Counts,
+    /// `vote` テーブルに行を持つプレイヤーの割合
+    pub voted_ratio: f64,
+    pub last_quit: LastQuits,
+}
+
+impl Default for Distributions {
+    fn default() -> Self {
+        Self {
+            break_count: Counts::LongTail { median: 100_000 },
+            build_count: Counts::LongTail { median: 10_000 },
+            // 20tickが1秒なので、中央値は10時間ほど
+            play_ticks: Counts::LongTail { median: 720_000 },
+            vote_count: Counts::Uniform { max: 500 },
+            voted_ratio: 0.5,
+            last_quit: LastQuits {
+                never_quit_ratio: 0.05,
+                since: Utc.with_ymd_and_hms(2018, 1, 1, 0, 0, 0).unwrap(),
+                until: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+            },
+        }
+    }
+}
+
+/// ゲームDBの `playerdata` テーブルと `vote` テーブルの一行に入る値。
+///
+/// SQLのバックエンドに入れることも、ゲームDBから読み出したときと同じ検証を通してレコードにすることもできる。
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerdataRow {
+    pub name: String,
+    /// ハイフン付きかハイフン無しのUUID
+    pub uuid: String,
+    /// `None` なら NULL
+    pub lastquit: Option<DateTime<Utc>>,
+    pub totalbreaknum: i64,
+    pub playtick: i64,
+    pub build_count: f64,
+    /// `None` なら `vote` テーブルに行を持たない
+    pub vote_number: Option<i32>,
+}
+
+fn saturating_i64(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+impl Distributions {
+    /// シード `seed` から決まる `n` 人分の行を、分布に従って生成する
+    pub fn rows(&self, seed: u64, n: usize) -> Vec<PlayerdataRow> {
+        let mut rng = SplitMix64(seed);
+        (0..n)
+            .map(|_| {
+                let player = fake_player(rng.next_u64());
+                let last_quit = self.last_quit;
+                let lastquit = (rng.next_f64() >= last_quit.never_quit_ratio).then(|| {
+                    let range = (last_quit.until - last_quit.since).num_seconds().max(1);
+                    last_quit.since
+                        + Duration::seconds(saturating_i64(rng.up_to((range - 1) as u64)))
+                });
+
+                PlayerdataRow {
+                    name: player.last_known_name.to_string(),
+                    uuid: player.uuid.to_string(),
+                    lastquit,
+                    totalbreaknum: saturating_i64(self.break_count.sample(&mut rng)),
+                    playtick: saturating_i64(self.play_ticks.sample(&mut rng)),
+                    build_count: self.build_count.sample(&mut rng) as f64,
+                    vote_number: (rng.next_f64() < self.voted_ratio).then(|| {
+                        i32::try_from(self.vote_count.sample(&mut rng)).unwrap_or(i32::MAX)
+                    }),
+                }
+            })
+            .collect()
+    }
+}
+
+/// シード `seed` から決まる `n` 人分の行を、既定の分布に従って生成する
+pub fn playerdata_rows(seed: u64, n: usize) -> Vec<PlayerdataRow> {
+    Distributions::default().rows(seed, n)
+}
+
+impl PlayerdataRow {
+    /// ゲームDBから読み出したときと同じく、UUIDを解釈し名前を正規化する
+    pub fn player(&self) -> Player {
+        Player {
+            uuid: PlayerUuid::try_from(self.uuid.as_str()).expect("Fixture UUIDs are valid"),
+            last_known_name: PlayerName::new(&self.name, NameValidation::Lenient)
+                .expect("Lenient names are never rejected"),
+        }
+    }
+
+    /// 一度も退出していなければ `None`
+    pub fn last_quit(&self) -> Option<PlayerLastQuit> {
+        PlayerLastQuit::new(self.player(), self.lastquit)
+            .ok()
+            .map(|validated| validated.record)
+    }
+
+    pub fn break_count(&self) -> PlayerBreakCount {
+        PlayerBreakCount::new(self.player(), self.totalbreaknum, OutOfRange::Clamp)
+            .expect("Break counts are never rejected")
+            .record
+    }
+
+    /// 有限でない値は提供しないため `None`
+    pub fn build_count(&self) -> Option<PlayerBuildCount> {
+        PlayerBuildCount::new(self.player(), self.build_count, OutOfRange::Clamp)
+            .ok()
+            .map(|validated| validated.record)
+    }
+
+    pub fn play_ticks(&self) -> PlayerPlayTicks {
+        PlayerPlayTicks::new(self.player(), self.playtick, OutOfRange::Clamp)
+            .expect("Play ticks are never rejected")
+            .record
+    }
+
+    /// `vote` テーブルに行を持たなければ `None`
+    pub fn vote_count(&self) -> Option<PlayerVoteCount> {
+        self.vote_number.map(|vote_number| {
+            PlayerVoteCount::new(self.player(), vote_number, OutOfRange::Clamp)
+                .expect("Vote counts are never rejected")
+                .record
+        })
+    }
+}
+
+/// 決まった結果を返すデータソース
+#[derive(Debug, Clone)]
+pub struct FixedDataSource<T>(pub Result<Vec<T>, DataSourceError>);
+
+impl<T> FixedDataSource<T> {
+    pub fn ok(records: Vec<T>) -> Self {
+        Self(Ok(records))
+    }
+}
+
+#[async_trait]
+impl<T: Clone + Send + Sync> VecDataSource<T> for FixedDataSource<T> {
+    async fn fetch(&self) -> Result<Vec<T>, DataSourceError> {
+        self.0.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn the_same_seed_generates_the_same_rows() {
+        for seed in [0, 1, u64::MAX] {
+            assert_eq!(
+                playerdata_rows(seed, 50),
+                playerdata_rows(seed, 50),
+                "seed = {seed}"
+            );
+        }
+        assert_ne!(playerdata_rows(1, 50), playerdata_rows(2, 50));
+        assert_eq!(fake_player(42).uuid, fake_player(42).uuid);
+    }
+
+    #[test]
+    fn rows_follow_their_distributions() {
+        let seed = 7;
+        let distributions = Distributions {
+            break_count: Counts::Constant(3),
+            vote_count: Counts::Uniform { max: 10 },
+            voted_ratio: 1.0,
+            last_quit: LastQuits {
+                never_quit_ratio: 0.0,
+                ..Distributions::default().last_quit
+            },
+            ..Distributions::default()
+        };
+
+        for row in distributions.rows(seed, 200) {
+            assert_eq!(row.totalbreaknum, 3, "seed = {seed}");
+            assert!(
+                matches!(row.vote_number, Some(0..=10)),
+                "seed = {seed}, row = {row:?}"
+            );
+            let lastquit = row.lastquit.expect("every player has quit");
+            assert!(
+                (distributions.last_quit.since..distributions.last_quit.until).contains(&lastquit),
+                "seed = {seed}, row = {row:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rows_convert_to_records_through_domain_validation() {
+        let seed = 11;
+        let rows = playerdata_rows(seed, 100);
+
+        assert_eq!(
+            rows.iter().filter_map(PlayerdataRow::last_quit).count(),
+            rows.iter().filter(|row| row.lastquit.is_some()).count(),
+            "seed = {seed}"
+        );
+        assert_eq!(
+            rows.iter().filter_map(PlayerdataRow::vote_count).count(),
+            rows.iter().filter(|row| row.vote_number.is_some()).count(),
+            "seed = {seed}"
+        );
+        for row in &rows {
+            assert_eq!(row.player().uuid.to_string(), row.uuid, "seed = {seed}");
+        }
+    }
+
+    #[tokio::test]
+    async fn fixed_data_sources_return_their_records() {
+        let rows = playerdata_rows(3, 5);
+        let source = FixedDataSource::ok(rows.iter().map(PlayerdataRow::break_count).collect());
+
+        assert_eq!(source.fetch().await.unwrap().len(), 5);
+    }
+}